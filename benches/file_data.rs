@@ -0,0 +1,281 @@
+// Compares the time to read every tile of an archive with FileDataSource and
+// ContainerDataSource against CachedFileDataSource, with and without a zstd
+// dictionary. Run with:
+//
+//     cargo bench --bench file_data
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use legion_prof_viewer::archive_data::{ArchiveFormat, DataSourceArchiveWriter};
+use legion_prof_viewer::cached_file_data::CachedFileDataSource;
+use legion_prof_viewer::container_data::ContainerDataSource;
+use legion_prof_viewer::data::{
+    Color32, CounterTile, DataSource, DataSourceDescription, DataSourceInfo, DataSourceResult,
+    EntryID, EntryIndex, EntryInfo, Field, FieldID, FieldSchema, Item, ItemMeta, ItemUID,
+    SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, TileID,
+    TileSet, UtilPoint,
+};
+use legion_prof_viewer::deferred_data::DeferredDataSourceWrapper;
+use legion_prof_viewer::file_data::FileDataSource;
+use legion_prof_viewer::timestamp::{Interval, Timestamp};
+
+const SLOTS: u64 = 32;
+const ROWS: u64 = 4;
+const DURATION: i64 = 1_000_000;
+// Items are evenly spaced, one per this many ns in each row
+const ITEM_SPACING: i64 = 500;
+
+// Deterministic, so that runs are comparable
+struct SyntheticDataSource {
+    info: DataSourceInfo,
+    duration_field: FieldID,
+}
+
+impl SyntheticDataSource {
+    fn new() -> Self {
+        let mut field_schema = FieldSchema::new();
+        let duration_field = field_schema.insert("Duration".to_owned(), false);
+        let slots = (0..SLOTS)
+            .map(|i| EntryInfo::Slot {
+                short_name: format!("p{i}"),
+                long_name: format!("Processor {i}"),
+                max_rows: ROWS,
+            })
+            .collect();
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: Some(Box::new(EntryInfo::Summary {
+                    color: Color32::BLUE,
+                })),
+                counters: Vec::new(),
+                slots,
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(DURATION)),
+            tile_set: TileSet::default(),
+            field_schema,
+            warning_message: None,
+        };
+        Self {
+            info,
+            duration_field,
+        }
+    }
+
+    // The items of one row that start within the tile
+    fn items(tile_id: TileID, row: u64) -> impl Iterator<Item = (ItemUID, Interval)> {
+        let first = (tile_id.0.start.0 + ITEM_SPACING - 1) / ITEM_SPACING;
+        let last = (tile_id.0.stop.0 + ITEM_SPACING - 1) / ITEM_SPACING;
+        (first..last).map(move |i| {
+            let start = i * ITEM_SPACING;
+            let stop = start + ITEM_SPACING * (3 + (i + row as i64) % 5) / 8;
+            (
+                ItemUID(row << 32 | i as u64),
+                Interval::new(Timestamp(start), Timestamp(stop)),
+            )
+        })
+    }
+}
+
+impl DataSource for SyntheticDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec!["Synthetic Data Source".to_owned()],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+        Ok(self.info.clone())
+    }
+
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SummaryTile> {
+        let step = (tile_id.0.duration_ns() / 1000).max(1);
+        let utilization = (tile_id.0.start.0..tile_id.0.stop.0)
+            .step_by(step as usize)
+            .map(|t| UtilPoint {
+                time: Timestamp(t),
+                util: (t / step % 17) as f32 / 16.0,
+            })
+            .collect();
+        Ok(SummaryTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SummaryTileData { utilization },
+        })
+    }
+
+    fn fetch_counter_tile(
+        &self,
+        _entry_id: &EntryID,
+        _tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<CounterTile> {
+        unreachable!()
+    }
+
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotTile> {
+        let colors = [Color32::BLUE, Color32::GREEN, Color32::RED, Color32::YELLOW];
+        let items = (0..ROWS)
+            .map(|row| {
+                Self::items(tile_id, row)
+                    .map(|(item_uid, interval)| Item {
+                        item_uid,
+                        interval,
+                        color: colors[(item_uid.0 % 4) as usize],
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(SlotTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotTileData {
+                items,
+                edges: Vec::new(),
+            },
+        })
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotMetaTile> {
+        let items = (0..ROWS)
+            .map(|row| {
+                Self::items(tile_id, row)
+                    .map(|(item_uid, interval)| ItemMeta {
+                        item_uid,
+                        original_interval: interval,
+                        title: format!("Task {}", item_uid.0 % 64),
+                        fields: vec![(
+                            self.duration_field,
+                            Field::Duration(interval.duration_ns()),
+                            None,
+                        )],
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(SlotMetaTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: SlotMetaTileData { items },
+        })
+    }
+}
+
+fn write_archive(path: &Path, format: ArchiveFormat, dictionary: bool) {
+    DataSourceArchiveWriter::new(
+        DeferredDataSourceWrapper::new(SyntheticDataSource::new()),
+        4,
+        4,
+        path,
+        true,
+        1,
+    )
+    .with_format(format)
+    .with_dictionary(dictionary.then_some(64 << 10))
+    .write()
+    .expect("unable to write archive");
+}
+
+// Reads every tile in the archive once, returning the time per tile
+fn read_all(data_source: &dyn DataSource) -> Duration {
+    let info = data_source.fetch_info().expect("unable to read info");
+    let entry_ids = info.entry_info.entry_ids();
+    let start = Instant::now();
+    let mut tiles = 0;
+    for tile_ids in &info.tile_set.tiles {
+        for entry_id in &entry_ids {
+            for tile_id in tile_ids {
+                match entry_id.last_index().unwrap() {
+                    EntryIndex::Summary => {
+                        data_source
+                            .fetch_summary_tile(entry_id, *tile_id, false)
+                            .unwrap();
+                        tiles += 1;
+                    }
+                    EntryIndex::Counter(..) => {
+                        data_source
+                            .fetch_counter_tile(entry_id, *tile_id, false)
+                            .unwrap();
+                        tiles += 1;
+                    }
+                    EntryIndex::Slot(..) => {
+                        data_source
+                            .fetch_slot_tile(entry_id, *tile_id, false)
+                            .unwrap();
+                        data_source
+                            .fetch_slot_meta_tile(entry_id, *tile_id, false)
+                            .unwrap();
+                        tiles += 2;
+                    }
+                }
+            }
+        }
+    }
+    start.elapsed() / tiles
+}
+
+fn report(name: &str, time: Duration) {
+    println!("{:<48} {:>10.1} us/tile", name, time.as_secs_f64() * 1e6);
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("file_data_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut results = Vec::new();
+    for dictionary in [false, true] {
+        let suffix = if dictionary { " (dictionary)" } else { "" };
+
+        let path = dir.join(format!("archive_{dictionary}"));
+        write_archive(&path, ArchiveFormat::Directory, dictionary);
+        let time = read_all(&FileDataSource::new(&path));
+        results.push((format!("FileDataSource{suffix}"), time));
+        let cached = CachedFileDataSource::new(&path);
+        results.push((
+            format!("CachedFileDataSource, cold{suffix}"),
+            read_all(&cached),
+        ));
+        results.push((
+            format!("CachedFileDataSource, warm{suffix}"),
+            read_all(&cached),
+        ));
+
+        let path = dir.join(format!("archive_{dictionary}.lpv"));
+        write_archive(&path, ArchiveFormat::Container, dictionary);
+        let time = read_all(&ContainerDataSource::new(&path));
+        results.push((format!("ContainerDataSource{suffix}"), time));
+        let cached = CachedFileDataSource::new(&path);
+        results.push((
+            format!("CachedFileDataSource, cold .lpv{suffix}"),
+            read_all(&cached),
+        ));
+        results.push((
+            format!("CachedFileDataSource, warm .lpv{suffix}"),
+            read_all(&cached),
+        ));
+    }
+
+    println!();
+    for (name, time) in results {
+        report(&name, time);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
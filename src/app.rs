@@ -1,4 +1,5 @@
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
 use std::fmt;
 use std::time::Duration;
 #[cfg(not(target_arch = "wasm32"))]
@@ -11,14 +12,21 @@ use egui_extras::{Column, TableBuilder};
 #[cfg(not(target_arch = "wasm32"))]
 use itertools::Itertools;
 use percentage::{Percentage, PercentageInteger};
-use regex::{escape, Regex};
 use serde::{Deserialize, Serialize};
 
+use crate::critical_path::{CriticalPath, CriticalPathAnalysis};
 use crate::data::{
-    DataSourceInfo, EntryID, EntryIndex, EntryInfo, Field, FieldID, FieldSchema, ItemLink,
-    ItemMeta, ItemUID, SlotMetaTileData, SlotTileData, SummaryTileData, TileID, TileSet, UtilPoint,
+    CounterPoint, CounterTileData, DataSourceError, DataSourceInfo, DataSourceResult, EdgeKind,
+    EntryID, EntryIndex, EntryInfo, Field, FieldID, FieldSchema, ItemEdge, ItemLink, ItemMeta,
+    ItemUID, SearchQuery, SlotMetaTileData, SlotTileData, SummaryTileData, TileID, TileKind,
+    TileRequest, TileSet, UtilPoint,
 };
-use crate::deferred_data::{CountingDeferredDataSource, DeferredDataSource};
+use crate::deferred_data::{
+    CachingDeferredDataSource, CountingDeferredDataSource, DeduplicatingDeferredDataSource,
+    DeferredDataSource, SearchResponse,
+};
+use crate::search::SearchMatcher;
+use crate::size::Bytes;
 use crate::timestamp::{
     Interval, Timestamp, TimestampDisplay, TimestampParseError, TimestampUnits,
 };
@@ -26,8 +34,9 @@ use crate::timestamp::{
 /// Overview:
 ///   ProfApp -> Context, Window *
 ///   Window -> Config, Panel
-///   Panel -> Summary, { Panel | Slot } *
+///   Panel -> Summary, Counter *, { Panel | Slot } *
 ///   Summary
+///   Counter
 ///   Slot -> Item *
 ///
 /// Context:
@@ -49,6 +58,9 @@ use crate::timestamp::{
 /// Summary:
 ///   * Utilization widget
 ///
+/// Counter:
+///   * Time series widget (e.g., memory usage, queue depth)
+///
 /// Slot:
 ///   * One Slot for each processor, channel, memory
 ///   * Viewer widget for items
@@ -57,7 +69,20 @@ use crate::timestamp::{
 struct Summary {
     entry_id: EntryID,
     color: Color32,
-    tiles: BTreeMap<TileID, Option<SummaryTileData>>,
+    tiles: BTreeMap<TileID, Option<DataSourceResult<SummaryTileData>>>,
+    last_view_interval: Option<Interval>,
+}
+
+#[derive(Debug, Clone)]
+struct Counter {
+    entry_id: EntryID,
+    short_name: String,
+    long_name: String,
+    color: Color32,
+    unit: String,
+    min: f64,
+    max: f64,
+    tiles: BTreeMap<TileID, Option<DataSourceResult<CounterTileData>>>,
     last_view_interval: Option<Interval>,
 }
 
@@ -69,8 +94,8 @@ struct Slot {
     expanded: bool,
     max_rows: u64,
     tile_ids: Vec<TileID>,
-    tiles: BTreeMap<TileID, Option<SlotTileData>>,
-    tile_metas: BTreeMap<TileID, Option<SlotMetaTileData>>,
+    tiles: BTreeMap<TileID, Option<DataSourceResult<SlotTileData>>>,
+    tile_metas: BTreeMap<TileID, Option<DataSourceResult<SlotMetaTileData>>>,
     last_view_interval: Option<Interval>,
 }
 
@@ -82,6 +107,7 @@ struct Panel<S: Entry> {
     expanded: bool,
 
     summary: Option<Summary>,
+    counters: Vec<Counter>,
     slots: Vec<S>,
 }
 
@@ -115,8 +141,9 @@ struct SearchCacheItem {
     interval: Interval,
 
     // For vertical scroll, we need the item's row index (note: reversed,
-    // because we're in screen space)
-    irow: usize,
+    // because we're in screen space). Not known for results returned by the
+    // data source, in which case we find the row once the tile loads
+    irow: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -130,11 +157,20 @@ struct SearchState {
     last_search_field: FieldID,
     whole_word: bool,
     last_whole_word: bool,
-    last_word_regex: Option<Regex>,
+    last_matcher: Option<SearchMatcher>,
     include_collapsed_entries: bool,
     last_include_collapsed_entries: bool,
     last_view_interval: Option<Interval>,
 
+    // Searches are sent to the data source when it supports them, and
+    // otherwise run against the tiles we've loaded
+    remote_search: bool,
+    remote_query: Option<SearchQuery>,
+    remote_pending: bool,
+    remote_error: Option<String>,
+    // The data source found more matches than it sent
+    remote_truncated: bool,
+
     // Cache of matching items
     result_set: BTreeSet<ItemUID>,
     result_cache: BTreeMap<EntryID, BTreeMap<TileID, BTreeMap<ItemUID, SearchCacheItem>>>,
@@ -157,10 +193,29 @@ struct Config {
     tile_set: TileSet,
     warning_message: Option<String>,
 
-    data_source: CountingDeferredDataSource<Box<dyn DeferredDataSource>>,
+    // Counting must be outermost, since the other layers answer requests on
+    // behalf of the data source
+    data_source: CountingDeferredDataSource<
+        CachingDeferredDataSource<DeduplicatingDeferredDataSource<Box<dyn DeferredDataSource>>>,
+    >,
 
     search_state: SearchState,
 
+    // Needed to (re)start the critical path analysis
+    entry_info: EntryInfo,
+
+    // Kinds of edges the user has chosen to display
+    edge_kinds: BTreeSet<EdgeKind>,
+
+    // Critical path, once the user has asked for one
+    critical_path: Option<CriticalPathAnalysis>,
+
+    // Per-frame state used to draw edges (and the critical path): the
+    // screen space rects of every item we drew, and the edges delivered
+    // with the tiles we drew
+    item_rects: BTreeMap<ItemUID, Rect>,
+    visible_edges: BTreeSet<ItemEdge>,
+
     // When the user clicks on an item, we put it here
     items_selected: BTreeMap<ItemUID, ItemDetail>,
 
@@ -172,6 +227,37 @@ struct Config {
 
     last_request_interval: Option<Interval>,
     request_tile_cache: Vec<TileID>,
+
+    prefetcher: Prefetcher,
+}
+
+// Why a tile is being prefetched, from most to least likely to be needed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum PrefetchReason {
+    Pan,
+    Zoom,
+    Scroll,
+}
+
+// Tiles that aren't visible, but probably will be soon. Visible tiles are
+// always requested immediately; these wait until the data source is idle and
+// are then requested in priority order until the budget runs out. Everything
+// is reset when the view changes, and prefetches still in flight are
+// cancelled unless the new view wants them too.
+#[derive(Default)]
+struct Prefetcher {
+    view_interval: Option<Interval>,
+    // Tiles adjacent to the view, shared by every entry
+    neighbor_tiles: Vec<(PrefetchReason, TileID)>,
+    // Sequence numbers break ties in the order tiles were queued
+    queue: BinaryHeap<Reverse<(PrefetchReason, u64, TileRequest)>>,
+    queued: BTreeSet<TileRequest>,
+    next_seq: u64,
+    // Prefetches sent to the data source, which are cancelled if the view
+    // moves away before they arrive
+    issued: Vec<TileRequest>,
+    // Estimated bytes requested since the view changed
+    spent: u64,
 }
 
 struct Window {
@@ -244,6 +330,17 @@ enum ItemLinkNavigationMode {
     Pan,
 }
 
+// How much memory (per window, per view) we're willing to spend on tiles the
+// user hasn't looked at yet. Zero disables prefetching.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct PrefetchBudget(Bytes);
+
+impl Default for PrefetchBudget {
+    fn default() -> Self {
+        Self(Bytes(32 << 20))
+    }
+}
+
 impl ItemLinkNavigationMode {
     fn label_text(&self) -> &'static str {
         match *self {
@@ -285,6 +382,9 @@ struct Context {
 
     item_link_mode: ItemLinkNavigationMode,
 
+    #[serde(default)]
+    prefetch_budget: PrefetchBudget,
+
     toggle_dark_mode: bool,
 
     debug: bool,
@@ -292,6 +392,10 @@ struct Context {
     #[serde(skip)]
     show_controls: bool,
 
+    #[serde(skip)]
+    show_critical_path: bool,
+    critical_path_docked: bool,
+
     #[serde(skip)]
     view_interval_history: IntervalState,
     #[serde(skip)]
@@ -305,6 +409,10 @@ struct ProfApp {
     #[serde(skip)]
     pending_data_sources: VecDeque<Box<dyn DeferredDataSource>>,
 
+    // Data sources that failed to load, to be reported to the user.
+    #[serde(skip)]
+    load_errors: Vec<String>,
+
     #[serde(skip)]
     windows: Vec<Window>,
 
@@ -325,6 +433,7 @@ trait Entry {
     fn find_slot(&self, entry_id: &EntryID, level: u64) -> Option<&Slot>;
     fn find_slot_mut(&mut self, entry_id: &EntryID, level: u64) -> Option<&mut Slot>;
     fn find_summary_mut(&mut self, entry_id: &EntryID, level: u64) -> Option<&mut Summary>;
+    fn find_counter_mut(&mut self, entry_id: &EntryID, level: u64) -> Option<&mut Counter>;
 
     fn expand_slot(&mut self, entry_id: &EntryID, level: u64);
 
@@ -332,6 +441,10 @@ trait Entry {
 
     fn search(&mut self, config: &mut Config);
 
+    // Called for rows just outside the viewport, which the user is likely
+    // to scroll to next
+    fn prefetch(&self, config: &mut Config, cx: &Context);
+
     fn label(&mut self, ui: &mut egui::Ui, rect: Rect, cx: &Context) {
         let response = ui.allocate_rect(
             rect,
@@ -384,8 +497,43 @@ trait Entry {
     fn toggle_expanded(&mut self);
 }
 
+// Shade the part of the screen covered by a tile that failed to load, and
+// explain what went wrong when the user hovers over it.
+fn render_tile_error(
+    ui: &mut egui::Ui,
+    rect: Rect,
+    tile_id: TileID,
+    error: &DataSourceError,
+    mut hover_pos: Option<Pos2>,
+    cx: &Context,
+) -> Option<Pos2> {
+    if !cx.view_interval.overlaps(tile_id.0) {
+        return hover_pos;
+    }
+
+    let start = cx.view_interval.unlerp(tile_id.0.start).at_least(0.0);
+    let stop = cx.view_interval.unlerp(tile_id.0.stop).at_most(1.0);
+    let min = rect.lerp_inside(Vec2::new(start, 0.0));
+    let max = rect.lerp_inside(Vec2::new(stop, 1.0));
+    let tile_rect = Rect::from_min_max(min, max);
+
+    let color = Color32::RED.gamma_multiply(0.2);
+    ui.painter().rect(tile_rect, 0.0, color, Stroke::NONE);
+
+    if hover_pos.is_some_and(|h| tile_rect.contains(h)) {
+        hover_pos = None;
+        ui.show_tooltip(
+            "tile_error_tooltip",
+            &tile_rect,
+            format!("Failed to load data: {error}"),
+        );
+    }
+    hover_pos
+}
+
 impl Summary {
-    fn clear(&mut self) {
+    fn clear(&mut self, config: &mut Config, cx: &Context) {
+        config.cancel_stale_tiles(TileKind::Summary, &self.entry_id, &self.tiles, cx);
         self.tiles.clear();
     }
 
@@ -396,6 +544,7 @@ impl Summary {
                 .fetch_summary_tile(&self.entry_id, tile_id, false);
             self.tiles.insert(tile_id, None);
         }
+        config.prefetch_neighbors(TileKind::Summary, &self.entry_id, cx.view_interval);
     }
 }
 
@@ -437,6 +586,10 @@ impl Entry for Summary {
         Some(self)
     }
 
+    fn find_counter_mut(&mut self, _entry_id: &EntryID, _level: u64) -> Option<&mut Counter> {
+        unreachable!()
+    }
+
     fn expand_slot(&mut self, _entry_id: &EntryID, _level: u64) {
         unreachable!()
     }
@@ -449,6 +602,10 @@ impl Entry for Summary {
         unreachable!()
     }
 
+    fn prefetch(&self, config: &mut Config, cx: &Context) {
+        config.prefetch_row(TileKind::Summary, &self.entry_id, cx.view_interval);
+    }
+
     fn content(
         &mut self,
         ui: &mut egui::Ui,
@@ -463,11 +620,8 @@ impl Entry for Summary {
         let response = ui.allocate_rect(rect, egui::Sense::hover());
         let hover_pos = response.hover_pos(); // where is the mouse hovering?
 
-        if self
-            .last_view_interval
-            .map_or(true, |i| i != cx.view_interval)
-        {
-            self.clear();
+        if self.last_view_interval != Some(cx.view_interval) {
+            self.clear(config, cx);
         }
         self.last_view_interval = Some(cx.view_interval);
         if self.tiles.is_empty() {
@@ -502,7 +656,15 @@ impl Entry for Summary {
         let mut last_util: Option<&UtilPoint> = None;
         let mut last_point: Option<Pos2> = None;
         let mut hover_util = None;
-        for tile in self.tiles.values().flatten() {
+        for (tile_id, tile) in &self.tiles {
+            let tile = match tile {
+                Some(Ok(tile)) => tile,
+                Some(Err(error)) => {
+                    render_tile_error(ui, rect, *tile_id, error, hover_pos, cx);
+                    continue;
+                }
+                None => continue,
+            };
             for util in &tile.utilization {
                 let mut point = util_to_screen(util);
                 if let Some(mut last) = last_point {
@@ -569,11 +731,326 @@ impl Entry for Summary {
     }
 }
 
+// Formats a counter value according to the counter's unit
+struct CounterValue<'a>(f64, &'a str);
+
+impl<'a> fmt::Display for CounterValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let CounterValue(value, unit) = *self;
+        match unit {
+            "B" => write!(f, "{}", Bytes(value.max(0.0) as u64)),
+            "B/s" => write!(f, "{}/s", Bytes(value.max(0.0) as u64)),
+            "ns" => write!(f, "{}", Timestamp(value as i64)),
+            _ => {
+                const PREFIXES: [(f64, &str); 4] =
+                    [(1e12, "T"), (1e9, "G"), (1e6, "M"), (1e3, "k")];
+                let (scale, prefix) = PREFIXES
+                    .into_iter()
+                    .find(|(scale, _)| value.abs() >= *scale)
+                    .unwrap_or((1.0, ""));
+                let s = format!("{:.2}", value / scale);
+                let s = s.trim_end_matches('0').trim_end_matches('.');
+                if unit.is_empty() {
+                    write!(f, "{s}{prefix}")
+                } else {
+                    write!(f, "{s} {prefix}{unit}")
+                }
+            }
+        }
+    }
+}
+
+impl Counter {
+    fn clear(&mut self, config: &mut Config, cx: &Context) {
+        config.cancel_stale_tiles(TileKind::Counter, &self.entry_id, &self.tiles, cx);
+        self.tiles.clear();
+    }
+
+    fn inflate(&mut self, config: &mut Config, cx: &mut Context) {
+        for tile_id in config.request_tiles(cx.view_interval) {
+            config
+                .data_source
+                .fetch_counter_tile(&self.entry_id, tile_id, false);
+            self.tiles.insert(tile_id, None);
+        }
+        config.prefetch_neighbors(TileKind::Counter, &self.entry_id, cx.view_interval);
+    }
+
+    // Pick the range of the vertical axis: fit whatever is visible, rounded
+    // out to a multiple of the tick spacing. Returns (min, max, tick).
+    fn axis_range(&self, cx: &Context) -> (f64, f64, f64) {
+        let mut lo = f64::INFINITY;
+        let mut hi = f64::NEG_INFINITY;
+        for tile in self.tiles.values() {
+            let Some(Ok(tile)) = tile else {
+                continue;
+            };
+            for (i, point) in tile.points.iter().enumerate() {
+                let stop = tile
+                    .points
+                    .get(i + 1)
+                    .map_or(Timestamp(i64::MAX), |p| p.time);
+                if cx.view_interval.overlaps(Interval::new(point.time, stop)) {
+                    lo = lo.min(point.value);
+                    hi = hi.max(point.value);
+                }
+            }
+        }
+
+        // Nothing loaded yet, so fall back to the range of the whole profile
+        if lo > hi {
+            lo = self.min;
+            hi = self.max;
+        }
+        if lo >= hi {
+            hi = lo + 1.0;
+        }
+
+        // Tick spacing is 1, 2 or 5 times a power of 10, giving 2-5 ticks
+        const MAX_TICKS: f64 = 4.0;
+        let rough = (hi - lo) / MAX_TICKS;
+        let magnitude = 10f64.powf(rough.log10().floor());
+        let tick = [1.0, 2.0, 5.0, 10.0]
+            .into_iter()
+            .map(|x| x * magnitude)
+            .find(|x| *x >= rough)
+            .unwrap();
+        ((lo / tick).floor() * tick, (hi / tick).ceil() * tick, tick)
+    }
+}
+
+impl Entry for Counter {
+    fn new(info: &EntryInfo, entry_id: EntryID) -> Self {
+        if let EntryInfo::Counter {
+            short_name,
+            long_name,
+            color,
+            unit,
+            min,
+            max,
+        } = info
+        {
+            Self {
+                entry_id,
+                short_name: short_name.to_owned(),
+                long_name: long_name.to_owned(),
+                color: *color,
+                unit: unit.to_owned(),
+                min: *min,
+                max: *max,
+                tiles: BTreeMap::new(),
+                last_view_interval: None,
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
+    fn entry_id(&self) -> &EntryID {
+        &self.entry_id
+    }
+    fn label_text(&self) -> &str {
+        &self.short_name
+    }
+    fn hover_text(&self) -> &str {
+        &self.long_name
+    }
+
+    fn find_slot(&self, _entry_id: &EntryID, _level: u64) -> Option<&Slot> {
+        unreachable!()
+    }
+
+    fn find_slot_mut(&mut self, _entry_id: &EntryID, _level: u64) -> Option<&mut Slot> {
+        unreachable!()
+    }
+
+    fn find_summary_mut(&mut self, _entry_id: &EntryID, _level: u64) -> Option<&mut Summary> {
+        unreachable!()
+    }
+
+    fn find_counter_mut(&mut self, entry_id: &EntryID, level: u64) -> Option<&mut Counter> {
+        assert_eq!(entry_id.level(), level);
+        Some(self)
+    }
+
+    fn expand_slot(&mut self, _entry_id: &EntryID, _level: u64) {
+        unreachable!()
+    }
+
+    fn inflate_meta(&mut self, _config: &mut Config, _cx: &mut Context) {
+        unreachable!()
+    }
+
+    fn search(&mut self, _config: &mut Config) {
+        unreachable!()
+    }
+
+    fn prefetch(&self, config: &mut Config, cx: &Context) {
+        config.prefetch_row(TileKind::Counter, &self.entry_id, cx.view_interval);
+    }
+
+    fn content(
+        &mut self,
+        ui: &mut egui::Ui,
+        rect: Rect,
+        _viewport: Rect,
+        config: &mut Config,
+        cx: &mut Context,
+    ) {
+        cx.slot_rect = Some(rect); // Save slot rect for use later
+
+        const TOOLTIP_RADIUS: f32 = 4.0;
+        let response = ui.allocate_rect(rect, egui::Sense::hover());
+        let mut hover_pos = response.hover_pos(); // where is the mouse hovering?
+
+        if self.last_view_interval != Some(cx.view_interval) {
+            self.clear(config, cx);
+        }
+        self.last_view_interval = Some(cx.view_interval);
+        if self.tiles.is_empty() {
+            self.inflate(config, cx);
+        }
+
+        let style = ui.style();
+        let visuals = style.interact_selectable(&response, false);
+        ui.painter()
+            .rect(rect, 0.0, visuals.bg_fill, visuals.bg_stroke);
+
+        // Draw the axis first so that the data goes on top of it
+        let (axis_min, axis_max, tick) = self.axis_range(cx);
+        let value_to_y = |value: f64| {
+            let ratio = ((value - axis_min) / (axis_max - axis_min)) as f32;
+            rect.lerp_inside(Vec2::new(0.0, 1.0 - ratio)).y
+        };
+
+        let font_id = TextStyle::Small.resolve(style);
+        let grid_stroke = Stroke::new(visuals.bg_stroke.width, visuals.bg_stroke.color);
+        let mut value = axis_min;
+        while value <= axis_max + tick * 0.5 {
+            let y = value_to_y(value);
+            ui.painter().hline(rect.x_range(), y, grid_stroke);
+            let align = if value == axis_min {
+                Align2::LEFT_BOTTOM
+            } else {
+                Align2::LEFT_TOP
+            };
+            ui.painter().text(
+                Pos2::new(rect.min.x + 2.0, y),
+                align,
+                CounterValue(value, &self.unit).to_string(),
+                font_id.clone(),
+                visuals.text_color(),
+            );
+            value += tick;
+        }
+
+        let stroke = Stroke::new(visuals.fg_stroke.width, self.color);
+        let time_to_x = |time: Timestamp| {
+            let ratio = cx.view_interval.unlerp(time).clamp(0.0, 1.0);
+            rect.lerp_inside(Vec2::new(ratio, 0.0)).x
+        };
+
+        // Counters hold their value until the next point, so draw them as
+        // a step function
+        let mut hover_value = None;
+        for (tile_id, tile) in &self.tiles {
+            let tile = match tile {
+                Some(Ok(tile)) => tile,
+                Some(Err(error)) => {
+                    hover_pos = render_tile_error(ui, rect, *tile_id, error, hover_pos, cx);
+                    continue;
+                }
+                None => continue,
+            };
+
+            let mut last: Option<Pos2> = None;
+            for (i, point) in tile.points.iter().enumerate() {
+                // The last point in each tile holds until the end of the tile
+                let stop = tile.points.get(i + 1).map_or(tile_id.0.stop, |p| p.time);
+                let interval = Interval::new(point.time, stop);
+                if !cx.view_interval.overlaps(interval) {
+                    last = None;
+                    continue;
+                }
+
+                let y = value_to_y(point.value);
+                let start = Pos2::new(time_to_x(point.time), y);
+                let end = Pos2::new(time_to_x(stop), y);
+                if let Some(last) = last {
+                    ui.painter().line_segment([last, start], stroke);
+                }
+                ui.painter().line_segment([start, end], stroke);
+                last = Some(end);
+
+                if let Some(hover) = hover_pos {
+                    if start.x <= hover.x && hover.x < end.x {
+                        let hover_point = Pos2::new(hover.x, y);
+                        ui.painter()
+                            .circle_stroke(hover_point, TOOLTIP_RADIUS, visuals.fg_stroke);
+                        let time = cx
+                            .view_interval
+                            .lerp((hover.x - rect.left()) / rect.width());
+                        hover_value = Some(CounterPoint {
+                            time,
+                            value: point.value,
+                        });
+                    }
+                }
+            }
+        }
+
+        if let Some(point) = hover_value {
+            let time = cx.view_interval.unlerp(point.time);
+            let value_rect = Rect::from_min_max(
+                rect.lerp_inside(Vec2::new(time - 0.05, 0.0)),
+                rect.lerp_inside(Vec2::new(time + 0.05, 1.0)),
+            );
+            ui.show_tooltip(
+                "counter_tooltip",
+                &value_rect,
+                format!(
+                    "{}: {} at {}",
+                    self.long_name,
+                    CounterValue(point.value, &self.unit),
+                    point.time
+                ),
+            );
+        }
+    }
+
+    fn height(&self, prefix: Option<&EntryID>, _config: &Config, cx: &Context) -> f32 {
+        assert!(prefix.is_none());
+        const ROWS: u64 = 4;
+        ROWS as f32 * cx.row_height
+    }
+
+    fn is_expandable(&self) -> bool {
+        false
+    }
+
+    fn toggle_expanded(&mut self) {
+        unreachable!();
+    }
+}
+
 impl fmt::Display for Field {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Field::I64(value) => write!(f, "{value}"),
             Field::U64(value) => write!(f, "{value}"),
+            Field::F64(value) => {
+                // Avoid printing every last digit, but switch to scientific
+                // notation before small values round to zero
+                if *value == 0.0 || (1e-3..1e9).contains(&value.abs()) {
+                    let s = format!("{value:.3}");
+                    write!(f, "{}", s.trim_end_matches('0').trim_end_matches('.'))
+                } else {
+                    write!(f, "{value:.3e}")
+                }
+            }
+            Field::Bool(value) => write!(f, "{value}"),
+            Field::Duration(value) => write!(f, "{}", Timestamp(*value)),
+            Field::Bytes(value) => write!(f, "{}", Bytes(*value)),
             Field::String(value) => write!(f, "{value}"),
             Field::Interval(value) => write!(f, "{value}"),
             Field::ItemLink(ItemLink { title, .. }) => write!(f, "{title}"),
@@ -591,6 +1068,37 @@ impl fmt::Display for Field {
     }
 }
 
+impl EdgeKind {
+    fn label_text(self) -> &'static str {
+        match self {
+            EdgeKind::Creation => "Creation",
+            EdgeKind::Dependence => "Dependence",
+        }
+    }
+
+    fn color(self) -> Color32 {
+        match self {
+            EdgeKind::Creation => Color32::from_rgb(0x1f, 0x77, 0xb4),
+            EdgeKind::Dependence => Color32::from_rgb(0xff, 0x7f, 0x0e),
+        }
+    }
+}
+
+// Used to outline items on the critical path and link them together
+const CRITICAL_PATH_COLOR: Color32 = Color32::GOLD;
+
+// Memory to spend (per profile) on tiles we've already fetched, so that
+// panning and zooming back to them doesn't fetch them again
+const TILE_CACHE_CAPACITY: usize = 256 << 20;
+
+// Prefetch requests are issued a few at a time, so that they don't delay
+// visible tiles the user asks for in the meantime
+const PREFETCH_BATCH_SIZE: usize = 32;
+// Assumed size of a tile until the cache has seen some real ones
+const PREFETCH_TILE_SIZE: u64 = 64 << 10;
+// How far outside the viewport to prefetch rows, as a fraction of its height
+const PREFETCH_MARGIN: f32 = 0.5;
+
 struct FieldWithName<'a>(&'a str, &'a Field);
 
 impl<'a> fmt::Display for FieldWithName<'a> {
@@ -613,7 +1121,9 @@ impl Slot {
         }
     }
 
-    fn clear(&mut self) {
+    fn clear(&mut self, config: &mut Config, cx: &Context) {
+        config.cancel_stale_tiles(TileKind::Slot, &self.entry_id, &self.tiles, cx);
+        config.cancel_stale_tiles(TileKind::SlotMeta, &self.entry_id, &self.tile_metas, cx);
         self.tile_ids.clear();
         self.tiles.clear();
         self.tile_metas.clear();
@@ -627,6 +1137,7 @@ impl Slot {
             self.tile_ids.push(tile_id);
             self.tiles.insert(tile_id, None);
         }
+        config.prefetch_neighbors(TileKind::Slot, &self.entry_id, cx.view_interval);
     }
 
     fn fetch_meta_tile(
@@ -643,6 +1154,7 @@ impl Slot {
                 None
             })
             .as_ref()
+            .and_then(|tile| tile.as_ref().ok())
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -659,16 +1171,30 @@ impl Slot {
     ) -> Option<Pos2> {
         // Hack: can't pass this as an argument because it aliases self.
         let tile_id = self.tile_ids[tile_index];
-        let tile = self.tiles.get(&tile_id).unwrap();
+        let tile = match self.tiles.get(&tile_id).unwrap() {
+            Some(Ok(tile)) => tile,
+            Some(Err(error)) => {
+                return render_tile_error(ui, rect, tile_id, error, hover_pos, cx);
+            }
+            None => {
+                // Tile hasn't finished loading.
+                return hover_pos;
+            }
+        };
 
-        if !tile.is_some() {
-            // Tile hasn't finished loading.
+        if !cx.view_interval.overlaps(tile_id.0) {
             return hover_pos;
         }
-        let tile = tile.as_ref().unwrap();
 
-        if !cx.view_interval.overlaps(tile_id.0) {
-            return hover_pos;
+        let critical_path = config.critical_path.as_ref().and_then(|a| a.path());
+
+        let show_edges = !config.edge_kinds.is_empty();
+        if show_edges {
+            config.visible_edges.extend(
+                tile.edges
+                    .iter()
+                    .filter(|edge| config.edge_kinds.contains(&edge.kind)),
+            );
         }
 
         // Track which item, if any, we're interacting with
@@ -695,7 +1221,7 @@ impl Slot {
 
             // Check if mouse is hovering over this row
             let row_rect = Rect::from_min_max(row_min, row_max);
-            let row_hover = hover_pos.map_or(false, |h| row_rect.contains(h));
+            let row_hover = hover_pos.is_some_and(|h| row_rect.contains(h));
 
             // Now handle the items
             for (item_idx, item) in row_items.iter().enumerate() {
@@ -712,7 +1238,7 @@ impl Slot {
                 let max = rect.lerp_inside(Vec2::new(stop, (irow as f32 + 0.95) / rows as f32));
 
                 let item_rect = Rect::from_min_max(min, max);
-                if row_hover && hover_pos.map_or(false, |h| item_rect.contains(h)) {
+                if row_hover && hover_pos.is_some_and(|h| item_rect.contains(h)) {
                     hover_pos = None;
                     interact_item = Some((row, item_idx, item_rect, tile_id));
                 }
@@ -730,7 +1256,23 @@ impl Slot {
                     color = Color32::RED;
                 }
 
-                ui.painter().rect(item_rect, 0.0, color, Stroke::NONE);
+                let on_critical_path = critical_path.is_some_and(|p| p.contains(item.item_uid));
+                let stroke = if on_critical_path {
+                    Stroke::new(2.0, CRITICAL_PATH_COLOR)
+                } else {
+                    Stroke::NONE
+                };
+
+                ui.painter().rect(item_rect, 0.0, color, stroke);
+
+                if show_edges || on_critical_path {
+                    // Items sliced across tiles may show up more than once
+                    config
+                        .item_rects
+                        .entry(item.item_uid)
+                        .and_modify(|r| *r = r.union(item_rect))
+                        .or_insert(item_rect);
+                }
             }
         }
 
@@ -837,6 +1379,10 @@ impl Entry for Slot {
         unreachable!()
     }
 
+    fn find_counter_mut(&mut self, _entry_id: &EntryID, _level: u64) -> Option<&mut Counter> {
+        unreachable!()
+    }
+
     fn expand_slot(&mut self, entry_id: &EntryID, level: u64) {
         assert_eq!(entry_id.level(), level);
         assert!(entry_id.slot_index(level - 1).is_some());
@@ -855,7 +1401,7 @@ impl Entry for Slot {
         }
 
         for (tile_id, tile) in &self.tile_metas {
-            if let Some(tile) = tile {
+            if let Some(Ok(tile)) = tile {
                 if !config.search_state.start_tile(self, *tile_id) {
                     continue;
                 }
@@ -873,6 +1419,10 @@ impl Entry for Slot {
         }
     }
 
+    fn prefetch(&self, config: &mut Config, cx: &Context) {
+        config.prefetch_row(TileKind::Slot, &self.entry_id, cx.view_interval);
+    }
+
     fn content(
         &mut self,
         ui: &mut egui::Ui,
@@ -887,11 +1437,8 @@ impl Entry for Slot {
         let mut hover_pos = response.hover_pos(); // where is the mouse hovering?
 
         if self.expanded {
-            if self
-                .last_view_interval
-                .map_or(true, |i| i != cx.view_interval)
-            {
-                self.clear();
+            if self.last_view_interval != Some(cx.view_interval) {
+                self.clear(config, cx);
             }
             self.last_view_interval = Some(cx.view_interval);
             if self.tiles.is_empty() {
@@ -944,12 +1491,21 @@ impl<S: Entry> Panel<S> {
         let max_y = min_y + slot.height(None, config, cx);
         *y = max_y + ROW_PADDING;
 
-        // Cull if out of bounds
+        // Cull if out of bounds, but prefetch rows close enough that the
+        // user is likely to scroll to them
         // Note: need to shift by rect.min to get to viewport space
+        let margin = viewport.height() * PREFETCH_MARGIN;
         if max_y - rect.min.y < viewport.min.y {
+            if max_y - rect.min.y >= viewport.min.y - margin {
+                slot.prefetch(config, cx);
+            }
             return false;
         } else if min_y - rect.min.y > viewport.max.y {
-            return true;
+            if min_y - rect.min.y > viewport.max.y + margin {
+                return true;
+            }
+            slot.prefetch(config, cx);
+            return false;
         }
 
         // Draw label and content
@@ -997,6 +1553,7 @@ impl<S: Entry> Entry for Panel<S> {
             short_name,
             long_name,
             summary,
+            counters,
             slots,
         } = info
         {
@@ -1004,6 +1561,11 @@ impl<S: Entry> Entry for Panel<S> {
             let summary = summary
                 .as_ref()
                 .map(|s| Summary::new(s, entry_id.summary()));
+            let counters = counters
+                .iter()
+                .enumerate()
+                .map(|(i, c)| Counter::new(c, entry_id.counter(i as u64)))
+                .collect();
             let slots = slots
                 .iter()
                 .enumerate()
@@ -1015,6 +1577,7 @@ impl<S: Entry> Entry for Panel<S> {
                 long_name: long_name.to_owned(),
                 expanded,
                 summary,
+                counters,
                 slots,
             }
         } else {
@@ -1054,6 +1617,21 @@ impl<S: Entry> Entry for Panel<S> {
         }
     }
 
+    fn find_counter_mut(&mut self, entry_id: &EntryID, level: u64) -> Option<&mut Counter> {
+        if level < entry_id.level() - 1 {
+            self.slots
+                .get_mut(entry_id.slot_index(level)? as usize)?
+                .find_counter_mut(entry_id, level + 1)
+        } else {
+            let Some(EntryIndex::Counter(index)) = entry_id.index(level) else {
+                return None;
+            };
+            self.counters
+                .get_mut(index as usize)?
+                .find_counter_mut(entry_id, level + 1)
+        }
+    }
+
     fn expand_slot(&mut self, entry_id: &EntryID, level: u64) {
         self.slots
             .get_mut(entry_id.slot_index(level).unwrap() as usize)
@@ -1090,6 +1668,13 @@ impl<S: Entry> Entry for Panel<S> {
         }
     }
 
+    fn prefetch(&self, config: &mut Config, cx: &Context) {
+        // Only the first row of a panel is close to the viewport
+        if let Some(summary) = &self.summary {
+            summary.prefetch(config, cx);
+        }
+    }
+
     fn content(
         &mut self,
         ui: &mut egui::Ui,
@@ -1103,6 +1688,12 @@ impl<S: Entry> Entry for Panel<S> {
             Self::render(ui, rect, viewport, summary, &mut y, config, cx);
         }
 
+        for counter in &mut self.counters {
+            if Self::render(ui, rect, viewport, counter, &mut y, config, cx) {
+                return;
+            }
+        }
+
         if self.expanded {
             for slot in &mut self.slots {
                 // Apply visibility settings
@@ -1126,12 +1717,17 @@ impl<S: Entry> Entry for Panel<S> {
         if let Some(summary) = &self.summary {
             total += summary.height(None, config, cx);
             rows += 1;
-        } else if !self.expanded {
+        } else if !self.expanded && self.counters.is_empty() {
             // Need some minimum space if this panel has no summary and is collapsed
             total += UNEXPANDED_ROWS as f32 * cx.row_height;
             rows += 1;
         }
 
+        for counter in &self.counters {
+            total += counter.height(None, config, cx);
+            rows += 1;
+        }
+
         if self.expanded {
             for slot in &self.slots {
                 if let Some(prefix) = prefix {
@@ -1184,11 +1780,17 @@ impl SearchState {
             last_search_field: title_id,
             whole_word: false,
             last_whole_word: false,
-            last_word_regex: None,
+            last_matcher: None,
             include_collapsed_entries: false,
             last_include_collapsed_entries: false,
             last_view_interval: None,
 
+            remote_search: true,
+            remote_query: None,
+            remote_pending: false,
+            remote_error: None,
+            remote_truncated: false,
+
             result_set: BTreeSet::new(),
             result_cache: BTreeMap::new(),
             entry_tree: BTreeMap::new(),
@@ -1201,7 +1803,7 @@ impl SearchState {
         self.entry_tree.clear();
     }
 
-    fn ensure_valid_cache(&mut self, cx: &Context) {
+    fn ensure_valid_cache(&mut self, field_schema: &FieldSchema, cx: &Context) {
         let mut invalidate = false;
 
         // Invalidate when the search query changes.
@@ -1231,51 +1833,40 @@ impl SearchState {
             self.last_include_collapsed_entries = self.include_collapsed_entries;
         }
 
-        // Invalidate when the view interval changes.
+        // Invalidate when the view interval changes. (Searches done by the
+        // data source cover the whole profile, so they stay valid.)
         if self.last_view_interval != Some(cx.view_interval) {
-            invalidate = true;
+            invalidate |= !self.remote_search;
             self.last_view_interval = Some(cx.view_interval);
         }
 
         if invalidate {
-            if self.whole_word {
-                let regex_string = format!("\\b{}\\b", escape(&self.query));
-                self.last_word_regex = Some(Regex::new(&regex_string).unwrap());
-            }
+            let query = self.search_query(cx.view_interval);
+            self.last_matcher = Some(SearchMatcher::new(&query, field_schema));
+            self.remote_query = None;
+            self.remote_pending = false;
+            self.remote_error = None;
+            self.remote_truncated = false;
 
             self.clear();
         }
     }
 
-    fn is_string_match(&self, s: &str) -> bool {
-        if self.whole_word {
-            let Some(regex) = &self.last_word_regex else {
-                unreachable!();
-            };
-            regex.is_match(s)
-        } else {
-            s.contains(&self.query)
-        }
-    }
-
-    fn is_field_match(&self, field: &Field) -> bool {
-        match field {
-            Field::String(s) => self.is_string_match(s),
-            Field::ItemLink(ItemLink { title, .. }) => self.is_string_match(title),
-            Field::Vec(fields) => fields.iter().any(|f| self.is_field_match(f)),
-            _ => false,
+    fn search_query(&self, interval: Interval) -> SearchQuery {
+        SearchQuery {
+            query: self.query.clone(),
+            field: (self.search_field != self.title_field).then_some(self.search_field),
+            whole_word: self.whole_word,
+            interval,
+            max_results: Self::MAX_SEARCH_RESULTS as u64,
         }
     }
 
     fn is_match(&self, item: &ItemMeta) -> bool {
-        let field = self.search_field;
-        if field == self.title_field {
-            self.is_string_match(&item.title)
-        } else if let Some((_, value, _)) = item.fields.iter().find(|(x, _, _)| *x == field) {
-            self.is_field_match(value)
-        } else {
-            false
-        }
+        let Some(matcher) = &self.last_matcher else {
+            unreachable!();
+        };
+        matcher.is_match(item)
     }
 
     const MAX_SEARCH_RESULTS: usize = 100_000;
@@ -1330,13 +1921,55 @@ impl SearchState {
                 .entry(item.item_uid)
                 .or_insert_with(|| SearchCacheItem {
                     item_uid: item.item_uid,
-                    irow,
+                    irow: Some(irow),
                     interval: item.original_interval,
                     title: item.title.clone(),
                 });
         }
     }
 
+    fn add_remote_results(&mut self, response: SearchResponse) {
+        // Ignore responses to queries that have since been invalidated
+        if self.remote_query.as_ref() != Some(&response.query) {
+            return;
+        }
+        self.remote_pending = false;
+
+        match response.result {
+            Ok(results) => {
+                self.remote_truncated = results.truncated;
+                let tile_id = TileID(response.query.interval);
+                for item in results.items {
+                    if self.result_set.insert(item.item_uid) {
+                        self.result_cache
+                            .entry(item.entry_id)
+                            .or_default()
+                            .entry(tile_id)
+                            .or_default()
+                            .insert(
+                                item.item_uid,
+                                SearchCacheItem {
+                                    item_uid: item.item_uid,
+                                    irow: None,
+                                    interval: item.interval,
+                                    title: item.title,
+                                },
+                            );
+                    }
+                }
+            }
+            // The data source doesn't support search, so from now on search
+            // the tiles we load instead
+            Err(DataSourceError::NotFound(_)) => {
+                self.remote_search = false;
+            }
+            // Otherwise try again when the query changes
+            Err(e) => {
+                self.remote_error = Some(e.to_string());
+            }
+        }
+    }
+
     fn build_entry_tree(&mut self) {
         for (entry_id, cache) in &self.result_cache {
             let cache_size: u64 = cache.values().map(|x| x.len() as u64).sum();
@@ -1377,13 +2010,22 @@ impl Config {
             interval,
             tile_set,
             warning_message,
-            data_source: CountingDeferredDataSource::new(data_source),
+            data_source: CountingDeferredDataSource::new(CachingDeferredDataSource::new(
+                DeduplicatingDeferredDataSource::new(data_source),
+                TILE_CACHE_CAPACITY,
+            )),
             search_state,
+            entry_info: info.entry_info,
+            edge_kinds: BTreeSet::new(),
+            critical_path: None,
+            item_rects: BTreeMap::new(),
+            visible_edges: BTreeSet::new(),
             items_selected: BTreeMap::new(),
             scroll_to_item: None,
             scroll_to_item_retry: None,
             last_request_interval: None,
             request_tile_cache: Vec::new(),
+            prefetcher: Prefetcher::default(),
         }
     }
 
@@ -1393,36 +2035,186 @@ impl Config {
             return self.request_tile_cache.clone();
         }
 
+        self.last_request_interval = Some(request_interval);
+        self.request_tile_cache = self.tiles_for_interval(request_interval);
+        self.request_tile_cache.clone()
+    }
+
+    fn tiles_for_interval(&self, request_interval: Interval) -> Vec<TileID> {
         if self.tile_set.tiles.is_empty() {
             // For dynamic profiles, just return the request as one tile.
-            self.request_tile_cache = vec![TileID(request_interval)];
-            return self.request_tile_cache.clone();
+            return vec![TileID(request_interval)];
         }
 
         // We're in a static profile. Estimate the best zoom level, where
-        // "best" minimizes the ratio of the tile size to request size.
-        let request_duration = request_interval.duration_ns();
-        let chosen_level = self
-            .tile_set
+        // "best" minimizes the ratio of the tile size to request size. Tiles
+        // in a level may vary in size (e.g., with adaptive tiling), so only
+        // consider the ones overlapping the request.
+        let request_duration = request_interval.duration_ns().max(1);
+        self.tile_set
             .tiles
             .iter()
-            .min_by_key(|level| {
-                let d = level.first().unwrap().0.duration_ns();
-                if d < request_duration {
+            .filter_map(|level| {
+                let tiles: Vec<_> = level
+                    .iter()
+                    .filter(|tile| request_interval.overlaps(tile.0))
+                    .copied()
+                    .collect();
+                let total: i64 = tiles.iter().map(|tile| tile.0.duration_ns()).sum();
+                let d = (total / tiles.len().max(1) as i64).max(1);
+                let ratio = if d < request_duration {
                     request_duration / d
                 } else {
                     d / request_duration
-                }
+                };
+                (!tiles.is_empty()).then_some((ratio, tiles))
             })
-            .unwrap();
+            .min_by_key(|(ratio, _)| *ratio)
+            .map(|(_, tiles)| tiles)
+            .unwrap_or_default()
+    }
 
-        // Now filter to just tiles overlapping the requested interval.
-        self.request_tile_cache = chosen_level
+    // Cancel requests for tiles that haven't arrived yet and won't be needed
+    // in the current view. (Tiles that are still needed will be requested
+    // again, and the request that's already in flight will answer them.)
+    fn cancel_stale_tiles<T>(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tiles: &BTreeMap<TileID, Option<T>>,
+        cx: &Context,
+    ) {
+        let visible = self.request_tiles(cx.view_interval);
+        let stale: Vec<_> = tiles
             .iter()
-            .filter(|tile| request_interval.overlaps(tile.0))
-            .copied()
+            .filter(|(tile_id, tile)| tile.is_none() && !visible.contains(tile_id))
+            .map(|(tile_id, _)| TileRequest {
+                kind,
+                entry_id: entry_id.clone(),
+                tile_id: *tile_id,
+                full: false,
+            })
             .collect();
-        self.request_tile_cache.clone()
+        if !stale.is_empty() {
+            self.data_source.cancel_tiles(&stale);
+        }
+    }
+
+    fn start_prefetch(&mut self, view_interval: Interval) {
+        if self.prefetcher.view_interval == Some(view_interval) {
+            return;
+        }
+
+        // Static tiles line up with their neighbors, so look a whole view
+        // ahead. Dynamic tiles are only reused if we guess the exact interval
+        // the user asks for, so assume they'll pan with the arrow keys.
+        let duration = view_interval.duration_ns();
+        let pan_step = if self.tile_set.tiles.is_empty() {
+            let percent: PercentageInteger = Percentage::from(5);
+            percent.apply_to(duration)
+        } else {
+            duration
+        };
+
+        // Zoom steps are the same as ProfApp::zoom_in and zoom_out
+        let candidates = [
+            (PrefetchReason::Pan, view_interval.translate(-pan_step)),
+            (PrefetchReason::Pan, view_interval.translate(pan_step)),
+            (PrefetchReason::Zoom, view_interval.grow(-duration / 4)),
+            (PrefetchReason::Zoom, view_interval.grow(duration / 2)),
+        ];
+
+        let mut seen: BTreeSet<_> = self.request_tiles(view_interval).into_iter().collect();
+        let mut neighbor_tiles = Vec::new();
+        for (reason, interval) in candidates {
+            let interval = interval.intersection(self.interval);
+            if interval.duration_ns() <= 0 {
+                continue;
+            }
+            for tile_id in self.tiles_for_interval(interval) {
+                if seen.insert(tile_id) {
+                    neighbor_tiles.push((reason, tile_id));
+                }
+            }
+        }
+
+        // Keep prefetches that are still useful from the new view
+        let (issued, stale): (Vec<_>, Vec<_>) = std::mem::take(&mut self.prefetcher.issued)
+            .into_iter()
+            .partition(|request| seen.contains(&request.tile_id));
+        if !stale.is_empty() {
+            self.data_source.cancel_tiles(&stale);
+        }
+
+        self.prefetcher = Prefetcher {
+            view_interval: Some(view_interval),
+            neighbor_tiles,
+            queued: issued.iter().cloned().collect(),
+            issued,
+            ..Default::default()
+        };
+    }
+
+    fn prefetch_neighbors(&mut self, kind: TileKind, entry_id: &EntryID, view_interval: Interval) {
+        self.start_prefetch(view_interval);
+        for i in 0..self.prefetcher.neighbor_tiles.len() {
+            let (reason, tile_id) = self.prefetcher.neighbor_tiles[i];
+            self.prefetcher.push(
+                reason,
+                TileRequest {
+                    kind,
+                    entry_id: entry_id.clone(),
+                    tile_id,
+                    full: false,
+                },
+            );
+        }
+    }
+
+    fn prefetch_row(&mut self, kind: TileKind, entry_id: &EntryID, view_interval: Interval) {
+        self.start_prefetch(view_interval);
+        for tile_id in self.request_tiles(view_interval) {
+            self.prefetcher.push(
+                PrefetchReason::Scroll,
+                TileRequest {
+                    kind,
+                    entry_id: entry_id.clone(),
+                    tile_id,
+                    full: false,
+                },
+            );
+        }
+    }
+
+    fn issue_prefetches(&mut self, budget: PrefetchBudget) {
+        // Never compete with requests for visible tiles
+        if self.data_source.outstanding_requests() > 0 {
+            return;
+        }
+
+        let cache = self.data_source.data_source();
+        let stats = cache.stats();
+        let tile_size = stats
+            .size
+            .checked_div(stats.tiles)
+            .map_or(PREFETCH_TILE_SIZE, |size| size as u64);
+
+        let mut requests = Vec::new();
+        while requests.len() < PREFETCH_BATCH_SIZE && self.prefetcher.spent < budget.0 .0 {
+            let Some(Reverse((_, _, request))) = self.prefetcher.queue.pop() else {
+                break;
+            };
+            if cache.contains(&request) {
+                continue;
+            }
+            self.prefetcher.spent += tile_size;
+            requests.push(request);
+        }
+
+        if !requests.is_empty() {
+            self.data_source.fetch_tiles(&requests);
+            self.prefetcher.issued.extend(requests);
+        }
     }
 
     fn scroll_to_item(&mut self, item_loc: ItemLocator) {
@@ -1438,6 +2230,19 @@ impl Config {
     }
 }
 
+impl Prefetcher {
+    fn push(&mut self, reason: PrefetchReason, request: TileRequest) {
+        if self.queued.insert(request.clone()) {
+            self.queue.push(Reverse((reason, self.next_seq, request)));
+            self.next_seq += 1;
+        }
+    }
+
+    fn is_done(&self, budget: PrefetchBudget) -> bool {
+        self.queue.is_empty() || self.spent >= budget.0 .0
+    }
+}
+
 impl Window {
     fn new(data_source: Box<dyn DeferredDataSource>, info: DataSourceInfo, index: u64) -> Self {
         Self {
@@ -1459,6 +2264,10 @@ impl Window {
         self.panel.find_summary_mut(entry_id, 0)
     }
 
+    fn find_counter_mut(&mut self, entry_id: &EntryID) -> Option<&mut Counter> {
+        self.panel.find_counter_mut(entry_id, 0)
+    }
+
     fn expand_slot(&mut self, entry_id: &EntryID) {
         self.panel.expand_slot(entry_id, 0);
     }
@@ -1472,7 +2281,7 @@ impl Window {
     fn find_item_irow(&self, entry_id: &EntryID, item_uid: ItemUID) -> Option<usize> {
         let slot = self.find_slot(entry_id)?;
         for tile in slot.tiles.values() {
-            let Some(tile) = tile else {
+            let Some(Ok(tile)) = tile else {
                 continue;
             };
             for (row, items) in tile.items.iter().enumerate() {
@@ -1490,7 +2299,7 @@ impl Window {
     fn find_item_meta(&self, entry_id: &EntryID, item_uid: ItemUID) -> Option<&ItemMeta> {
         let slot = self.find_slot(entry_id)?;
         for tile in slot.tile_metas.values() {
-            let Some(tile) = tile else {
+            let Some(Ok(tile)) = tile else {
                 continue;
             };
             for items in &tile.items {
@@ -1564,11 +2373,50 @@ impl Window {
                     }
                 }
 
+                self.config.item_rects.clear();
+                self.config.visible_edges.clear();
+
                 // Root panel has no label
                 self.panel.content(ui, rect, viewport, &mut self.config, cx);
+
+                Self::render_edges(ui, &self.config);
             });
     }
 
+    fn render_edges(ui: &mut egui::Ui, config: &Config) {
+        for edge in &config.visible_edges {
+            // Only draw edges where we can see both ends
+            let (Some(src), Some(dst)) = (
+                config.item_rects.get(&edge.src),
+                config.item_rects.get(&edge.dst),
+            ) else {
+                continue;
+            };
+
+            let origin = src.right_center();
+            let target = dst.left_center();
+            let stroke = Stroke::new(1.5, edge.kind.color());
+            ui.painter().arrow(origin, target - origin, stroke);
+        }
+
+        let critical_path = config.critical_path.as_ref().and_then(|a| a.path());
+        if let Some(path) = critical_path {
+            for pair in path.segments.windows(2) {
+                let (Some(src), Some(dst)) = (
+                    config.item_rects.get(&pair[0].item_uid),
+                    config.item_rects.get(&pair[1].item_uid),
+                ) else {
+                    continue;
+                };
+
+                let origin = src.right_center();
+                let target = dst.left_center();
+                let stroke = Stroke::new(2.5, CRITICAL_PATH_COLOR);
+                ui.painter().arrow(origin, target - origin, stroke);
+            }
+        }
+    }
+
     fn node_selection(&mut self, ui: &mut egui::Ui, cx: &Context) {
         ui.subheading("Node Selection", cx);
         let total = self.panel.slots.len().saturating_sub(1) as u64;
@@ -1632,6 +2480,177 @@ impl Window {
         });
     }
 
+    fn edge_selection(&mut self, ui: &mut egui::Ui, cx: &Context) {
+        ui.subheading("Show Edges", cx);
+        ui.horizontal_wrapped(|ui| {
+            for kind in EdgeKind::ALL {
+                let initial = self.config.edge_kinds.contains(&kind);
+                let mut enabled = initial;
+                ui.toggle_value(
+                    &mut enabled,
+                    RichText::new(kind.label_text()).color(kind.color()),
+                );
+                if initial != enabled {
+                    if enabled {
+                        self.config.edge_kinds.insert(kind);
+                    } else {
+                        self.config.edge_kinds.remove(&kind);
+                    }
+                }
+            }
+        });
+    }
+
+    fn critical_path_controls(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
+        ui.subheading("Critical Path", cx);
+        ui.horizontal(|ui| {
+            let label = if self.config.critical_path.is_some() {
+                "Recompute"
+            } else {
+                "Compute"
+            };
+            if ui.button(label).clicked() {
+                let config = &mut self.config;
+                config.critical_path = Some(CriticalPathAnalysis::start(
+                    &config.entry_info,
+                    config.interval,
+                    &config.tile_set,
+                    &mut config.data_source,
+                ));
+                cx.show_critical_path = true;
+            }
+            if self.config.critical_path.is_some() {
+                if ui.button("Show").clicked() {
+                    cx.show_critical_path = true;
+                }
+                if ui.button("Clear").clicked() {
+                    self.config.critical_path = None;
+                }
+            }
+        });
+
+        let Some(analysis) = &self.config.critical_path else {
+            return;
+        };
+        if let Some(error) = analysis.error() {
+            ui.label(RichText::new(format!("Failed to compute: {error}")).color(Color32::RED));
+        } else if let Some(path) = analysis.path() {
+            match path.interval() {
+                Some(interval) => {
+                    ui.label(format!(
+                        "{} items, {}",
+                        path.segments.len(),
+                        Timestamp(interval.duration_ns())
+                    ));
+                }
+                None => {
+                    ui.label("No items found.");
+                }
+            }
+        } else {
+            ui.add(egui::ProgressBar::new(analysis.progress()).show_percentage());
+        }
+    }
+
+    fn critical_path_segments(
+        &mut self,
+        ui: &mut egui::Ui,
+        path: &CriticalPath,
+        cx: &Context,
+    ) -> Option<(ItemLocator, Interval)> {
+        let font_id = TextStyle::Body.resolve(ui.style());
+        let row_height = ui.fonts(|f| f.row_height(&font_id));
+
+        let mut result = None;
+        TableBuilder::new(ui)
+            .striped(true)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .column(Column::auto())
+            .column(Column::auto())
+            .column(Column::auto())
+            .column(Column::auto())
+            .column(Column::auto())
+            .column(Column::remainder())
+            .header(row_height, |mut header| {
+                for name in ["Item", "Slot", "Start", "Duration", "Delay", "Via"] {
+                    header.col(|ui| {
+                        ui.strong(name);
+                    });
+                }
+            })
+            .body(|body| {
+                body.rows(row_height, path.segments.len(), |mut row| {
+                    let segment = &path.segments[row.index()];
+                    row.col(|ui| {
+                        let title = match &segment.title {
+                            Some(title) => title.chars().take(50).collect(),
+                            None => format!("Item <Item UID: {}>", segment.item_uid.0),
+                        };
+                        let button = egui::widgets::Button::new(title).small();
+                        if ui
+                            .add(button)
+                            .on_hover_text(cx.item_link_mode.label_text())
+                            .clicked()
+                        {
+                            let item_loc = ItemLocator {
+                                entry_id: segment.entry_id.clone(),
+                                irow: Some(segment.irow),
+                                item_uid: segment.item_uid,
+                            };
+                            result = Some((item_loc, segment.interval));
+                        }
+                    });
+                    row.col(|ui| {
+                        if let Some(slot) = self.find_slot(&segment.entry_id) {
+                            ui.label(slot.hover_text());
+                        }
+                    });
+                    row.col(|ui| {
+                        ui.label(segment.interval.start.to_string());
+                    });
+                    row.col(|ui| {
+                        ui.label(Timestamp(segment.interval.duration_ns()).to_string());
+                    });
+                    row.col(|ui| {
+                        ui.label(Timestamp(segment.delay_ns).to_string());
+                    });
+                    row.col(|ui| {
+                        if let Some(kind) = segment.edge_kind {
+                            ui.label(RichText::new(kind.label_text()).color(kind.color()));
+                        }
+                    });
+                });
+            });
+        result
+    }
+
+    fn critical_path_panel(
+        &mut self,
+        ui: &mut egui::Ui,
+        cx: &Context,
+    ) -> Option<(ItemLocator, Interval)> {
+        ui.heading(format!("Profile {}: Critical Path", self.index));
+
+        // Hack: take the analysis to avoid a mutability conflict
+        let analysis = self.config.critical_path.take()?;
+        let mut result = None;
+        match analysis.path() {
+            Some(path) if !path.segments.is_empty() => {
+                ui.push_id(self.index, |ui| {
+                    result = self.critical_path_segments(ui, path, cx);
+                });
+            }
+            Some(_) => {
+                ui.label("No items found.");
+            }
+            None => {
+                ui.label("Critical path will be displayed once data is available.");
+            }
+        }
+        self.config.critical_path = Some(analysis);
+        result
+    }
+
     fn select_interval(&mut self, ui: &mut egui::Ui, cx: &mut Context) {
         ui.subheading("Interval", cx);
         let start_res = ui
@@ -1711,12 +2730,18 @@ impl Window {
         ui.add_space(WIDGET_PADDING);
         self.expand_collapse(ui, cx);
         ui.add_space(WIDGET_PADDING);
+        self.edge_selection(ui, cx);
+        ui.add_space(WIDGET_PADDING);
+        self.critical_path_controls(ui, cx);
+        ui.add_space(WIDGET_PADDING);
         self.select_interval(ui, cx);
     }
 
     fn search(&mut self, cx: &mut Context) {
         // Invalidate cache if the search query changed.
-        self.config.search_state.ensure_valid_cache(cx);
+        self.config
+            .search_state
+            .ensure_valid_cache(&self.config.field_schema, cx);
 
         // If search query empty, skip search. (Note: do this after
         // invalidating cache, otherwise we get leftover search results when
@@ -1725,6 +2750,19 @@ impl Window {
             return;
         }
 
+        // Ask the data source to search, if it can. Results arrive with the
+        // tiles in ProfApp::update.
+        let search_state = &mut self.config.search_state;
+        if search_state.remote_search {
+            if search_state.remote_query.is_none() {
+                let query = search_state.search_query(cx.total_interval);
+                self.config.data_source.fetch_search(&query);
+                search_state.remote_query = Some(query);
+                search_state.remote_pending = true;
+            }
+            return;
+        }
+
         // Expand meta tiles. (Including collapsed entries, if requested).
         self.panel.inflate_meta(&mut self.config, cx);
 
@@ -1750,6 +2788,7 @@ impl Window {
             let query_size = ui.available_size().x - button_size.x - ui.spacing().item_spacing.x;
             egui::TextEdit::singleline(&mut self.config.search_state.query)
                 .desired_width(query_size)
+                .hint_text("Text, or a comparison like: size > 1MiB")
                 .show(ui);
             if ui.button(button_label).clicked() {
                 self.config.search_state.query.clear();
@@ -1772,9 +2811,13 @@ impl Window {
             &mut self.config.search_state.whole_word,
             "Match whole words only",
         );
-        ui.checkbox(
-            &mut self.config.search_state.include_collapsed_entries,
-            "Include collapsed processors",
+        // Searches done by the data source always include every processor
+        ui.add_enabled(
+            !self.config.search_state.remote_search,
+            egui::Checkbox::new(
+                &mut self.config.search_state.include_collapsed_entries,
+                "Include collapsed processors",
+            ),
         );
 
         self.search(cx);
@@ -1786,8 +2829,21 @@ impl Window {
             return;
         }
 
+        if let Some(error) = &self.config.search_state.remote_error {
+            ui.label(format!(
+                "Search failed ({error}). Edit the query to try again."
+            ));
+            return;
+        }
+
         if self.config.search_state.result_set.is_empty() {
-            ui.label("No results found. Expand search to include collapsed processors?");
+            if self.config.search_state.remote_pending {
+                ui.label("Searching...");
+            } else if self.config.search_state.remote_search {
+                ui.label("No results found.");
+            } else {
+                ui.label("No results found. Expand search to include collapsed processors?");
+            }
 
             return;
         }
@@ -1802,6 +2858,9 @@ impl Window {
         } else {
             ui.label(format!("Found {} results.", num_results));
         }
+        if self.config.search_state.remote_truncated {
+            ui.label("There are more results. Refine the query to see them.");
+        }
 
         self.config.search_state.build_entry_tree();
 
@@ -1835,7 +2894,7 @@ impl Window {
                                                     ProfApp::zoom(cx, interval);
                                                     scroll_target = Some(ItemLocator {
                                                         entry_id: level2_slot.entry_id.clone(),
-                                                        irow: Some(item.irow),
+                                                        irow: item.irow,
                                                         item_uid: item.item_uid,
                                                     });
                                                     level2_slot.expanded = true;
@@ -1894,6 +2953,7 @@ impl ProfApp {
         }
         result.pending_data_sources.clear();
         result.pending_data_sources.extend(data_sources);
+        result.load_errors.clear();
 
         result.windows.clear();
 
@@ -1975,6 +3035,50 @@ impl ProfApp {
         ProfApp::update_interval_select_state(cx);
     }
 
+    fn zoom_to_item(
+        window: &mut Window,
+        cx: &mut Context,
+        item_loc: ItemLocator,
+        interval: Interval,
+    ) {
+        let interval = match cx.item_link_mode {
+            // In Zoom mode, put the item in the center of the view
+            // interval with a small amount of padding on either side.
+            ItemLinkNavigationMode::Zoom => interval.grow(interval.duration_ns() / 20),
+            // In Pan mode, maintain the current window size but shift
+            // the center to place the item in the middle of it.
+            ItemLinkNavigationMode::Pan => cx
+                .view_interval
+                .translate(interval.center().0 - cx.view_interval.center().0),
+        };
+        ProfApp::zoom(cx, interval);
+        window.expand_slot(&item_loc.entry_id);
+        window.config.scroll_to_item(item_loc);
+    }
+
+    fn critical_path(
+        ui: &mut egui::Ui,
+        windows: &mut [Window],
+        cx: &mut Context,
+    ) -> Option<(usize, ItemLocator, Interval)> {
+        ui.checkbox(&mut cx.critical_path_docked, "Dock Panel");
+
+        let mut result = None;
+        ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .show(ui, |ui| {
+                for (index, window) in windows.iter_mut().enumerate() {
+                    if window.config.critical_path.is_none() {
+                        continue;
+                    }
+                    if let Some((item_loc, interval)) = window.critical_path_panel(ui, cx) {
+                        result = Some((index, item_loc, interval));
+                    }
+                }
+            });
+        result
+    }
+
     fn undo_pan_zoom(cx: &mut Context) {
         if cx.view_interval_history.index == 0 {
             return;
@@ -2241,7 +3345,11 @@ impl ProfApp {
         }
     }
 
-    fn display_controls(ui: &mut egui::Ui, mode: &mut ItemLinkNavigationMode) {
+    fn display_controls(
+        ui: &mut egui::Ui,
+        mode: &mut ItemLinkNavigationMode,
+        prefetch_budget: &mut PrefetchBudget,
+    ) {
         fn show_row_ui(
             body: &mut egui_extras::TableBody<'_>,
             label: &str,
@@ -2288,6 +3396,13 @@ impl ProfApp {
                             ui.selectable_value(mode, ItemLinkNavigationMode::Pan, "Pan");
                         });
                 });
+                show_row_ui(&mut body, "Prefetch Budget", |ui: &mut _| {
+                    let mut mib = prefetch_budget.0 .0 >> 20;
+                    let max = (TILE_CACHE_CAPACITY >> 21) as u64;
+                    ui.add(Slider::new(&mut mib, 0..=max).suffix(" MiB"))
+                        .on_hover_text("Memory used to load data just outside the view");
+                    prefetch_budget.0 = Bytes(mib << 20);
+                });
             });
     }
 
@@ -2307,10 +3422,14 @@ impl ProfApp {
         mode: ItemLinkNavigationMode,
     ) -> Vec<(String, Option<&'static str>)> {
         match field {
-            Field::I64(value) => vec![(format!("{value}"), None)],
-            Field::U64(value) => vec![(format!("{value}"), None)],
+            Field::I64(_)
+            | Field::U64(_)
+            | Field::F64(_)
+            | Field::Bool(_)
+            | Field::Duration(_)
+            | Field::Bytes(_)
+            | Field::Interval(_) => vec![(format!("{field}"), None)],
             Field::String(value) => vec![(value.to_string(), None)],
-            Field::Interval(value) => vec![(format!("{value}"), None)],
             Field::ItemLink(ItemLink { title, .. }) => {
                 vec![(title.to_string(), Some(mode.label_text()))]
             }
@@ -2365,10 +3484,14 @@ impl ProfApp {
             }
         };
         match field {
-            Field::I64(value) => label(ui, &format!("{value}")),
-            Field::U64(value) => label(ui, &format!("{value}")),
+            Field::I64(_)
+            | Field::U64(_)
+            | Field::F64(_)
+            | Field::Bool(_)
+            | Field::Duration(_)
+            | Field::Bytes(_)
+            | Field::Interval(_) => label(ui, &format!("{field}")),
             Field::String(value) => label(ui, value),
-            Field::Interval(value) => label(ui, &format!("{value}")),
             Field::ItemLink(ItemLink {
                 title,
                 item_uid,
@@ -2480,6 +3603,7 @@ impl eframe::App for ProfApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         let Self {
             pending_data_sources,
+            load_errors,
             windows,
             cx,
             #[cfg(not(target_arch = "wasm32"))]
@@ -2490,17 +3614,24 @@ impl eframe::App for ProfApp {
         if let Some(mut source) = pending_data_sources.pop_front() {
             // We made one request, so we know there is always zero or one
             // elements in this list.
-            if let Some(info) = source.get_infos().pop() {
-                let window = Window::new(source, info, windows.len() as u64);
-                if windows.is_empty() {
-                    cx.total_interval = window.config.interval;
-                } else {
-                    cx.total_interval = cx.total_interval.union(window.config.interval);
+            match source.get_infos().pop() {
+                Some(Ok(info)) => {
+                    let window = Window::new(source, info, windows.len() as u64);
+                    if windows.is_empty() {
+                        cx.total_interval = window.config.interval;
+                    } else {
+                        cx.total_interval = cx.total_interval.union(window.config.interval);
+                    }
+                    ProfApp::zoom(cx, cx.total_interval);
+                    windows.push(window);
+                }
+                Some(Err(error)) => {
+                    let locator = source.fetch_description().source_locator.join(", ");
+                    load_errors.push(format!("Unable to load {locator}: {error}"));
+                }
+                None => {
+                    pending_data_sources.push_front(source);
                 }
-                ProfApp::zoom(cx, cx.total_interval);
-                windows.push(window);
-            } else {
-                pending_data_sources.push_front(source);
             }
         }
 
@@ -2512,31 +3643,60 @@ impl eframe::App for ProfApp {
                     entry
                         .tiles
                         .entry(tile.tile_id)
-                        .and_modify(|t| *t = Some(tile.data));
+                        .and_modify(|t| *t = Some(tile.result.map(|x| x.data)));
+                }
+            }
+
+            for tile in window.config.data_source.get_counter_tiles() {
+                if let Some(entry) = window.find_counter_mut(&tile.entry_id) {
+                    // If the entry doesn't exist, we already zoomed away and
+                    // are no longer interested in this tile.
+                    entry
+                        .tiles
+                        .entry(tile.tile_id)
+                        .and_modify(|t| *t = Some(tile.result.map(|x| x.data)));
                 }
             }
 
             for tile in window.config.data_source.get_slot_tiles() {
+                if let Some(analysis) = &mut window.config.critical_path {
+                    if analysis.accepts_slot_tile(&tile) {
+                        analysis.add_slot_tile(tile, &mut window.config.data_source);
+                        continue;
+                    }
+                }
+
                 if let Some(entry) = window.find_slot_mut(&tile.entry_id) {
                     // If the entry doesn't exist, we already zoomed away and
                     // are no longer interested in this tile.
                     entry
                         .tiles
                         .entry(tile.tile_id)
-                        .and_modify(|t| *t = Some(tile.data));
+                        .and_modify(|t| *t = Some(tile.result.map(|x| x.data)));
                 }
             }
 
             for tile in window.config.data_source.get_slot_meta_tiles() {
+                if let Some(analysis) = &mut window.config.critical_path {
+                    if analysis.accepts_slot_meta_tile(&tile) {
+                        analysis.add_slot_meta_tile(tile);
+                        continue;
+                    }
+                }
+
                 if let Some(entry) = window.find_slot_mut(&tile.entry_id) {
                     // If the entry doesn't exist, we already zoomed away and
                     // are no longer interested in this tile.
                     entry
                         .tile_metas
                         .entry(tile.tile_id)
-                        .and_modify(|t| *t = Some(tile.data));
+                        .and_modify(|t| *t = Some(tile.result.map(|x| x.data)));
                 }
             }
+
+            for response in window.config.data_source.get_search_results() {
+                window.config.search_state.add_remote_results(response);
+            }
         }
 
         let mut _fps = 0.0;
@@ -2623,6 +3783,20 @@ impl eframe::App for ProfApp {
                             ui.label(format!("FPS: {_fps:.0}"));
                         }
                     }
+
+                    if cx.debug {
+                        for window in windows.iter() {
+                            let stats = window.config.data_source.data_source().stats();
+                            ui.label(format!(
+                                "Cache {}: {} hits, {} misses, {} / {}",
+                                window.index,
+                                stats.hits,
+                                stats.misses,
+                                Bytes(stats.size as u64),
+                                Bytes(stats.capacity as u64),
+                            ));
+                        }
+                    }
                 });
 
                 ui.separator();
@@ -2630,6 +3804,21 @@ impl eframe::App for ProfApp {
             });
         });
 
+        // The critical path panel can either be docked to the right of the
+        // profiles, or float in its own window
+        let mut critical_path_target = None;
+        let mut show_critical_path = cx.show_critical_path;
+        if show_critical_path && cx.critical_path_docked {
+            egui::SidePanel::right("critical_path_panel")
+                .resizable(true)
+                .show(ctx, |ui| {
+                    if ui.button("Close").clicked() {
+                        show_critical_path = false;
+                    }
+                    critical_path_target = Self::critical_path(ui, windows, cx);
+                });
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             // Use body font to figure out how tall to draw rectangles.
             let font_id = TextStyle::Body.resolve(ui.style());
@@ -2641,6 +3830,10 @@ impl eframe::App for ProfApp {
             ui.scroll_with_delta(Vec2::new(0.0, y_scroll_delta));
             cx.row_scroll_delta = 0;
 
+            for error in load_errors.iter() {
+                ui.label(RichText::new(error).color(Color32::RED));
+            }
+
             let mut remaining = windows.len();
             // Only wrap in a frame if more than one profile
             if remaining > 1 {
@@ -2666,7 +3859,23 @@ impl eframe::App for ProfApp {
         egui::Window::new("Controls")
             .open(&mut cx.show_controls)
             .resizable(false)
-            .show(ctx, |ui| Self::display_controls(ui, &mut cx.item_link_mode));
+            .show(ctx, |ui| {
+                Self::display_controls(ui, &mut cx.item_link_mode, &mut cx.prefetch_budget)
+            });
+
+        if show_critical_path && !cx.critical_path_docked {
+            egui::Window::new("Critical Path")
+                .open(&mut show_critical_path)
+                .resizable(true)
+                .show(ctx, |ui| {
+                    critical_path_target = Self::critical_path(ui, windows, cx);
+                });
+        }
+        cx.show_critical_path = show_critical_path;
+
+        if let Some((index, item_loc, interval)) = critical_path_target {
+            Self::zoom_to_item(&mut windows[index], cx, item_loc, interval);
+        }
 
         for window in windows.iter_mut() {
             let mut zoom_target = None;
@@ -2706,29 +3915,22 @@ impl eframe::App for ProfApp {
             std::mem::swap(&mut items_selected, &mut window.config.items_selected);
 
             if let Some((item_loc, interval)) = zoom_target {
-                let interval = match cx.item_link_mode {
-                    // In Zoom mode, put the item in the center of the view
-                    // interval with a small amount of padding on either side.
-                    ItemLinkNavigationMode::Zoom => interval.grow(interval.duration_ns() / 20),
-                    // In Pan mode, maintain the current window size but shift
-                    // the center to place the item in the middle of it.
-                    ItemLinkNavigationMode::Pan => cx
-                        .view_interval
-                        .translate(interval.center().0 - cx.view_interval.center().0),
-                };
-                ProfApp::zoom(cx, interval);
-                window.expand_slot(&item_loc.entry_id);
-                window.config.scroll_to_item(item_loc);
+                Self::zoom_to_item(window, cx, item_loc, interval);
             }
         }
 
         Self::keyboard(ctx, cx, windows);
 
+        for window in windows.iter_mut() {
+            window.config.issue_prefetches(cx.prefetch_budget);
+        }
+
         // Keep repainting as long as we have outstanding requests.
         if !pending_data_sources.is_empty()
-            || windows
-                .iter()
-                .any(|w| w.config.data_source.outstanding_requests() > 0)
+            || windows.iter().any(|w| {
+                w.config.data_source.outstanding_requests() > 0
+                    || !w.config.prefetcher.is_done(cx.prefetch_budget)
+            })
         {
             ctx.request_repaint_after(Duration::from_millis(50));
         }
@@ -2847,3 +4049,264 @@ pub fn start(data_sources: Vec<Box<dyn DeferredDataSource>>) {
             .expect("failed to start eframe");
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::data::{CounterTile, DataSourceDescription, SlotMetaTile, SlotTile, SummaryTile};
+    use crate::deferred_data::TileResult;
+
+    #[derive(Default)]
+    struct Recorded {
+        fetched: Vec<TileRequest>,
+        pending: Vec<TileRequest>,
+        cancelled: Vec<TileRequest>,
+    }
+
+    // Records summary tile requests, and answers them when asked. The log is
+    // shared, since the config takes ownership of the data source
+    #[derive(Clone, Default)]
+    struct RecordingDataSource(Rc<RefCell<Recorded>>);
+
+    impl DeferredDataSource for RecordingDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&mut self) {
+            unimplemented!();
+        }
+
+        fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+            Vec::new()
+        }
+
+        fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            let req = TileRequest {
+                kind: TileKind::Summary,
+                entry_id: entry_id.clone(),
+                tile_id,
+                full,
+            };
+            let mut recorded = self.0.borrow_mut();
+            recorded.fetched.push(req.clone());
+            recorded.pending.push(req);
+        }
+
+        fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+            std::mem::take(&mut self.0.borrow_mut().pending)
+                .into_iter()
+                .map(|req| {
+                    let tile = SummaryTile {
+                        entry_id: req.entry_id.clone(),
+                        tile_id: req.tile_id,
+                        data: SummaryTileData {
+                            utilization: Vec::new(),
+                        },
+                    };
+                    TileResult::new(&req.entry_id, req.tile_id, req.full, Ok(tile))
+                })
+                .collect()
+        }
+
+        fn fetch_counter_tile(&mut self, _entry_id: &EntryID, _tile_id: TileID, _full: bool) {
+            unimplemented!();
+        }
+
+        fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+            Vec::new()
+        }
+
+        fn fetch_slot_tile(&mut self, _entry_id: &EntryID, _tile_id: TileID, _full: bool) {
+            unimplemented!();
+        }
+
+        fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+            Vec::new()
+        }
+
+        fn fetch_slot_meta_tile(&mut self, _entry_id: &EntryID, _tile_id: TileID, _full: bool) {
+            unimplemented!();
+        }
+
+        fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+            Vec::new()
+        }
+
+        fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+            self.0.borrow_mut().cancelled.extend_from_slice(requests);
+        }
+
+        fn fetch_search(&mut self, _query: &SearchQuery) {
+            unimplemented!();
+        }
+
+        fn get_search_results(&mut self) -> Vec<SearchResponse> {
+            Vec::new()
+        }
+    }
+
+    fn tile(i: i64) -> TileID {
+        TileID(Interval::new(Timestamp(i * 100), Timestamp((i + 1) * 100)))
+    }
+
+    fn entry(i: u64) -> EntryID {
+        EntryID::root().child(0).child(0).child(i)
+    }
+
+    fn summary(entry_id: EntryID, tile_id: TileID) -> TileRequest {
+        TileRequest {
+            kind: TileKind::Summary,
+            entry_id,
+            tile_id,
+            full: false,
+        }
+    }
+
+    // A static profile with a single level of ten tiles
+    fn config(data_source: &RecordingDataSource) -> Config {
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: None,
+                counters: Vec::new(),
+                slots: Vec::new(),
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(1000)),
+            tile_set: TileSet {
+                tiles: vec![(0..10).map(tile).collect()],
+            },
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+        };
+        Config::new(Box::new(data_source.clone()), info)
+    }
+
+    #[test]
+    fn test_prefetch_cancel() {
+        let recording = RecordingDataSource::default();
+        let mut config = config(&recording);
+        let budget = PrefetchBudget::default();
+
+        // Panning either way from tile 4 shows tiles 3 and 5, and zooming
+        // out shows both of them again
+        config.prefetch_neighbors(TileKind::Summary, &entry(0), tile(4).0);
+        config.issue_prefetches(budget);
+        assert_eq!(
+            recording.0.borrow().fetched,
+            [summary(entry(0), tile(3)), summary(entry(0), tile(5))]
+        );
+
+        // Tile 5 is still a neighbor of tile 6, so only tile 3 is cancelled
+        config.prefetch_neighbors(TileKind::Summary, &entry(0), tile(6).0);
+        assert_eq!(recording.0.borrow().cancelled, [summary(entry(0), tile(3))]);
+
+        // Moving again cancels the prefetch that was kept, but not the
+        // one that was already cancelled
+        config.prefetch_neighbors(TileKind::Summary, &entry(0), tile(9).0);
+        assert_eq!(
+            recording.0.borrow().cancelled,
+            [summary(entry(0), tile(3)), summary(entry(0), tile(5))]
+        );
+
+        // Once a prefetch has arrived, it isn't in flight to cancel
+        config.data_source.get_summary_tiles();
+        config.issue_prefetches(budget);
+        assert_eq!(
+            recording.0.borrow().fetched.last(),
+            Some(&summary(entry(0), tile(8)))
+        );
+        config.data_source.get_summary_tiles();
+        config.prefetch_neighbors(TileKind::Summary, &entry(0), tile(0).0);
+        config.issue_prefetches(budget);
+        let recorded = recording.0.borrow();
+        assert_eq!(recorded.cancelled.len(), 2);
+        assert_eq!(recorded.fetched.last(), Some(&summary(entry(0), tile(1))));
+    }
+
+    #[test]
+    fn test_prefetch_in_flight() {
+        let recording = RecordingDataSource::default();
+        let mut config = config(&recording);
+        let budget = PrefetchBudget::default();
+
+        // Two neighbors for each entry
+        for i in 0..40 {
+            config.prefetch_neighbors(TileKind::Summary, &entry(i), tile(4).0);
+        }
+
+        // Nothing is prefetched while a visible tile is outstanding
+        config
+            .data_source
+            .fetch_summary_tile(&entry(0), tile(4), false);
+        config.issue_prefetches(budget);
+        assert_eq!(recording.0.borrow().fetched.len(), 1);
+
+        // Then one batch at a time, each once the last has arrived
+        config.data_source.get_summary_tiles();
+        config.issue_prefetches(budget);
+        assert_eq!(recording.0.borrow().fetched.len(), 1 + PREFETCH_BATCH_SIZE);
+        config.issue_prefetches(budget);
+        assert_eq!(recording.0.borrow().fetched.len(), 1 + PREFETCH_BATCH_SIZE);
+        assert_eq!(
+            config.data_source.outstanding_requests(),
+            PREFETCH_BATCH_SIZE as u64
+        );
+
+        config.data_source.get_summary_tiles();
+        config.issue_prefetches(budget);
+        assert_eq!(
+            recording.0.borrow().fetched.len(),
+            1 + 2 * PREFETCH_BATCH_SIZE
+        );
+        config.data_source.get_summary_tiles();
+        config.issue_prefetches(budget);
+        assert_eq!(recording.0.borrow().fetched.len(), 1 + 80);
+        assert!(config.prefetcher.is_done(budget));
+    }
+
+    #[test]
+    fn test_prefetch_budget() {
+        let recording = RecordingDataSource::default();
+        let mut config = config(&recording);
+
+        // With an empty cache, every tile is assumed to be the default size
+        let budget = PrefetchBudget(Bytes(3 * PREFETCH_TILE_SIZE));
+        for i in 0..4 {
+            config.prefetch_neighbors(TileKind::Summary, &entry(i), tile(4).0);
+        }
+        config.issue_prefetches(budget);
+        assert_eq!(recording.0.borrow().fetched.len(), 3);
+        assert!(config.prefetcher.is_done(budget));
+        assert!(!config.prefetcher.is_done(PrefetchBudget::default()));
+
+        // The budget is reset when the view moves
+        config.data_source.get_summary_tiles();
+        config.prefetch_neighbors(TileKind::Summary, &entry(0), tile(7).0);
+        config.issue_prefetches(budget);
+        assert_eq!(recording.0.borrow().fetched.len(), 5);
+    }
+
+    #[test]
+    fn test_counter_value() {
+        let format = |value, unit| CounterValue(value, unit).to_string();
+        assert_eq!(format(1536.0, "B"), Bytes(1536).to_string());
+        assert_eq!(format(1536.0, "B/s"), format!("{}/s", Bytes(1536)));
+        assert_eq!(format(-1.0, "B"), Bytes(0).to_string());
+        assert_eq!(format(2500.0, "ns"), Timestamp(2500).to_string());
+        assert_eq!(format(2500.0, ""), "2.5k");
+        assert_eq!(format(1e6, "ops"), "1 Mops");
+        assert_eq!(format(-3.25e9, "ops"), "-3.25 Gops");
+        assert_eq!(format(1.5e12, ""), "1.5T");
+        assert_eq!(format(12.0, "ops"), "12 ops");
+        assert_eq!(format(0.126, ""), "0.13");
+        assert_eq!(format(0.0, ""), "0");
+    }
+}
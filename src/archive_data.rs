@@ -1,37 +1,123 @@
-use std::fs::{create_dir, remove_dir_all, File};
-use std::io;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{create_dir, create_dir_all, remove_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+
+use rayon::prelude::*;
 
 use serde::Serialize;
 
-use crate::data::{DataSourceInfo, EntryID, EntryIDSlug, EntryIndex, EntryInfo, TileID, TileSet};
-use crate::deferred_data::{CountingDeferredDataSource, DeferredDataSource};
+use zstd::dict::EncoderDictionary;
+
+use crate::container::{
+    self, BlobRange, ContainerToc, ContainerWriter, DICTIONARY_KEY, INFO_KEY, PREFIX_SIZE,
+};
+use crate::data::{
+    DataSource, DataSourceError, DataSourceInfo, DataSourceResult, EntryID, EntryIDSlug,
+    EntryIndex, TileID, TileKind, TileRequest, TileSet,
+};
+use crate::deferred_data::{CountingDeferredDataSource, DeferredDataSource, TileResult};
+use crate::format;
 use crate::http::schema::TileRequestRef;
+use crate::manifest::{container_manifest_path, Manifest, ManifestEntry, MANIFEST_KEY};
+use crate::retile_data::RetilingDeferredDataSource;
 use crate::timestamp::{Interval, Timestamp};
 
+// How the writer divides the profile into tiles at each level
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TilingMode {
+    // Level i has branch_factor^i tiles of equal duration
+    Uniform,
+    // Tiles at the finest level hold about items_per_tile items each, so
+    // dense periods get short tiles and sparse periods get long ones. Level i
+    // has at most branch_factor^i tiles, with boundaries chosen so that each
+    // holds a similar number of items.
+    Adaptive { items_per_tile: u64 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    // A directory tree with one file per tile
+    Directory,
+    // A single file with a table of contents (see container.rs)
+    Container,
+}
+
+// Where the index.html of a directory archive sends the browser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViewerLocation {
+    // A web viewer hosted elsewhere (e.g., DEFAULT_VIEWER_URL)
+    Hosted(String),
+    // A compiled web viewer (the output of trunk build) to copy into the
+    // archive, so that it can be browsed without reaching any other host
+    Bundled(PathBuf),
+}
+
+pub const DEFAULT_VIEWER_URL: &str = "https://legion.stanford.edu/prof-viewer/";
+
+// Where a bundled viewer goes in the archive
+const VIEWER_DIR: &str = "viewer";
+
+// Where the archive's files (or blobs) go. Each is recorded in the manifest
+// once it's completely written.
+#[derive(Clone)]
+enum ArchiveSink {
+    Directory {
+        path: PathBuf,
+        manifest: Arc<Mutex<File>>,
+    },
+    Container {
+        writer: Arc<Mutex<ContainerWriter<BufWriter<File>>>>,
+        manifest: Arc<Mutex<File>>,
+    },
+}
+
 pub struct DataSourceArchiveWriter<T: DeferredDataSource> {
-    data_source: CountingDeferredDataSource<T>,
+    // Static sources are re-tiled to fit the new tile hierarchy
+    data_source: CountingDeferredDataSource<RetilingDeferredDataSource<T>>,
     levels: u32,
     branch_factor: u64,
     path: PathBuf,
     force: bool,
     zstd_compression: i32,
+    tiling: TilingMode,
+    format: ArchiveFormat,
+    resume: bool,
+    viewer: ViewerLocation,
+    dictionary_size: Option<usize>,
 }
 
-fn create_unique_dir<P: AsRef<Path>>(path: P, force: bool) -> io::Result<PathBuf> {
+const MAX_IN_FLIGHT_REQUESTS: u64 = 100;
+
+// Dictionaries are trained on (at most) this many of the coarsest tiles
+const MAX_DICTIONARY_SAMPLES: usize = 256;
+
+// Bounds how many times adaptive tiling will split a tile that's too dense
+const MAX_SPLIT_ROUNDS: u32 = 8;
+
+fn create_unique<P: AsRef<Path>>(
+    path: P,
+    force: bool,
+    create: fn(&Path) -> io::Result<()>,
+    remove: fn(&Path) -> io::Result<()>,
+) -> io::Result<PathBuf> {
     let mut path = path.as_ref().to_owned();
     if force {
         println!("Removing previous contents of {:?}", &path);
-        let _ = remove_dir_all(&path); // ignore failure, we'll catch it on create
-        create_dir(&path)?;
-    } else if create_dir(&path).is_err() {
+        let _ = remove(&path); // ignore failure, we'll catch it on create
+        create(&path)?;
+    } else if create(&path).is_err() {
         let mut i = 1;
         let retry_limit = 100;
         loop {
             let mut f = path.file_name().unwrap().to_owned();
             f.push(format!(".{}", i));
             let p = path.with_file_name(f);
-            let r = create_dir(&p);
+            let r = create(&p);
             if r.is_ok() {
                 path.clone_from(&p);
                 break;
@@ -45,50 +131,351 @@ fn create_unique_dir<P: AsRef<Path>>(path: P, force: bool) -> io::Result<PathBuf
     Ok(path)
 }
 
-fn write_data<T>(path: PathBuf, data: T, zstd_compression: i32) -> io::Result<()>
-where
-    T: Serialize,
-{
-    let mut f = zstd::Encoder::new(File::create(path)?, zstd_compression)?;
-    ciborium::into_writer(&data, &mut f).expect("ciborium encoding failed");
-    f.finish()?;
+fn create_unique_dir<P: AsRef<Path>>(path: P, force: bool) -> io::Result<PathBuf> {
+    create_unique(path, force, |p| create_dir(p), |p| remove_dir_all(p))
+}
+
+fn create_unique_file<P: AsRef<Path>>(path: P, force: bool) -> io::Result<PathBuf> {
+    create_unique(
+        path,
+        force,
+        |p| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(p)
+                .map(|_| ())
+        },
+        |p| remove_file(p),
+    )
+}
+
+// See manifest.rs. A directory archive's manifest is stored with its tiles,
+// and a single-file archive's next to it.
+fn manifest_path(path: &Path, format: ArchiveFormat) -> PathBuf {
+    match format {
+        ArchiveFormat::Directory => path.join(MANIFEST_KEY),
+        ArchiveFormat::Container => container_manifest_path(path),
+    }
+}
+
+fn open_manifest(path: &Path) -> io::Result<Arc<Mutex<File>>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Arc::new(Mutex::new(file)))
+}
+
+// Reads the manifest left by a previous write, and drops any partial line at
+// the end so that new lines can be appended
+fn resume_manifest(path: &Path) -> io::Result<(Manifest, Arc<Mutex<File>>)> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let manifest = Manifest::parse(&contents).map_err(io::Error::other)?;
+    let file = open_manifest(path)?;
+    let valid = contents.rfind('\n').map_or(0, |i| i + 1);
+    file.lock().unwrap().set_len(valid as u64)?;
+    Ok((manifest, file))
+}
+
+// Keeps the blobs of a single-file archive that the manifest lists (and that
+// actually made it into the file), and discards everything after them. If
+// the file was finished, its table of contents is kept too, so that the file
+// stays readable until the new one is written.
+fn resume_container(
+    path: &Path,
+    manifest: &mut Manifest,
+) -> io::Result<ContainerWriter<BufWriter<File>>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut prefix = [0; PREFIX_SIZE as usize];
+    let old_toc = match file.read_exact(&mut prefix) {
+        Ok(()) => container::decode_prefix(&prefix)
+            .ok()
+            .filter(|range| range.end() <= len),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+        Err(e) => return Err(e),
+    };
+    let range = |entry: &ManifestEntry| {
+        entry.offset.map(|offset| BlobRange {
+            offset,
+            length: entry.length,
+        })
+    };
+    manifest
+        .blobs
+        .retain(|_, entry| range(entry).is_some_and(|range| range.end() <= len));
+
+    let toc = ContainerToc {
+        blobs: manifest
+            .blobs
+            .iter()
+            .map(|(key, entry)| (key.clone(), range(entry).unwrap()))
+            .collect(),
+    };
+    let end = toc.blobs.values().chain(&old_toc).map(BlobRange::end).max();
+    file.set_len(end.unwrap_or(PREFIX_SIZE))?;
+    ContainerWriter::reopen(BufWriter::new(file), toc)
+}
+
+// How blobs are compressed. Only tiles are compressed with the archive's
+// dictionary (if it has one), so that everything else can be read without it
+#[derive(Clone)]
+enum Compression {
+    Plain(i32),
+    Dictionary(Arc<EncoderDictionary<'static>>),
+}
+
+impl Compression {
+    fn encode<T: Serialize>(&self, data: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Plain(zstd_compression) => {
+                format::write(Vec::new(), data, *zstd_compression)
+            }
+            Compression::Dictionary(dictionary) => {
+                format::write_with_dictionary(Vec::new(), data, dictionary)
+            }
+        }
+    }
+}
+
+fn write_blob(sink: &ArchiveSink, key: &str, blob: &[u8]) -> io::Result<()> {
+    match sink {
+        ArchiveSink::Directory { path, manifest } => {
+            // Write to a temporary file and move it into place once it's
+            // complete, so that a partial file never has a tile's name
+            let path = path.join(key);
+            let mut tmp_name = path.file_name().unwrap().to_owned();
+            tmp_name.push(".tmp");
+            let tmp_path = path.with_file_name(tmp_name);
+            std::fs::write(&tmp_path, blob)?;
+            rename(tmp_path, path)?;
+
+            let line = ManifestEntry::new(blob, None).to_line(key);
+            manifest.lock().unwrap().write_all(line.as_bytes())?;
+        }
+        ArchiveSink::Container { writer, manifest } => {
+            let mut writer = writer.lock().unwrap();
+            let range = writer.append(key.to_owned(), blob)?;
+            // The blob must be in the file before the manifest says so
+            writer.flush()?;
+
+            let line = ManifestEntry::new(blob, Some(range.offset)).to_line(key);
+            manifest.lock().unwrap().write_all(line.as_bytes())?;
+        }
+    }
     Ok(())
 }
 
-fn spawn_write<T>(path: PathBuf, data: T, zstd_compression: i32, scope: &rayon::Scope<'_>)
-where
+// The first error from the writes spawned below. Once one fails, the rest
+// are skipped, and the error is returned once their scope ends
+type WriteError = Arc<Mutex<Option<io::Error>>>;
+
+fn check_writes(error: &WriteError) -> io::Result<()> {
+    match error.lock().unwrap().take() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn spawn_write<T>(
+    sink: &ArchiveSink,
+    key: String,
+    data: T,
+    compression: &Compression,
+    error: &WriteError,
+    scope: &rayon::Scope<'_>,
+) where
     T: Serialize + Send + Sync + 'static,
 {
+    let sink = sink.clone();
+    let compression = compression.clone();
+    let error = error.clone();
     scope.spawn(move |_| {
-        // FIXME (Elliott): is there a better way to handle I/O failure?
-        write_data(path, data, zstd_compression).unwrap();
+        if error.lock().unwrap().is_some() {
+            return;
+        }
+        // Compress in parallel (container blobs are appended one at a time)
+        let result = compression
+            .encode(&data)
+            .and_then(|blob| write_blob(&sink, &key, &blob));
+        if let Err(e) = result {
+            error.lock().unwrap().get_or_insert(e);
+        }
     });
 }
 
-fn walk_entry_list(info: &EntryInfo) -> Vec<EntryID> {
-    let mut result = Vec::new();
-    fn walk(info: &EntryInfo, entry_id: EntryID, result: &mut Vec<EntryID>) {
-        match info {
-            EntryInfo::Panel { summary, slots, .. } => {
-                if let Some(summary) = summary {
-                    walk(summary, entry_id.summary(), result);
-                }
-                for (i, slot) in slots.iter().enumerate() {
-                    walk(slot, entry_id.child(i as u64), result)
-                }
-            }
-            EntryInfo::Slot { .. } => {
-                result.push(entry_id);
+// The uncompressed payload, to train dictionaries with
+fn dictionary_sample<T: Serialize>(data: &T) -> Vec<u8> {
+    let mut sample = Vec::new();
+    ciborium::into_writer(data, &mut sample).expect("ciborium encoding failed");
+    sample
+}
+
+fn write_tiles<T: DeferredDataSource>(
+    data_source: &mut T,
+    sink: &ArchiveSink,
+    compression: &Compression,
+    error: &WriteError,
+    scope: &rayon::Scope<'_>,
+) -> io::Result<()> {
+    for tile in data_source.get_summary_tiles() {
+        let tile = check_tile(tile)?;
+        let key = container::tile_key(TileKind::Summary, &tile.entry_id, tile.tile_id);
+        spawn_write(sink, key, tile, compression, error, scope);
+    }
+    for tile in data_source.get_counter_tiles() {
+        let tile = check_tile(tile)?;
+        let key = container::tile_key(TileKind::Counter, &tile.entry_id, tile.tile_id);
+        spawn_write(sink, key, tile, compression, error, scope);
+    }
+    for tile in data_source.get_slot_tiles() {
+        let tile = check_tile(tile)?;
+        let key = container::tile_key(TileKind::Slot, &tile.entry_id, tile.tile_id);
+        spawn_write(sink, key, tile, compression, error, scope);
+    }
+    for tile in data_source.get_slot_meta_tiles() {
+        let tile = check_tile(tile)?;
+        let key = container::tile_key(TileKind::SlotMeta, &tile.entry_id, tile.tile_id);
+        spawn_write(sink, key, tile, compression, error, scope);
+    }
+    Ok(())
+}
+
+impl ArchiveSink {
+    fn finish(self, zstd_compression: i32) -> io::Result<()> {
+        if let ArchiveSink::Container { writer, .. } = self {
+            // Only call this once all writes have completed
+            let writer = Arc::into_inner(writer).unwrap().into_inner().unwrap();
+            writer.finish(zstd_compression)?;
+        }
+        Ok(())
+    }
+}
+
+fn uniform_tiles(duration: i64, num_tiles: u64) -> Vec<TileID> {
+    let num_tiles = num_tiles as i64;
+    (0..num_tiles)
+        .map(|i| {
+            let start = Timestamp(duration * i / num_tiles);
+            let stop = Timestamp(duration * (i + 1) / num_tiles);
+            TileID(Interval::new(start, stop))
+        })
+        .collect()
+}
+
+fn split_tile(tile_id: TileID, branch_factor: u64) -> impl Iterator<Item = TileID> {
+    let Interval { start, stop } = tile_id.0;
+    uniform_tiles(stop.0 - start.0, branch_factor)
+        .into_iter()
+        .map(move |tile| {
+            TileID(Interval::new(
+                Timestamp(tile.0.start.0 + start.0),
+                Timestamp(tile.0.stop.0 + start.0),
+            ))
+        })
+}
+
+// Splits a tile that holds more than items_per_tile of the items (until it
+// can't be split further, or has been split MAX_SPLIT_ROUNDS times), and
+// records the resulting tiles along with the number of items in each
+fn split_dense_tile(
+    tile_id: TileID,
+    items: &[Interval],
+    items_per_tile: u64,
+    branch_factor: u64,
+    round: u32,
+    leaves: &mut BTreeMap<TileID, u64>,
+) {
+    let items: Vec<_> = items
+        .iter()
+        .filter(|item| item.overlaps(tile_id.0))
+        .copied()
+        .collect();
+    let count = items.len() as u64;
+    let splittable = tile_id.0.duration_ns() >= branch_factor as i64;
+    if count > items_per_tile && splittable && round < MAX_SPLIT_ROUNDS {
+        for child in split_tile(tile_id, branch_factor) {
+            split_dense_tile(
+                child,
+                &items,
+                items_per_tile,
+                branch_factor,
+                round + 1,
+                leaves,
+            );
+        }
+    } else {
+        leaves.insert(tile_id, count);
+    }
+}
+
+// Joins neighboring tiles as long as the result stays within items_per_tile
+fn merge_sparse_tiles(tiles: BTreeMap<TileID, u64>, items_per_tile: u64) -> Vec<(TileID, u64)> {
+    let mut result: Vec<(TileID, u64)> = Vec::new();
+    for (tile_id, count) in tiles {
+        if let Some((last, last_count)) = result.last_mut() {
+            if *last_count + count <= items_per_tile {
+                last.0.stop = tile_id.0.stop;
+                *last_count += count;
+                continue;
             }
-            EntryInfo::Summary { .. } => {
-                result.push(entry_id);
+        }
+        result.push((tile_id, count));
+    }
+    result
+}
+
+// Builds coarser levels on top of the finest one by grouping its tiles into
+// at most branch_factor^level tiles of roughly equal item counts. Tile
+// boundaries at each level are also boundaries at every finer level.
+fn adaptive_levels(finest: &[(TileID, u64)], levels: u32, branch_factor: u64) -> Vec<Vec<TileID>> {
+    // Count each tile as one extra item so that empty stretches still get
+    // divided up
+    let weights: Vec<_> = finest.iter().map(|(_, count)| count + 1).collect();
+    let total: u64 = weights.iter().sum();
+
+    let mut result: Vec<Vec<TileID>> = Vec::new();
+    for level in 0..levels - 1 {
+        let num_tiles = branch_factor.saturating_pow(level);
+        if num_tiles >= finest.len() as u64 {
+            break;
+        }
+        let mut tiles = Vec::new();
+        let mut start = None;
+        let mut sum = 0;
+        for ((tile_id, _), weight) in finest.iter().zip(&weights) {
+            let group_start = *start.get_or_insert(tile_id.0.start);
+            sum += weight;
+            // Close the group once it reaches its share of the total
+            let group = tiles.len() as u128 + 1;
+            if sum as u128 * num_tiles as u128 >= total as u128 * group {
+                tiles.push(TileID(Interval::new(group_start, tile_id.0.stop)));
+                start = None;
             }
         }
+        result.push(tiles);
     }
-    walk(info, EntryID::root(), &mut result);
+    result.push(finest.iter().map(|(tile_id, _)| *tile_id).collect());
+    result.dedup();
     result
 }
 
+fn check_tile<T>(tile: TileResult<T>) -> io::Result<T> {
+    tile.result.map_err(|e| {
+        io::Error::other(format!(
+            "failed to fetch tile {}: {}",
+            TileRequestRef {
+                entry_id: &tile.entry_id,
+                tile_id: tile.tile_id,
+            }
+            .to_slug(),
+            e
+        ))
+    })
+}
+
 impl<T: DeferredDataSource> DataSourceArchiveWriter<T> {
     pub fn new(
         data_source: T,
@@ -101,175 +488,1383 @@ impl<T: DeferredDataSource> DataSourceArchiveWriter<T> {
         assert!(levels >= 1);
         assert!(branch_factor >= 2);
         Self {
-            data_source: CountingDeferredDataSource::new(data_source),
+            data_source: CountingDeferredDataSource::new(RetilingDeferredDataSource::new(
+                data_source,
+            )),
             levels,
             branch_factor,
             path: path.as_ref().to_owned(),
             force,
             zstd_compression,
+            tiling: TilingMode::Uniform,
+            format: ArchiveFormat::Directory,
+            resume: false,
+            viewer: ViewerLocation::Hosted(DEFAULT_VIEWER_URL.to_owned()),
+            dictionary_size: None,
         }
     }
 
-    fn check_info(&mut self) -> Option<DataSourceInfo> {
-        // We requested this once, so we know we'll get zero or one result
-        self.data_source.get_infos().pop()
+    // Compress tiles with a zstd dictionary of up to the given size (in
+    // bytes), trained on a sample of the tiles. This mostly helps archives
+    // with many small tiles. Readers load the dictionary from the archive.
+    pub fn with_dictionary(mut self, dictionary_size: Option<usize>) -> Self {
+        self.dictionary_size = dictionary_size;
+        self
     }
 
-    fn write_info(&mut self, info: DataSourceInfo, scope: &rayon::Scope<'_>) {
-        let path = self.path.join("info");
-        spawn_write(path, info, self.zstd_compression, scope);
+    // Continue an interrupted write to the same path, keeping every tile
+    // that was completely written, instead of starting a new archive
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
     }
 
-    fn write_summary_tiles(&mut self, scope: &rayon::Scope<'_>) {
-        for tile in self.data_source.get_summary_tiles() {
-            let mut path = self.path.join("summary_tile");
-            let req = TileRequestRef {
-                entry_id: &tile.entry_id,
-                tile_id: tile.tile_id,
-            };
-            path.push(req.to_slug());
-            spawn_write(path, tile, self.zstd_compression, scope);
+    // Only applies to directory archives, since single-file archives have
+    // no index.html
+    pub fn with_viewer(mut self, viewer: ViewerLocation) -> Self {
+        self.viewer = viewer;
+        self
+    }
+
+    pub fn with_format(mut self, format: ArchiveFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_tiling(mut self, tiling: TilingMode) -> Self {
+        if let TilingMode::Adaptive { items_per_tile } = tiling {
+            assert!(items_per_tile >= 1);
         }
+        self.tiling = tiling;
+        self
     }
 
-    fn write_slot_tiles(&mut self, scope: &rayon::Scope<'_>) {
-        for tile in self.data_source.get_slot_tiles() {
-            let mut path = self.path.join("slot_tile");
-            let req = TileRequestRef {
-                entry_id: &tile.entry_id,
-                tile_id: tile.tile_id,
-            };
-            path.push(req.to_slug());
-            spawn_write(path, tile, self.zstd_compression, scope);
+    fn receive_items(
+        &mut self,
+        items: &mut BTreeMap<TileID, Vec<Interval>>,
+        limit: u64,
+    ) -> io::Result<()> {
+        while self.data_source.outstanding_requests() > limit {
+            for tile in self.data_source.get_slot_tiles() {
+                let tile = check_tile(tile)?;
+                let intervals = items.get_mut(&tile.tile_id).unwrap();
+                intervals.extend(tile.data.items.iter().flatten().map(|item| item.interval));
+            }
         }
+        Ok(())
     }
 
-    fn write_slot_meta_tiles(&mut self, scope: &rayon::Scope<'_>) {
-        for tile in self.data_source.get_slot_meta_tiles() {
-            let mut path = self.path.join("slot_meta_tile");
-            let req = TileRequestRef {
-                entry_id: &tile.entry_id,
-                tile_id: tile.tile_id,
+    // The intervals of the items in each tile, across all slots
+    fn collect_items(
+        &mut self,
+        entry_ids: &[EntryID],
+        tile_ids: &[TileID],
+    ) -> io::Result<BTreeMap<TileID, Vec<Interval>>> {
+        let mut items: BTreeMap<_, _> = tile_ids
+            .iter()
+            .map(|tile_id| (*tile_id, Vec::new()))
+            .collect();
+        for entry_id in entry_ids {
+            let Some(EntryIndex::Slot(..)) = entry_id.last_index() else {
+                continue;
             };
-            path.push(req.to_slug());
-            spawn_write(path, tile, self.zstd_compression, scope);
+            for tile_id in tile_ids {
+                self.data_source.fetch_slot_tile(entry_id, *tile_id, true);
+            }
+            self.receive_items(&mut items, MAX_IN_FLIGHT_REQUESTS)?;
         }
+        self.receive_items(&mut items, 0)?;
+        Ok(items)
     }
 
-    pub fn write(mut self) -> io::Result<()> {
-        self.path = create_unique_dir(&self.path, self.force)?;
-        println!("Created output directory {:?}", &self.path);
-        create_dir(self.path.join("summary_tile"))?;
-        create_dir(self.path.join("slot_tile"))?;
-        create_dir(self.path.join("slot_meta_tile"))?;
+    fn adaptive_tile_set(
+        &mut self,
+        entry_ids: &[EntryID],
+        duration: i64,
+        items_per_tile: u64,
+    ) -> io::Result<Vec<Vec<TileID>>> {
+        // Start from what the finest uniform level would be, and split tiles
+        // that hold too many items until they fit. Each of those tiles is
+        // fetched once, and split from the intervals of its items. Only a few
+        // tiles are measured at a time, so that only their items are held
+        let tiles = uniform_tiles(duration, self.branch_factor.pow(self.levels - 1));
+        let slots = entry_ids
+            .iter()
+            .filter(|entry_id| matches!(entry_id.last_index(), Some(EntryIndex::Slot(..))))
+            .count() as u64;
+        let chunk_size = (MAX_IN_FLIGHT_REQUESTS / slots.max(1)).max(1) as usize;
+        info!("Measuring item density in {} tiles", tiles.len());
+        let mut leaves = BTreeMap::new();
+        for chunk in tiles.chunks(chunk_size) {
+            for (tile_id, items) in self.collect_items(entry_ids, chunk)? {
+                split_dense_tile(
+                    tile_id,
+                    &items,
+                    items_per_tile,
+                    self.branch_factor,
+                    0,
+                    &mut leaves,
+                );
+            }
+        }
 
-        self.data_source.fetch_info();
-        let mut info = None;
-        while info.is_none() {
-            info = self.check_info();
+        let finest = merge_sparse_tiles(leaves, items_per_tile);
+        Ok(adaptive_levels(&finest, self.levels, self.branch_factor))
+    }
+
+    fn check_info(&mut self) -> Option<DataSourceResult<DataSourceInfo>> {
+        // We requested this once, so we know we'll get zero or one result
+        self.data_source.get_infos().pop()
+    }
+
+    fn write_info(
+        &mut self,
+        sink: &ArchiveSink,
+        info: DataSourceInfo,
+        error: &WriteError,
+        scope: &rayon::Scope<'_>,
+    ) {
+        let compression = Compression::Plain(self.zstd_compression);
+        spawn_write(sink, INFO_KEY.to_owned(), info, &compression, error, scope);
+    }
+
+    fn write_tiles(
+        &mut self,
+        sink: &ArchiveSink,
+        compression: &Compression,
+        error: &WriteError,
+        scope: &rayon::Scope<'_>,
+    ) -> io::Result<()> {
+        write_tiles(&mut self.data_source, sink, compression, error, scope)
+    }
+
+    // Trains on the coarsest tiles, which are fetched again when the tiles
+    // are written. Returns None if there's too little data to train on
+    fn train_dictionary(
+        &mut self,
+        entry_ids: &[EntryID],
+        tile_set: &[Vec<TileID>],
+        dictionary_size: usize,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let mut requests = Vec::new();
+        for (level, tile_ids) in tile_set.iter().enumerate() {
+            let full = level == tile_set.len() - 1;
+            requests.extend(level_tiles(entry_ids, tile_ids, full));
+            if requests.len() >= MAX_DICTIONARY_SAMPLES {
+                break;
+            }
+        }
+        requests.truncate(MAX_DICTIONARY_SAMPLES);
+
+        let mut samples = Vec::new();
+        for chunk in requests.chunks(MAX_IN_FLIGHT_REQUESTS as usize) {
+            self.data_source.fetch_tiles(chunk);
+            while self.data_source.outstanding_requests() > 0 {
+                for tile in self.data_source.get_summary_tiles() {
+                    samples.push(dictionary_sample(&check_tile(tile)?));
+                }
+                for tile in self.data_source.get_counter_tiles() {
+                    samples.push(dictionary_sample(&check_tile(tile)?));
+                }
+                for tile in self.data_source.get_slot_tiles() {
+                    samples.push(dictionary_sample(&check_tile(tile)?));
+                }
+                for tile in self.data_source.get_slot_meta_tiles() {
+                    samples.push(dictionary_sample(&check_tile(tile)?));
+                }
+            }
+        }
+
+        match zstd::dict::from_samples(&samples, dictionary_size) {
+            Ok(dictionary) => Ok(Some(dictionary)),
+            Err(e) => {
+                warn!("Unable to train dictionary ({}), continuing without one", e);
+                Ok(None)
+            }
         }
-        let mut info = info.unwrap();
+    }
 
-        let entry_ids = walk_entry_list(&info.entry_info);
-        for entry_id in &entry_ids {
+    // Directories may already exist when resuming
+    fn create_directories(&self, entry_ids: &[EntryID]) -> io::Result<()> {
+        for entry_id in entry_ids {
             let entry_dir = format!("{}", EntryIDSlug(entry_id));
             match entry_id.last_index().unwrap() {
                 EntryIndex::Summary => {
-                    create_dir(self.path.join("summary_tile").join(&entry_dir))?;
+                    create_dir_all(self.path.join("summary_tile").join(&entry_dir))?;
+                }
+                EntryIndex::Counter(..) => {
+                    create_dir_all(self.path.join("counter_tile").join(&entry_dir))?;
                 }
                 EntryIndex::Slot(..) => {
-                    create_dir(self.path.join("slot_tile").join(&entry_dir))?;
-                    create_dir(self.path.join("slot_meta_tile").join(&entry_dir))?;
+                    create_dir_all(self.path.join("slot_tile").join(&entry_dir))?;
+                    create_dir_all(self.path.join("slot_meta_tile").join(&entry_dir))?;
                 }
             }
         }
+        Ok(())
+    }
 
-        // For now, this only works on dynamic data sources
-        assert!(info.tile_set.tiles.is_empty());
+    // Reads a blob written by a previous run
+    fn read_archived_blob(&self, manifest: &Manifest, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = manifest.blobs.get(key) else {
+            return Ok(None);
+        };
+        let blob = match entry.offset {
+            Some(offset) => container::read_blob(
+                &mut File::open(&self.path)?,
+                BlobRange {
+                    offset,
+                    length: entry.length,
+                },
+            )?,
+            None => std::fs::read(self.path.join(key))?,
+        };
+        entry.check(key, &blob).map_err(io::Error::other)?;
+        Ok(Some(blob))
+    }
 
-        let mut tile_set = Vec::new();
+    // Reads the info written by a previous run, which must have come from
+    // the same profile. Its tile set is reused, so that the tiles written so
+    // far still fit.
+    fn read_archived_info(&self, manifest: &Manifest) -> io::Result<Option<DataSourceInfo>> {
+        let Some(blob) = self.read_archived_blob(manifest, INFO_KEY)? else {
+            return Ok(None);
+        };
+        let info = format::read(&blob[..], "archive").map_err(io::Error::other)?;
+        Ok(Some(info))
+    }
 
-        for level in 0..self.levels {
-            let num_tiles = self.branch_factor.pow(level) as i64;
-            let duration = info.interval.duration_ns();
-            let tile_ids: Vec<_> = (0..num_tiles)
-                .map(|i| {
-                    let start = Timestamp(duration * i / num_tiles);
-                    let stop = Timestamp(duration * (i + 1) / num_tiles);
-                    TileID(Interval::new(start, stop))
-                })
-                .collect();
-            tile_set.push(tile_ids);
+    pub fn write(mut self) -> io::Result<()> {
+        // Better to find out now than after writing every tile
+        if let ViewerLocation::Bundled(viewer) = &self.viewer {
+            if !viewer.join("index.html").is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{:?} is not a compiled web viewer (no index.html)", viewer),
+                ));
+            }
+        }
+
+        let resume = self.resume && self.path.exists();
+        if resume {
+            info!("Resuming output {:?}", &self.path);
+        } else {
+            match self.format {
+                ArchiveFormat::Directory => {
+                    self.path = create_unique_dir(&self.path, self.force)?;
+                    info!("Created output directory {:?}", &self.path);
+                }
+                ArchiveFormat::Container => {
+                    self.path = create_unique_file(&self.path, self.force)?;
+                    info!("Created output file {:?}", &self.path);
+                }
+            }
         }
 
-        info.tile_set = TileSet {
-            tiles: tile_set.clone(),
+        // A single-file archive's manifest isn't removed along with it, so
+        // one from an earlier write may still be there
+        let manifest_path = manifest_path(&self.path, self.format);
+        let (mut manifest, manifest_file) = if resume {
+            resume_manifest(&manifest_path)?
+        } else {
+            File::create(&manifest_path)?;
+            (Manifest::default(), open_manifest(&manifest_path)?)
+        };
+        let sink = match self.format {
+            ArchiveFormat::Directory => ArchiveSink::Directory {
+                path: self.path.clone(),
+                manifest: manifest_file,
+            },
+            ArchiveFormat::Container => {
+                let writer = if resume {
+                    resume_container(&self.path, &mut manifest)?
+                } else {
+                    let file = OpenOptions::new().write(true).open(&self.path)?;
+                    ContainerWriter::new(BufWriter::new(file))?
+                };
+                ArchiveSink::Container {
+                    writer: Arc::new(Mutex::new(writer)),
+                    manifest: manifest_file,
+                }
+            }
         };
 
-        rayon::in_place_scope(|s| {
-            self.write_info(info, s);
-        });
+        self.data_source.fetch_info();
+        let mut info = None;
+        while info.is_none() {
+            info = self.check_info();
+        }
+        let mut info = info.unwrap().map_err(io::Error::other)?;
 
-        for level in 0..self.levels {
-            let tile_ids = &tile_set[level as usize];
-            let full = level == self.levels - 1;
+        let entry_ids = info.entry_info.entry_ids();
+        if self.format == ArchiveFormat::Directory {
+            self.create_directories(&entry_ids)?;
+        }
 
-            println!("Writing level {} with {} tiles", level, tile_ids.len());
+        let archived_info = self.read_archived_info(&manifest)?;
+        let tile_set = if let Some(archived_info) = archived_info {
+            if archived_info.entry_info.entry_ids() != entry_ids
+                || archived_info.interval != info.interval
+            {
+                return Err(io::Error::other(format!(
+                    "cannot resume {:?}: it was written from a different profile",
+                    &self.path
+                )));
+            }
+            info!("Found {} complete files", manifest.blobs.len());
+            archived_info.tile_set.tiles
+        } else {
+            let duration = info.interval.duration_ns();
+            match self.tiling {
+                TilingMode::Uniform => (0..self.levels)
+                    .map(|level| uniform_tiles(duration, self.branch_factor.pow(level)))
+                    .collect(),
+                TilingMode::Adaptive { items_per_tile } => {
+                    self.adaptive_tile_set(&entry_ids, duration, items_per_tile)?
+                }
+            }
+        };
 
-            const MAX_IN_FLIGHT_REQUESTS: u64 = 100;
+        // The dictionary is decided along with the tile set, before any tiles
+        // are written, so that either all tiles use it or none do
+        let dictionary = if manifest.contains(DICTIONARY_KEY) {
+            self.read_archived_blob(&manifest, DICTIONARY_KEY)?
+        } else if let (Some(size), false) = (self.dictionary_size, manifest.contains(INFO_KEY)) {
+            let dictionary = self.train_dictionary(&entry_ids, &tile_set, size)?;
+            if let Some(dictionary) = &dictionary {
+                write_blob(&sink, DICTIONARY_KEY, dictionary)?;
+            }
+            dictionary
+        } else {
+            None
+        };
+        let compression = match dictionary {
+            Some(dictionary) => Compression::Dictionary(Arc::new(EncoderDictionary::copy(
+                &dictionary,
+                self.zstd_compression,
+            ))),
+            None => Compression::Plain(self.zstd_compression),
+        };
 
-            for entry_id in &entry_ids {
-                match entry_id.last_index().unwrap() {
-                    EntryIndex::Summary => {
-                        for tile_id in tile_ids {
-                            self.data_source
-                                .fetch_summary_tile(entry_id, *tile_id, full);
-                        }
-                    }
-                    EntryIndex::Slot(..) => {
-                        for tile_id in tile_ids {
-                            self.data_source.fetch_slot_tile(entry_id, *tile_id, full);
-                            self.data_source
-                                .fetch_slot_meta_tile(entry_id, *tile_id, full);
-                        }
-                    }
-                }
+        let error = WriteError::default();
+        if !manifest.contains(INFO_KEY) {
+            info.tile_set = TileSet {
+                tiles: tile_set.clone(),
+            };
+            rayon::in_place_scope(|s| {
+                self.write_info(&sink, info, &error, s);
+            });
+            check_writes(&error)?;
+        }
+
+        for (level, tile_ids) in tile_set.iter().enumerate() {
+            let full = level == tile_set.len() - 1;
+            let requests: Vec<_> = level_tiles(&entry_ids, tile_ids, full)
+                .into_iter()
+                .filter(|req| {
+                    let key = container::tile_key(req.kind, &req.entry_id, req.tile_id);
+                    !manifest.contains(&key)
+                })
+                .collect();
+
+            println!(
+                "Writing level {} with {} tiles ({} files)",
+                level,
+                tile_ids.len(),
+                requests.len()
+            );
+
+            for chunk in requests.chunks(MAX_IN_FLIGHT_REQUESTS as usize) {
+                self.data_source.fetch_tiles(chunk);
 
                 // Bound the number of in-flight requests so we don't use too much memory.
                 rayon::in_place_scope(|s| {
                     while self.data_source.outstanding_requests() > MAX_IN_FLIGHT_REQUESTS {
-                        self.write_summary_tiles(s);
-                        self.write_slot_tiles(s);
-                        self.write_slot_meta_tiles(s);
+                        self.write_tiles(&sink, &compression, &error, s)?;
                     }
-                });
+                    Ok::<_, io::Error>(())
+                })?;
+                check_writes(&error)?;
             }
         }
 
         rayon::in_place_scope(|s| {
             while self.data_source.outstanding_requests() > 0 {
-                self.write_summary_tiles(s);
-                self.write_slot_tiles(s);
-                self.write_slot_meta_tiles(s);
+                self.write_tiles(&sink, &compression, &error, s)?;
             }
-        });
+            Ok::<_, io::Error>(())
+        })?;
+        check_writes(&error)?;
 
-        std::fs::write(
-            self.path.join("index.html"),
-            "<html>
-<script>
-window.onload = function() {
-  var prof = location
-  if(location.protocol !== 'https:') {
+        match sink {
+            ArchiveSink::Directory { .. } => self.write_index(),
+            // All writes have completed by the end of the scope above
+            ArchiveSink::Container { .. } => sink.finish(self.zstd_compression),
+        }
+    }
+
+    fn write_index(&self) -> io::Result<()> {
+        let viewer_url = match &self.viewer {
+            ViewerLocation::Hosted(url) => url.clone(),
+            ViewerLocation::Bundled(viewer) => {
+                copy_dir(viewer, &self.path.join(VIEWER_DIR))?;
+                format!("{}/", VIEWER_DIR)
+            }
+        };
+        std::fs::write(self.path.join("index.html"), index_html(&viewer_url))?;
+
+        Ok(())
+    }
+}
+
+// A page that opens the archive it's in with the viewer at the given URL
+fn index_html(viewer_url: &str) -> String {
+    // An https viewer can only load archives over https
+    let upgrade = if viewer_url.starts_with("https:") {
+        "  if(location.protocol !== 'https:') {
     prof = location.replace(`https:${location.href.substring(location.protocol.length)}`);
   }
-  window.location.replace(\"https://legion.stanford.edu/prof-viewer/?url=\"+prof.href);
-}
+"
+    } else {
+        ""
+    };
+    // A JSON string is a JS string too. Escaping < as well keeps the URL
+    // from closing the script element (e.g., with "</script>")
+    let viewer_url = serde_json::to_string(viewer_url)
+        .unwrap()
+        .replace('<', "\\u003c");
+    format!(
+        "<html>
+<script>
+window.onload = function() {{
+  var prof = location
+{}  window.location.replace({}+\"?url=\"+prof.href);
+}}
 </script>
 </html>
 ",
-        )?;
+        upgrade, viewer_url
+    )
+}
 
-        Ok(())
+fn copy_dir(src: &Path, dst: &Path) -> io::Result<()> {
+    create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TileProblem {
+    // The tile doesn't exist
+    Missing,
+    // The tile exists, but couldn't be read or decoded
+    Corrupt(DataSourceError),
+    // The tile decoded, but is for a different entry or tile than its path
+    Mismatched { entry_id: EntryID, tile_id: TileID },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveReport {
+    pub tiles_checked: u64,
+    pub problems: Vec<(TileRequest, TileProblem)>,
+}
+
+impl fmt::Display for TileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileProblem::Missing => write!(f, "missing"),
+            TileProblem::Corrupt(e) => write!(f, "corrupt ({e})"),
+            TileProblem::Mismatched { entry_id, tile_id } => write!(
+                f,
+                "holds the wrong tile ({})",
+                TileRequestRef {
+                    entry_id,
+                    tile_id: *tile_id
+                }
+                .to_slug()
+            ),
+        }
+    }
+}
+
+impl ArchiveReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+// Every tile of the given entries at one level
+fn level_tiles(entry_ids: &[EntryID], tile_ids: &[TileID], full: bool) -> Vec<TileRequest> {
+    let mut requests = Vec::new();
+    for entry_id in entry_ids {
+        for kind in entry_id.tile_kinds() {
+            requests.extend(tile_ids.iter().map(|tile_id| TileRequest {
+                kind: *kind,
+                entry_id: entry_id.clone(),
+                tile_id: *tile_id,
+                full,
+            }));
+        }
+    }
+    requests
+}
+
+// Every tile the archive's info says it has
+fn expected_tiles(info: &DataSourceInfo) -> Vec<TileRequest> {
+    let entry_ids = info.entry_info.entry_ids();
+    let levels = &info.tile_set.tiles;
+    levels
+        .iter()
+        .enumerate()
+        .flat_map(|(level, tile_ids)| level_tiles(&entry_ids, tile_ids, level == levels.len() - 1))
+        .collect()
+}
+
+fn check_archive_tile<D: DataSource>(data_source: &D, req: &TileRequest) -> Option<TileProblem> {
+    let TileRequest {
+        kind,
+        entry_id,
+        tile_id,
+        full,
+    } = req;
+    let result = match kind {
+        TileKind::Summary => data_source
+            .fetch_summary_tile(entry_id, *tile_id, *full)
+            .map(|tile| (tile.entry_id, tile.tile_id)),
+        TileKind::Counter => data_source
+            .fetch_counter_tile(entry_id, *tile_id, *full)
+            .map(|tile| (tile.entry_id, tile.tile_id)),
+        TileKind::Slot => data_source
+            .fetch_slot_tile(entry_id, *tile_id, *full)
+            .map(|tile| (tile.entry_id, tile.tile_id)),
+        TileKind::SlotMeta => data_source
+            .fetch_slot_meta_tile(entry_id, *tile_id, *full)
+            .map(|tile| (tile.entry_id, tile.tile_id)),
+    };
+    match result {
+        Ok((e, t)) if e == *entry_id && t == *tile_id => None,
+        Ok((entry_id, tile_id)) => Some(TileProblem::Mismatched { entry_id, tile_id }),
+        Err(DataSourceError::NotFound(_)) => Some(TileProblem::Missing),
+        Err(e) => Some(TileProblem::Corrupt(e)),
+    }
+}
+
+// Checks that every tile listed in an archive's info exists, decodes, and is
+// the tile it claims to be. The data source is whatever reads the archive
+// (e.g., FileDataSource or ContainerDataSource). Fails only if the info
+// itself can't be read.
+pub fn verify_archive<D: DataSource + Sync>(data_source: &D) -> DataSourceResult<ArchiveReport> {
+    let info = data_source.fetch_info()?;
+    if info.tile_set.tiles.is_empty() {
+        return Err(DataSourceError::Decode(
+            "archive info does not list any tiles".to_owned(),
+        ));
+    }
+
+    let requests = expected_tiles(&info);
+    let problems = requests
+        .par_iter()
+        .filter_map(|req| Some((req.clone(), check_archive_tile(data_source, req)?)))
+        .collect();
+    Ok(ArchiveReport {
+        tiles_checked: requests.len() as u64,
+        problems,
+    })
+}
+
+// Regenerates the tiles listed in a report from the source the archive was
+// originally written from, and writes them back into the archive at path
+// (either a directory, or a file written with ArchiveFormat::Container).
+pub fn repair_archive<T: DeferredDataSource>(
+    path: impl AsRef<Path>,
+    report: &ArchiveReport,
+    data_source: T,
+    zstd_compression: i32,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let requests: Vec<_> = report.problems.iter().map(|(req, _)| req.clone()).collect();
+    if requests.is_empty() {
+        return Ok(());
+    }
+
+    // Tiles must be compressed with the archive's dictionary, if it has one
+    let (sink, dictionary) = if path.is_dir() {
+        // The entry's directory may be missing too
+        for req in &requests {
+            let key = container::tile_key(req.kind, &req.entry_id, req.tile_id);
+            create_dir_all(path.join(key).parent().unwrap())?;
+        }
+        let sink = ArchiveSink::Directory {
+            path: path.to_owned(),
+            manifest: open_manifest(&manifest_path(path, ArchiveFormat::Directory))?,
+        };
+        let dictionary = match std::fs::read(path.join(DICTIONARY_KEY)) {
+            Ok(dictionary) => Some(dictionary),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        (sink, dictionary)
+    } else {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let toc = container::read_toc(&mut file).map_err(io::Error::other)?;
+        let dictionary = match toc.get(DICTIONARY_KEY) {
+            Ok(range) => Some(container::read_blob(&mut file, range)?),
+            Err(_) => None,
+        };
+        let writer = ContainerWriter::reopen(BufWriter::new(file), toc)?;
+        let sink = ArchiveSink::Container {
+            writer: Arc::new(Mutex::new(writer)),
+            manifest: open_manifest(&manifest_path(path, ArchiveFormat::Container))?,
+        };
+        (sink, dictionary)
+    };
+    let compression = match dictionary {
+        Some(dictionary) => Compression::Dictionary(Arc::new(EncoderDictionary::copy(
+            &dictionary,
+            zstd_compression,
+        ))),
+        None => Compression::Plain(zstd_compression),
+    };
+
+    let mut data_source =
+        CountingDeferredDataSource::new(RetilingDeferredDataSource::new(data_source));
+    data_source.fetch_info();
+    loop {
+        if let Some(info) = data_source.get_infos().pop() {
+            info.map_err(io::Error::other)?;
+            break;
+        }
+    }
+
+    info!("Regenerating {} tiles", requests.len());
+    let error = WriteError::default();
+    rayon::in_place_scope(|s| {
+        for chunk in requests.chunks(MAX_IN_FLIGHT_REQUESTS as usize) {
+            data_source.fetch_tiles(chunk);
+            while data_source.outstanding_requests() > 0 {
+                write_tiles(&mut data_source, &sink, &compression, &error, s)?;
+            }
+        }
+        Ok::<_, io::Error>(())
+    })?;
+    check_writes(&error)?;
+    sink.finish(zstd_compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use crate::container_data::ContainerDataSource;
+    use crate::data::{
+        Color32, CounterTile, DataSourceDescription, EntryInfo, FieldSchema, Item, ItemMeta,
+        ItemUID, SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData, SummaryTile,
+        SummaryTileData, UtilPoint,
+    };
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::file_data::FileDataSource;
+
+    fn tile(start: i64, stop: i64) -> TileID {
+        TileID(Interval::new(Timestamp(start), Timestamp(stop)))
+    }
+
+    #[test]
+    fn test_split_tile() {
+        let tiles: Vec<_> = split_tile(tile(10, 20), 4).collect();
+        assert_eq!(
+            tiles,
+            vec![tile(10, 12), tile(12, 15), tile(15, 17), tile(17, 20)]
+        );
+    }
+
+    #[test]
+    fn test_merge_sparse_tiles() {
+        let counts = [(tile(0, 10), 8), (tile(10, 20), 1), (tile(20, 30), 0)]
+            .into_iter()
+            .chain([(tile(30, 40), 2), (tile(40, 50), 9)])
+            .collect();
+        assert_eq!(
+            merge_sparse_tiles(counts, 10),
+            vec![(tile(0, 30), 9), (tile(30, 40), 2), (tile(40, 50), 9)]
+        );
+    }
+
+    #[test]
+    fn test_split_dense_tile() {
+        // Four items in the first quarter, and one in the last. Items that
+        // cross a boundary count on both sides
+        let items: Vec<_> = [(0, 5), (5, 10), (10, 15), (15, 25), (90, 95)]
+            .into_iter()
+            .map(|(start, stop)| Interval::new(Timestamp(start), Timestamp(stop)))
+            .collect();
+        let mut leaves = BTreeMap::new();
+        split_dense_tile(tile(0, 100), &items, 2, 2, 0, &mut leaves);
+        assert_eq!(
+            leaves.into_iter().collect::<Vec<_>>(),
+            vec![
+                (tile(0, 6), 2),
+                (tile(6, 12), 2),
+                (tile(12, 25), 2),
+                (tile(25, 50), 0),
+                (tile(50, 100), 1)
+            ]
+        );
+
+        // Splitting stops after MAX_SPLIT_ROUNDS
+        let mut leaves = BTreeMap::new();
+        split_dense_tile(tile(0, 1 << 20), &items, 2, 2, 0, &mut leaves);
+        assert_eq!(leaves.first_key_value(), Some((&tile(0, 1 << 12), &5)));
+    }
+
+    #[test]
+    fn test_adaptive_levels() {
+        // A dense startup followed by a long, quiet period
+        let finest = [
+            (tile(0, 1), 100),
+            (tile(1, 2), 100),
+            (tile(2, 3), 100),
+            (tile(3, 100), 100),
+        ];
+        let levels = adaptive_levels(&finest, 3, 2);
+        assert_eq!(
+            levels,
+            vec![
+                vec![tile(0, 100)],
+                vec![tile(0, 2), tile(2, 100)],
+                finest.iter().map(|(tile_id, _)| *tile_id).collect(),
+            ]
+        );
+
+        // Levels that would be no coarser than the finest are skipped
+        let levels = adaptive_levels(&finest[..1], 3, 2);
+        assert_eq!(levels, vec![vec![tile(0, 1)]]);
+    }
+
+    // A panel with a summary and one (empty) slot. The summary has a point
+    // per 10 ns, so that there's something to train dictionaries on
+    struct EmptyDataSource;
+
+    impl DataSource for EmptyDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+            Ok(DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "P".to_owned(),
+                    long_name: "Panel".to_owned(),
+                    summary: Some(Box::new(EntryInfo::Summary {
+                        color: Color32::BLUE,
+                    })),
+                    counters: Vec::new(),
+                    slots: vec![EntryInfo::Slot {
+                        short_name: "S".to_owned(),
+                        long_name: "Slot".to_owned(),
+                        max_rows: 1,
+                    }],
+                },
+                interval: Interval::new(Timestamp(0), Timestamp(100)),
+                tile_set: TileSet::default(),
+                field_schema: FieldSchema::new(),
+                warning_message: None,
+            })
+        }
+
+        fn fetch_summary_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SummaryTile> {
+            let (start, stop) = (tile_id.0.start.0, tile_id.0.stop.0);
+            Ok(SummaryTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SummaryTileData {
+                    utilization: (start..stop)
+                        .step_by(10)
+                        .map(|t| UtilPoint {
+                            time: Timestamp(t),
+                            util: (t % 30) as f32 / 30.0,
+                        })
+                        .collect(),
+                },
+            })
+        }
+
+        fn fetch_counter_tile(
+            &self,
+            _entry_id: &EntryID,
+            _tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<CounterTile> {
+            unreachable!()
+        }
+
+        fn fetch_slot_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SlotTile> {
+            Ok(SlotTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SlotTileData {
+                    items: vec![Vec::new()],
+                    edges: Vec::new(),
+                },
+            })
+        }
+
+        fn fetch_slot_meta_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SlotMetaTile> {
+            Ok(SlotMetaTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SlotMetaTileData {
+                    items: vec![Vec::new()],
+                },
+            })
+        }
+    }
+
+    // A panel with one slot, which has an item per ns for the first 16 ns and
+    // then one every 20 ns. Item UIDs are their start times
+    struct DenseDataSource;
+
+    impl DenseDataSource {
+        const INTERVAL: Interval = Interval {
+            start: Timestamp(0),
+            stop: Timestamp(100),
+        };
+
+        fn items(tile_id: TileID) -> impl Iterator<Item = (ItemUID, Interval)> {
+            (0..16)
+                .chain((20..100).step_by(20))
+                .map(|t| {
+                    (
+                        ItemUID(t as u64),
+                        Interval::new(Timestamp(t), Timestamp(t + 1)),
+                    )
+                })
+                .filter(move |(_, interval)| interval.overlaps(tile_id.0))
+        }
+    }
+
+    impl DataSource for DenseDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+            Ok(DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "P".to_owned(),
+                    long_name: "Panel".to_owned(),
+                    summary: None,
+                    counters: Vec::new(),
+                    slots: vec![EntryInfo::Slot {
+                        short_name: "S".to_owned(),
+                        long_name: "Slot".to_owned(),
+                        max_rows: 1,
+                    }],
+                },
+                interval: Self::INTERVAL,
+                tile_set: TileSet::default(),
+                field_schema: FieldSchema::new(),
+                warning_message: None,
+            })
+        }
+
+        fn fetch_summary_tile(
+            &self,
+            _entry_id: &EntryID,
+            _tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SummaryTile> {
+            unreachable!()
+        }
+
+        fn fetch_counter_tile(
+            &self,
+            _entry_id: &EntryID,
+            _tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<CounterTile> {
+            unreachable!()
+        }
+
+        fn fetch_slot_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SlotTile> {
+            let items = Self::items(tile_id)
+                .map(|(item_uid, interval)| Item {
+                    item_uid,
+                    interval,
+                    color: Color32::BLUE,
+                })
+                .collect();
+            Ok(SlotTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SlotTileData {
+                    items: vec![items],
+                    edges: Vec::new(),
+                },
+            })
+        }
+
+        fn fetch_slot_meta_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SlotMetaTile> {
+            let items = Self::items(tile_id)
+                .map(|(item_uid, interval)| ItemMeta {
+                    item_uid,
+                    original_interval: interval,
+                    title: format!("item {}", item_uid.0),
+                    fields: Vec::new(),
+                })
+                .collect();
+            Ok(SlotMetaTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SlotMetaTileData { items: vec![items] },
+            })
+        }
+    }
+
+    #[test]
+    fn test_adaptive_round_trip() {
+        let path = std::env::temp_dir().join(format!("adaptive_{}.lpv", std::process::id()));
+        DataSourceArchiveWriter::new(
+            DeferredDataSourceWrapper::new(DenseDataSource),
+            3,
+            2,
+            &path,
+            true,
+            1,
+        )
+        .with_format(ArchiveFormat::Container)
+        .with_tiling(TilingMode::Adaptive { items_per_tile: 4 })
+        .write()
+        .unwrap();
+
+        let archive = ContainerDataSource::new(&path);
+        let info = archive.fetch_info().unwrap();
+        assert_eq!(info.interval, DenseDataSource::INTERVAL);
+        assert!(verify_archive(&archive).unwrap().is_ok());
+
+        // The dense start gets short tiles, and the rest long ones. Each level
+        // covers the whole profile
+        let finest = info.tile_set.tiles.last().unwrap();
+        assert!(finest[0].0.duration_ns() < finest.last().unwrap().0.duration_ns());
+        for level in &info.tile_set.tiles {
+            assert_eq!(level[0].0.start, Timestamp(0));
+            assert_eq!(level.last().unwrap().0.stop, Timestamp(100));
+            assert!(level.windows(2).all(|w| w[0].0.stop == w[1].0.start));
+        }
+
+        // Every item comes back, and each tile holds about as many as asked
+        let slot = EntryID::root().child(0);
+        let mut item_uids = BTreeSet::new();
+        for tile_id in finest {
+            let tile = archive.fetch_slot_meta_tile(&slot, *tile_id, true).unwrap();
+            let items: Vec<_> = tile.data.items.iter().flatten().collect();
+            assert!(
+                items.len() <= 4,
+                "{:?} holds {} items",
+                tile_id,
+                items.len()
+            );
+            item_uids.extend(items.iter().map(|item| item.item_uid.0));
+        }
+        let expected: BTreeSet<_> = DenseDataSource::items(TileID(DenseDataSource::INTERVAL))
+            .map(|(item_uid, _)| item_uid.0)
+            .collect();
+        assert_eq!(item_uids, expected);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(container_manifest_path(&path)).unwrap();
+    }
+
+    // Writes a directory archive with two levels, for a test to damage
+    fn write_test_archive(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let writer = DataSourceArchiveWriter::new(
+            DeferredDataSourceWrapper::new(EmptyDataSource),
+            2,
+            2,
+            &path,
+            true,
+            1,
+        );
+        writer.write().unwrap();
+
+        let report = verify_archive(&FileDataSource::new(&path)).unwrap();
+        // Two entries (summary and slot, which has two kinds) over three tiles
+        assert_eq!(report.tiles_checked, 9);
+        assert!(report.is_ok());
+        path
+    }
+
+    // Checks that the one problem found is the expected one, and that
+    // repairing it leaves a valid archive
+    fn verify_and_repair(path: &Path, check: impl Fn(TileKind, TileID, &TileProblem) -> bool) {
+        let archive = FileDataSource::new(path);
+        let report = verify_archive(&archive).unwrap();
+        let [(req, problem)] = &report.problems[..] else {
+            panic!("expected one problem, found {:?}", report.problems);
+        };
+        assert!(check(req.kind, req.tile_id, problem), "{:?}", problem);
+
+        repair_archive(
+            path,
+            &report,
+            DeferredDataSourceWrapper::new(EmptyDataSource),
+            1,
+        )
+        .unwrap();
+        assert!(verify_archive(&archive).unwrap().is_ok());
+
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_repair_missing() {
+        let path = write_test_archive("repair_missing");
+        let slot = EntryID::root().child(0);
+        let key = container::tile_key(TileKind::Slot, &slot, tile(0, 50));
+        std::fs::remove_file(path.join(key)).unwrap();
+
+        verify_and_repair(&path, |kind, tile_id, problem| {
+            (kind, tile_id, problem) == (TileKind::Slot, tile(0, 50), &TileProblem::Missing)
+        });
+    }
+
+    #[test]
+    fn test_repair_corrupt() {
+        let path = write_test_archive("repair_corrupt");
+        let slot = EntryID::root().child(0);
+        let key = container::tile_key(TileKind::SlotMeta, &slot, tile(50, 100));
+        std::fs::write(path.join(key), b"garbage").unwrap();
+
+        verify_and_repair(&path, |kind, tile_id, problem| {
+            kind == TileKind::SlotMeta
+                && tile_id == tile(50, 100)
+                && matches!(problem, TileProblem::Corrupt(_))
+        });
+    }
+
+    #[test]
+    fn test_repair_mismatched() {
+        let path = write_test_archive("repair_mismatched");
+        // Without the manifest's checksums, a tile copied over another can
+        // only be caught by its contents
+        std::fs::remove_file(path.join(MANIFEST_KEY)).unwrap();
+        let summary = EntryID::root().summary();
+        let key = |tile_id| path.join(container::tile_key(TileKind::Summary, &summary, tile_id));
+        std::fs::copy(key(tile(0, 50)), key(tile(0, 100))).unwrap();
+
+        let mismatched = TileProblem::Mismatched {
+            entry_id: summary.clone(),
+            tile_id: tile(0, 50),
+        };
+        verify_and_repair(&path, |kind, tile_id, problem| {
+            (kind, tile_id, problem) == (TileKind::Summary, tile(0, 100), &mismatched)
+        });
+    }
+
+    #[test]
+    fn test_write_error() {
+        // The directory doesn't exist, so the write fails
+        let path = std::env::temp_dir().join(format!("write_error_{}", std::process::id()));
+        let manifest_path = path.with_extension("manifest");
+        let sink = ArchiveSink::Directory {
+            path,
+            manifest: open_manifest(&manifest_path).unwrap(),
+        };
+        let error = WriteError::default();
+        rayon::in_place_scope(|s| {
+            let compression = Compression::Plain(1);
+            spawn_write(&sink, INFO_KEY.to_owned(), 1, &compression, &error, s);
+        });
+        let e = check_writes(&error).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        // The error is only reported once
+        assert!(check_writes(&error).is_ok());
+
+        std::fs::remove_file(&manifest_path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_tile() {
+        let path = std::env::temp_dir().join(format!("truncated_archive_{}", std::process::id()));
+        let writer = DataSourceArchiveWriter::new(
+            DeferredDataSourceWrapper::new(EmptyDataSource),
+            1,
+            2,
+            &path,
+            true,
+            1,
+        );
+        writer.write().unwrap();
+
+        // Simulate an upload that was cut short
+        let slot = EntryID::root().child(0);
+        let tile_path = path.join(container::tile_key(TileKind::Slot, &slot, tile(0, 100)));
+        let blob = std::fs::read(&tile_path).unwrap();
+        std::fs::write(&tile_path, &blob[..blob.len() - 1]).unwrap();
+
+        let archive = FileDataSource::new(&path);
+        let result = archive.fetch_slot_tile(&slot, tile(0, 100), false);
+        let Err(DataSourceError::Decode(message)) = result else {
+            panic!("truncated tile was not detected");
+        };
+        assert!(message.contains("is corrupt"));
+        assert!(archive.fetch_info().is_ok());
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_viewer() {
+        let dir = std::env::temp_dir().join(format!("viewer_archive_{}", std::process::id()));
+        let dist = dir.join("dist");
+        create_dir_all(dist.join("assets")).unwrap();
+        std::fs::write(dist.join("index.html"), "viewer").unwrap();
+        std::fs::write(dist.join("assets").join("viewer.wasm"), "wasm").unwrap();
+
+        let writer = |viewer: &Path| {
+            DataSourceArchiveWriter::new(
+                DeferredDataSourceWrapper::new(EmptyDataSource),
+                1,
+                2,
+                dir.join("archive"),
+                true,
+                1,
+            )
+            .with_viewer(ViewerLocation::Bundled(viewer.to_owned()))
+        };
+        assert!(writer(&dir.join("missing")).write().is_err());
+        writer(&dist).write().unwrap();
+
+        let archive = dir.join("archive");
+        let bundled = archive.join(VIEWER_DIR);
+        assert_eq!(
+            std::fs::read_to_string(bundled.join("index.html")).unwrap(),
+            "viewer"
+        );
+        assert!(bundled.join("assets").join("viewer.wasm").is_file());
+        let index = std::fs::read_to_string(archive.join("index.html")).unwrap();
+        assert!(index.contains("\"viewer/\"+\"?url=\""));
+        assert!(!index.contains("https:"));
+
+        let index = index_html("http://viewer.internal/");
+        assert!(index.contains("\"http://viewer.internal/\"+\"?url=\""));
+        assert!(!index.contains("https:"));
+        assert!(index_html(DEFAULT_VIEWER_URL).contains("location.protocol !== 'https:'"));
+        let index = index_html("http://viewer.internal/</script><script>alert(\"hi\")//");
+        assert!(index.contains(
+            "\"http://viewer.internal/\\u003c/script>\\u003cscript>alert(\\\"hi\\\")//\"+\"?url=\""
+        ));
+        assert_eq!(index.matches("</script>").count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume() {
+        let path = std::env::temp_dir().join(format!("resume_archive_{}.lpv", std::process::id()));
+        let writer = |resume| {
+            DataSourceArchiveWriter::new(
+                DeferredDataSourceWrapper::new(EmptyDataSource),
+                2,
+                2,
+                &path,
+                true,
+                1,
+            )
+            .with_format(ArchiveFormat::Container)
+            .with_resume(resume)
+        };
+        writer(false).write().unwrap();
+
+        // Pretend the write was interrupted after the first few blobs, in the
+        // middle of a manifest line
+        let manifest = manifest_path(&path, ArchiveFormat::Container);
+        let contents = std::fs::read_to_string(&manifest).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 10);
+        let partial = format!("{}\n{}", lines[..4].join("\n"), &lines[4][..5]);
+        std::fs::write(&manifest, partial).unwrap();
+
+        writer(true).write().unwrap();
+        let report = verify_archive(&ContainerDataSource::new(&path)).unwrap();
+        assert_eq!(report.tiles_checked, 9);
+        assert!(report.is_ok());
+        let contents = std::fs::read_to_string(&manifest).unwrap();
+        assert_eq!(contents.lines().count(), 10);
+        assert!(contents.starts_with(&lines[..4].join("\n")));
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&manifest).unwrap();
+    }
+
+    #[test]
+    fn test_interrupted_resume() {
+        let path =
+            std::env::temp_dir().join(format!("interrupted_archive_{}.lpv", std::process::id()));
+        let writer = |resume| {
+            DataSourceArchiveWriter::new(
+                DeferredDataSourceWrapper::new(EmptyDataSource),
+                2,
+                2,
+                &path,
+                true,
+                1,
+            )
+            .with_format(ArchiveFormat::Container)
+            .with_resume(resume)
+        };
+        writer(false).write().unwrap();
+
+        // Start resuming a write that lost the end of its manifest, and stop
+        // after a blob, before the new table of contents is written
+        let manifest = manifest_path(&path, ArchiveFormat::Container);
+        let contents = std::fs::read_to_string(&manifest).unwrap();
+        let lines: Vec<_> = contents.lines().take(4).collect();
+        std::fs::write(&manifest, lines.join("\n") + "\n").unwrap();
+        let (mut resumed, _) = resume_manifest(&manifest).unwrap();
+        let mut container = resume_container(&path, &mut resumed).unwrap();
+        container.append("extra".to_owned(), b"blob").unwrap();
+        container.flush().unwrap();
+        drop(container);
+
+        // The old table of contents is still there
+        let report = verify_archive(&ContainerDataSource::new(&path)).unwrap();
+        assert_eq!(report.tiles_checked, 9);
+        assert!(report.is_ok());
+
+        // And the write can still be resumed
+        writer(true).write().unwrap();
+        let report = verify_archive(&ContainerDataSource::new(&path)).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(
+            std::fs::read_to_string(&manifest).unwrap().lines().count(),
+            10
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&manifest).unwrap();
+    }
+
+    #[test]
+    fn test_overwrite_container() {
+        let path =
+            std::env::temp_dir().join(format!("overwrite_archive_{}.lpv", std::process::id()));
+        let write = || {
+            DataSourceArchiveWriter::new(
+                DeferredDataSourceWrapper::new(EmptyDataSource),
+                2,
+                2,
+                &path,
+                true,
+                1,
+            )
+            .with_format(ArchiveFormat::Container)
+            .write()
+        };
+        write().unwrap();
+        write().unwrap();
+
+        let manifest = manifest_path(&path, ArchiveFormat::Container);
+        assert_eq!(
+            std::fs::read_to_string(&manifest).unwrap().lines().count(),
+            10
+        );
+        let report = verify_archive(&ContainerDataSource::new(&path)).unwrap();
+        assert!(report.is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&manifest).unwrap();
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let dir = std::env::temp_dir().join(format!("dictionary_archive_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        for format in [ArchiveFormat::Directory, ArchiveFormat::Container] {
+            let path = match format {
+                ArchiveFormat::Directory => dir.join("archive"),
+                ArchiveFormat::Container => dir.join("archive.lpv"),
+            };
+            DataSourceArchiveWriter::new(
+                DeferredDataSourceWrapper::new(EmptyDataSource),
+                5,
+                2,
+                &path,
+                true,
+                1,
+            )
+            .with_format(format)
+            .with_dictionary(Some(1024))
+            .write()
+            .unwrap();
+
+            let report = match format {
+                ArchiveFormat::Directory => {
+                    assert!(path.join(DICTIONARY_KEY).is_file());
+                    verify_archive(&FileDataSource::new(&path)).unwrap()
+                }
+                ArchiveFormat::Container => {
+                    verify_archive(&ContainerDataSource::new(&path)).unwrap()
+                }
+            };
+            assert!(report.is_ok());
+
+            let summary = EntryID::root().summary();
+            let archive = FileDataSource::new(dir.join("archive"));
+            let result = archive.fetch_summary_tile(&summary, tile(0, 100), false);
+            assert_eq!(result.unwrap().data.utilization.len(), 10);
+        }
+
+        // Tiles can't be read without the dictionary
+        let path = dir.join("archive");
+        std::fs::remove_file(path.join(DICTIONARY_KEY)).unwrap();
+        std::fs::remove_file(path.join(MANIFEST_KEY)).unwrap();
+        let summary = EntryID::root().summary();
+        let archive = FileDataSource::new(&path);
+        assert!(archive.fetch_info().is_ok());
+        assert!(archive
+            .fetch_summary_tile(&summary, tile(0, 100), false)
+            .is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
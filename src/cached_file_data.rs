@@ -0,0 +1,337 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::container::{self, BlobRange, ContainerToc, DICTIONARY_KEY, INFO_KEY, PREFIX_SIZE};
+use crate::data::{
+    CounterTile, DataSource, DataSourceDescription, DataSourceError, DataSourceInfo,
+    DataSourceResult, EntryID, SearchQuery, SearchResults, SlotMetaTile, SlotTile, SummaryTile,
+    Tile, TileID, TileKind,
+};
+use crate::deferred_data::{CacheStats, TileCache};
+use crate::file_io::{self, io_error, read_optional};
+use crate::format::{self, Decompressor};
+use crate::manifest::{container_manifest_path, Manifest, MANIFEST_KEY};
+use crate::search;
+
+// Reads either kind of archive (a directory or a single file), for servers
+// and tools that read many tiles from the same archive. Compared to
+// FileDataSource and ContainerDataSource, it:
+//
+//   * reads single-file archives with positional reads, so that threads
+//     don't take turns seeking and reading,
+//   * reuses zstd contexts (with the archive's dictionary already loaded)
+//     from one tile to the next, and
+//   * keeps recently decoded tiles in memory, so that tiles requested again
+//     aren't decoded again.
+pub struct CachedFileDataSource {
+    pub path: PathBuf,
+    // Failure to open the archive is reported on every fetch
+    state: DataSourceResult<Archive>,
+    cache: Mutex<TileCache>,
+    // Idle decompressors, one per thread that has fetched concurrently
+    decompressors: Mutex<Vec<Decompressor>>,
+}
+
+pub const DEFAULT_CACHE_CAPACITY: usize = 256 << 20;
+
+enum Blobs {
+    Directory,
+    Container { file: File, toc: ContainerToc },
+}
+
+struct Archive {
+    blobs: Blobs,
+    // Single-file archives keep theirs in a sidecar file, which may not have
+    // been copied along with them, in which case nothing is checked
+    manifest: Manifest,
+    // Empty if the archive doesn't have one
+    dictionary: Vec<u8>,
+}
+
+fn read_range(path: &Path, file: &File, range: BlobRange) -> DataSourceResult<Vec<u8>> {
+    file_io::read_at(file, range).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => DataSourceError::Decode(e.to_string()),
+        _ => io_error(path, e),
+    })
+}
+
+impl Archive {
+    fn open(path: &Path) -> DataSourceResult<Self> {
+        if path.is_dir() {
+            let manifest = read_optional(&path.join(MANIFEST_KEY))?;
+            let manifest = Manifest::parse(&String::from_utf8_lossy(&manifest))?;
+            let dictionary = read_optional(&path.join(DICTIONARY_KEY))?;
+            manifest.check(DICTIONARY_KEY, &dictionary)?;
+            return Ok(Self {
+                blobs: Blobs::Directory,
+                manifest,
+                dictionary,
+            });
+        }
+
+        // Archives may be repaired or rewritten while they're open. (Not
+        // memory-mapped, because a mapped file that shrinks crashes the
+        // process.)
+        let file = File::open(path).map_err(|e| io_error(path, e))?;
+        let prefix = BlobRange {
+            offset: 0,
+            length: PREFIX_SIZE,
+        };
+        // Too short to be an archive
+        let prefix = match read_range(path, &file, prefix) {
+            Err(DataSourceError::Decode(_)) => Vec::new(),
+            result => result?,
+        };
+        let toc_range = container::decode_prefix(&prefix)?;
+        let toc: ContainerToc = format::read(&read_range(path, &file, toc_range)?[..], "archive")?;
+        let dictionary = match toc.get(DICTIONARY_KEY) {
+            Ok(range) => read_range(path, &file, range)?,
+            Err(_) => Vec::new(),
+        };
+        let manifest = read_optional(&container_manifest_path(path))?;
+        let manifest = Manifest::parse(&String::from_utf8_lossy(&manifest))?;
+        manifest.check(DICTIONARY_KEY, &dictionary)?;
+        Ok(Self {
+            blobs: Blobs::Container { file, toc },
+            manifest,
+            dictionary,
+        })
+    }
+
+    fn blob(&self, path: &Path, key: &str) -> DataSourceResult<Vec<u8>> {
+        let blob = match &self.blobs {
+            Blobs::Directory => {
+                let path = path.join(key);
+                std::fs::read(&path).map_err(|e| io_error(&path, e))?
+            }
+            Blobs::Container { file, toc } => read_range(path, file, toc.get(key)?)?,
+        };
+        self.manifest.check(key, &blob)?;
+        Ok(blob)
+    }
+}
+
+fn decode_tile(
+    decompressor: &mut Decompressor,
+    kind: TileKind,
+    blob: &[u8],
+) -> DataSourceResult<Tile> {
+    Ok(match kind {
+        TileKind::Summary => Tile::Summary(decompressor.read(blob, "archive")?),
+        TileKind::Counter => Tile::Counter(decompressor.read(blob, "archive")?),
+        TileKind::Slot => Tile::Slot(decompressor.read(blob, "archive")?),
+        TileKind::SlotMeta => Tile::SlotMeta(decompressor.read(blob, "archive")?),
+    })
+}
+
+impl CachedFileDataSource {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self::with_cache_capacity(path, DEFAULT_CACHE_CAPACITY)
+    }
+
+    // The capacity is the (approximate) size of the decoded tiles, in bytes
+    pub fn with_cache_capacity(path: impl AsRef<Path>, capacity: usize) -> Self {
+        let path = path.as_ref().to_owned();
+        let state = Archive::open(&path);
+        Self {
+            path,
+            state,
+            cache: Mutex::new(TileCache::new(capacity)),
+            decompressors: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+
+    fn read_tile(
+        &self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tile_id: TileID,
+    ) -> DataSourceResult<Tile> {
+        // Archived tiles are the same whether or not they're full
+        let cache_key = (kind, entry_id.clone(), tile_id, false);
+        if let Some(tile) = self.cache.lock().unwrap().get(&cache_key) {
+            return Ok(tile);
+        }
+
+        let archive = self.state.as_ref().map_err(Clone::clone)?;
+        let blob = archive.blob(&self.path, &container::tile_key(kind, entry_id, tile_id))?;
+
+        // Decode outside of the lock, so that threads don't wait on each other
+        let decompressor = self.decompressors.lock().unwrap().pop();
+        let mut decompressor = match decompressor {
+            Some(decompressor) => decompressor,
+            None => Decompressor::with_dictionary(&archive.dictionary)?,
+        };
+        let result = decode_tile(&mut decompressor, kind, &blob);
+        self.decompressors.lock().unwrap().push(decompressor);
+
+        let tile = result?;
+        self.cache.lock().unwrap().insert(cache_key, tile.clone());
+        Ok(tile)
+    }
+}
+
+impl DataSource for CachedFileDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![String::from(self.path.to_string_lossy())],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+        let archive = self.state.as_ref().map_err(Clone::clone)?;
+        format::read(&archive.blob(&self.path, INFO_KEY)?[..], "archive")
+    }
+
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SummaryTile> {
+        let Tile::Summary(tile) = self.read_tile(TileKind::Summary, entry_id, tile_id)? else {
+            unreachable!()
+        };
+        Ok(tile)
+    }
+
+    fn fetch_counter_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<CounterTile> {
+        let Tile::Counter(tile) = self.read_tile(TileKind::Counter, entry_id, tile_id)? else {
+            unreachable!()
+        };
+        Ok(tile)
+    }
+
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotTile> {
+        let Tile::Slot(tile) = self.read_tile(TileKind::Slot, entry_id, tile_id)? else {
+            unreachable!()
+        };
+        Ok(tile)
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotMetaTile> {
+        let Tile::SlotMeta(tile) = self.read_tile(TileKind::SlotMeta, entry_id, tile_id)? else {
+            unreachable!()
+        };
+        Ok(tile)
+    }
+
+    fn search(&self, query: &SearchQuery) -> DataSourceResult<SearchResults> {
+        search::scan_slot_meta_tiles(self, query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::container::ContainerWriter;
+    use crate::data::{SummaryTileData, UtilPoint};
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn tile() -> SummaryTile {
+        SummaryTile {
+            entry_id: EntryID::root().child(0).summary(),
+            tile_id: TileID(Interval::new(Timestamp(0), Timestamp(10))),
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: Timestamp(5),
+                    util: 0.5,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn test_container() {
+        let path = std::env::temp_dir().join(format!("cached_archive_{}.lpv", std::process::id()));
+        let t = tile();
+        let key = container::tile_key(TileKind::Summary, &t.entry_id, t.tile_id);
+        let file = File::create(&path).unwrap();
+        let mut writer = ContainerWriter::new(file).unwrap();
+        writer
+            .append(key, &format::write(Vec::new(), &t, 1).unwrap())
+            .unwrap();
+        writer.finish(1).unwrap();
+
+        let archive = CachedFileDataSource::new(&path);
+        for _ in 0..2 {
+            let result = archive.fetch_summary_tile(&t.entry_id, t.tile_id, true);
+            assert_eq!(result.unwrap().data.utilization, t.data.utilization);
+        }
+        let stats = archive.cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.tiles), (1, 1, 1));
+
+        let missing = archive.fetch_summary_tile(&EntryID::root().summary(), t.tile_id, false);
+        assert!(matches!(missing, Err(DataSourceError::NotFound(_))));
+        assert!(matches!(
+            archive.fetch_info(),
+            Err(DataSourceError::NotFound(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_container() {
+        let path =
+            std::env::temp_dir().join(format!("truncated_cached_{}.lpv", std::process::id()));
+        let t = tile();
+        let key = container::tile_key(TileKind::Summary, &t.entry_id, t.tile_id);
+        let mut writer = ContainerWriter::new(File::create(&path).unwrap()).unwrap();
+        let range = writer
+            .append(key, &format::write(Vec::new(), &t, 1).unwrap())
+            .unwrap();
+        writer.finish(1).unwrap();
+
+        // The file shrinks while it's open, as when it's rewritten in place
+        let archive = CachedFileDataSource::new(&path);
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(range.offset + 1).unwrap();
+        let result = archive.fetch_summary_tile(&t.entry_id, t.tile_id, false);
+        assert!(matches!(result, Err(DataSourceError::Decode(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_directory() {
+        let path = std::env::temp_dir().join(format!("cached_archive_{}", std::process::id()));
+        let t = tile();
+        let key = container::tile_key(TileKind::Summary, &t.entry_id, t.tile_id);
+        std::fs::create_dir_all(path.join(&key).parent().unwrap()).unwrap();
+        std::fs::write(path.join(&key), b"garbage").unwrap();
+
+        // Failures aren't cached
+        let archive = CachedFileDataSource::new(&path);
+        assert!(archive
+            .fetch_summary_tile(&t.entry_id, t.tile_id, false)
+            .is_err());
+        std::fs::write(path.join(&key), format::write(Vec::new(), &t, 1).unwrap()).unwrap();
+        let result = archive.fetch_summary_tile(&t.entry_id, t.tile_id, false);
+        assert_eq!(result.unwrap().tile_id, t.tile_id);
+        assert_eq!(archive.cache_stats().tiles, 1);
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
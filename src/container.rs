@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{DataSourceError, DataSourceResult, EntryID, Tile, TileID, TileKind};
+use crate::format;
+use crate::http::schema::TileRequestRef;
+
+// A single-file archive. The file starts with a fixed-size prefix that
+// locates the table of contents, followed by the same blobs (a FormatHeader
+// and a zstd-compressed CBOR payload) that a directory archive stores as
+// separate files. The table of contents is itself a blob, written last, and
+// maps the path each blob would have in a directory archive (e.g., "info" or
+// "slot_tile/<entry>/<tile>") to its location in the file.
+//
+// Since every blob can be located with the table of contents, the file can
+// be read with random access, either locally or with HTTP range requests.
+
+pub const CONTAINER_MAGIC: [u8; 8] = *b"LPVARCH1";
+
+// The web viewer uses this to tell single-file archives apart from servers
+// and directory archives
+pub const CONTAINER_EXTENSION: &str = "lpv";
+
+// Magic, followed by the offset and length of the table of contents (as
+// little-endian u64s)
+pub const PREFIX_SIZE: u64 = 24;
+
+pub const INFO_KEY: &str = "info";
+
+// The zstd dictionary that tiles are compressed with, if the archive has one
+// (stored as is, not as a FormatHeader and payload)
+pub const DICTIONARY_KEY: &str = "dictionary";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct BlobRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ContainerToc {
+    pub blobs: BTreeMap<String, BlobRange>,
+}
+
+pub struct ContainerWriter<W: Write + Seek> {
+    writer: W,
+    offset: u64,
+    toc: ContainerToc,
+}
+
+impl BlobRange {
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+
+    // The HTTP Range header value for this blob (ranges are inclusive)
+    pub fn to_header(&self) -> String {
+        format!("bytes={}-{}", self.offset, self.end().saturating_sub(1))
+    }
+}
+
+impl ContainerToc {
+    pub fn get(&self, key: &str) -> DataSourceResult<BlobRange> {
+        self.blobs
+            .get(key)
+            .copied()
+            .ok_or_else(|| DataSourceError::NotFound(key.to_owned()))
+    }
+}
+
+pub fn tile_key(kind: TileKind, entry_id: &EntryID, tile_id: TileID) -> String {
+    let dir = match kind {
+        TileKind::Summary => "summary_tile",
+        TileKind::Counter => "counter_tile",
+        TileKind::Slot => "slot_tile",
+        TileKind::SlotMeta => "slot_meta_tile",
+    };
+    format!("{}/{}", dir, TileRequestRef { entry_id, tile_id }.to_slug())
+}
+
+pub fn encode_prefix(toc: BlobRange) -> [u8; PREFIX_SIZE as usize] {
+    let mut prefix = [0; PREFIX_SIZE as usize];
+    prefix[..8].copy_from_slice(&CONTAINER_MAGIC);
+    prefix[8..16].copy_from_slice(&toc.offset.to_le_bytes());
+    prefix[16..].copy_from_slice(&toc.length.to_le_bytes());
+    prefix
+}
+
+// Returns the location of the table of contents
+pub fn decode_prefix(prefix: &[u8]) -> DataSourceResult<BlobRange> {
+    if prefix.len() < PREFIX_SIZE as usize || prefix[..8] != CONTAINER_MAGIC {
+        return Err(DataSourceError::Decode(
+            "archive is not a single-file archive (bad magic number)".to_owned(),
+        ));
+    }
+    let offset = u64::from_le_bytes(prefix[8..16].try_into().unwrap());
+    let length = u64::from_le_bytes(prefix[16..24].try_into().unwrap());
+    if length == 0 {
+        return Err(DataSourceError::Decode(
+            "single-file archive is incomplete (no table of contents)".to_owned(),
+        ));
+    }
+    Ok(BlobRange { offset, length })
+}
+
+// The dictionary is empty if the archive doesn't have one
+pub fn read_tile(kind: TileKind, blob: &[u8], dictionary: &[u8]) -> DataSourceResult<Tile> {
+    use format::read_with_dictionary as read;
+    Ok(match kind {
+        TileKind::Summary => Tile::Summary(read(blob, "archive", dictionary)?),
+        TileKind::Counter => Tile::Counter(read(blob, "archive", dictionary)?),
+        TileKind::Slot => Tile::Slot(read(blob, "archive", dictionary)?),
+        TileKind::SlotMeta => Tile::SlotMeta(read(blob, "archive", dictionary)?),
+    })
+}
+
+impl<W: Write + Seek> ContainerWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        // The prefix is filled in by finish, so until then the file reads as
+        // incomplete
+        writer.write_all(&[0; PREFIX_SIZE as usize])?;
+        Ok(Self {
+            writer,
+            offset: PREFIX_SIZE,
+            toc: ContainerToc::default(),
+        })
+    }
+
+    fn write_blob(&mut self, blob: &[u8]) -> io::Result<BlobRange> {
+        self.writer.write_all(blob)?;
+        let range = BlobRange {
+            offset: self.offset,
+            length: blob.len() as u64,
+        };
+        self.offset += range.length;
+        Ok(range)
+    }
+
+    // Reopens a finished container (with the given table of contents) to add
+    // or replace blobs. The new blobs and table of contents go at the end,
+    // and the old ones are left in place (unreferenced), so that the file
+    // stays valid if this is interrupted.
+    pub fn reopen(mut writer: W, toc: ContainerToc) -> io::Result<Self> {
+        let offset = writer.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            writer,
+            offset,
+            toc,
+        })
+    }
+
+    pub fn append(&mut self, key: String, blob: &[u8]) -> io::Result<BlobRange> {
+        let range = self.write_blob(blob)?;
+        self.toc.blobs.insert(key, range);
+        Ok(range)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn finish(mut self, zstd_compression: i32) -> io::Result<W> {
+        let toc = format::write(Vec::new(), &self.toc, zstd_compression)?;
+        let range = self.write_blob(&toc)?;
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&encode_prefix(range))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub fn read_blob<R: Read + Seek>(reader: &mut R, range: BlobRange) -> io::Result<Vec<u8>> {
+    // Check first, so that a corrupt table of contents can't make us
+    // allocate more than the file holds
+    let len = reader.seek(SeekFrom::End(0))?;
+    if range
+        .offset
+        .checked_add(range.length)
+        .map_or(true, |end| end > len)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "single-file archive is truncated",
+        ));
+    }
+    let mut blob = vec![0; range.length as usize];
+    reader.seek(SeekFrom::Start(range.offset))?;
+    reader.read_exact(&mut blob)?;
+    Ok(blob)
+}
+
+// Reads the table of contents of a finished container
+pub fn read_toc<R: Read + Seek>(reader: &mut R) -> DataSourceResult<ContainerToc> {
+    let prefix = read_blob(
+        reader,
+        BlobRange {
+            offset: 0,
+            length: PREFIX_SIZE,
+        },
+    )?;
+    let toc_range = decode_prefix(&prefix)?;
+    let blob = read_blob(reader, toc_range)?;
+    format::read(&blob[..], "archive")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::data::{SummaryTile, SummaryTileData, UtilPoint};
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn tile(start: i64) -> SummaryTile {
+        SummaryTile {
+            entry_id: EntryID::root().child(0).summary(),
+            tile_id: TileID(Interval::new(Timestamp(start), Timestamp(start + 10))),
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: Timestamp(start),
+                    util: 1.0,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        for start in [0, 10] {
+            let t = tile(start);
+            let key = tile_key(TileKind::Summary, &t.entry_id, t.tile_id);
+            writer
+                .append(key, &format::write(Vec::new(), &t, 1).unwrap())
+                .unwrap();
+        }
+        let buf = writer.finish(1).unwrap().into_inner();
+
+        let toc_range = decode_prefix(&buf[..PREFIX_SIZE as usize]).unwrap();
+        let toc_blob = &buf[toc_range.offset as usize..toc_range.end() as usize];
+        let toc: ContainerToc = format::read(toc_blob, "archive").unwrap();
+        assert_eq!(toc.blobs.len(), 2);
+
+        let t = tile(10);
+        let range = toc
+            .get(&tile_key(TileKind::Summary, &t.entry_id, t.tile_id))
+            .unwrap();
+        let blob = &buf[range.offset as usize..range.end() as usize];
+        let Tile::Summary(result) = read_tile(TileKind::Summary, blob, &[]).unwrap() else {
+            panic!("wrong tile kind");
+        };
+        assert_eq!(result.tile_id, t.tile_id);
+        assert_eq!(result.data.utilization, t.data.utilization);
+
+        assert!(matches!(
+            toc.get(INFO_KEY),
+            Err(DataSourceError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_reopen() {
+        let t = tile(0);
+        let key = tile_key(TileKind::Summary, &t.entry_id, t.tile_id);
+        let blob = format::write(Vec::new(), &t, 1).unwrap();
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.append(key.clone(), &blob).unwrap();
+        let mut cursor = writer.finish(1).unwrap();
+
+        let toc = read_toc(&mut cursor).unwrap();
+        let mut writer = ContainerWriter::reopen(cursor, toc).unwrap();
+        writer.append(INFO_KEY.to_owned(), &blob).unwrap();
+        let buf = writer.finish(1).unwrap().into_inner();
+
+        let toc_range = decode_prefix(&buf).unwrap();
+        let toc_blob = &buf[toc_range.offset as usize..toc_range.end() as usize];
+        let toc: ContainerToc = format::read(toc_blob, "archive").unwrap();
+        assert_eq!(toc.blobs.len(), 2);
+        assert!(toc.get(INFO_KEY).unwrap().offset > toc.get(&key).unwrap().offset);
+    }
+
+    #[test]
+    fn test_read_blob_out_of_range() {
+        let mut cursor = Cursor::new(vec![0; 32]);
+        let range = |offset, length| BlobRange { offset, length };
+        assert_eq!(read_blob(&mut cursor, range(8, 24)).unwrap().len(), 24);
+        // A corrupt table of contents can't make us allocate (or read) more
+        // than the file holds
+        for range in [range(8, 25), range(0, u64::MAX), range(u64::MAX, 2)] {
+            let e = read_blob(&mut cursor, range).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        // Dropped without calling finish
+        let buf = writer.writer.into_inner();
+        assert!(matches!(
+            decode_prefix(&buf),
+            Err(DataSourceError::Decode(_))
+        ));
+        assert_eq!(
+            BlobRange {
+                offset: 24,
+                length: 8
+            }
+            .to_header(),
+            "bytes=24-31"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::container::{self, BlobRange, ContainerToc, DICTIONARY_KEY, INFO_KEY, PREFIX_SIZE};
+use crate::data::{
+    CounterTile, DataSource, DataSourceDescription, DataSourceInfo, DataSourceResult, EntryID,
+    SearchQuery, SearchResults, SlotMetaTile, SlotTile, SummaryTile, TileID, TileKind,
+};
+use crate::file_io::{self, io_error, read_optional};
+use crate::format;
+use crate::manifest::{container_manifest_path, Manifest};
+use crate::search;
+
+// Reads a single-file archive (see container.rs), the counterpart of
+// FileDataSource for directory archives
+pub struct ContainerDataSource {
+    pub path: PathBuf,
+    // Failure to open the file is reported on every fetch
+    state: DataSourceResult<Container>,
+}
+
+struct Container {
+    // Read with positional reads, so that threads don't wait on each other
+    file: File,
+    toc: ContainerToc,
+    // Kept in a sidecar file, which may not have been copied along with the
+    // archive, in which case nothing is checked (as in FileDataSource)
+    manifest: Manifest,
+    // Empty if the archive doesn't have one
+    dictionary: Vec<u8>,
+}
+
+impl ContainerDataSource {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_owned();
+        let state = Self::open(&path);
+        Self { path, state }
+    }
+
+    fn open(path: &Path) -> DataSourceResult<Container> {
+        let file = File::open(path).map_err(|e| io_error(path, e))?;
+        let prefix = file_io::read_at(
+            &file,
+            BlobRange {
+                offset: 0,
+                length: PREFIX_SIZE,
+            },
+        )
+        .map_err(|e| io_error(path, e))?;
+        let toc_range = container::decode_prefix(&prefix)?;
+        let toc_blob = file_io::read_at(&file, toc_range).map_err(|e| io_error(path, e))?;
+        let toc: ContainerToc = format::read(&toc_blob[..], "archive")?;
+        let manifest = read_optional(&container_manifest_path(path))?;
+        let manifest = Manifest::parse(&String::from_utf8_lossy(&manifest))?;
+        let dictionary = match toc.get(DICTIONARY_KEY) {
+            Ok(range) => file_io::read_at(&file, range).map_err(|e| io_error(path, e))?,
+            Err(_) => Vec::new(),
+        };
+        manifest.check(DICTIONARY_KEY, &dictionary)?;
+        Ok(Container {
+            file,
+            toc,
+            manifest,
+            dictionary,
+        })
+    }
+
+    fn read_blob(&self, key: &str) -> DataSourceResult<Vec<u8>> {
+        let container = self.state.as_ref().map_err(Clone::clone)?;
+        let range = container.toc.get(key)?;
+        let blob = file_io::read_at(&container.file, range).map_err(|e| io_error(&self.path, e))?;
+        container.manifest.check(key, &blob)?;
+        Ok(blob)
+    }
+
+    fn read_tile<T>(
+        &self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tile_id: TileID,
+    ) -> DataSourceResult<T>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let blob = self.read_blob(&container::tile_key(kind, entry_id, tile_id))?;
+        let container = self.state.as_ref().map_err(Clone::clone)?;
+        format::read_with_dictionary(&blob[..], "archive", &container.dictionary)
+    }
+}
+
+impl DataSource for ContainerDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![String::from(self.path.to_string_lossy())],
+        }
+    }
+
+    fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+        let blob = self.read_blob(INFO_KEY)?;
+        format::read(&blob[..], "archive")
+    }
+
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SummaryTile> {
+        self.read_tile(TileKind::Summary, entry_id, tile_id)
+    }
+
+    fn fetch_counter_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<CounterTile> {
+        self.read_tile(TileKind::Counter, entry_id, tile_id)
+    }
+
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotTile> {
+        self.read_tile(TileKind::Slot, entry_id, tile_id)
+    }
+
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotMetaTile> {
+        self.read_tile(TileKind::SlotMeta, entry_id, tile_id)
+    }
+
+    fn search(&self, query: &SearchQuery) -> DataSourceResult<SearchResults> {
+        search::scan_slot_meta_tiles(self, query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::container::ContainerWriter;
+    use crate::data::{
+        DataSourceError, EntryInfo, FieldSchema, SummaryTileData, TileSet, UtilPoint,
+    };
+    use crate::manifest::ManifestEntry;
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn write_container(path: &Path) -> SummaryTile {
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: None,
+                counters: Vec::new(),
+                slots: Vec::new(),
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(10)),
+            tile_set: TileSet::default(),
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+        };
+        let tile = SummaryTile {
+            entry_id: EntryID::root().summary(),
+            tile_id: TileID(info.interval),
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: Timestamp(5),
+                    util: 0.5,
+                }],
+            },
+        };
+        let mut writer = ContainerWriter::new(File::create(path).unwrap()).unwrap();
+        writer
+            .append(
+                INFO_KEY.to_owned(),
+                &format::write(Vec::new(), &info, 1).unwrap(),
+            )
+            .unwrap();
+        writer
+            .append(
+                container::tile_key(TileKind::Summary, &tile.entry_id, tile.tile_id),
+                &format::write(Vec::new(), &tile, 1).unwrap(),
+            )
+            .unwrap();
+        writer.finish(1).unwrap();
+        tile
+    }
+
+    #[test]
+    fn test_read() {
+        let path = std::env::temp_dir().join(format!("container_data_{}.lpv", std::process::id()));
+        let tile = write_container(&path);
+
+        let archive = ContainerDataSource::new(&path);
+        assert_eq!(archive.fetch_info().unwrap().interval, tile.tile_id.0);
+        let result = archive.fetch_summary_tile(&tile.entry_id, tile.tile_id, false);
+        assert_eq!(result.unwrap().data.utilization, tile.data.utilization);
+        let missing =
+            archive.fetch_summary_tile(&EntryID::root().child(0).summary(), tile.tile_id, false);
+        assert!(matches!(missing, Err(DataSourceError::NotFound(_))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_manifest() {
+        let path = std::env::temp_dir().join(format!(
+            "container_data_manifest_{}.lpv",
+            std::process::id()
+        ));
+        let tile = write_container(&path);
+        let manifest_path = container_manifest_path(&path);
+        let entry = ManifestEntry::new(b"something else", None);
+        std::fs::write(&manifest_path, entry.to_line(INFO_KEY)).unwrap();
+
+        let archive = ContainerDataSource::new(&path);
+        assert!(matches!(
+            archive.fetch_info(),
+            Err(DataSourceError::Decode(_))
+        ));
+        // Blobs that aren't listed aren't checked
+        let result = archive.fetch_summary_tile(&tile.entry_id, tile.tile_id, false);
+        assert!(result.is_ok());
+
+        std::fs::remove_file(&manifest_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let path =
+            std::env::temp_dir().join(format!("container_data_bad_{}.lpv", std::process::id()));
+        let archive = ContainerDataSource::new(&path);
+        assert!(matches!(
+            archive.fetch_info(),
+            Err(DataSourceError::NotFound(_))
+        ));
+
+        std::fs::write(&path, vec![0; PREFIX_SIZE as usize]).unwrap();
+        let archive = ContainerDataSource::new(&path);
+        assert!(matches!(
+            archive.fetch_info(),
+            Err(DataSourceError::Decode(_))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
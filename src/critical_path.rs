@@ -0,0 +1,656 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::data::{
+    DataSourceError, DataSourceResult, EdgeKind, EntryID, EntryIndex, EntryInfo, ItemUID,
+    SlotMetaTile, SlotTile, TileID, TileSet,
+};
+use crate::deferred_data::{DeferredDataSource, TileResult};
+use crate::timestamp::Interval;
+
+#[derive(Debug, Clone)]
+pub struct CriticalPathSegment {
+    pub item_uid: ItemUID,
+    pub entry_id: EntryID,
+    // Row index of the item (note: reversed, because the app works in
+    // screen space)
+    pub irow: usize,
+    // As with ItemMeta::original_interval, this covers the entire item even
+    // if it was sliced across multiple tiles
+    pub interval: Interval,
+    // Populated once the item's metadata has been fetched
+    pub title: Option<String>,
+    // How we got here from the previous segment (None for the first segment)
+    pub edge_kind: Option<EdgeKind>,
+    // Time between the previous segment finishing and this one starting
+    pub delay_ns: i64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CriticalPath {
+    pub segments: Vec<CriticalPathSegment>,
+    item_uids: BTreeSet<ItemUID>,
+}
+
+impl CriticalPath {
+    fn new(segments: Vec<CriticalPathSegment>) -> Self {
+        let item_uids = segments.iter().map(|s| s.item_uid).collect();
+        Self {
+            segments,
+            item_uids,
+        }
+    }
+
+    pub fn contains(&self, item_uid: ItemUID) -> bool {
+        self.item_uids.contains(&item_uid)
+    }
+
+    pub fn interval(&self) -> Option<Interval> {
+        let first = self.segments.first()?;
+        let last = self.segments.last()?;
+        Some(Interval::new(first.interval.start, last.interval.stop))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ItemRecord {
+    entry_id: EntryID,
+    tile_id: TileID,
+    irow: usize,
+    interval: Interval,
+}
+
+// Computes the critical path through a profile: starting from the item that
+// finishes last, repeatedly step backwards to whichever predecessor finished
+// last, until we reach an item with no predecessors.
+//
+// The analysis does not own a data source, so that it can share one with the
+// app. Callers are responsible for passing any tiles that the analysis
+// accepts (see accepts_slot_tile and accepts_slot_meta_tile).
+pub struct CriticalPathAnalysis {
+    items: BTreeMap<ItemUID, ItemRecord>,
+    predecessors: BTreeMap<ItemUID, Vec<(ItemUID, EdgeKind)>>,
+
+    // Slot tiles are requested a few at a time (see MAX_IN_FLIGHT_REQUESTS),
+    // so they wait here until there's room
+    queued_slot_tiles: VecDeque<(EntryID, TileID)>,
+    pending_slot_tiles: BTreeSet<(EntryID, TileID)>,
+    pending_slot_meta_tiles: BTreeSet<(EntryID, TileID)>,
+    total_slot_tiles: usize,
+
+    path: Option<CriticalPath>,
+    error: Option<DataSourceError>,
+}
+
+// Every slot tile in the profile is needed, which on a large profile is far
+// more than should be requested at once
+const MAX_IN_FLIGHT_REQUESTS: usize = 100;
+
+impl CriticalPathAnalysis {
+    pub fn start<D>(
+        entry_info: &EntryInfo,
+        interval: Interval,
+        tile_set: &TileSet,
+        data_source: &mut D,
+    ) -> Self
+    where
+        D: DeferredDataSource + ?Sized,
+    {
+        // We need every item, so use the finest available level of tiles
+        let tile_ids = match tile_set.tiles.last() {
+            Some(level) => level.clone(),
+            None => vec![TileID(interval)],
+        };
+
+        let mut queued_slot_tiles = VecDeque::new();
+        for entry_id in entry_info.entry_ids() {
+            if let Some(EntryIndex::Slot(..)) = entry_id.last_index() {
+                for tile_id in &tile_ids {
+                    queued_slot_tiles.push_back((entry_id.clone(), *tile_id));
+                }
+            }
+        }
+
+        let total_slot_tiles = queued_slot_tiles.len();
+        let mut result = Self {
+            items: BTreeMap::new(),
+            predecessors: BTreeMap::new(),
+            queued_slot_tiles,
+            pending_slot_tiles: BTreeSet::new(),
+            pending_slot_meta_tiles: BTreeSet::new(),
+            total_slot_tiles,
+            path: None,
+            error: None,
+        };
+        result.request_slot_tiles(data_source);
+        if result.pending_slot_tiles.is_empty() {
+            result.finish_slot_tiles(data_source);
+        }
+        result
+    }
+
+    fn request_slot_tiles<D>(&mut self, data_source: &mut D)
+    where
+        D: DeferredDataSource + ?Sized,
+    {
+        while self.pending_slot_tiles.len() < MAX_IN_FLIGHT_REQUESTS {
+            let Some((entry_id, tile_id)) = self.queued_slot_tiles.pop_front() else {
+                break;
+            };
+            data_source.fetch_slot_tile(&entry_id, tile_id, true);
+            self.pending_slot_tiles.insert((entry_id, tile_id));
+        }
+    }
+
+    pub fn accepts_slot_tile(&self, tile: &TileResult<SlotTile>) -> bool {
+        // The app never requests full tiles, so this can't be one of its own
+        tile.full
+            && self
+                .pending_slot_tiles
+                .contains(&(tile.entry_id.clone(), tile.tile_id))
+    }
+
+    pub fn add_slot_tile<D>(&mut self, tile: TileResult<SlotTile>, data_source: &mut D)
+    where
+        D: DeferredDataSource + ?Sized,
+    {
+        if !self
+            .pending_slot_tiles
+            .remove(&(tile.entry_id.clone(), tile.tile_id))
+        {
+            return;
+        }
+
+        match tile.result {
+            Ok(tile) => self.record_slot_tile(tile),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+
+        self.request_slot_tiles(data_source);
+        if self.pending_slot_tiles.is_empty() {
+            self.finish_slot_tiles(data_source);
+        }
+    }
+
+    pub fn accepts_slot_meta_tile(&self, tile: &TileResult<SlotMetaTile>) -> bool {
+        tile.full
+            && self
+                .pending_slot_meta_tiles
+                .contains(&(tile.entry_id.clone(), tile.tile_id))
+    }
+
+    pub fn add_slot_meta_tile(&mut self, tile: TileResult<SlotMetaTile>) {
+        if !self
+            .pending_slot_meta_tiles
+            .remove(&(tile.entry_id.clone(), tile.tile_id))
+        {
+            return;
+        }
+
+        // Titles are nice to have, so don't fail the analysis without them
+        let (Ok(tile), Some(path)) = (tile.result, &mut self.path) else {
+            return;
+        };
+        for items in tile.data.items {
+            for item in items {
+                if !path.contains(item.item_uid) {
+                    continue;
+                }
+                for segment in &mut path.segments {
+                    if segment.item_uid == item.item_uid {
+                        segment.title = Some(item.title.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.pending_slot_tiles.is_empty() && self.pending_slot_meta_tiles.is_empty()
+    }
+
+    // Returns the fraction of slot tiles that have been loaded so far
+    pub fn progress(&self) -> f32 {
+        if self.total_slot_tiles == 0 {
+            return 1.0;
+        }
+        let loaded =
+            self.total_slot_tiles - self.pending_slot_tiles.len() - self.queued_slot_tiles.len();
+        loaded as f32 / self.total_slot_tiles as f32
+    }
+
+    pub fn error(&self) -> Option<&DataSourceError> {
+        self.error.as_ref()
+    }
+
+    // Available as soon as all slot tiles are loaded (titles may trickle in
+    // afterwards)
+    pub fn path(&self) -> Option<&CriticalPath> {
+        self.path.as_ref()
+    }
+
+    fn record_slot_tile(&mut self, tile: SlotTile) {
+        let rows = tile.data.items.len();
+        for (row, row_items) in tile.data.items.into_iter().enumerate() {
+            for item in row_items {
+                self.items
+                    .entry(item.item_uid)
+                    .and_modify(|record| {
+                        record.interval = record.interval.union(item.interval);
+                    })
+                    .or_insert_with(|| ItemRecord {
+                        entry_id: tile.entry_id.clone(),
+                        tile_id: tile.tile_id,
+                        irow: rows - row - 1,
+                        interval: item.interval,
+                    });
+            }
+        }
+
+        for edge in tile.data.edges {
+            let preds = self.predecessors.entry(edge.dst).or_default();
+            // Edges are repeated in every tile the destination overlaps
+            if !preds.contains(&(edge.src, edge.kind)) {
+                preds.push((edge.src, edge.kind));
+            }
+        }
+    }
+
+    fn finish_slot_tiles<D>(&mut self, data_source: &mut D)
+    where
+        D: DeferredDataSource + ?Sized,
+    {
+        if self.error.is_some() {
+            return;
+        }
+
+        let path = self.compute_path();
+
+        // Fetch metadata so we can show titles along the path
+        for segment in &path.segments {
+            let record = &self.items[&segment.item_uid];
+            let key = (record.entry_id.clone(), record.tile_id);
+            if self.pending_slot_meta_tiles.insert(key) {
+                data_source.fetch_slot_meta_tile(&record.entry_id, record.tile_id, true);
+            }
+        }
+
+        // Free memory we no longer need
+        self.items.clear();
+        self.predecessors.clear();
+
+        self.path = Some(path);
+    }
+
+    fn compute_path(&self) -> CriticalPath {
+        let Some((&last, _)) = self
+            .items
+            .iter()
+            .max_by_key(|(_, record)| record.interval.stop)
+        else {
+            return CriticalPath::default();
+        };
+
+        // Each element records the kind of the edge leading into that item
+        let mut visited = BTreeSet::new();
+        let mut chain = vec![(last, None)];
+        visited.insert(last);
+        let mut current = last;
+        loop {
+            // Step back to the predecessor that finished last, since that is
+            // the one that held us up
+            let next = self.predecessors.get(&current).and_then(|preds| {
+                preds
+                    .iter()
+                    .filter(|(src, _)| !visited.contains(src))
+                    .filter_map(|(src, kind)| Some((*src, *kind, self.items.get(src)?)))
+                    .max_by_key(|(_, _, record)| record.interval.stop)
+            });
+            let Some((src, kind, _)) = next else {
+                break;
+            };
+            chain.last_mut().unwrap().1 = Some(kind);
+            chain.push((src, None));
+            visited.insert(src);
+            current = src;
+        }
+        chain.reverse();
+
+        let mut segments: Vec<CriticalPathSegment> = Vec::new();
+        for (item_uid, edge_kind) in chain {
+            let record = &self.items[&item_uid];
+            let delay_ns = segments.last().map_or(0, |prev| {
+                (record.interval.start.0 - prev.interval.stop.0).max(0)
+            });
+            segments.push(CriticalPathSegment {
+                item_uid,
+                entry_id: record.entry_id.clone(),
+                irow: record.irow,
+                interval: record.interval,
+                title: None,
+                edge_kind,
+                delay_ns,
+            });
+        }
+        CriticalPath::new(segments)
+    }
+}
+
+// Computes the critical path without a UI, e.g., for use from a script or
+// command-line tool. Nothing here blocks, so on the web (where threads can't
+// sleep), poll can be called once per frame.
+pub struct CriticalPathComputation<T> {
+    data_source: T,
+    // Started once the info arrives
+    analysis: Option<CriticalPathAnalysis>,
+}
+
+impl<T: DeferredDataSource> CriticalPathComputation<T> {
+    pub fn new(mut data_source: T) -> Self {
+        data_source.fetch_info();
+        Self {
+            data_source,
+            analysis: None,
+        }
+    }
+
+    // Takes whatever the data source has for us, and returns the result once
+    // the analysis is complete
+    pub fn poll(&mut self) -> Option<DataSourceResult<CriticalPath>> {
+        let analysis = match &mut self.analysis {
+            Some(analysis) => analysis,
+            None => {
+                // We requested this once, so we know we'll get zero or one result
+                let info = match self.data_source.get_infos().pop()? {
+                    Ok(info) => info,
+                    Err(e) => return Some(Err(e)),
+                };
+                self.analysis.insert(CriticalPathAnalysis::start(
+                    &info.entry_info,
+                    info.interval,
+                    &info.tile_set,
+                    &mut self.data_source,
+                ))
+            }
+        };
+
+        for tile in self.data_source.get_slot_tiles() {
+            analysis.add_slot_tile(tile, &mut self.data_source);
+        }
+        for tile in self.data_source.get_slot_meta_tiles() {
+            analysis.add_slot_meta_tile(tile);
+        }
+        if !analysis.is_done() {
+            return None;
+        }
+        if let Some(error) = analysis.error.take() {
+            return Some(Err(error));
+        }
+        Some(Ok(analysis.path.take().unwrap_or_default()))
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.analysis
+            .as_ref()
+            .map_or(0.0, |analysis| analysis.progress())
+    }
+}
+
+// Blocks until the analysis is complete
+#[cfg(not(target_arch = "wasm32"))]
+pub fn compute_critical_path<T: DeferredDataSource>(
+    data_source: T,
+) -> DataSourceResult<CriticalPath> {
+    let mut computation = CriticalPathComputation::new(data_source);
+    loop {
+        if let Some(result) = computation.poll() {
+            return result;
+        }
+        // So that waiting on a remote source doesn't spin
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{
+        Color32, CounterTile, DataSourceDescription, DataSourceInfo, FieldSchema, Item, ItemEdge,
+        SearchQuery, SlotTileData, SummaryTile,
+    };
+    use crate::deferred_data::SearchResponse;
+    use crate::timestamp::Timestamp;
+
+    fn analysis() -> CriticalPathAnalysis {
+        CriticalPathAnalysis {
+            items: BTreeMap::new(),
+            predecessors: BTreeMap::new(),
+            queued_slot_tiles: VecDeque::new(),
+            pending_slot_tiles: BTreeSet::new(),
+            pending_slot_meta_tiles: BTreeSet::new(),
+            total_slot_tiles: 0,
+            path: None,
+            error: None,
+        }
+    }
+
+    fn item(uid: u64, start: i64, stop: i64) -> Item {
+        Item {
+            item_uid: ItemUID(uid),
+            interval: Interval::new(Timestamp(start), Timestamp(stop)),
+            color: Color32::WHITE,
+        }
+    }
+
+    fn edge(src: u64, dst: u64, kind: EdgeKind) -> ItemEdge {
+        ItemEdge {
+            src: ItemUID(src),
+            dst: ItemUID(dst),
+            kind,
+        }
+    }
+
+    fn tile(items: Vec<Vec<Item>>, edges: Vec<ItemEdge>) -> SlotTile {
+        SlotTile {
+            entry_id: EntryID::root().child(0),
+            tile_id: TileID(Interval::new(Timestamp(0), Timestamp(100))),
+            data: SlotTileData { items, edges },
+        }
+    }
+
+    #[test]
+    fn test_latest_predecessor() {
+        let mut analysis = analysis();
+        analysis.record_slot_tile(tile(
+            vec![
+                vec![item(0, 0, 10), item(1, 20, 30)],
+                vec![item(2, 0, 15), item(3, 40, 50)],
+            ],
+            vec![
+                edge(0, 3, EdgeKind::Dependence),
+                edge(2, 1, EdgeKind::Creation),
+                edge(1, 3, EdgeKind::Dependence),
+            ],
+        ));
+
+        let path = analysis.compute_path();
+        let uids: Vec<_> = path.segments.iter().map(|s| s.item_uid.0).collect();
+        assert_eq!(uids, vec![2, 1, 3]);
+
+        let kinds: Vec<_> = path.segments.iter().map(|s| s.edge_kind).collect();
+        assert_eq!(
+            kinds,
+            vec![None, Some(EdgeKind::Creation), Some(EdgeKind::Dependence)]
+        );
+
+        let delays: Vec<_> = path.segments.iter().map(|s| s.delay_ns).collect();
+        assert_eq!(delays, vec![0, 5, 10]);
+
+        assert_eq!(
+            path.interval(),
+            Some(Interval::new(Timestamp(0), Timestamp(50)))
+        );
+        assert!(path.contains(ItemUID(1)));
+        assert!(!path.contains(ItemUID(0)));
+    }
+
+    #[test]
+    fn test_sliced_items_and_cycles() {
+        let mut analysis = analysis();
+        // Item 1 is sliced across two tiles, and its edges are repeated
+        analysis.record_slot_tile(tile(
+            vec![vec![item(0, 0, 10), item(1, 20, 50)]],
+            vec![edge(0, 1, EdgeKind::Dependence)],
+        ));
+        analysis.record_slot_tile(tile(
+            vec![vec![item(1, 50, 60)]],
+            vec![
+                edge(0, 1, EdgeKind::Dependence),
+                edge(1, 0, EdgeKind::Dependence),
+            ],
+        ));
+
+        let path = analysis.compute_path();
+        let uids: Vec<_> = path.segments.iter().map(|s| s.item_uid.0).collect();
+        assert_eq!(uids, vec![0, 1]);
+        assert_eq!(
+            path.segments[1].interval,
+            Interval::new(Timestamp(20), Timestamp(60))
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        let path = analysis().compute_path();
+        assert!(path.segments.is_empty());
+        assert_eq!(path.interval(), None);
+    }
+
+    // A profile with many empty slots, which answers a few slot tiles per
+    // poll and records how many were ever outstanding at once
+    #[derive(Default)]
+    struct ManySlots {
+        slots: usize,
+        infos: Vec<DataSourceResult<DataSourceInfo>>,
+        pending: VecDeque<(EntryID, TileID)>,
+        requested: usize,
+        max_in_flight: usize,
+    }
+
+    impl DeferredDataSource for ManySlots {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&mut self) {
+            let slots = (0..self.slots)
+                .map(|i| EntryInfo::Slot {
+                    short_name: format!("s{}", i),
+                    long_name: format!("slot {}", i),
+                    max_rows: 1,
+                })
+                .collect();
+            self.infos.push(Ok(DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "root".to_owned(),
+                    long_name: "root".to_owned(),
+                    summary: None,
+                    counters: Vec::new(),
+                    slots,
+                },
+                interval: Interval::new(Timestamp(0), Timestamp(100)),
+                tile_set: TileSet::default(),
+                field_schema: FieldSchema::new(),
+                warning_message: None,
+            }));
+        }
+
+        fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+            std::mem::take(&mut self.infos)
+        }
+
+        fn fetch_summary_tile(&mut self, _: &EntryID, _: TileID, _: bool) {
+            unimplemented!();
+        }
+
+        fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+            Vec::new()
+        }
+
+        fn fetch_counter_tile(&mut self, _: &EntryID, _: TileID, _: bool) {
+            unimplemented!();
+        }
+
+        fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+            Vec::new()
+        }
+
+        fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, _: bool) {
+            self.pending.push_back((entry_id.clone(), tile_id));
+            self.requested += 1;
+            self.max_in_flight = self.max_in_flight.max(self.pending.len());
+        }
+
+        fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+            let count = self.pending.len().min(7);
+            self.pending
+                .drain(..count)
+                .map(|(entry_id, tile_id)| {
+                    let data = SlotTileData {
+                        items: Vec::new(),
+                        edges: Vec::new(),
+                    };
+                    let tile = SlotTile {
+                        entry_id: entry_id.clone(),
+                        tile_id,
+                        data,
+                    };
+                    TileResult::new(&entry_id, tile_id, true, Ok(tile))
+                })
+                .collect()
+        }
+
+        fn fetch_slot_meta_tile(&mut self, _: &EntryID, _: TileID, _: bool) {
+            unimplemented!();
+        }
+
+        fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+            Vec::new()
+        }
+
+        fn fetch_search(&mut self, _: &SearchQuery) {
+            unimplemented!();
+        }
+
+        fn get_search_results(&mut self) -> Vec<SearchResponse> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let mut computation = CriticalPathComputation::new(ManySlots {
+            slots: 250,
+            ..Default::default()
+        });
+        let mut polls = 0;
+        let path = loop {
+            polls += 1;
+            if let Some(result) = computation.poll() {
+                break result.unwrap();
+            }
+            assert!(computation.progress() < 1.0);
+        };
+        assert!(path.segments.is_empty());
+        assert_eq!(polls, 250usize.div_ceil(7));
+        assert_eq!(computation.data_source.requested, 250);
+        assert_eq!(
+            computation.data_source.max_in_flight,
+            MAX_IN_FLIGHT_REQUESTS
+        );
+    }
+}
@@ -8,14 +8,16 @@ use std::collections::BTreeSet;
 use crate::timestamp::{Interval, Timestamp};
 
 // We encode EntryID as i64 because it allows us to pack Summary into the
-// value -1. Users shouldn't need to know about this and interact through the
-// methods below, or via EntryIndex.
+// value -1, and counters into the values -2 and below. Users shouldn't need
+// to know about this and interact through the methods below, or via
+// EntryIndex.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct EntryID(Vec<i64>);
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 pub enum EntryIndex {
     Summary,
+    Counter(u64),
     Slot(u64),
 }
 
@@ -34,6 +36,9 @@ pub enum EntryInfo {
         short_name: String,
         long_name: String,
         summary: Option<Box<EntryInfo>>,
+        // Counters are displayed alongside the summary
+        #[serde(default)]
+        counters: Vec<EntryInfo>,
         slots: Vec<EntryInfo>,
     },
     Slot {
@@ -44,6 +49,17 @@ pub enum EntryInfo {
     Summary {
         color: Color32,
     },
+    Counter {
+        short_name: String,
+        long_name: String,
+        color: Color32,
+        // Values with a recognized unit ("B", "B/s" or "ns") are displayed
+        // in human-readable form, otherwise the unit is shown verbatim
+        unit: String,
+        // Range of values over the entire profile
+        min: f64,
+        max: f64,
+    },
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default, Deserialize, Serialize)]
@@ -52,6 +68,13 @@ pub struct UtilPoint {
     pub util: f32,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default, Deserialize, Serialize)]
+pub struct CounterPoint {
+    pub time: Timestamp,
+    // The counter holds this value until the next point
+    pub value: f64,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ItemLink {
     pub item_uid: ItemUID,
@@ -108,6 +131,13 @@ impl FieldSchema {
         self.field_ids.get(field_name).copied()
     }
 
+    pub fn get_id_ignore_case(&self, field_name: &str) -> Option<FieldID> {
+        self.field_ids
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field_name))
+            .map(|(_, field_id)| *field_id)
+    }
+
     pub fn get_name(&self, field_id: FieldID) -> Option<&str> {
         self.field_names.get(&field_id).map(|x| x.as_str())
     }
@@ -135,6 +165,10 @@ impl Default for FieldSchema {
 pub enum Field {
     I64(i64),
     U64(u64),
+    F64(f64),
+    Bool(bool),
+    Duration(i64 /* ns */),
+    Bytes(u64),
     String(String),
     Interval(Interval),
     ItemLink(ItemLink),
@@ -152,6 +186,25 @@ pub struct Item {
     pub color: Color32,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub enum EdgeKind {
+    // The source item created (e.g., launched) the destination item
+    Creation,
+    // The destination item could not start until the source item finished
+    Dependence,
+}
+
+impl EdgeKind {
+    pub const ALL: [EdgeKind; 2] = [EdgeKind::Creation, EdgeKind::Dependence];
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct ItemEdge {
+    pub src: ItemUID,
+    pub dst: ItemUID,
+    pub kind: EdgeKind,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ItemMeta {
     pub item_uid: ItemUID,
@@ -183,9 +236,26 @@ pub struct SummaryTile {
     pub data: SummaryTileData,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CounterTileData {
+    // Sorted by time. The first point should give the value in effect at the
+    // start of the tile, and the last point holds until the end of the tile
+    pub points: Vec<CounterPoint>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CounterTile {
+    pub entry_id: EntryID,
+    pub tile_id: TileID,
+    pub data: CounterTileData,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SlotTileData {
     pub items: Vec<Vec<Item>>, // row -> [item]
+    // Edges are delivered with the tile(s) containing their destination item
+    #[serde(default)]
+    pub edges: Vec<ItemEdge>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -207,18 +277,171 @@ pub struct SlotMetaTile {
     pub data: SlotMetaTileData,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchQuery {
+    pub query: String,
+    // Field to search, or None to search item titles
+    pub field: Option<FieldID>,
+    pub whole_word: bool,
+    // Only items overlapping this interval are returned
+    pub interval: Interval,
+    pub max_results: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchResults {
+    pub items: Vec<ItemLink>,
+    // Set when more than max_results items matched
+    pub truncated: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum TileKind {
+    Summary,
+    Counter,
+    Slot,
+    SlotMeta,
+}
+
+// A request for a single tile, used to fetch many tiles at once
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct TileRequest {
+    pub kind: TileKind,
+    pub entry_id: EntryID,
+    pub tile_id: TileID,
+    pub full: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Tile {
+    Summary(SummaryTile),
+    Counter(CounterTile),
+    Slot(SlotTile),
+    SlotMeta(SlotMetaTile),
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DataSourceDescription {
     pub source_locator: Vec<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DataSourceError {
+    // The underlying storage or network failed
+    Io(String),
+    // Data was retrieved but could not be decoded
+    Decode(String),
+    // The requested info or tile does not exist
+    NotFound(String),
+    // Data was written in a format this viewer does not understand
+    ProtocolVersion(String),
+    // A remote server responded with an error
+    Server { status: u16, message: String },
+    // The request was cancelled before it completed
+    Cancelled,
+}
+
+pub type DataSourceResult<T> = Result<T, DataSourceError>;
+
+impl fmt::Display for DataSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataSourceError::Io(message) => write!(f, "I/O error: {message}"),
+            DataSourceError::Decode(message) => write!(f, "decode error: {message}"),
+            DataSourceError::NotFound(message) => write!(f, "not found: {message}"),
+            DataSourceError::ProtocolVersion(message) => {
+                write!(f, "unsupported protocol version: {message}")
+            }
+            DataSourceError::Server { status, message } => {
+                write!(f, "server error ({status}): {message}")
+            }
+            DataSourceError::Cancelled => write!(f, "request was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for DataSourceError {}
+
+impl From<std::io::Error> for DataSourceError {
+    fn from(e: std::io::Error) -> DataSourceError {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => DataSourceError::NotFound(e.to_string()),
+            _ => DataSourceError::Io(e.to_string()),
+        }
+    }
+}
+
+impl<T: fmt::Debug> From<ciborium::de::Error<T>> for DataSourceError {
+    fn from(e: ciborium::de::Error<T>) -> DataSourceError {
+        DataSourceError::Decode(e.to_string())
+    }
+}
+
 pub trait DataSource {
     fn fetch_description(&self) -> DataSourceDescription;
-    fn fetch_info(&self) -> DataSourceInfo;
-    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SummaryTile;
-    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool) -> SlotTile;
-    fn fetch_slot_meta_tile(&self, entry_id: &EntryID, tile_id: TileID, full: bool)
-        -> SlotMetaTile;
+    fn fetch_info(&self) -> DataSourceResult<DataSourceInfo>;
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> DataSourceResult<SummaryTile>;
+    fn fetch_counter_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> DataSourceResult<CounterTile>;
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> DataSourceResult<SlotTile>;
+    fn fetch_slot_meta_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+    ) -> DataSourceResult<SlotMetaTile>;
+    // Returns one result per request, in the same order. Data sources that
+    // can serve many tiles more cheaply than one at a time should override
+    // this
+    fn fetch_tiles(&self, requests: &[TileRequest]) -> Vec<DataSourceResult<Tile>> {
+        requests
+            .iter()
+            .map(|req| {
+                let TileRequest {
+                    kind,
+                    entry_id,
+                    tile_id,
+                    full,
+                } = req;
+                match kind {
+                    TileKind::Summary => self
+                        .fetch_summary_tile(entry_id, *tile_id, *full)
+                        .map(Tile::Summary),
+                    TileKind::Counter => self
+                        .fetch_counter_tile(entry_id, *tile_id, *full)
+                        .map(Tile::Counter),
+                    TileKind::Slot => self
+                        .fetch_slot_tile(entry_id, *tile_id, *full)
+                        .map(Tile::Slot),
+                    TileKind::SlotMeta => self
+                        .fetch_slot_meta_tile(entry_id, *tile_id, *full)
+                        .map(Tile::SlotMeta),
+                }
+            })
+            .collect()
+    }
+    // Search is optional: data sources that can't search any faster than
+    // the viewer can on its own should leave this unimplemented, and the
+    // viewer will fall back to searching the tiles it loads
+    fn search(&self, _query: &SearchQuery) -> DataSourceResult<SearchResults> {
+        Err(DataSourceError::NotFound(
+            "search is not supported by this data source".to_owned(),
+        ))
+    }
 }
 
 impl EntryID {
@@ -232,6 +455,13 @@ impl EntryID {
         result
     }
 
+    pub fn counter(&self, index: u64) -> Self {
+        let index: i64 = index.try_into().expect("unable to fit in i64");
+        let mut result = self.clone();
+        result.0.push(-2 - index);
+        result
+    }
+
     pub fn child(&self, index: u64) -> Self {
         let mut result = self.clone();
         result
@@ -254,22 +484,33 @@ impl EntryID {
         (*last).try_into().ok()
     }
 
+    fn decode_index(value: i64) -> EntryIndex {
+        match value {
+            -1 => EntryIndex::Summary,
+            v if v < -1 => EntryIndex::Counter((-2 - v) as u64),
+            v => EntryIndex::Slot(v as u64),
+        }
+    }
+
     pub fn last_index(&self) -> Option<EntryIndex> {
         let last = self.0.last()?;
-        Some(
-            (*last)
-                .try_into()
-                .map_or(EntryIndex::Summary, EntryIndex::Slot),
-        )
+        Some(Self::decode_index(*last))
+    }
+
+    // The kinds of tiles this entry has (slots have both items and their
+    // metadata)
+    pub fn tile_kinds(&self) -> &'static [TileKind] {
+        match self.last_index() {
+            Some(EntryIndex::Summary) => &[TileKind::Summary],
+            Some(EntryIndex::Counter(..)) => &[TileKind::Counter],
+            Some(EntryIndex::Slot(..)) => &[TileKind::Slot, TileKind::SlotMeta],
+            None => &[],
+        }
     }
 
     pub fn index(&self, level: u64) -> Option<EntryIndex> {
         let last = self.0.get(level as usize)?;
-        Some(
-            (*last)
-                .try_into()
-                .map_or(EntryIndex::Summary, EntryIndex::Slot),
-        )
+        Some(Self::decode_index(*last))
     }
 
     pub fn has_prefix(&self, prefix: &EntryID) -> bool {
@@ -298,6 +539,9 @@ impl EntryInfo {
                 (EntryIndex::Summary, EntryInfo::Panel { summary, .. }) => {
                     return summary.as_deref();
                 }
+                (EntryIndex::Counter(j), EntryInfo::Panel { counters, .. }) => {
+                    return counters.get(j as usize);
+                }
                 (EntryIndex::Slot(j), EntryInfo::Panel { slots, .. }) => {
                     result = slots.get(j as usize)?;
                 }
@@ -307,6 +551,39 @@ impl EntryInfo {
         Some(result)
     }
 
+    // Returns the IDs of every slot, summary and counter, in depth-first order
+    pub fn entry_ids(&self) -> Vec<EntryID> {
+        let mut result = Vec::new();
+        fn walk(info: &EntryInfo, entry_id: EntryID, result: &mut Vec<EntryID>) {
+            match info {
+                EntryInfo::Panel {
+                    summary,
+                    counters,
+                    slots,
+                    ..
+                } => {
+                    if let Some(summary) = summary {
+                        walk(summary, entry_id.summary(), result);
+                    }
+                    for (i, counter) in counters.iter().enumerate() {
+                        walk(counter, entry_id.counter(i as u64), result)
+                    }
+                    for (i, slot) in slots.iter().enumerate() {
+                        walk(slot, entry_id.child(i as u64), result)
+                    }
+                }
+                EntryInfo::Slot { .. } => {
+                    result.push(entry_id);
+                }
+                EntryInfo::Summary { .. } | EntryInfo::Counter { .. } => {
+                    result.push(entry_id);
+                }
+            }
+        }
+        walk(self, EntryID::root(), &mut result);
+        result
+    }
+
     pub fn nodes(&self) -> u64 {
         if let EntryInfo::Panel { slots, .. } = self {
             slots.len() as u64
@@ -340,6 +617,27 @@ impl EntryInfo {
     }
 }
 
+impl CounterTileData {
+    // The points of a whole counter (sorted by time) that fall in the tile,
+    // starting with the value in effect at its start
+    pub fn slice(points: &[CounterPoint], interval: Interval) -> Self {
+        let mut result = Vec::new();
+        for (i, point) in points.iter().enumerate() {
+            if interval.contains(point.time) {
+                result.push(*point);
+            } else if point.time < interval.start
+                && points.get(i + 1).map_or(true, |p| p.time > interval.start)
+            {
+                result.push(CounterPoint {
+                    time: interval.start,
+                    value: point.value,
+                });
+            }
+        }
+        Self { points: result }
+    }
+}
+
 #[derive(Debug)]
 pub enum SlugParseError {
     ParseInt(std::num::ParseIntError),
@@ -420,3 +718,96 @@ impl EntryID {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: i64, stop: i64) -> Interval {
+        Interval::new(Timestamp(start), Timestamp(stop))
+    }
+
+    #[test]
+    fn test_counter_entry_id() {
+        let panel = EntryID::root().child(3);
+        for index in [0, 1, 7, i64::MAX as u64 - 2] {
+            let counter = panel.counter(index);
+            assert_eq!(counter.last_index(), Some(EntryIndex::Counter(index)));
+            assert_eq!(counter.tile_kinds(), &[TileKind::Counter]);
+            assert_eq!(counter.last_slot_index(), None);
+            assert!(counter.has_prefix(&panel));
+
+            let slug = EntryIDSlug(&counter).to_string();
+            assert_eq!(EntryID::from_slug(&slug).unwrap(), counter);
+        }
+        // Counters don't collide with the summary or with slots
+        assert_eq!(EntryID::root().counter(0).0, vec![-2]);
+        assert_eq!(EntryID::root().summary().0, vec![-1]);
+        assert_eq!(
+            EntryID::root().summary().last_index(),
+            Some(EntryIndex::Summary)
+        );
+        assert_eq!(
+            EntryID::root().child(0).last_index(),
+            Some(EntryIndex::Slot(0))
+        );
+    }
+
+    #[test]
+    fn test_counter_entry_info() {
+        let counter = |name: &str| EntryInfo::Counter {
+            short_name: name.to_owned(),
+            long_name: name.to_owned(),
+            color: Color32::BLUE,
+            unit: "B".to_owned(),
+            min: 0.0,
+            max: 1.0,
+        };
+        let info = EntryInfo::Panel {
+            short_name: "root".to_owned(),
+            long_name: "root".to_owned(),
+            summary: Some(Box::new(EntryInfo::Summary {
+                color: Color32::BLUE,
+            })),
+            counters: vec![counter("a"), counter("b")],
+            slots: Vec::new(),
+        };
+        let root = EntryID::root();
+        assert_eq!(
+            info.entry_ids(),
+            vec![root.summary(), root.counter(0), root.counter(1)]
+        );
+        let Some(EntryInfo::Counter { short_name, .. }) = info.get(&root.counter(1)) else {
+            panic!("expected a counter");
+        };
+        assert_eq!(short_name, "b");
+        assert!(info.get(&root.counter(2)).is_none());
+    }
+
+    #[test]
+    fn test_counter_slice() {
+        let points: Vec<_> = [(0, 1.0), (30, 2.0), (60, 3.0)]
+            .into_iter()
+            .map(|(t, value)| CounterPoint {
+                time: Timestamp(t),
+                value,
+            })
+            .collect();
+        let slice = |start, stop| -> Vec<_> {
+            CounterTileData::slice(&points, interval(start, stop))
+                .points
+                .iter()
+                .map(|p| (p.time.0, p.value))
+                .collect()
+        };
+        assert_eq!(slice(0, 100), vec![(0, 1.0), (30, 2.0), (60, 3.0)]);
+        // The value in effect at the start is carried in
+        assert_eq!(slice(40, 90), vec![(40, 2.0), (60, 3.0)]);
+        assert_eq!(slice(90, 100), vec![(90, 3.0)]);
+        // Points at the start are kept as they are, and those at the end
+        // belong to the next tile
+        assert_eq!(slice(30, 60), vec![(30, 2.0)]);
+        // Nothing before the first point
+        assert_eq!(slice(-10, 0), vec![]);
+    }
+}
@@ -1,26 +1,219 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
 use crate::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile,
-    SummaryTile, TileID,
+    Color32, CounterPoint, CounterTile, DataSource, DataSourceDescription, DataSourceError,
+    DataSourceInfo, DataSourceResult, EntryID, Field, FieldID, Item, ItemEdge, ItemMeta,
+    SearchQuery, SearchResults, SlotMetaTile, SlotTile, SummaryTile, Tile, TileID, TileKind,
+    TileRequest, UtilPoint,
 };
 
+// Tiles are returned along with the request that produced them, so that a
+// failed request can still be matched up with its entry and tile.
+#[derive(Debug, Clone)]
+pub struct TileResult<T> {
+    pub entry_id: EntryID,
+    pub tile_id: TileID,
+    pub full: bool,
+    pub result: DataSourceResult<T>,
+}
+
+impl<T> TileResult<T> {
+    pub fn new(
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        result: DataSourceResult<T>,
+    ) -> Self {
+        Self {
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+            result,
+        }
+    }
+}
+
+// Likewise, search results are returned along with their query.
+#[derive(Debug, Clone)]
+pub struct SearchResponse {
+    pub query: SearchQuery,
+    pub result: DataSourceResult<SearchResults>,
+}
+
+impl SearchResponse {
+    pub fn new(query: &SearchQuery, result: DataSourceResult<SearchResults>) -> Self {
+        Self {
+            query: query.clone(),
+            result,
+        }
+    }
+}
+
+// Sorts the results of a batch of tile requests into the queues for each
+// kind of tile, so that callers receive them from the usual get_* methods.
+pub fn sort_tile_results(
+    requests: &[TileRequest],
+    results: impl IntoIterator<Item = DataSourceResult<Tile>>,
+    summary_tiles: &mut Vec<TileResult<SummaryTile>>,
+    counter_tiles: &mut Vec<TileResult<CounterTile>>,
+    slot_tiles: &mut Vec<TileResult<SlotTile>>,
+    slot_meta_tiles: &mut Vec<TileResult<SlotMetaTile>>,
+) {
+    for (req, result) in requests.iter().zip(results) {
+        let mismatch = || DataSourceError::Decode(format!("expected a {:?} tile", req.kind));
+        let (entry_id, tile_id, full) = (&req.entry_id, req.tile_id, req.full);
+        match req.kind {
+            TileKind::Summary => {
+                let result = result.and_then(|tile| match tile {
+                    Tile::Summary(tile) => Ok(tile),
+                    _ => Err(mismatch()),
+                });
+                summary_tiles.push(TileResult::new(entry_id, tile_id, full, result));
+            }
+            TileKind::Counter => {
+                let result = result.and_then(|tile| match tile {
+                    Tile::Counter(tile) => Ok(tile),
+                    _ => Err(mismatch()),
+                });
+                counter_tiles.push(TileResult::new(entry_id, tile_id, full, result));
+            }
+            TileKind::Slot => {
+                let result = result.and_then(|tile| match tile {
+                    Tile::Slot(tile) => Ok(tile),
+                    _ => Err(mismatch()),
+                });
+                slot_tiles.push(TileResult::new(entry_id, tile_id, full, result));
+            }
+            TileKind::SlotMeta => {
+                let result = result.and_then(|tile| match tile {
+                    Tile::SlotMeta(tile) => Ok(tile),
+                    _ => Err(mismatch()),
+                });
+                slot_meta_tiles.push(TileResult::new(entry_id, tile_id, full, result));
+            }
+        }
+    }
+}
+
+// Cancels tiles for a data source that queues requests (in pending) before
+// sending them as jobs. Queued requests are answered right away; the others
+// are aborted once all the tiles of their job are cancelled.
+pub fn cancel_tile_requests(
+    requests: &[TileRequest],
+    pending: &mut Vec<TileRequest>,
+    jobs: &Mutex<PendingJobs>,
+    summary_tiles: &mut Vec<TileResult<SummaryTile>>,
+    counter_tiles: &mut Vec<TileResult<CounterTile>>,
+    slot_tiles: &mut Vec<TileResult<SlotTile>>,
+    slot_meta_tiles: &mut Vec<TileResult<SlotMetaTile>>,
+) {
+    let cancelled: BTreeSet<_> = requests.iter().collect();
+    let (dropped, kept): (Vec<_>, Vec<_>) = std::mem::take(pending)
+        .into_iter()
+        .partition(|req| cancelled.contains(req));
+    *pending = kept;
+    sort_tile_results(
+        &dropped,
+        dropped.iter().map(|_| Err(DataSourceError::Cancelled)),
+        summary_tiles,
+        counter_tiles,
+        slot_tiles,
+        slot_meta_tiles,
+    );
+
+    jobs.lock().unwrap().cancel(requests);
+}
+
+// Lets a job that was handed off (to a thread pool, or the network) find
+// out whether anyone still wants its results.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Jobs in flight for a data source that supports cancellation. Each job
+// covers one or more tiles, and is cancelled once all of them have been.
+#[derive(Debug, Default)]
+pub struct PendingJobs {
+    next_id: u64,
+    jobs: BTreeMap<u64, (Vec<TileRequest>, CancelToken)>,
+}
+
+impl PendingJobs {
+    pub fn start(&mut self, requests: &[TileRequest]) -> (u64, CancelToken) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let token = CancelToken::default();
+        self.jobs.insert(id, (requests.to_vec(), token.clone()));
+        (id, token)
+    }
+
+    pub fn finish(&mut self, id: u64) {
+        self.jobs.remove(&id);
+    }
+
+    pub fn cancel(&mut self, requests: &[TileRequest]) {
+        let cancelled: BTreeSet<_> = requests.iter().collect();
+        for (remaining, token) in self.jobs.values_mut() {
+            remaining.retain(|req| !cancelled.contains(req));
+            if remaining.is_empty() {
+                token.0.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
 pub trait DeferredDataSource {
     fn fetch_description(&self) -> DataSourceDescription;
     fn fetch_info(&mut self);
-    fn get_infos(&mut self) -> Vec<DataSourceInfo>;
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>>;
     fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile>;
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>>;
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>>;
     fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile>;
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>>;
     fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool);
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile>;
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>>;
+    // Results are delivered through the get_* method for each kind of tile
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        for req in requests {
+            let TileRequest {
+                kind,
+                entry_id,
+                tile_id,
+                full,
+            } = req;
+            match kind {
+                TileKind::Summary => self.fetch_summary_tile(entry_id, *tile_id, *full),
+                TileKind::Counter => self.fetch_counter_tile(entry_id, *tile_id, *full),
+                TileKind::Slot => self.fetch_slot_tile(entry_id, *tile_id, *full),
+                TileKind::SlotMeta => self.fetch_slot_meta_tile(entry_id, *tile_id, *full),
+            }
+        }
+    }
+    // Tells the data source that tiles are no longer needed. Every request
+    // is still answered, but requests that hadn't completed yet may be
+    // answered with DataSourceError::Cancelled instead of the tile
+    fn cancel_tiles(&mut self, _requests: &[TileRequest]) {}
+    fn fetch_search(&mut self, query: &SearchQuery);
+    fn get_search_results(&mut self) -> Vec<SearchResponse>;
 }
 
 pub struct DeferredDataSourceWrapper<T: DataSource> {
     data_source: T,
-    infos: Vec<DataSourceInfo>,
-    summary_tiles: Vec<SummaryTile>,
-    slot_tiles: Vec<SlotTile>,
-    slot_meta_tiles: Vec<SlotMetaTile>,
+    infos: Vec<DataSourceResult<DataSourceInfo>>,
+    summary_tiles: Vec<TileResult<SummaryTile>>,
+    counter_tiles: Vec<TileResult<CounterTile>>,
+    slot_tiles: Vec<TileResult<SlotTile>>,
+    slot_meta_tiles: Vec<TileResult<SlotMetaTile>>,
+    search_results: Vec<SearchResponse>,
 }
 
 impl<T: DataSource> DeferredDataSourceWrapper<T> {
@@ -29,8 +222,10 @@ impl<T: DataSource> DeferredDataSourceWrapper<T> {
             data_source,
             infos: Vec::new(),
             summary_tiles: Vec::new(),
+            counter_tiles: Vec::new(),
             slot_tiles: Vec::new(),
             slot_meta_tiles: Vec::new(),
+            search_results: Vec::new(),
         }
     }
 }
@@ -44,38 +239,78 @@ impl<T: DataSource> DeferredDataSource for DeferredDataSourceWrapper<T> {
         self.infos.push(self.data_source.fetch_info());
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
         std::mem::take(&mut self.infos)
     }
 
     fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let result = self.data_source.fetch_summary_tile(entry_id, tile_id, full);
         self.summary_tiles
-            .push(self.data_source.fetch_summary_tile(entry_id, tile_id, full));
+            .push(TileResult::new(entry_id, tile_id, full, result));
     }
 
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile> {
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
         std::mem::take(&mut self.summary_tiles)
     }
 
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let result = self.data_source.fetch_counter_tile(entry_id, tile_id, full);
+        self.counter_tiles
+            .push(TileResult::new(entry_id, tile_id, full, result));
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        std::mem::take(&mut self.counter_tiles)
+    }
+
     fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let result = self.data_source.fetch_slot_tile(entry_id, tile_id, full);
         self.slot_tiles
-            .push(self.data_source.fetch_slot_tile(entry_id, tile_id, full));
+            .push(TileResult::new(entry_id, tile_id, full, result));
     }
 
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile> {
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
         std::mem::take(&mut self.slot_tiles)
     }
 
     fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        self.slot_meta_tiles.push(
-            self.data_source
-                .fetch_slot_meta_tile(entry_id, tile_id, full),
-        );
+        let result = self
+            .data_source
+            .fetch_slot_meta_tile(entry_id, tile_id, full);
+        self.slot_meta_tiles
+            .push(TileResult::new(entry_id, tile_id, full, result));
     }
 
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile> {
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
         std::mem::take(&mut self.slot_meta_tiles)
     }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        let results = self.data_source.fetch_tiles(requests);
+        sort_tile_results(
+            requests,
+            results,
+            &mut self.summary_tiles,
+            &mut self.counter_tiles,
+            &mut self.slot_tiles,
+            &mut self.slot_meta_tiles,
+        );
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        // A search scans the whole profile, and this would run it on the
+        // caller's (i.e., the UI) thread. Decline, so that the viewer
+        // searches the tiles it loads instead. Use ParallelDeferredDataSource
+        // to search in the background
+        let result = Err(DataSourceError::NotFound(
+            "search is not supported by this data source".to_owned(),
+        ));
+        self.search_results.push(SearchResponse::new(query, result));
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        std::mem::take(&mut self.search_results)
+    }
 }
 
 pub struct CountingDeferredDataSource<T: DeferredDataSource> {
@@ -95,6 +330,10 @@ impl<T: DeferredDataSource> CountingDeferredDataSource<T> {
         self.outstanding_requests
     }
 
+    pub fn data_source(&self) -> &T {
+        &self.data_source
+    }
+
     fn start_request(&mut self) {
         self.outstanding_requests += 1;
     }
@@ -117,7 +356,7 @@ impl<T: DeferredDataSource> DeferredDataSource for CountingDeferredDataSource<T>
         self.data_source.fetch_info()
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
         let result = self.data_source.get_infos();
         self.finish_request(result)
     }
@@ -127,17 +366,27 @@ impl<T: DeferredDataSource> DeferredDataSource for CountingDeferredDataSource<T>
         self.data_source.fetch_summary_tile(entry_id, tile_id, full)
     }
 
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile> {
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
         let result = self.data_source.get_summary_tiles();
         self.finish_request(result)
     }
 
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.start_request();
+        self.data_source.fetch_counter_tile(entry_id, tile_id, full)
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        let result = self.data_source.get_counter_tiles();
+        self.finish_request(result)
+    }
+
     fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
         self.start_request();
         self.data_source.fetch_slot_tile(entry_id, tile_id, full)
     }
 
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile> {
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
         let result = self.data_source.get_slot_tiles();
         self.finish_request(result)
     }
@@ -148,10 +397,491 @@ impl<T: DeferredDataSource> DeferredDataSource for CountingDeferredDataSource<T>
             .fetch_slot_meta_tile(entry_id, tile_id, full)
     }
 
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile> {
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
         let result = self.data_source.get_slot_meta_tiles();
         self.finish_request(result)
     }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        self.outstanding_requests += requests.len() as u64;
+        self.data_source.fetch_tiles(requests)
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        self.data_source.cancel_tiles(requests)
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        self.start_request();
+        self.data_source.fetch_search(query)
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        let result = self.data_source.get_search_results();
+        self.finish_request(result)
+    }
+}
+
+pub type CacheKey = (TileKind, EntryID, TileID, bool);
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub tiles: usize,
+    // Approximate, in bytes
+    pub size: usize,
+    pub capacity: usize,
+}
+
+struct CacheEntry {
+    tile: Tile,
+    size: usize,
+    last_use: u64,
+}
+
+// Tiles are evicted in least recently used order once the total
+// (approximate) size of the cache exceeds its capacity.
+pub struct TileCache {
+    entries: BTreeMap<CacheKey, CacheEntry>,
+    lru: BTreeMap<u64, CacheKey>,
+    next_use: u64,
+    stats: CacheStats,
+}
+
+// Keeps recently fetched tiles in memory, so that tiles requested again
+// (e.g., after panning back and forth) don't go back to the underlying data
+// source.
+pub struct CachingDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    cache: TileCache,
+    // Cache hits, waiting to be picked up by the get_* methods
+    summary_tiles: Vec<TileResult<SummaryTile>>,
+    counter_tiles: Vec<TileResult<CounterTile>>,
+    slot_tiles: Vec<TileResult<SlotTile>>,
+    slot_meta_tiles: Vec<TileResult<SlotMetaTile>>,
+}
+
+fn estimate_size(tile: &Tile) -> usize {
+    use std::mem::size_of;
+
+    let data_size = match tile {
+        Tile::Summary(tile) => tile.data.utilization.len() * size_of::<UtilPoint>(),
+        Tile::Counter(tile) => tile.data.points.len() * size_of::<CounterPoint>(),
+        Tile::Slot(tile) => {
+            let items: usize = tile.data.items.iter().map(|row| row.len()).sum();
+            items * size_of::<Item>() + tile.data.edges.len() * size_of::<ItemEdge>()
+        }
+        Tile::SlotMeta(tile) => tile
+            .data
+            .items
+            .iter()
+            .flatten()
+            .map(|item| {
+                // Ignores strings nested inside of fields
+                size_of::<ItemMeta>()
+                    + item.title.len()
+                    + item.fields.len() * size_of::<(FieldID, Field, Option<Color32>)>()
+            })
+            .sum(),
+    };
+    size_of::<Tile>() + data_size
+}
+
+impl TileCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            next_use: 0,
+            stats: CacheStats {
+                capacity,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // Does not count as a use of the tile, so that checking for a tile
+    // doesn't keep it alive
+    pub fn contains(&self, key: &CacheKey) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn get(&mut self, key: &CacheKey) -> Option<Tile> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.lru.remove(&entry.last_use);
+        entry.last_use = self.next_use;
+        self.lru.insert(self.next_use, key.clone());
+        self.next_use += 1;
+        Some(entry.tile.clone())
+    }
+
+    pub fn insert(&mut self, key: CacheKey, tile: Tile) {
+        let size = estimate_size(&tile);
+        if size > self.stats.capacity || self.entries.contains_key(&key) {
+            return;
+        }
+
+        while self.stats.size + size > self.stats.capacity {
+            let (_, old_key) = self.lru.pop_first().unwrap();
+            let old_entry = self.entries.remove(&old_key).unwrap();
+            self.stats.size -= old_entry.size;
+            self.stats.evictions += 1;
+        }
+
+        self.lru.insert(self.next_use, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                tile,
+                size,
+                last_use: self.next_use,
+            },
+        );
+        self.next_use += 1;
+        self.stats.size += size;
+        self.stats.tiles = self.entries.len();
+    }
+}
+
+impl<T: DeferredDataSource> CachingDeferredDataSource<T> {
+    pub fn new(data_source: T, capacity: usize) -> Self {
+        Self {
+            data_source,
+            cache: TileCache::new(capacity),
+            summary_tiles: Vec::new(),
+            counter_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    // Does not count as a use of the tile, so that checking for a tile
+    // doesn't keep it alive
+    pub fn contains(&self, request: &TileRequest) -> bool {
+        let key = (
+            request.kind,
+            request.entry_id.clone(),
+            request.tile_id,
+            request.full,
+        );
+        self.cache.contains(&key)
+    }
+
+    fn insert_results<R: Clone>(
+        &mut self,
+        kind: TileKind,
+        results: &[TileResult<R>],
+        wrap: impl Fn(R) -> Tile,
+    ) {
+        for result in results {
+            if let Ok(tile) = &result.result {
+                let key = (kind, result.entry_id.clone(), result.tile_id, result.full);
+                self.cache.insert(key, wrap(tile.clone()));
+            }
+        }
+    }
+
+    fn fetch(&mut self, kind: TileKind, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tiles(&[TileRequest {
+            kind,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        }]);
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for CachingDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Summary, entry_id, tile_id, full)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+        let tiles = self.data_source.get_summary_tiles();
+        self.insert_results(TileKind::Summary, &tiles, Tile::Summary);
+        let mut result = std::mem::take(&mut self.summary_tiles);
+        result.extend(tiles);
+        result
+    }
+
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Counter, entry_id, tile_id, full)
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        let tiles = self.data_source.get_counter_tiles();
+        self.insert_results(TileKind::Counter, &tiles, Tile::Counter);
+        let mut result = std::mem::take(&mut self.counter_tiles);
+        result.extend(tiles);
+        result
+    }
+
+    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Slot, entry_id, tile_id, full)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+        let tiles = self.data_source.get_slot_tiles();
+        self.insert_results(TileKind::Slot, &tiles, Tile::Slot);
+        let mut result = std::mem::take(&mut self.slot_tiles);
+        result.extend(tiles);
+        result
+    }
+
+    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::SlotMeta, entry_id, tile_id, full)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+        let tiles = self.data_source.get_slot_meta_tiles();
+        self.insert_results(TileKind::SlotMeta, &tiles, Tile::SlotMeta);
+        let mut result = std::mem::take(&mut self.slot_meta_tiles);
+        result.extend(tiles);
+        result
+    }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        let mut hits = Vec::new();
+        let mut hit_tiles = Vec::new();
+        let mut misses = Vec::new();
+        for req in requests {
+            let key = (req.kind, req.entry_id.clone(), req.tile_id, req.full);
+            if let Some(tile) = self.cache.get(&key) {
+                hits.push(req.clone());
+                hit_tiles.push(Ok(tile));
+            } else {
+                misses.push(req.clone());
+            }
+        }
+
+        sort_tile_results(
+            &hits,
+            hit_tiles,
+            &mut self.summary_tiles,
+            &mut self.counter_tiles,
+            &mut self.slot_tiles,
+            &mut self.slot_meta_tiles,
+        );
+        if !misses.is_empty() {
+            self.data_source.fetch_tiles(&misses);
+        }
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        self.data_source.cancel_tiles(requests)
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        self.data_source.fetch_search(query)
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        self.data_source.get_search_results()
+    }
+}
+
+// Merges requests for a tile that is already being fetched, and hands out
+// a copy of the response to each requester once it arrives.
+pub struct DeduplicatingDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    outstanding: BTreeMap<CacheKey, Waiters>,
+}
+
+#[derive(Debug, Default)]
+struct Waiters {
+    // Number of requesters waiting on the tile
+    total: u64,
+    // Requesters that have since cancelled. The request itself is only
+    // cancelled once all of them have
+    cancelled: u64,
+}
+
+impl<T: DeferredDataSource> DeduplicatingDeferredDataSource<T> {
+    pub fn new(data_source: T) -> Self {
+        Self {
+            data_source,
+            outstanding: BTreeMap::new(),
+        }
+    }
+
+    // Returns true if this is the first request for the tile
+    fn start_request(&mut self, req: &TileRequest) -> bool {
+        let key = (req.kind, req.entry_id.clone(), req.tile_id, req.full);
+        let waiters = self.outstanding.entry(key).or_default();
+        waiters.total += 1;
+        waiters.total == 1
+    }
+
+    // Returns true if this was the last requester still waiting on the tile
+    fn cancel_request(&mut self, req: &TileRequest) -> bool {
+        let key = (req.kind, req.entry_id.clone(), req.tile_id, req.full);
+        let Some(waiters) = self.outstanding.get_mut(&key) else {
+            return false;
+        };
+        if waiters.cancelled == waiters.total {
+            return false;
+        }
+        waiters.cancelled += 1;
+        waiters.cancelled == waiters.total
+    }
+
+    fn fan_out<R: Clone>(
+        &mut self,
+        kind: TileKind,
+        results: Vec<TileResult<R>>,
+    ) -> Vec<TileResult<R>> {
+        let mut fanned_out = Vec::new();
+        let mut retry = Vec::new();
+        for result in results {
+            let key = (kind, result.entry_id.clone(), result.tile_id, result.full);
+            let waiters = self.outstanding.remove(&key).unwrap_or(Waiters {
+                total: 1,
+                cancelled: 0,
+            });
+            let mut count = waiters.total;
+            let cancelled = matches!(result.result, Err(DataSourceError::Cancelled));
+            if cancelled && waiters.cancelled < waiters.total {
+                // Someone asked for the tile again after it was cancelled, so
+                // only the requesters who cancelled get the cancellation
+                count = waiters.cancelled;
+                retry.push(TileRequest {
+                    kind,
+                    entry_id: result.entry_id.clone(),
+                    tile_id: result.tile_id,
+                    full: result.full,
+                });
+                self.outstanding.insert(
+                    key,
+                    Waiters {
+                        total: waiters.total - waiters.cancelled,
+                        cancelled: 0,
+                    },
+                );
+            }
+            for _ in 0..count {
+                fanned_out.push(result.clone());
+            }
+        }
+        if !retry.is_empty() {
+            self.data_source.fetch_tiles(&retry);
+        }
+        fanned_out
+    }
+
+    fn fetch(&mut self, kind: TileKind, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tiles(&[TileRequest {
+            kind,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        }]);
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for DeduplicatingDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+        self.data_source.get_infos()
+    }
+
+    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Summary, entry_id, tile_id, full)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+        let tiles = self.data_source.get_summary_tiles();
+        self.fan_out(TileKind::Summary, tiles)
+    }
+
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Counter, entry_id, tile_id, full)
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        let tiles = self.data_source.get_counter_tiles();
+        self.fan_out(TileKind::Counter, tiles)
+    }
+
+    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Slot, entry_id, tile_id, full)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+        let tiles = self.data_source.get_slot_tiles();
+        self.fan_out(TileKind::Slot, tiles)
+    }
+
+    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::SlotMeta, entry_id, tile_id, full)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+        let tiles = self.data_source.get_slot_meta_tiles();
+        self.fan_out(TileKind::SlotMeta, tiles)
+    }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        let requests: Vec<_> = requests
+            .iter()
+            .filter(|req| self.start_request(req))
+            .cloned()
+            .collect();
+        if !requests.is_empty() {
+            self.data_source.fetch_tiles(&requests);
+        }
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        let requests: Vec<_> = requests
+            .iter()
+            .filter(|req| self.cancel_request(req))
+            .cloned()
+            .collect();
+        if !requests.is_empty() {
+            self.data_source.cancel_tiles(&requests);
+        }
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        self.data_source.fetch_search(query)
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        self.data_source.get_search_results()
+    }
 }
 
 impl DeferredDataSource for Box<dyn DeferredDataSource> {
@@ -163,7 +893,7 @@ impl DeferredDataSource for Box<dyn DeferredDataSource> {
         self.as_mut().fetch_info()
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
         self.as_mut().get_infos()
     }
 
@@ -171,15 +901,23 @@ impl DeferredDataSource for Box<dyn DeferredDataSource> {
         self.as_mut().fetch_summary_tile(entry_id, tile_id, full)
     }
 
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile> {
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
         self.as_mut().get_summary_tiles()
     }
 
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.as_mut().fetch_counter_tile(entry_id, tile_id, full)
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        self.as_mut().get_counter_tiles()
+    }
+
     fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
         self.as_mut().fetch_slot_tile(entry_id, tile_id, full)
     }
 
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile> {
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
         self.as_mut().get_slot_tiles()
     }
 
@@ -187,7 +925,447 @@ impl DeferredDataSource for Box<dyn DeferredDataSource> {
         self.as_mut().fetch_slot_meta_tile(entry_id, tile_id, full)
     }
 
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile> {
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
         self.as_mut().get_slot_meta_tiles()
     }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        self.as_mut().fetch_tiles(requests)
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        self.as_mut().cancel_tiles(requests)
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        self.as_mut().fetch_search(query)
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        self.as_mut().get_search_results()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{SlotMetaTileData, SlotTileData, SummaryTileData};
+    use crate::timestamp::{Interval, Timestamp};
+
+    // Records every request, and answers them (in order) when asked
+    #[derive(Default)]
+    struct MockDataSource {
+        requests: Vec<TileRequest>,
+        pending: Vec<TileRequest>,
+        cancelled: BTreeSet<TileRequest>,
+    }
+
+    impl MockDataSource {
+        fn request(&mut self, kind: TileKind, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            let req = TileRequest {
+                kind,
+                entry_id: entry_id.clone(),
+                tile_id,
+                full,
+            };
+            self.requests.push(req.clone());
+            self.pending.push(req);
+        }
+
+        fn take(&mut self, kind: TileKind) -> Vec<TileRequest> {
+            let (taken, rest) = std::mem::take(&mut self.pending)
+                .into_iter()
+                .partition(|req| req.kind == kind);
+            self.pending = rest;
+            taken
+        }
+    }
+
+    impl DeferredDataSource for MockDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&mut self) {
+            unimplemented!();
+        }
+
+        fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+            Vec::new()
+        }
+
+        fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            self.request(TileKind::Summary, entry_id, tile_id, full);
+        }
+
+        fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+            self.take(TileKind::Summary)
+                .into_iter()
+                .map(|req| {
+                    let tile = SummaryTile {
+                        entry_id: req.entry_id.clone(),
+                        tile_id: req.tile_id,
+                        data: SummaryTileData {
+                            utilization: vec![UtilPoint::default(); 10],
+                        },
+                    };
+                    TileResult::new(&req.entry_id, req.tile_id, req.full, Ok(tile))
+                })
+                .collect()
+        }
+
+        fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            self.request(TileKind::Counter, entry_id, tile_id, full);
+        }
+
+        // Counter tiles always fail, to check that errors are passed through
+        fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+            self.take(TileKind::Counter)
+                .into_iter()
+                .map(|req| {
+                    let error = DataSourceError::NotFound("counter".to_owned());
+                    TileResult::new(&req.entry_id, req.tile_id, req.full, Err(error))
+                })
+                .collect()
+        }
+
+        fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            self.request(TileKind::Slot, entry_id, tile_id, full);
+        }
+
+        // Only slot tiles can be cancelled
+        fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+            self.take(TileKind::Slot)
+                .into_iter()
+                .map(|req| {
+                    if self.cancelled.remove(&req) {
+                        let error = DataSourceError::Cancelled;
+                        return TileResult::new(&req.entry_id, req.tile_id, req.full, Err(error));
+                    }
+                    let tile = SlotTile {
+                        entry_id: req.entry_id.clone(),
+                        tile_id: req.tile_id,
+                        data: SlotTileData {
+                            items: Vec::new(),
+                            edges: Vec::new(),
+                        },
+                    };
+                    TileResult::new(&req.entry_id, req.tile_id, req.full, Ok(tile))
+                })
+                .collect()
+        }
+
+        fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+            self.request(TileKind::SlotMeta, entry_id, tile_id, full);
+        }
+
+        fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+            self.take(TileKind::SlotMeta)
+                .into_iter()
+                .map(|req| {
+                    let tile = SlotMetaTile {
+                        entry_id: req.entry_id.clone(),
+                        tile_id: req.tile_id,
+                        data: SlotMetaTileData { items: Vec::new() },
+                    };
+                    TileResult::new(&req.entry_id, req.tile_id, req.full, Ok(tile))
+                })
+                .collect()
+        }
+
+        fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+            self.cancelled.extend(requests.iter().cloned());
+        }
+
+        fn fetch_search(&mut self, _query: &SearchQuery) {
+            unimplemented!();
+        }
+
+        fn get_search_results(&mut self) -> Vec<SearchResponse> {
+            Vec::new()
+        }
+    }
+
+    fn entry(i: u64) -> EntryID {
+        EntryID::root().child(0).child(0).child(i)
+    }
+
+    fn tile(i: i64) -> TileID {
+        TileID(Interval::new(Timestamp(i * 10), Timestamp((i + 1) * 10)))
+    }
+
+    fn summary_size() -> usize {
+        let mut mock = MockDataSource::default();
+        mock.fetch_summary_tile(&entry(0), tile(0), false);
+        let tile = mock.get_summary_tiles().pop().unwrap().result.unwrap();
+        estimate_size(&Tile::Summary(tile))
+    }
+
+    #[test]
+    fn test_dedup_pending() {
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        for _ in 0..3 {
+            dedup.fetch_slot_tile(&entry(0), tile(0), false);
+        }
+        assert_eq!(dedup.data_source.requests.len(), 1);
+
+        let tiles = dedup.get_slot_tiles();
+        assert_eq!(tiles.len(), 3);
+        for t in &tiles {
+            assert_eq!(t.entry_id, entry(0));
+            assert_eq!(t.tile_id, tile(0));
+            assert!(t.result.is_ok());
+        }
+        assert!(dedup.outstanding.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_after_response() {
+        // Deduplication only applies while the request is outstanding
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        dedup.fetch_slot_meta_tile(&entry(0), tile(0), false);
+        assert_eq!(dedup.get_slot_meta_tiles().len(), 1);
+        dedup.fetch_slot_meta_tile(&entry(0), tile(0), false);
+        assert_eq!(dedup.get_slot_meta_tiles().len(), 1);
+        assert_eq!(dedup.data_source.requests.len(), 2);
+    }
+
+    #[test]
+    fn test_dedup_distinct() {
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        dedup.fetch_slot_tile(&entry(0), tile(0), false);
+        dedup.fetch_slot_tile(&entry(0), tile(0), true);
+        dedup.fetch_slot_tile(&entry(1), tile(0), false);
+        dedup.fetch_slot_meta_tile(&entry(0), tile(0), false);
+        assert_eq!(dedup.data_source.requests.len(), 4);
+        assert_eq!(dedup.get_slot_tiles().len(), 3);
+        assert_eq!(dedup.get_slot_meta_tiles().len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_errors() {
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        dedup.fetch_counter_tile(&entry(0).counter(0), tile(0), false);
+        dedup.fetch_counter_tile(&entry(0).counter(0), tile(0), false);
+        let tiles = dedup.get_counter_tiles();
+        assert_eq!(tiles.len(), 2);
+        assert!(tiles.iter().all(|t| t.result.is_err()));
+        assert_eq!(dedup.data_source.requests.len(), 1);
+    }
+
+    #[test]
+    fn test_dedup_batch() {
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        dedup.fetch_summary_tile(&entry(0), tile(0), false);
+        let req = |i| TileRequest {
+            kind: TileKind::Summary,
+            entry_id: entry(0),
+            tile_id: tile(i),
+            full: false,
+        };
+        dedup.fetch_tiles(&[req(0), req(1), req(1)]);
+        assert_eq!(dedup.data_source.requests, vec![req(0), req(1)]);
+
+        let tiles = dedup.get_summary_tiles();
+        assert_eq!(tiles.iter().filter(|t| t.tile_id == tile(0)).count(), 2);
+        assert_eq!(tiles.iter().filter(|t| t.tile_id == tile(1)).count(), 2);
+    }
+
+    fn slot_request(i: u64) -> TileRequest {
+        TileRequest {
+            kind: TileKind::Slot,
+            entry_id: entry(i),
+            tile_id: tile(0),
+            full: false,
+        }
+    }
+
+    #[test]
+    fn test_pending_jobs() {
+        let mut jobs = PendingJobs::default();
+        let (_, single) = jobs.start(&[slot_request(0)]);
+        let (_, batch) = jobs.start(&[slot_request(1), slot_request(2)]);
+        let (finished_id, finished) = jobs.start(&[slot_request(3)]);
+        jobs.finish(finished_id);
+
+        jobs.cancel(&[slot_request(0), slot_request(1), slot_request(3)]);
+        assert!(single.is_cancelled());
+        // Part of the batch is still wanted
+        assert!(!batch.is_cancelled());
+        assert!(!finished.is_cancelled());
+
+        jobs.cancel(&[slot_request(2)]);
+        assert!(batch.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancel_tile_requests() {
+        let jobs = Mutex::new(PendingJobs::default());
+        let (_, sent) = jobs.lock().unwrap().start(&[slot_request(0)]);
+        let mut pending = vec![slot_request(1), slot_request(2)];
+        let (mut summary, mut counter, mut slot, mut slot_meta) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        cancel_tile_requests(
+            &[slot_request(0), slot_request(1)],
+            &mut pending,
+            &jobs,
+            &mut summary,
+            &mut counter,
+            &mut slot,
+            &mut slot_meta,
+        );
+        assert_eq!(pending, [slot_request(2)]);
+        assert!(sent.is_cancelled());
+        // Only the queued request is answered here
+        assert_eq!(slot.len(), 1);
+        assert!(matches!(slot[0].result, Err(DataSourceError::Cancelled)));
+    }
+
+    #[test]
+    fn test_dedup_cancel() {
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        dedup.fetch_tiles(&[slot_request(0), slot_request(0)]);
+
+        // Only cancelled once nobody is waiting
+        dedup.cancel_tiles(&[slot_request(0)]);
+        assert!(dedup.data_source.cancelled.is_empty());
+        dedup.cancel_tiles(&[slot_request(0)]);
+        assert!(dedup.data_source.cancelled.contains(&slot_request(0)));
+
+        let tiles = dedup.get_slot_tiles();
+        assert_eq!(tiles.len(), 2);
+        assert!(tiles
+            .iter()
+            .all(|t| matches!(t.result, Err(DataSourceError::Cancelled))));
+        assert!(dedup.outstanding.is_empty());
+    }
+
+    #[test]
+    fn test_dedup_cancel_refetch() {
+        let mut dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        dedup.fetch_tiles(&[slot_request(0)]);
+        dedup.cancel_tiles(&[slot_request(0)]);
+        dedup.fetch_tiles(&[slot_request(0)]);
+
+        // The cancelled request gets the cancellation, and the tile is
+        // requested again on behalf of the new one
+        let tiles = dedup.get_slot_tiles();
+        assert_eq!(tiles.len(), 1);
+        assert!(matches!(tiles[0].result, Err(DataSourceError::Cancelled)));
+        assert_eq!(dedup.data_source.requests.len(), 2);
+
+        let tiles = dedup.get_slot_tiles();
+        assert_eq!(tiles.len(), 1);
+        assert!(tiles[0].result.is_ok());
+    }
+
+    #[test]
+    fn test_dedup_counting() {
+        // Every request still receives a response, so the count balances
+        let dedup = DeduplicatingDeferredDataSource::new(MockDataSource::default());
+        let mut counting = CountingDeferredDataSource::new(dedup);
+        counting.fetch_summary_tile(&entry(0), tile(0), false);
+        counting.fetch_summary_tile(&entry(0), tile(0), false);
+        assert_eq!(counting.outstanding_requests(), 2);
+        assert_eq!(counting.get_summary_tiles().len(), 2);
+        assert_eq!(counting.outstanding_requests(), 0);
+    }
+
+    #[test]
+    fn test_cache_hit() {
+        let mut cache = CachingDeferredDataSource::new(MockDataSource::default(), 1 << 20);
+        cache.fetch_summary_tile(&entry(0), tile(0), false);
+        assert_eq!(cache.get_summary_tiles().len(), 1);
+
+        cache.fetch_summary_tile(&entry(0), tile(0), false);
+        let tiles = cache.get_summary_tiles();
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].entry_id, entry(0));
+        assert_eq!(tiles[0].tile_id, tile(0));
+        assert!(tiles[0].result.is_ok());
+
+        assert_eq!(cache.data_source.requests.len(), 1);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.tiles), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_cache_contains() {
+        let mut cache = CachingDeferredDataSource::new(MockDataSource::default(), 1 << 20);
+        let request = TileRequest {
+            kind: TileKind::Summary,
+            entry_id: entry(0),
+            tile_id: tile(0),
+            full: false,
+        };
+        cache.fetch_tiles(std::slice::from_ref(&request));
+        assert!(!cache.contains(&request));
+
+        cache.get_summary_tiles();
+        assert!(cache.contains(&request));
+        assert!(!cache.contains(&TileRequest {
+            full: true,
+            ..request
+        }));
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let mut cache = CachingDeferredDataSource::new(MockDataSource::default(), 1 << 20);
+        cache.fetch_slot_tile(&entry(0), tile(0), false);
+        cache.fetch_slot_meta_tile(&entry(0), tile(0), false);
+        cache.get_slot_tiles();
+        cache.get_slot_meta_tiles();
+
+        // Differs by kind, entry, tile and full, respectively
+        cache.fetch_summary_tile(&entry(0), tile(0), false);
+        cache.fetch_slot_tile(&entry(1), tile(0), false);
+        cache.fetch_slot_tile(&entry(0), tile(1), false);
+        cache.fetch_slot_tile(&entry(0), tile(0), true);
+        assert_eq!(cache.data_source.requests.len(), 6);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn test_cache_errors() {
+        let mut cache = CachingDeferredDataSource::new(MockDataSource::default(), 1 << 20);
+        for _ in 0..2 {
+            cache.fetch_counter_tile(&entry(0).counter(0), tile(0), false);
+            let tiles = cache.get_counter_tiles();
+            assert_eq!(tiles.len(), 1);
+            assert!(tiles[0].result.is_err());
+        }
+        assert_eq!(cache.data_source.requests.len(), 2);
+        assert_eq!(cache.stats().tiles, 0);
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let size = summary_size();
+        let mut cache = CachingDeferredDataSource::new(MockDataSource::default(), 2 * size);
+        for i in 0..3 {
+            cache.fetch_summary_tile(&entry(0), tile(i), false);
+            if i == 1 {
+                // Touch tile 0 so that tile 1 is evicted instead
+                cache.get_summary_tiles();
+                cache.fetch_summary_tile(&entry(0), tile(0), false);
+            }
+            cache.get_summary_tiles();
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.tiles, 2);
+        assert_eq!(stats.size, 2 * size);
+
+        cache.fetch_summary_tile(&entry(0), tile(0), false);
+        cache.fetch_summary_tile(&entry(0), tile(1), false);
+        cache.get_summary_tiles();
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.data_source.requests.len(), 4);
+    }
 }
@@ -1,32 +1,61 @@
-use std::fs::File;
 use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
+use crate::container::{tile_key, DICTIONARY_KEY, INFO_KEY};
 use crate::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile,
-    SummaryTile, TileID,
+    CounterTile, DataSource, DataSourceDescription, DataSourceInfo, DataSourceResult, EntryID,
+    SearchQuery, SearchResults, SlotMetaTile, SlotTile, SummaryTile, TileID, TileKind,
 };
-use crate::http::schema::TileRequestRef;
+use crate::file_io::{io_error, read_optional};
+use crate::format;
+use crate::manifest::{Manifest, MANIFEST_KEY};
+use crate::search;
 
 pub struct FileDataSource {
     pub basedir: PathBuf,
+    // The archive's manifest and dictionary. Archives written before the
+    // manifest was introduced don't have one, so their tiles aren't checked,
+    // and archives without a dictionary have an empty one. Failure to read
+    // either is reported on every fetch
+    state: DataSourceResult<(Manifest, Vec<u8>)>,
 }
 
 impl FileDataSource {
     pub fn new(basedir: impl AsRef<Path>) -> Self {
-        Self {
-            basedir: basedir.as_ref().to_owned(),
-        }
+        let basedir = basedir.as_ref().to_owned();
+        let state = Self::open(&basedir);
+        Self { basedir, state }
+    }
+
+    fn open(basedir: &Path) -> DataSourceResult<(Manifest, Vec<u8>)> {
+        let manifest = read_optional(&basedir.join(MANIFEST_KEY))?;
+        let manifest = Manifest::parse(&String::from_utf8_lossy(&manifest))?;
+        let dictionary = read_optional(&basedir.join(DICTIONARY_KEY))?;
+        manifest.check(DICTIONARY_KEY, &dictionary)?;
+        Ok((manifest, dictionary))
     }
 
-    fn read_file<T>(&self, path: impl AsRef<Path>) -> T
+    fn read_blob(&self, key: &str) -> DataSourceResult<Vec<u8>> {
+        let (manifest, _) = self.state.as_ref().map_err(Clone::clone)?;
+        let path = self.basedir.join(key);
+        let blob = std::fs::read(&path).map_err(|e| io_error(&path, e))?;
+        manifest.check(key, &blob)?;
+        Ok(blob)
+    }
+
+    fn read_tile<T>(
+        &self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tile_id: TileID,
+    ) -> DataSourceResult<T>
     where
         T: for<'a> Deserialize<'a>,
     {
-        let f = File::open(path).expect("opening file failed");
-        let f = zstd::Decoder::new(f).expect("zstd decompression failed");
-        ciborium::from_reader(f).expect("cbor decoding failed")
+        let blob = self.read_blob(&tile_key(kind, entry_id, tile_id))?;
+        let (_, dictionary) = self.state.as_ref().map_err(Clone::clone)?;
+        format::read_with_dictionary(&blob[..], "archive", dictionary)
     }
 }
 
@@ -36,23 +65,35 @@ impl DataSource for FileDataSource {
             source_locator: vec![String::from(self.basedir.to_string_lossy())],
         }
     }
-    fn fetch_info(&self) -> DataSourceInfo {
-        let path = self.basedir.join("info");
-        self.read_file::<DataSourceInfo>(&path)
+    fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+        format::read(&self.read_blob(INFO_KEY)?[..], "archive")
+    }
+
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SummaryTile> {
+        self.read_tile(TileKind::Summary, entry_id, tile_id)
     }
 
-    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut path = self.basedir.join("summary_tile");
-        path.push(&req.to_slug());
-        self.read_file::<SummaryTile>(&path)
+    fn fetch_counter_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<CounterTile> {
+        self.read_tile(TileKind::Counter, entry_id, tile_id)
     }
 
-    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut path = self.basedir.join("slot_tile");
-        path.push(&req.to_slug());
-        self.read_file::<SlotTile>(&path)
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotTile> {
+        self.read_tile(TileKind::Slot, entry_id, tile_id)
     }
 
     fn fetch_slot_meta_tile(
@@ -60,10 +101,11 @@ impl DataSource for FileDataSource {
         entry_id: &EntryID,
         tile_id: TileID,
         _full: bool,
-    ) -> SlotMetaTile {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut path = self.basedir.join("slot_meta_tile");
-        path.push(&req.to_slug());
-        self.read_file::<SlotMetaTile>(&path)
+    ) -> DataSourceResult<SlotMetaTile> {
+        self.read_tile(TileKind::SlotMeta, entry_id, tile_id)
+    }
+
+    fn search(&self, query: &SearchQuery) -> DataSourceResult<SearchResults> {
+        search::scan_slot_meta_tiles(self, query)
     }
 }
@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::container::BlobRange;
+use crate::data::{DataSourceError, DataSourceResult};
+
+pub fn io_error(path: &Path, e: io::Error) -> DataSourceError {
+    match e.kind() {
+        io::ErrorKind::NotFound => DataSourceError::NotFound(path.display().to_string()),
+        _ => DataSourceError::Io(format!("{}: {}", path.display(), e)),
+    }
+}
+
+// Missing files are treated as empty
+pub fn read_optional(path: &Path) -> DataSourceResult<Vec<u8>> {
+    match std::fs::read(path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+// Reads a blob from a single-file archive with a positional read, which
+// (unlike seeking and reading) lets threads share the file without a lock.
+// Unlike a memory map, a file that is truncated or rewritten while it's open
+// produces errors rather than crashing the process (with SIGBUS).
+pub fn read_at(file: &File, range: BlobRange) -> io::Result<Vec<u8>> {
+    // Check first, so that a corrupt table of contents can't make us
+    // allocate more than the file holds
+    let len = file.metadata()?.len();
+    if range
+        .offset
+        .checked_add(range.length)
+        .map_or(true, |end| end > len)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "single-file archive is truncated",
+        ));
+    }
+    let mut blob = vec![0; range.length as usize];
+    read_exact_at(file, &mut blob, range.offset)?;
+    Ok(blob)
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use std::io::{self, BufRead, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use zstd::dict::EncoderDictionary;
+use zstd::stream::raw::{Decoder, Operation};
+
+use crate::data::{DataSourceError, DataSourceResult};
+
+// Every file in an archive, and every response from the server, is laid out
+// as an uncompressed CBOR FormatHeader followed by a zstd-compressed CBOR
+// payload. Archives written before the header was introduced consist of the
+// payload only; we treat these as schema version 0.
+//
+// Archives may also include a zstd dictionary (trained on their tiles), in
+// which case every tile's payload is compressed with it.
+
+pub const MAGIC: u32 = 0x4c50_5646; // "LPVF"
+
+// Bump this whenever the layout of the payload changes in a way that serde
+// defaults can't paper over, and add a step to migrate() below.
+pub const SCHEMA_VERSION: u32 = 1;
+pub const MIN_SCHEMA_VERSION: u32 = 0;
+
+pub const PRODUCER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FormatHeader {
+    pub magic: u32,
+    pub schema_version: u32,
+    pub producer_version: String,
+}
+
+impl FormatHeader {
+    pub fn current() -> Self {
+        Self {
+            magic: MAGIC,
+            schema_version: SCHEMA_VERSION,
+            producer_version: PRODUCER_VERSION.to_owned(),
+        }
+    }
+
+    // The kind of data (e.g., "archive") is used to make error messages
+    // more helpful
+    pub fn check(&self, kind: &str) -> DataSourceResult<()> {
+        if self.magic != MAGIC {
+            return Err(DataSourceError::Decode(format!(
+                "{kind} has an unrecognized format (bad magic number {:#x})",
+                self.magic
+            )));
+        }
+        if !(MIN_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&self.schema_version) {
+            return Err(DataSourceError::ProtocolVersion(format!(
+                "{kind} was written by version {} (producer {}), viewer supports {}..{}",
+                self.schema_version, self.producer_version, MIN_SCHEMA_VERSION, SCHEMA_VERSION
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub fn write<T, W>(mut writer: W, data: &T, zstd_compression: i32) -> io::Result<W>
+where
+    T: Serialize,
+    W: Write,
+{
+    ciborium::into_writer(&FormatHeader::current(), &mut writer).expect("ciborium encoding failed");
+    let mut f = zstd::Encoder::new(writer, zstd_compression)?;
+    ciborium::into_writer(data, &mut f).expect("ciborium encoding failed");
+    f.finish()
+}
+
+// The compression level is part of the dictionary
+pub fn write_with_dictionary<T, W>(
+    mut writer: W,
+    data: &T,
+    dictionary: &EncoderDictionary<'_>,
+) -> io::Result<W>
+where
+    T: Serialize,
+    W: Write,
+{
+    ciborium::into_writer(&FormatHeader::current(), &mut writer).expect("ciborium encoding failed");
+    let mut f = zstd::Encoder::with_prepared_dictionary(writer, dictionary)?;
+    ciborium::into_writer(data, &mut f).expect("ciborium encoding failed");
+    f.finish()
+}
+
+// Returns the schema version of the payload that follows
+fn read_header<R: BufRead>(reader: &mut R, kind: &str) -> DataSourceResult<u32> {
+    let legacy = reader.fill_buf()?.starts_with(&ZSTD_MAGIC);
+    if legacy {
+        return Ok(0);
+    }
+    let header: FormatHeader = ciborium::from_reader(reader)?;
+    header.check(kind)?;
+    Ok(header.schema_version)
+}
+
+fn decode_payload<T, R>(payload: R, schema_version: u32) -> DataSourceResult<T>
+where
+    T: for<'a> Deserialize<'a>,
+    R: Read,
+{
+    if schema_version == SCHEMA_VERSION {
+        return Ok(ciborium::from_reader(payload)?);
+    }
+
+    let mut value: ciborium::Value = ciborium::from_reader(payload)?;
+    migrate(&mut value, schema_version)?;
+    value
+        .deserialized()
+        .map_err(|e| DataSourceError::Decode(e.to_string()))
+}
+
+pub fn read<T, R>(reader: R, kind: &str) -> DataSourceResult<T>
+where
+    T: for<'a> Deserialize<'a>,
+    R: BufRead,
+{
+    read_with_dictionary(reader, kind, &[])
+}
+
+// An empty dictionary is the same as none
+pub fn read_with_dictionary<T, R>(
+    mut reader: R,
+    kind: &str,
+    dictionary: &[u8],
+) -> DataSourceResult<T>
+where
+    T: for<'a> Deserialize<'a>,
+    R: BufRead,
+{
+    let schema_version = read_header(&mut reader, kind)?;
+    let f = zstd::Decoder::with_dictionary(reader, dictionary)?;
+    decode_payload(f, schema_version)
+}
+
+// Reads whole blobs, reusing the same zstd context (with its dictionary
+// already loaded) and buffers from one blob to the next. This is much
+// cheaper than read() when decoding many small blobs.
+pub struct Decompressor {
+    decoder: Decoder<'static>,
+    buffer: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl Decompressor {
+    pub fn new() -> io::Result<Self> {
+        Self::with_dictionary(&[])
+    }
+
+    pub fn with_dictionary(dictionary: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            decoder: Decoder::with_dictionary(dictionary)?,
+            buffer: vec![0; zstd::Decoder::<&[u8]>::recommended_output_size()],
+            payload: Vec::new(),
+        })
+    }
+
+    fn decompress(&mut self, mut input: &[u8]) -> io::Result<()> {
+        self.decoder.reinit()?;
+        self.payload.clear();
+        loop {
+            let status = self.decoder.run_on_buffers(input, &mut self.buffer)?;
+            self.payload
+                .extend_from_slice(&self.buffer[..status.bytes_written]);
+            input = &input[status.bytes_read..];
+            // Zero means the frame is complete (and entirely flushed)
+            if status.remaining == 0 {
+                return Ok(());
+            }
+            if input.is_empty() && status.bytes_written == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "incomplete zstd frame",
+                ));
+            }
+        }
+    }
+
+    pub fn read<T>(&mut self, mut blob: &[u8], kind: &str) -> DataSourceResult<T>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let schema_version = read_header(&mut blob, kind)?;
+        self.decompress(blob)?;
+        decode_payload(&self.payload[..], schema_version)
+    }
+}
+
+// Upgrade a payload written with an older schema version, one version at a
+// time, so that it can be decoded with the current definitions in data.rs.
+fn migrate(_value: &mut ciborium::Value, from_version: u32) -> DataSourceResult<()> {
+    for version in from_version..SCHEMA_VERSION {
+        match version {
+            // Version 0 predates the header, but otherwise has the same
+            // layout (newer fields all have serde defaults)
+            0 => {}
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::{EntryID, SummaryTile, SummaryTileData, TileID, UtilPoint};
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn tile() -> SummaryTile {
+        SummaryTile {
+            entry_id: EntryID::root().child(3).summary(),
+            tile_id: TileID(Interval::new(Timestamp(0), Timestamp(10))),
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: Timestamp(5),
+                    util: 0.5,
+                }],
+            },
+        }
+    }
+
+    fn write_with_header<T: Serialize>(header: &FormatHeader, data: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(header, &mut buf).unwrap();
+        let mut f = zstd::Encoder::new(buf, 1).unwrap();
+        ciborium::into_writer(data, &mut f).unwrap();
+        f.finish().unwrap()
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let buf = write(Vec::new(), &tile(), 1).unwrap();
+        let result: SummaryTile = read(&buf[..], "archive").unwrap();
+        assert_eq!(result.entry_id, tile().entry_id);
+        assert_eq!(result.data.utilization, tile().data.utilization);
+    }
+
+    #[test]
+    fn test_legacy() {
+        let mut f = zstd::Encoder::new(Vec::new(), 1).unwrap();
+        ciborium::into_writer(&tile(), &mut f).unwrap();
+        let buf = f.finish().unwrap();
+
+        let result: SummaryTile = read(&buf[..], "archive").unwrap();
+        assert_eq!(result.tile_id, tile().tile_id);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let header = FormatHeader {
+            schema_version: SCHEMA_VERSION + 1,
+            producer_version: "9.9.9".to_owned(),
+            ..FormatHeader::current()
+        };
+        let buf = write_with_header(&header, &tile());
+
+        let result: DataSourceResult<SummaryTile> = read(&buf[..], "archive");
+        assert_eq!(
+            result.unwrap_err(),
+            DataSourceError::ProtocolVersion(format!(
+                "archive was written by version {} (producer 9.9.9), viewer supports {}..{}",
+                SCHEMA_VERSION + 1,
+                MIN_SCHEMA_VERSION,
+                SCHEMA_VERSION
+            ))
+        );
+    }
+
+    #[test]
+    fn test_dictionary() {
+        let samples: Vec<_> = (0..100)
+            .map(|i| {
+                let mut sample = Vec::new();
+                let mut t = tile();
+                t.data.utilization[0].time = Timestamp(i);
+                ciborium::into_writer(&t, &mut sample).unwrap();
+                sample
+            })
+            .collect();
+        let dictionary = zstd::dict::from_samples(&samples, 1 << 10).unwrap();
+        let prepared = EncoderDictionary::copy(&dictionary, 1);
+        let buf = write_with_dictionary(Vec::new(), &tile(), &prepared).unwrap();
+
+        let result: SummaryTile = read_with_dictionary(&buf[..], "archive", &dictionary).unwrap();
+        assert_eq!(result.data.utilization, tile().data.utilization);
+        assert!(read::<SummaryTile, _>(&buf[..], "archive").is_err());
+
+        // Contexts are reused from one read to the next
+        let mut decompressor = Decompressor::with_dictionary(&dictionary).unwrap();
+        for _ in 0..2 {
+            let result: SummaryTile = decompressor.read(&buf, "archive").unwrap();
+            assert_eq!(result.data.utilization, tile().data.utilization);
+        }
+        let truncated = &buf[..buf.len() - 4];
+        assert!(decompressor
+            .read::<SummaryTile>(truncated, "archive")
+            .is_err());
+    }
+
+    #[test]
+    fn test_decompressor() {
+        let mut decompressor = Decompressor::new().unwrap();
+        let buf = write(Vec::new(), &tile(), 1).unwrap();
+        let result: SummaryTile = decompressor.read(&buf, "archive").unwrap();
+        assert_eq!(result.tile_id, tile().tile_id);
+
+        let mut f = zstd::Encoder::new(Vec::new(), 1).unwrap();
+        ciborium::into_writer(&tile(), &mut f).unwrap();
+        let legacy = f.finish().unwrap();
+        let result: SummaryTile = decompressor.read(&legacy, "archive").unwrap();
+        assert_eq!(result.tile_id, tile().tile_id);
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        let header = FormatHeader {
+            magic: 0x1234,
+            ..FormatHeader::current()
+        };
+        let buf = write_with_header(&header, &tile());
+
+        let result: DataSourceResult<SummaryTile> = read(&buf[..], "archive");
+        assert!(matches!(result, Err(DataSourceError::Decode(_))));
+    }
+}
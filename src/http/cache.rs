@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use reqwest::header::{HeaderMap, CACHE_CONTROL, ETAG};
+
+use url::Url;
+
+// Keeps GET responses according to their Cache-Control and ETag headers, so
+// that responses that are still fresh aren't requested again, and stale ones
+// are revalidated (with If-None-Match) rather than downloaded again. Browsers
+// have an HTTP cache of their own, so this is only used on native builds.
+//
+// The time is passed in, rather than read, since std::time::Instant::now
+// isn't available on the web.
+pub struct HTTPCache {
+    state: Mutex<CacheState>,
+}
+
+pub enum CacheLookup {
+    // Can be used without asking the server
+    Fresh(Bytes),
+    // Can be used if the server says the ETag is still current
+    Stale { etag: String, body: Bytes },
+    Miss,
+}
+
+// Responses are kept by URL and Accept header, since servers choose the
+// format of a response from the latter (and say so with Vary: Accept)
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CacheKey {
+    url: Url,
+    accept: String,
+}
+
+impl CacheKey {
+    pub fn new(url: Url, accept: &str) -> Self {
+        Self {
+            url,
+            accept: accept.to_owned(),
+        }
+    }
+}
+
+struct CachedResponse {
+    body: Bytes,
+    etag: Option<String>,
+    expires: Instant,
+    order: u64,
+}
+
+// Responses are evicted oldest first once the total size of their bodies
+// exceeds the capacity
+struct CacheState {
+    entries: BTreeMap<CacheKey, CachedResponse>,
+    order: BTreeMap<u64, CacheKey>,
+    next_order: u64,
+    size: usize,
+    capacity: usize,
+}
+
+// How long a response may be used without revalidating it, or None if it
+// must not be stored at all. Without a Cache-Control header, responses are
+// always revalidated. No-store and no-cache override max-age, wherever they
+// appear
+fn max_age(headers: &HeaderMap) -> Option<Duration> {
+    let (mut max_age, mut no_cache) = (Duration::ZERO, false);
+    for value in headers.get_all(CACHE_CONTROL) {
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        for directive in value.split(',').map(|d| d.trim().to_ascii_lowercase()) {
+            if directive == "no-store" {
+                return None;
+            }
+            if directive == "no-cache" {
+                no_cache = true;
+            }
+            if let Some(secs) = directive.strip_prefix("max-age=") {
+                max_age = Duration::from_secs(secs.trim_matches('"').parse().unwrap_or(0));
+            }
+        }
+    }
+    Some(if no_cache { Duration::ZERO } else { max_age })
+}
+
+// Whether responses with these headers can be used for a while without
+// asking the server again
+pub fn reusable(headers: &HeaderMap) -> bool {
+    max_age(headers).is_some_and(|max_age| !max_age.is_zero())
+}
+
+fn etag(headers: &HeaderMap) -> Option<String> {
+    let etag = headers.get(ETAG)?.to_str().ok()?;
+    Some(etag.to_owned())
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &CacheKey) -> Option<CachedResponse> {
+        let entry = self.entries.remove(key)?;
+        self.order.remove(&entry.order);
+        self.size -= entry.body.len();
+        Some(entry)
+    }
+}
+
+impl HTTPCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                entries: BTreeMap::new(),
+                order: BTreeMap::new(),
+                next_order: 0,
+                size: 0,
+                capacity,
+            }),
+        }
+    }
+
+    pub fn lookup(&self, key: &CacheKey, now: Instant) -> CacheLookup {
+        let mut state = self.state.lock().unwrap();
+        let Some(entry) = state.entries.get(key) else {
+            return CacheLookup::Miss;
+        };
+        if now < entry.expires {
+            return CacheLookup::Fresh(entry.body.clone());
+        }
+        match &entry.etag {
+            Some(etag) => CacheLookup::Stale {
+                etag: etag.clone(),
+                body: entry.body.clone(),
+            },
+            // Nothing to revalidate with, so it's no use anymore
+            None => {
+                state.remove(key);
+                CacheLookup::Miss
+            }
+        }
+    }
+
+    // Whether lookup would find the response, either fresh or with an ETag
+    // to revalidate it with
+    pub fn contains(&self, key: &CacheKey, now: Instant) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .get(key)
+            .is_some_and(|entry| now < entry.expires || entry.etag.is_some())
+    }
+
+    pub fn store(&self, key: CacheKey, headers: &HeaderMap, body: Bytes, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+
+        let Some(max_age) = max_age(headers) else {
+            return;
+        };
+        let etag = etag(headers);
+        if (max_age.is_zero() && etag.is_none()) || body.len() > state.capacity {
+            return;
+        }
+
+        while state.size + body.len() > state.capacity {
+            let (_, old_key) = state.order.pop_first().unwrap();
+            state.remove(&old_key);
+        }
+
+        let order = state.next_order;
+        state.next_order += 1;
+        state.size += body.len();
+        state.order.insert(order, key.clone());
+        state.entries.insert(
+            key,
+            CachedResponse {
+                body,
+                etag,
+                expires: now + max_age,
+                order,
+            },
+        );
+    }
+
+    // Called when the server says a stale response is still current (with
+    // 304 Not Modified), which comes with a new lifetime
+    pub fn refresh(&self, key: &CacheKey, headers: &HeaderMap, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let Some(max_age) = max_age(headers) else {
+            state.remove(key);
+            return;
+        };
+        if let Some(entry) = state.entries.get_mut(key) {
+            entry.expires = now + max_age;
+            if let Some(etag) = etag(headers) {
+                entry.etag = Some(etag);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use reqwest::header::HeaderValue;
+
+    fn headers(etag: Option<&str>, cache_control: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = etag {
+            headers.insert(ETAG, HeaderValue::from_str(etag).unwrap());
+        }
+        headers.insert(CACHE_CONTROL, HeaderValue::from_str(cache_control).unwrap());
+        headers
+    }
+
+    fn url(path: &str) -> CacheKey {
+        let url = Url::parse("http://127.0.0.1:8080/")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        CacheKey::new(url, "*/*")
+    }
+
+    #[test]
+    fn test_lookup() {
+        let cache = HTTPCache::new(1 << 10);
+        let now = Instant::now();
+        let later = now + Duration::from_secs(120);
+        let body = Bytes::from_static(b"info");
+
+        cache.store(
+            url("info"),
+            &headers(Some("\"1\""), "public, max-age=60, immutable"),
+            body.clone(),
+            now,
+        );
+        assert!(matches!(cache.lookup(&url("info"), now), CacheLookup::Fresh(b) if b == body));
+        assert!(matches!(
+            cache.lookup(&url("info"), later),
+            CacheLookup::Stale { etag, .. } if etag == "\"1\""
+        ));
+        cache.refresh(&url("info"), &headers(None, "max-age=600"), later);
+        assert!(matches!(
+            cache.lookup(&url("info"), later),
+            CacheLookup::Fresh(_)
+        ));
+
+        // Revalidated every time
+        cache.store(
+            url("search"),
+            &headers(Some("\"2\""), "no-cache"),
+            body.clone(),
+            now,
+        );
+        assert!(matches!(
+            cache.lookup(&url("search"), now),
+            CacheLookup::Stale { .. }
+        ));
+        assert!(cache.contains(&url("search"), later));
+
+        // Not worth keeping
+        cache.store(
+            url("a"),
+            &headers(Some("\"3\""), "no-store"),
+            body.clone(),
+            now,
+        );
+        cache.store(url("b"), &headers(None, "no-cache"), body.clone(), now);
+        assert!(matches!(cache.lookup(&url("a"), now), CacheLookup::Miss));
+        assert!(matches!(cache.lookup(&url("b"), now), CacheLookup::Miss));
+
+        // Expired, with nothing to revalidate with
+        cache.store(url("c"), &headers(None, "max-age=60"), body, now);
+        assert!(matches!(
+            cache.lookup(&url("c"), now),
+            CacheLookup::Fresh(_)
+        ));
+        assert!(!cache.contains(&url("c"), later));
+        assert!(matches!(cache.lookup(&url("c"), later), CacheLookup::Miss));
+    }
+
+    #[test]
+    fn test_reusable() {
+        assert!(reusable(&headers(None, "public, max-age=60, immutable")));
+        assert!(!reusable(&headers(None, "no-cache")));
+        assert!(!reusable(&headers(None, "max-age=60, no-store")));
+        assert!(!reusable(&HeaderMap::new()));
+        // Whichever comes first
+        assert!(!reusable(&headers(None, "no-cache, max-age=60")));
+        assert!(!reusable(&headers(None, "max-age=60, no-cache")));
+    }
+
+    #[test]
+    fn test_accept() {
+        let cache = HTTPCache::new(1 << 10);
+        let now = Instant::now();
+        let h = headers(Some("\"1\""), "max-age=60");
+        let info = |accept| CacheKey::new(url("info").url, accept);
+        cache.store(info("*/*"), &h, Bytes::from_static(b"native"), now);
+        cache.store(info("application/json"), &h, Bytes::from_static(b"{}"), now);
+
+        assert!(matches!(
+            cache.lookup(&info("*/*"), now),
+            CacheLookup::Fresh(b) if b == "native"
+        ));
+        assert!(matches!(
+            cache.lookup(&info("application/json"), now),
+            CacheLookup::Fresh(b) if b == "{}"
+        ));
+        assert!(matches!(
+            cache.lookup(&info("application/cbor"), now),
+            CacheLookup::Miss
+        ));
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = HTTPCache::new(8);
+        let now = Instant::now();
+        let h = headers(Some("\"1\""), "max-age=60");
+        cache.store(url("a"), &h, Bytes::from_static(b"1234"), now);
+        cache.store(url("b"), &h, Bytes::from_static(b"1234"), now);
+        cache.store(url("c"), &h, Bytes::from_static(b"1234"), now);
+        cache.store(url("d"), &h, Bytes::from_static(b"too large"), now);
+
+        assert!(matches!(cache.lookup(&url("a"), now), CacheLookup::Miss));
+        assert!(matches!(
+            cache.lookup(&url("b"), now),
+            CacheLookup::Fresh(_)
+        ));
+        assert!(matches!(
+            cache.lookup(&url("c"), now),
+            CacheLookup::Fresh(_)
+        ));
+        assert!(matches!(cache.lookup(&url("d"), now), CacheLookup::Miss));
+    }
+}
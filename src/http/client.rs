@@ -1,34 +1,172 @@
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
 
 use bytes::Buf;
 
 use log::info;
 
 #[cfg(not(target_arch = "wasm32"))]
-use reqwest::blocking::{Client, ClientBuilder};
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder};
 #[cfg(target_arch = "wasm32")]
-use reqwest::{Client, ClientBuilder};
+use reqwest::{Client, ClientBuilder, RequestBuilder};
+
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::header::HeaderMap;
 
 use serde::Deserialize;
 
 use url::Url;
 
+use crate::container::{DICTIONARY_KEY, INFO_KEY};
 use crate::data::{
-    DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile, SummaryTile, TileID,
+    CounterTile, DataSourceDescription, DataSourceError, DataSourceInfo, DataSourceResult, EntryID,
+    SearchQuery, SlotMetaTile, SlotTile, SummaryTile, Tile, TileID, TileKind, TileRequest,
 };
-use crate::deferred_data::DeferredDataSource;
-use crate::http::fetch::{fetch, DataSourceResponse};
-use crate::http::schema::TileRequestRef;
+use crate::deferred_data::{
+    cancel_tile_requests, sort_tile_results, CancelToken, DeferredDataSource, PendingJobs,
+    SearchResponse, TileResult,
+};
+use crate::format;
+use crate::http::cache::HTTPCache;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::http::cache::{reusable, CacheKey};
+use crate::http::fetch::{fetch_cached, DataSourceResponse};
+use crate::http::schema::{SearchParams, TileRequestRef, SERVER_HEADER};
+use crate::manifest::{Manifest, ManifestEntry, MANIFEST_KEY};
 
 pub struct HTTPClientDataSource {
     pub baseurl: Url,
     pub client: Client,
-    infos: Arc<Mutex<Vec<DataSourceInfo>>>,
-    summary_tiles: Arc<Mutex<Vec<SummaryTile>>>,
-    slot_tiles: Arc<Mutex<Vec<SlotTile>>>,
-    slot_meta_tiles: Arc<Mutex<Vec<SlotMetaTile>>>,
+    infos: Arc<Mutex<Vec<DataSourceResult<DataSourceInfo>>>>,
+    summary_tiles: Arc<Mutex<Vec<TileResult<SummaryTile>>>>,
+    counter_tiles: Arc<Mutex<Vec<TileResult<CounterTile>>>>,
+    slot_tiles: Arc<Mutex<Vec<TileResult<SlotTile>>>>,
+    slot_meta_tiles: Arc<Mutex<Vec<TileResult<SlotMetaTile>>>>,
+    search_results: Arc<Mutex<Vec<SearchResponse>>>,
+    // Tiles requested since the last call to one of the get_* methods. These
+    // are sent to the server together, to cut down on per-request overhead
+    pending_tiles: Vec<TileRequest>,
+    // Static hosts and servers that predate batching will reject the batch,
+    // after which we send tiles individually (starting with the rejected ones)
+    batch_supported: Arc<AtomicBool>,
+    retry_tiles: Arc<Mutex<Vec<TileRequest>>>,
+    // Tile requests that have been sent, but not answered
+    jobs: Arc<Mutex<PendingJobs>>,
+    // Archives on static hosts list the size and checksum of every file, so
+    // that truncated files are caught before decoding, and may have a
+    // dictionary for their tiles. Servers have neither, so these are empty
+    manifest: Arc<Mutex<Manifest>>,
+    dictionary: Arc<Mutex<Arc<Vec<u8>>>>,
+    // Only used on native builds (see fetch_cached)
+    http_cache: Arc<HTTPCache>,
+}
+
+// How to check and decode a response
+#[derive(Default)]
+struct Decoding {
+    // The file's key, and what the manifest says it should contain
+    expected: Option<(String, ManifestEntry)>,
+    dictionary: Arc<Vec<u8>>,
+}
+
+// The requests made by fetch_info, one after another: the info, then (from
+// static hosts only) the manifest to check it against, and then the
+// dictionary, if the manifest lists one
+struct InfoFetch {
+    client: Client,
+    http_cache: Arc<HTTPCache>,
+    baseurl: Url,
+    manifest: Arc<Mutex<Manifest>>,
+    dictionary: Arc<Mutex<Arc<Vec<u8>>>>,
+    infos: Arc<Mutex<Vec<DataSourceResult<DataSourceInfo>>>>,
 }
 
+impl InfoFetch {
+    fn fetch(self, key: &str, on_done: impl 'static + Send + FnOnce(Self, DataSourceResponse)) {
+        let url = self.baseurl.join(key).expect("invalid baseurl");
+        let request = HTTPClientDataSource::get_with(&self.client, url);
+        let http_cache = self.http_cache.clone();
+        fetch_cached(request, &http_cache, None, move |response| match response {
+            Ok(response) => on_done(self, response),
+            Err(e) => self.infos.lock().unwrap().push(Err(e)),
+        });
+    }
+
+    fn fetch_info(self) {
+        self.fetch(INFO_KEY, |fetch, response| {
+            // Servers have neither a manifest nor a dictionary
+            if response.headers.contains_key(SERVER_HEADER) {
+                let info = HTTPClientDataSource::decode(Ok(response), Decoding::default());
+                fetch.infos.lock().unwrap().push(info);
+            } else {
+                fetch.fetch_manifest(response);
+            }
+        });
+    }
+
+    fn fetch_manifest(self, info: DataSourceResponse) {
+        let url = self.baseurl.join(MANIFEST_KEY).expect("invalid baseurl");
+        let request = HTTPClientDataSource::get_with(&self.client, url);
+        let http_cache = self.http_cache.clone();
+        fetch_cached(request, &http_cache, None, move |response| {
+            let manifest = response.and_then(|response| {
+                let contents = std::str::from_utf8(&response.body)
+                    .map_err(|e| DataSourceError::Decode(e.to_string()))?;
+                Manifest::parse(contents)
+            });
+            match manifest {
+                Ok(manifest) => self.check_info(manifest, info),
+                // Archives written before manifests were introduced don't
+                // have one, which is fine
+                Err(DataSourceError::NotFound(_)) => self.check_info(Manifest::default(), info),
+                Err(e) => self.infos.lock().unwrap().push(Err(e)),
+            }
+        });
+    }
+
+    // The info is only handed out once the dictionary (if the manifest lists
+    // one) has been fetched, since tiles can't be decoded without it
+    fn check_info(self, manifest: Manifest, response: DataSourceResponse) {
+        let decoding = Decoding {
+            expected: HTTPClientDataSource::expected(&manifest, INFO_KEY),
+            ..Default::default()
+        };
+        let info = HTTPClientDataSource::decode::<DataSourceInfo>(Ok(response), decoding);
+        let dictionary_expected = HTTPClientDataSource::expected(&manifest, DICTIONARY_KEY);
+        *self.manifest.lock().unwrap() = manifest;
+
+        let (info, (key, entry)) = match (info, dictionary_expected) {
+            (Ok(info), Some(expected)) => (info, expected),
+            (info, _) => {
+                self.infos.lock().unwrap().push(info);
+                return;
+            }
+        };
+        self.fetch(DICTIONARY_KEY, move |fetch, response| {
+            let result = entry.check(&key, &response.body).map(|()| {
+                *fetch.dictionary.lock().unwrap() = Arc::new(response.body.to_vec());
+                info
+            });
+            fetch.infos.lock().unwrap().push(result);
+        });
+    }
+}
+
+// Keep batches small enough that large frames are still spread over several
+// concurrent requests
+const MAX_BATCH_SIZE: usize = 256;
+
+// Compressed response bodies, in bytes. Tiles are cached once decoded too
+// (see CachingDeferredDataSource), so this mostly saves round trips for
+// tiles that have been evicted from that cache
+const HTTP_CACHE_CAPACITY: usize = 64 << 20;
+
+// The viewer's own format, which the server sends unless asked for another.
+// Cached responses are kept under the Accept header they were requested with
+const ACCEPT: &str = "*/*";
+
 impl HTTPClientDataSource {
     pub fn new(baseurl: Url) -> Self {
         Self {
@@ -36,31 +174,284 @@ impl HTTPClientDataSource {
             client: ClientBuilder::new().build().unwrap(),
             infos: Arc::new(Mutex::new(Vec::new())),
             summary_tiles: Arc::new(Mutex::new(Vec::new())),
+            counter_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_meta_tiles: Arc::new(Mutex::new(Vec::new())),
+            search_results: Arc::new(Mutex::new(Vec::new())),
+            pending_tiles: Vec::new(),
+            batch_supported: Arc::new(AtomicBool::new(true)),
+            retry_tiles: Arc::new(Mutex::new(Vec::new())),
+            jobs: Arc::new(Mutex::new(PendingJobs::default())),
+            manifest: Arc::new(Mutex::new(Manifest::default())),
+            dictionary: Arc::new(Mutex::new(Arc::new(Vec::new()))),
+            http_cache: Arc::new(HTTPCache::new(HTTP_CACHE_CAPACITY)),
         }
     }
 
-    fn request<T>(&mut self, url: Url, container: Arc<Mutex<Vec<T>>>)
+    fn decode<T>(
+        response: DataSourceResult<DataSourceResponse>,
+        decoding: Decoding,
+    ) -> DataSourceResult<T>
     where
-        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
+        T: for<'a> Deserialize<'a>,
     {
+        let body = response?.body;
+        if let Some((key, entry)) = decoding.expected {
+            entry.check(&key, &body)?;
+        }
+        format::read_with_dictionary(body.reader(), "server response", &decoding.dictionary)
+    }
+
+    fn get(&self, url: Url) -> RequestBuilder {
+        Self::get_with(&self.client, url)
+    }
+
+    fn get_with(client: &Client, url: Url) -> RequestBuilder {
         info!("fetch: {}", url);
-        let request = self
-            .client
+        client
             .get(url)
-            .header("Accept", "*/*")
-            .header("Content-Type", "application/octet-stream;");
-        fetch(
+            .header("Accept", ACCEPT)
+            .header("Content-Type", "application/octet-stream;")
+    }
+
+    fn expected(manifest: &Manifest, key: &str) -> Option<(String, ManifestEntry)> {
+        manifest
+            .blobs
+            .get(key)
+            .map(|entry| (key.to_owned(), *entry))
+    }
+
+    fn tile_decoding(&self, key: &str) -> Decoding {
+        Decoding {
+            expected: Self::expected(&self.manifest.lock().unwrap(), key),
+            dictionary: self.dictionary.lock().unwrap().clone(),
+        }
+    }
+
+    fn send<T>(
+        request: RequestBuilder,
+        cache: &Arc<HTTPCache>,
+        cancel: Option<CancelToken>,
+        decoding: Decoding,
+        on_done: impl 'static + Send + FnOnce(DataSourceResult<T>),
+    ) where
+        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
+    {
+        fetch_cached(
             request,
-            move |response: Result<DataSourceResponse, String>| {
-                let f = response.unwrap().body.reader();
-                let f = zstd::Decoder::new(f).expect("zstd decompression failed");
-                let result = ciborium::from_reader(f).expect("cbor decoding failed");
-                container.lock().unwrap().push(result);
+            cache,
+            cancel,
+            move |response: DataSourceResult<DataSourceResponse>| {
+                on_done(Self::decode(response, decoding));
             },
         );
     }
+
+    // Sends a request that can be cancelled with cancel_tiles
+    fn send_tiles(
+        &mut self,
+        request: RequestBuilder,
+        requests: &[TileRequest],
+        on_done: impl 'static + Send + FnOnce(DataSourceResult<DataSourceResponse>),
+    ) {
+        let (id, token) = self.jobs.lock().unwrap().start(requests);
+        let jobs = self.jobs.clone();
+        fetch_cached(request, &self.http_cache, Some(token), move |response| {
+            jobs.lock().unwrap().finish(id);
+            on_done(response);
+        });
+    }
+
+    // The URL of a tile requested on its own, and its key in the manifest
+    fn tile_url(baseurl: &Url, req: &TileRequest) -> (Url, String) {
+        let route = match req.kind {
+            TileKind::Summary => "summary_tile/",
+            TileKind::Counter => "counter_tile/",
+            TileKind::Slot => "slot_tile/",
+            TileKind::SlotMeta => "slot_meta_tile/",
+        };
+        let slug = TileRequestRef {
+            entry_id: &req.entry_id,
+            tile_id: req.tile_id,
+        }
+        .to_slug();
+        let mut url = baseurl
+            .join(route)
+            .and_then(|u| u.join(&slug))
+            .expect("invalid baseurl");
+        url.set_query(Some(&format!("full={}", req.full)));
+        (url, format!("{}{}", route, slug))
+    }
+
+    fn request_tile<T>(&mut self, req: &TileRequest, container: Arc<Mutex<Vec<TileResult<T>>>>)
+    where
+        T: 'static + Sync + Send + for<'a> Deserialize<'a>,
+    {
+        let (url, key) = Self::tile_url(&self.baseurl, req);
+        let request = self.get(url);
+        let decoding = self.tile_decoding(&key);
+        let (entry_id, tile_id, full) = (req.entry_id.clone(), req.tile_id, req.full);
+        self.send_tiles(request, std::slice::from_ref(req), move |response| {
+            let result = Self::decode(response, decoding);
+            container
+                .lock()
+                .unwrap()
+                .push(TileResult::new(&entry_id, tile_id, full, result));
+        });
+    }
+
+    fn request_single_tile(&mut self, req: &TileRequest) {
+        match req.kind {
+            TileKind::Summary => {
+                let container = self.summary_tiles.clone();
+                self.request_tile(req, container);
+            }
+            TileKind::Counter => {
+                let container = self.counter_tiles.clone();
+                self.request_tile(req, container);
+            }
+            TileKind::Slot => {
+                let container = self.slot_tiles.clone();
+                self.request_tile(req, container);
+            }
+            TileKind::SlotMeta => {
+                let container = self.slot_meta_tiles.clone();
+                self.request_tile(req, container);
+            }
+        }
+    }
+
+    // Batches are POSTs, which can't be revalidated. But if the server says
+    // its responses can be reused without asking, each tile is kept as if it
+    // had been requested on its own
+    #[cfg(not(target_arch = "wasm32"))]
+    fn store_tiles(
+        cache: &HTTPCache,
+        baseurl: &Url,
+        headers: &HeaderMap,
+        requests: &[TileRequest],
+        results: &[DataSourceResult<Tile>],
+    ) {
+        if !reusable(headers) {
+            return;
+        }
+        let now = Instant::now();
+        for (req, result) in requests.iter().zip(results) {
+            let body = match (req.kind, result) {
+                (TileKind::Summary, Ok(Tile::Summary(tile))) => format::write(Vec::new(), tile, 1),
+                (TileKind::Counter, Ok(Tile::Counter(tile))) => format::write(Vec::new(), tile, 1),
+                (TileKind::Slot, Ok(Tile::Slot(tile))) => format::write(Vec::new(), tile, 1),
+                (TileKind::SlotMeta, Ok(Tile::SlotMeta(tile))) => {
+                    format::write(Vec::new(), tile, 1)
+                }
+                _ => continue,
+            };
+            let (url, _) = Self::tile_url(baseurl, req);
+            let key = CacheKey::new(url, ACCEPT);
+            cache.store(key, headers, body.expect("encoding failed").into(), now);
+        }
+    }
+
+    fn request_batch(&mut self, requests: Vec<TileRequest>) {
+        let url = self.baseurl.join("tiles").expect("invalid baseurl");
+        info!("fetch: {} ({} tiles)", url, requests.len());
+        let body = format::write(Vec::new(), &requests, 1).expect("encoding failed");
+        let request = self
+            .client
+            .post(url)
+            .header("Accept", ACCEPT)
+            .header("Content-Type", "application/octet-stream;")
+            .body(body);
+
+        let summary_tiles = self.summary_tiles.clone();
+        let counter_tiles = self.counter_tiles.clone();
+        let slot_tiles = self.slot_tiles.clone();
+        let slot_meta_tiles = self.slot_meta_tiles.clone();
+        let batch_supported = self.batch_supported.clone();
+        let retry_tiles = self.retry_tiles.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        let (http_cache, baseurl) = (self.http_cache.clone(), self.baseurl.clone());
+        let batch = requests.clone();
+        self.send_tiles(request, &batch, move |response| {
+            #[cfg(not(target_arch = "wasm32"))]
+            let headers = match &response {
+                Ok(response) => response.headers.clone(),
+                Err(_) => HeaderMap::new(),
+            };
+            // Only servers support batches, and servers have no manifest
+            let result = Self::decode::<Vec<DataSourceResult<Tile>>>(response, Decoding::default());
+            let results = match result {
+                // Static hosts can't answer batches, and say so in various
+                // ways: 404, 405 (e.g., GitHub Pages or S3) or 501 (e.g.,
+                // python -m http.server)
+                Err(DataSourceError::NotFound(_) | DataSourceError::Server { .. }) => {
+                    batch_supported.store(false, Ordering::Relaxed);
+                    retry_tiles.lock().unwrap().extend(requests);
+                    return;
+                }
+                Ok(results) if results.len() == requests.len() => results,
+                Ok(results) => {
+                    let error = DataSourceError::Decode(format!(
+                        "requested {} tiles but server returned {}",
+                        requests.len(),
+                        results.len()
+                    ));
+                    vec![Err(error); requests.len()]
+                }
+                Err(error) => vec![Err(error); requests.len()],
+            };
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::store_tiles(&http_cache, &baseurl, &headers, &requests, &results);
+            sort_tile_results(
+                &requests,
+                results,
+                &mut summary_tiles.lock().unwrap(),
+                &mut counter_tiles.lock().unwrap(),
+                &mut slot_tiles.lock().unwrap(),
+                &mut slot_meta_tiles.lock().unwrap(),
+            );
+        });
+    }
+
+    fn fetch_tile(&mut self, req: TileRequest) {
+        if self.batch_supported.load(Ordering::Relaxed) {
+            self.pending_tiles.push(req);
+        } else {
+            self.request_single_tile(&req);
+        }
+    }
+
+    // Called before handing out results, which the viewer does once per
+    // frame, so that everything requested in a frame goes out together
+    fn flush(&mut self) {
+        let retry_tiles = std::mem::take(&mut *self.retry_tiles.lock().unwrap());
+        for req in &retry_tiles {
+            self.request_single_tile(req);
+        }
+
+        let pending_tiles = std::mem::take(&mut self.pending_tiles);
+        #[cfg(not(target_arch = "wasm32"))]
+        let pending_tiles = self.request_cached_tiles(pending_tiles);
+        for batch in pending_tiles.chunks(MAX_BATCH_SIZE) {
+            self.request_batch(batch.to_vec());
+        }
+    }
+
+    // Tiles that are already in the cache are requested on their own, so that
+    // they're answered from it (or revalidated) rather than sent again. The
+    // rest are returned, to be batched
+    #[cfg(not(target_arch = "wasm32"))]
+    fn request_cached_tiles(&mut self, requests: Vec<TileRequest>) -> Vec<TileRequest> {
+        let now = Instant::now();
+        let (cached, uncached): (Vec<_>, Vec<_>) = requests.into_iter().partition(|req| {
+            let (url, _) = Self::tile_url(&self.baseurl, req);
+            self.http_cache.contains(&CacheKey::new(url, ACCEPT), now)
+        });
+        for req in &cached {
+            self.request_single_tile(req);
+        }
+        uncached
+    }
 }
 
 impl DeferredDataSource for HTTPClientDataSource {
@@ -71,56 +462,390 @@ impl DeferredDataSource for HTTPClientDataSource {
     }
 
     fn fetch_info(&mut self) {
-        let url = self.baseurl.join("info").expect("invalid baseurl");
-        self.request::<DataSourceInfo>(url, self.infos.clone());
+        InfoFetch {
+            client: self.client.clone(),
+            http_cache: self.http_cache.clone(),
+            baseurl: self.baseurl.clone(),
+            manifest: self.manifest.clone(),
+            dictionary: self.dictionary.clone(),
+            infos: self.infos.clone(),
+        }
+        .fetch_info();
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
         std::mem::take(&mut self.infos.lock().unwrap())
     }
 
     fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut url = self
-            .baseurl
-            .join("summary_tile/")
-            .and_then(|u| u.join(&req.to_slug()))
-            .expect("invalid baseurl");
-        url.set_query(Some(&format!("full={}", full)));
-        self.request::<SummaryTile>(url, self.summary_tiles.clone());
+        self.fetch_tile(TileRequest {
+            kind: TileKind::Summary,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
     }
 
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile> {
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+        self.flush();
         std::mem::take(&mut self.summary_tiles.lock().unwrap())
     }
 
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tile(TileRequest {
+            kind: TileKind::Counter,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        self.flush();
+        std::mem::take(&mut self.counter_tiles.lock().unwrap())
+    }
+
     fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut url = self
-            .baseurl
-            .join("slot_tile/")
-            .and_then(|u| u.join(&req.to_slug()))
-            .expect("invalid baseurl");
-        url.set_query(Some(&format!("full={}", full)));
-        self.request::<SlotTile>(url, self.slot_tiles.clone());
+        self.fetch_tile(TileRequest {
+            kind: TileKind::Slot,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
     }
 
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile> {
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+        self.flush();
         std::mem::take(&mut self.slot_tiles.lock().unwrap())
     }
 
     fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let req = TileRequestRef { entry_id, tile_id };
-        let mut url = self
-            .baseurl
-            .join("slot_meta_tile/")
-            .and_then(|u| u.join(&req.to_slug()))
-            .expect("invalid baseurl");
-        url.set_query(Some(&format!("full={}", full)));
-        self.request::<SlotMetaTile>(url, self.slot_meta_tiles.clone());
+        self.fetch_tile(TileRequest {
+            kind: TileKind::SlotMeta,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
     }
 
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile> {
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+        self.flush();
         std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
     }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        for req in requests {
+            self.fetch_tile(req.clone());
+        }
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        cancel_tile_requests(
+            requests,
+            &mut self.pending_tiles,
+            &self.jobs,
+            &mut self.summary_tiles.lock().unwrap(),
+            &mut self.counter_tiles.lock().unwrap(),
+            &mut self.slot_tiles.lock().unwrap(),
+            &mut self.slot_meta_tiles.lock().unwrap(),
+        );
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        let url = self.baseurl.join("search").expect("invalid baseurl");
+        let request = self.get(url).query(&SearchParams::from(query));
+        let query = query.clone();
+        let search_results = self.search_results.clone();
+        Self::send(
+            request,
+            &self.http_cache,
+            None,
+            Decoding::default(),
+            move |result| {
+                search_results
+                    .lock()
+                    .unwrap()
+                    .push(SearchResponse::new(&query, result));
+            },
+        );
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        std::mem::take(&mut self.search_results.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::data::{
+        EntryInfo, FieldSchema, ItemLink, ItemUID, SearchResults, SummaryTileData, TileSet,
+        UtilPoint,
+    };
+    use crate::http::test_host::{serve, serve_with_headers, wait_for};
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn info() -> Vec<u8> {
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: None,
+                counters: Vec::new(),
+                slots: Vec::new(),
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(10)),
+            tile_set: TileSet::default(),
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+        };
+        format::write(Vec::new(), &info, 1).unwrap()
+    }
+
+    fn fetch_info(files: BTreeMap<String, Vec<u8>>) -> HTTPClientDataSource {
+        let (url, _) = serve(files, "no-cache");
+        let mut data_source = HTTPClientDataSource::new(url);
+        data_source.fetch_info();
+        data_source
+    }
+
+    #[test]
+    fn test_fetch_info() {
+        let info = info();
+        let dictionary = b"dictionary".to_vec();
+        let manifest = ManifestEntry::new(&info, None).to_line(INFO_KEY)
+            + &ManifestEntry::new(&dictionary, None).to_line(DICTIONARY_KEY);
+
+        // Without a manifest, nothing is checked
+        let files = BTreeMap::from([(INFO_KEY.to_owned(), info.clone())]);
+        let (url, requests) = serve(files, "no-cache");
+        let mut data_source = HTTPClientDataSource::new(url);
+        data_source.fetch_info();
+        let infos = wait_for(|| data_source.get_infos());
+        assert!(infos[0].is_ok());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                ("GET /info".to_owned(), 200),
+                ("GET /manifest".to_owned(), 404)
+            ]
+        );
+
+        // Servers have no manifest, so it isn't requested from them
+        let files = BTreeMap::from([(INFO_KEY.to_owned(), info.clone())]);
+        let headers = format!("Cache-Control: no-cache\r\n{}: 1\r\n", SERVER_HEADER);
+        let (url, requests) = serve_with_headers(files, headers);
+        let mut data_source = HTTPClientDataSource::new(url);
+        data_source.fetch_info();
+        let infos = wait_for(|| data_source.get_infos());
+        assert!(infos[0].is_ok());
+        assert_eq!(*requests.lock().unwrap(), [("GET /info".to_owned(), 200)]);
+
+        // A manifest that can't be read is reported, rather than skipped
+        let mut data_source = fetch_info(BTreeMap::from([
+            (INFO_KEY.to_owned(), info.clone()),
+            (MANIFEST_KEY.to_owned(), b"garbage\n".to_vec()),
+        ]));
+        let infos = wait_for(|| data_source.get_infos());
+        assert!(matches!(infos[0], Err(DataSourceError::Decode(_))));
+
+        // The dictionary is fetched before the info is handed out
+        let mut data_source = fetch_info(BTreeMap::from([
+            (INFO_KEY.to_owned(), info.clone()),
+            (DICTIONARY_KEY.to_owned(), dictionary.clone()),
+            (MANIFEST_KEY.to_owned(), manifest.clone().into_bytes()),
+        ]));
+        let infos = wait_for(|| data_source.get_infos());
+        assert!(infos[0].is_ok());
+        assert_eq!(**data_source.dictionary.lock().unwrap(), dictionary);
+
+        // A truncated info is caught
+        let mut data_source = fetch_info(BTreeMap::from([
+            (INFO_KEY.to_owned(), info[..info.len() - 1].to_vec()),
+            (DICTIONARY_KEY.to_owned(), dictionary.clone()),
+            (MANIFEST_KEY.to_owned(), manifest.clone().into_bytes()),
+        ]));
+        let infos = wait_for(|| data_source.get_infos());
+        assert!(matches!(infos[0], Err(DataSourceError::Decode(_))));
+
+        // And so is a damaged dictionary
+        let mut data_source = fetch_info(BTreeMap::from([
+            (INFO_KEY.to_owned(), info),
+            (DICTIONARY_KEY.to_owned(), b"damaged!!!".to_vec()),
+            (MANIFEST_KEY.to_owned(), manifest.into_bytes()),
+        ]));
+        let infos = wait_for(|| data_source.get_infos());
+        assert!(matches!(infos[0], Err(DataSourceError::Decode(_))));
+    }
+
+    fn summary_tile() -> (TileRequest, SummaryTile) {
+        let entry_id = EntryID::root().summary();
+        let tile_id = TileID(Interval::new(Timestamp(0), Timestamp(10)));
+        let request = TileRequest {
+            kind: TileKind::Summary,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full: false,
+        };
+        let tile = SummaryTile {
+            entry_id,
+            tile_id,
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: Timestamp(5),
+                    util: 0.5,
+                }],
+            },
+        };
+        (request, tile)
+    }
+
+    fn fetch_summary_tile(
+        data_source: &mut HTTPClientDataSource,
+        req: &TileRequest,
+    ) -> DataSourceResult<SummaryTile> {
+        data_source.fetch_summary_tile(&req.entry_id, req.tile_id, req.full);
+        let mut tiles = wait_for(|| data_source.get_summary_tiles());
+        tiles.pop().unwrap().result
+    }
+
+    #[test]
+    fn test_revalidate_tile() {
+        let (req, tile) = summary_tile();
+        let baseurl = Url::parse("http://unused/").unwrap();
+        let (url, key) = HTTPClientDataSource::tile_url(&baseurl, &req);
+        let body = format::write(Vec::new(), &tile, 1).unwrap();
+        // Without a batch endpoint, tiles are requested on their own
+        let (baseurl, requests) = serve(BTreeMap::from([(key, body)]), "no-cache");
+        let mut data_source = HTTPClientDataSource::new(baseurl);
+
+        let first = fetch_summary_tile(&mut data_source, &req).unwrap();
+        let second = fetch_summary_tile(&mut data_source, &req).unwrap();
+        assert_eq!(first.data.utilization, tile.data.utilization);
+        assert_eq!(second.data.utilization, tile.data.utilization);
+
+        let path = format!("GET {}?{}", url.path(), url.query().unwrap());
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                ("POST /tiles".to_owned(), 501),
+                (path.clone(), 200),
+                (path, 304)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_fallback() {
+        let (req, tile) = summary_tile();
+        let other = TileRequest {
+            full: true,
+            ..req.clone()
+        };
+        let baseurl = Url::parse("http://unused/").unwrap();
+        let body = format::write(Vec::new(), &tile, 1).unwrap();
+        let files = BTreeMap::from([
+            (
+                HTTPClientDataSource::tile_url(&baseurl, &req).1,
+                body.clone(),
+            ),
+            (HTTPClientDataSource::tile_url(&baseurl, &other).1, body),
+        ]);
+        let (baseurl, requests) = serve(files, "no-cache");
+        let mut data_source = HTTPClientDataSource::new(baseurl);
+
+        // Both tiles go out in one batch, which is rejected, and then on
+        // their own
+        data_source.fetch_tiles(&[req.clone(), other.clone()]);
+        let mut tiles = Vec::new();
+        while tiles.len() < 2 {
+            tiles.extend(wait_for(|| data_source.get_summary_tiles()));
+        }
+        assert!(tiles.iter().all(|tile| tile.result.is_ok()));
+
+        // Later tiles aren't batched at all
+        fetch_summary_tile(&mut data_source, &other).unwrap();
+        let requests = requests.lock().unwrap();
+        let methods: Vec<_> = requests
+            .iter()
+            .map(|(request, status)| (request.split(' ').next().unwrap(), *status))
+            .collect();
+        assert_eq!(
+            methods,
+            [("POST", 501), ("GET", 200), ("GET", 200), ("GET", 304)]
+        );
+    }
+
+    #[test]
+    fn test_reuse_batched_tiles() {
+        let (req, tile) = summary_tile();
+        let batch: Vec<DataSourceResult<Tile>> = vec![Ok(Tile::Summary(tile.clone()))];
+        let body = format::write(Vec::new(), &batch, 1).unwrap();
+        let (baseurl, requests) = serve(
+            BTreeMap::from([("tiles".to_owned(), body)]),
+            "public, max-age=60, immutable",
+        );
+        let mut data_source = HTTPClientDataSource::new(baseurl);
+
+        let first = fetch_summary_tile(&mut data_source, &req).unwrap();
+        // Answered from the cache, without asking the server
+        let second = fetch_summary_tile(&mut data_source, &req).unwrap();
+        assert_eq!(first.data.utilization, tile.data.utilization);
+        assert_eq!(second.data.utilization, tile.data.utilization);
+        assert_eq!(*requests.lock().unwrap(), [("POST /tiles".to_owned(), 200)]);
+    }
+
+    #[test]
+    fn test_search() {
+        let query = SearchQuery {
+            query: "copy".to_owned(),
+            field: None,
+            whole_word: true,
+            interval: Interval::new(Timestamp(0), Timestamp(10)),
+            max_results: 5,
+        };
+        let results = SearchResults {
+            items: vec![ItemLink {
+                item_uid: ItemUID(1),
+                title: "copy".to_owned(),
+                interval: Interval::new(Timestamp(2), Timestamp(3)),
+                entry_id: EntryID::root().child(0).child(0),
+            }],
+            truncated: true,
+        };
+        let body = format::write(Vec::new(), &results, 1).unwrap();
+        let (baseurl, requests) = serve(BTreeMap::from([("search".to_owned(), body)]), "no-cache");
+        let mut data_source = HTTPClientDataSource::new(baseurl.clone());
+
+        data_source.fetch_search(&query);
+        let mut responses = wait_for(|| data_source.get_search_results());
+        let response = responses.pop().unwrap();
+        assert_eq!(response.query, query);
+        let found = response.result.unwrap();
+        assert!(found.truncated);
+        assert_eq!(found.items[0].item_uid, ItemUID(1));
+        assert_eq!(found.items[0].entry_id, results.items[0].entry_id);
+
+        // The query is sent in full
+        let (request, status) = requests.lock().unwrap()[0].clone();
+        assert_eq!(status, 200);
+        let url = baseurl.join(request.trim_start_matches("GET /")).unwrap();
+        let params: BTreeMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["query"], "copy");
+        assert_eq!(params["whole_word"], "true");
+        assert_eq!(params["start"], "0");
+        assert_eq!(params["stop"], "10");
+        assert_eq!(params["max_results"], "5");
+
+        // Hosts that can't search say so
+        let (baseurl, _) = serve(BTreeMap::new(), "no-cache");
+        let mut data_source = HTTPClientDataSource::new(baseurl);
+        data_source.fetch_search(&query);
+        let mut responses = wait_for(|| data_source.get_search_results());
+        let response = responses.pop().unwrap();
+        assert!(matches!(response.result, Err(DataSourceError::NotFound(_))));
+    }
 }
@@ -1,21 +1,77 @@
+use std::sync::Arc;
+
 use bytes::Bytes;
 
+use reqwest::header::HeaderMap;
+
 #[cfg(not(target_arch = "wasm32"))]
 use reqwest::blocking::RequestBuilder;
 #[cfg(target_arch = "wasm32")]
 use reqwest::RequestBuilder;
 
+use crate::data::{DataSourceError, DataSourceResult};
+use crate::deferred_data::CancelToken;
+use crate::http::cache::HTTPCache;
+
 pub struct DataSourceResponse {
     pub body: Bytes,
+    // Empty if the response came from the cache without asking the server
+    pub headers: HeaderMap,
 }
 
+// A cancelled request fails with DataSourceError::Cancelled. Native builds
+// stop reading the response body part way through (and drop the connection),
+// while in the browser cancellation is only noticed before the request is
+// sent and before its body is read
 pub fn fetch(
     request: RequestBuilder,
-    on_done: impl 'static + Send + FnOnce(Result<DataSourceResponse, String>),
+    cancel: Option<CancelToken>,
+    on_done: impl 'static + Send + FnOnce(DataSourceResult<DataSourceResponse>),
+) {
+    #[cfg(not(target_arch = "wasm32"))]
+    crate::http::fetch_native::fetch(request, cancel, Box::new(on_done));
+
+    #[cfg(target_arch = "wasm32")]
+    crate::http::fetch_web::fetch(request, cancel, Box::new(on_done));
+}
+
+// Like fetch, but GET requests are answered from (or revalidated against) the
+// cache when possible. Browsers have an HTTP cache of their own, which
+// requests go through anyway, so the cache is only used on native builds
+pub fn fetch_cached(
+    request: RequestBuilder,
+    cache: &Arc<HTTPCache>,
+    cancel: Option<CancelToken>,
+    on_done: impl 'static + Send + FnOnce(DataSourceResult<DataSourceResponse>),
 ) {
     #[cfg(not(target_arch = "wasm32"))]
-    crate::http::fetch_native::fetch(request, Box::new(on_done));
+    crate::http::fetch_native::fetch_cached(request, cache.clone(), cancel, Box::new(on_done));
 
     #[cfg(target_arch = "wasm32")]
-    crate::http::fetch_web::fetch(request, Box::new(on_done));
+    {
+        let _ = cache;
+        crate::http::fetch_web::fetch(request, cancel, Box::new(on_done));
+    }
+}
+
+pub(crate) fn check_cancelled(cancel: &Option<CancelToken>) -> DataSourceResult<()> {
+    if cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+        return Err(DataSourceError::Cancelled);
+    }
+    Ok(())
+}
+
+pub(crate) fn status_error(status: reqwest::StatusCode, message: String) -> DataSourceError {
+    if status == reqwest::StatusCode::NOT_FOUND {
+        DataSourceError::NotFound(message)
+    } else {
+        DataSourceError::Server {
+            status: status.as_u16(),
+            message,
+        }
+    }
+}
+
+pub(crate) fn request_error(e: reqwest::Error) -> DataSourceError {
+    DataSourceError::Io(e.to_string())
 }
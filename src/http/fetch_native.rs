@@ -1,18 +1,186 @@
-use reqwest::blocking::RequestBuilder;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::http::fetch::DataSourceResponse;
+use bytes::Bytes;
+
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{HeaderMap, ACCEPT, IF_NONE_MATCH};
+use reqwest::{Method, StatusCode};
+
+use crate::data::{DataSourceError, DataSourceResult};
+use crate::deferred_data::CancelToken;
+use crate::http::cache::{CacheKey, CacheLookup, HTTPCache};
+use crate::http::fetch::{check_cancelled, request_error, status_error, DataSourceResponse};
+
+fn send(
+    request: RequestBuilder,
+    cancel: Option<CancelToken>,
+) -> DataSourceResult<DataSourceResponse> {
+    // Requests can sit in the thread pool for a while, so check first
+    check_cancelled(&cancel)?;
+    let response = request.send().map_err(request_error)?;
+    receive(response, &cancel)
+}
+
+// Checks for cancellation between chunks, so that a cancelled download is
+// abandoned rather than read to the end
+fn read_body(mut response: Response, cancel: &Option<CancelToken>) -> DataSourceResult<Bytes> {
+    const CHUNK_SIZE: usize = 64 << 10;
+    let mut body = Vec::new();
+    loop {
+        check_cancelled(cancel)?;
+        let start = body.len();
+        body.resize(start + CHUNK_SIZE, 0);
+        match response.read(&mut body[start..]) {
+            Ok(0) => {
+                body.truncate(start);
+                return Ok(body.into());
+            }
+            Ok(n) => body.truncate(start + n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => body.truncate(start),
+            Err(e) => return Err(DataSourceError::Io(e.to_string())),
+        }
+    }
+}
+
+fn receive(
+    response: Response,
+    cancel: &Option<CancelToken>,
+) -> DataSourceResult<DataSourceResponse> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().unwrap_or_default();
+        return Err(status_error(status, message));
+    }
+    check_cancelled(cancel)?;
+    let headers = response.headers().clone();
+    let body = read_body(response, cancel)?;
+    Ok(DataSourceResponse { body, headers })
+}
+
+fn send_cached(
+    request: RequestBuilder,
+    cache: &HTTPCache,
+    cancel: Option<CancelToken>,
+) -> DataSourceResult<DataSourceResponse> {
+    let key = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .filter(|request| request.method() == Method::GET)
+        .map(|request| {
+            let accept = request.headers().get(ACCEPT);
+            let accept = accept.and_then(|value| value.to_str().ok()).unwrap_or("");
+            CacheKey::new(request.url().clone(), accept)
+        });
+    let Some(key) = key else {
+        return send(request, cancel);
+    };
+
+    let (request, stale) = match cache.lookup(&key, Instant::now()) {
+        CacheLookup::Fresh(body) => {
+            let headers = HeaderMap::new();
+            return Ok(DataSourceResponse { body, headers });
+        }
+        CacheLookup::Stale { etag, body } => (request.header(IF_NONE_MATCH, etag), Some(body)),
+        CacheLookup::Miss => (request, None),
+    };
+
+    check_cancelled(&cancel)?;
+    let response = request.send().map_err(request_error)?;
+    if let (StatusCode::NOT_MODIFIED, Some(body)) = (response.status(), stale) {
+        cache.refresh(&key, response.headers(), Instant::now());
+        let headers = response.headers().clone();
+        return Ok(DataSourceResponse { body, headers });
+    }
+    let result = receive(response, &cancel)?;
+    cache.store(key, &result.headers, result.body.clone(), Instant::now());
+    Ok(result)
+}
 
 pub fn fetch(
     request: RequestBuilder,
-    on_done: Box<dyn FnOnce(Result<DataSourceResponse, String>) + Send>,
+    cancel: Option<CancelToken>,
+    on_done: Box<dyn FnOnce(DataSourceResult<DataSourceResponse>) + Send>,
 ) {
     rayon::spawn(move || {
-        let result = request
-            .send()
-            .expect("request failed")
-            .bytes()
-            .expect("unable to get bytes");
+        let result = send(request, cancel);
 
-        on_done(Ok(DataSourceResponse { body: result }))
+        on_done(result)
     });
 }
+
+pub fn fetch_cached(
+    request: RequestBuilder,
+    cache: Arc<HTTPCache>,
+    cancel: Option<CancelToken>,
+    on_done: Box<dyn FnOnce(DataSourceResult<DataSourceResponse>) + Send>,
+) {
+    rayon::spawn(move || {
+        let result = send_cached(request, &cache, cancel);
+
+        on_done(result)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use crate::deferred_data::PendingJobs;
+
+    #[test]
+    fn test_cancel_body() {
+        // A server that trickles out a body far too large to finish, and
+        // reports when the client hangs up
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (started_tx, started) = mpsc::channel();
+        let (dropped_tx, dropped) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 \r\nContent-Length: {}\r\n\r\n",
+                1u64 << 40
+            )
+            .unwrap();
+            let chunk = vec![0; 4 << 10];
+            loop {
+                if stream.write_all(&chunk).is_err() {
+                    dropped_tx.send(()).unwrap();
+                    return;
+                }
+                let _ = started_tx.send(());
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        // Jobs without any requests left are cancelled
+        let mut jobs = PendingJobs::default();
+        let (_, cancel) = jobs.start(&[]);
+        let (done_tx, done) = mpsc::channel();
+        let client = reqwest::blocking::Client::new();
+        fetch(
+            client.get(url),
+            Some(cancel),
+            Box::new(move |result| done_tx.send(result.map(|_| ())).unwrap()),
+        );
+
+        started.recv_timeout(Duration::from_secs(10)).unwrap();
+        jobs.cancel(&[]);
+        let result = done.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(result, Err(DataSourceError::Cancelled)));
+        dropped.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+}
@@ -1,6 +1,8 @@
 use reqwest::RequestBuilder;
 
-use crate::http::fetch::DataSourceResponse;
+use crate::data::DataSourceResult;
+use crate::deferred_data::CancelToken;
+use crate::http::fetch::{check_cancelled, request_error, status_error, DataSourceResponse};
 
 /// Spawn an async task.
 ///
@@ -13,20 +15,30 @@ where
     wasm_bindgen_futures::spawn_local(future);
 }
 
+async fn send(
+    request: RequestBuilder,
+    cancel: Option<CancelToken>,
+) -> DataSourceResult<DataSourceResponse> {
+    check_cancelled(&cancel)?;
+    let response = request.send().await.map_err(request_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(status_error(status, message));
+    }
+    check_cancelled(&cancel)?;
+    let headers = response.headers().clone();
+    let body = response.bytes().await.map_err(request_error)?;
+    Ok(DataSourceResponse { body, headers })
+}
+
 pub fn fetch(
     request: RequestBuilder,
-    on_done: Box<dyn FnOnce(Result<DataSourceResponse, String>) + Send>,
+    cancel: Option<CancelToken>,
+    on_done: Box<dyn FnOnce(DataSourceResult<DataSourceResponse>) + Send>,
 ) {
     spawn_future(async move {
-        let result = request
-            .send()
-            .await
-            .expect("request failed")
-            .bytes()
-            .await
-            .expect("unable to get bytes");
-
-        let res = Ok(DataSourceResponse { body: result });
+        let res = send(request, cancel).await;
 
         on_done(res)
     });
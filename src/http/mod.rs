@@ -1,8 +1,14 @@
 pub mod schema;
 
+#[cfg(feature = "client")]
+pub mod cache;
 #[cfg(feature = "client")]
 pub mod client;
+#[cfg(feature = "client")]
+pub mod range_client;
 
+#[cfg(feature = "server")]
+pub mod profiles;
 #[cfg(feature = "server")]
 pub mod server;
 
@@ -12,3 +18,6 @@ pub mod fetch;
 pub mod fetch_native;
 #[cfg(all(feature = "client", target_arch = "wasm32"))]
 pub mod fetch_web;
+
+#[cfg(all(test, feature = "client", not(target_arch = "wasm32")))]
+mod test_host;
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use crate::cached_file_data::CachedFileDataSource;
+use crate::data::DataSource;
+
+// A profile served by the server. The ID tells its responses apart from
+// those of other profiles (including earlier versions of the same profile)
+// in the response cache.
+pub struct Profile {
+    pub id: u64,
+    pub data_source: Box<dyn DataSource + Send + Sync + 'static>,
+    // Responses only depend on the profile, so they're all as old as it is.
+    // When that's unknown, responses are only validated by their ETags
+    pub last_modified: Option<SystemTime>,
+}
+
+pub const DEFAULT_SCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+// Each archive's name, path and profile
+type Profiles = BTreeMap<String, (PathBuf, Arc<Profile>)>;
+
+// The archives in a directory, each served under its file name (without the
+// .lpv extension of single-file archives). The directory is scanned when it's
+// made, and then again (every scan interval) by the server in the background,
+// so that archives can be added and removed while the server runs. Lookups
+// only ever see a finished scan, and don't wait for the next one. Archives
+// must be replaced by renaming the new one over the old, since until the
+// next scan the old file is still being read.
+pub struct ProfileDirectory {
+    pub path: PathBuf,
+    pub scan_interval: Duration,
+    profiles: RwLock<Arc<Profiles>>,
+    // The next ID to give out, which also keeps scans from running at once
+    next_id: Mutex<u64>,
+}
+
+// The name and modification time of the archive at the path, if it is one.
+// Directory archives are only complete once their index.html is written,
+// so that's the file whose time is used.
+fn archive_entry(path: &Path) -> Option<(String, SystemTime)> {
+    let name = path.file_name()?.to_str()?;
+    if path.is_dir() {
+        let modified = path.join("index.html").metadata().ok()?.modified().ok()?;
+        Some((name.to_owned(), modified))
+    } else {
+        let name = name.strip_suffix(".lpv")?;
+        let modified = path.metadata().ok()?.modified().ok()?;
+        Some((name.to_owned(), modified))
+    }
+}
+
+impl ProfileDirectory {
+    // IDs are given out starting at first_id, so that they don't collide
+    // with those of other profiles on the same server
+    pub fn new(path: impl AsRef<Path>, scan_interval: Duration, first_id: u64) -> Self {
+        let directory = Self {
+            path: path.as_ref().to_owned(),
+            scan_interval,
+            profiles: RwLock::new(Arc::new(BTreeMap::new())),
+            next_id: Mutex::new(first_id),
+        };
+        directory.rescan();
+        directory
+    }
+
+    fn snapshot(&self) -> Arc<Profiles> {
+        self.profiles.read().unwrap().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Profile>> {
+        let (_, profile) = self.snapshot().get(name)?.clone();
+        Some(profile)
+    }
+
+    // Sorted by name
+    pub fn list(&self) -> Vec<(String, Arc<Profile>)> {
+        self.snapshot()
+            .iter()
+            .map(|(name, (_, profile))| (name.clone(), profile.clone()))
+            .collect()
+    }
+
+    // Returns the IDs of the profiles that were removed or replaced, which
+    // won't be served again
+    pub fn rescan(&self) -> BTreeSet<u64> {
+        let mut next_id = self.next_id.lock().unwrap();
+        let old = self.snapshot();
+        let new = match self.scan(&old, &mut next_id) {
+            Ok(new) => Arc::new(new),
+            Err(e) => {
+                // Keep serving what we have
+                warn!("Unable to scan {:?} for profiles: {}", self.path, e);
+                return BTreeSet::new();
+            }
+        };
+        *self.profiles.write().unwrap() = new.clone();
+
+        let kept: BTreeSet<_> = new.values().map(|(_, profile)| profile.id).collect();
+        old.values()
+            .map(|(_, profile)| profile.id)
+            .filter(|id| !kept.contains(id))
+            .collect()
+    }
+
+    // Archives that haven't changed since the last scan keep their data
+    // sources (and so their cached responses). Archives that can't be read
+    // (e.g., that are still being written) are left out until a later scan.
+    fn scan(&self, old: &Profiles, next_id: &mut u64) -> std::io::Result<Profiles> {
+        let mut profiles = BTreeMap::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            let Some((name, modified)) = archive_entry(&path) else {
+                continue;
+            };
+            if let Some((old_path, profile)) = old.get(&name) {
+                if *old_path == path && profile.last_modified == Some(modified) {
+                    profiles.insert(name, (path, profile.clone()));
+                    continue;
+                }
+            }
+
+            let data_source = CachedFileDataSource::new(&path);
+            if data_source.fetch_info().is_err() {
+                continue;
+            }
+            let profile = Profile {
+                id: *next_id,
+                data_source: Box::new(data_source),
+                last_modified: Some(modified),
+            };
+            *next_id += 1;
+            profiles.insert(name, (path, Arc::new(profile)));
+        }
+        Ok(profiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::container::{ContainerWriter, INFO_KEY};
+    use crate::data::{DataSourceInfo, EntryInfo, FieldSchema, TileSet};
+    use crate::format;
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn write_archive(path: &Path, stop: i64) {
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: None,
+                counters: Vec::new(),
+                slots: Vec::new(),
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(stop)),
+            tile_set: TileSet::default(),
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+        };
+        let file = std::fs::File::create(path).unwrap();
+        let mut writer = ContainerWriter::new(file).unwrap();
+        writer
+            .append(
+                INFO_KEY.to_owned(),
+                &format::write(Vec::new(), &info, 1).unwrap(),
+            )
+            .unwrap();
+        writer.finish(1).unwrap();
+    }
+
+    // As archives that are being served must be replaced
+    fn replace_archive(path: &Path, stop: i64) {
+        let temp = path.with_extension("tmp");
+        write_archive(&temp, stop);
+        std::fs::rename(&temp, path).unwrap();
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = std::env::temp_dir().join(format!("profile_directory_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("unfinished")).unwrap();
+        write_archive(&dir.join("a.lpv"), 10);
+        std::fs::write(dir.join("b.lpv"), b"still being written").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not an archive").unwrap();
+
+        let profiles = ProfileDirectory::new(&dir, Duration::ZERO, 1);
+        let names: Vec<_> = profiles.list().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["a"]);
+        let a = profiles.get("a").unwrap();
+        assert_eq!(a.id, 1);
+        assert!(profiles.get("b").is_none());
+
+        // Nothing changes until the directory is scanned again
+        replace_archive(&dir.join("b.lpv"), 20);
+        std::fs::remove_file(dir.join("a.lpv")).unwrap();
+        write_archive(&dir.join("c.lpv"), 30);
+        assert!(profiles.get("a").is_some());
+        assert_eq!(profiles.rescan(), BTreeSet::from([1]));
+        let names: Vec<_> = profiles.list().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["b", "c"]);
+        let info = profiles.get("b").unwrap().data_source.fetch_info().unwrap();
+        assert_eq!(info.interval.stop, Timestamp(20));
+
+        // Unchanged archives are kept as they are, and replaced ones get new IDs
+        let b = profiles.get("b").unwrap();
+        let c = profiles.get("c").unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        replace_archive(&dir.join("c.lpv"), 40);
+        // The old archive is still readable until then
+        let info = c.data_source.fetch_info().unwrap();
+        assert_eq!(info.interval.stop, Timestamp(30));
+        assert_eq!(profiles.rescan(), BTreeSet::from([c.id]));
+        assert!(Arc::ptr_eq(&b, &profiles.get("b").unwrap()));
+        assert_ne!(profiles.get("c").unwrap().id, c.id);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,571 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+use log::info;
+
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest::blocking::{Client, ClientBuilder};
+#[cfg(target_arch = "wasm32")]
+use reqwest::{Client, ClientBuilder};
+
+use url::Url;
+
+use crate::container::{self, BlobRange, ContainerToc, DICTIONARY_KEY, INFO_KEY, PREFIX_SIZE};
+use crate::data::{
+    CounterTile, DataSourceDescription, DataSourceError, DataSourceInfo, DataSourceResult, EntryID,
+    SearchQuery, SlotMetaTile, SlotTile, SummaryTile, TileID, TileKind, TileRequest,
+};
+use crate::deferred_data::{
+    cancel_tile_requests, sort_tile_results, CancelToken, DeferredDataSource, PendingJobs,
+    SearchResponse, TileResult,
+};
+use crate::format;
+use crate::http::fetch::fetch;
+use crate::manifest::{Manifest, MANIFEST_KEY};
+
+// Reads a single-file archive (see container.rs) from a static file host,
+// using HTTP range requests. No server-side support is needed beyond ranges,
+// and hosts that ignore the Range header still work (if slowly).
+pub struct HTTPRangeDataSource {
+    pub url: Url,
+    pub client: Client,
+    // Set once fetch_info has read it (or failed to). Tiles requested before
+    // then wait for it
+    toc: Arc<Mutex<Option<DataSourceResult<Arc<ContainerToc>>>>>,
+    // Kept in a sidecar file (see manifest.rs), which may not have been
+    // uploaded along with the archive, in which case nothing is checked
+    manifest: Arc<Mutex<Arc<Manifest>>>,
+    // Empty if the archive doesn't have one
+    dictionary: Arc<Mutex<Arc<Vec<u8>>>>,
+    infos: Arc<Mutex<Vec<DataSourceResult<DataSourceInfo>>>>,
+    summary_tiles: Arc<Mutex<Vec<TileResult<SummaryTile>>>>,
+    counter_tiles: Arc<Mutex<Vec<TileResult<CounterTile>>>>,
+    slot_tiles: Arc<Mutex<Vec<TileResult<SlotTile>>>>,
+    slot_meta_tiles: Arc<Mutex<Vec<TileResult<SlotMetaTile>>>>,
+    search_results: Vec<SearchResponse>,
+    // Tiles requested since the last call to one of the get_* methods. Tiles
+    // that are stored close together are fetched with a single request
+    pending_tiles: Vec<TileRequest>,
+    // Tile requests that have been sent, but not answered
+    jobs: Arc<Mutex<PendingJobs>>,
+}
+
+// Blobs this close together are fetched together, even though that means
+// downloading the bytes in between
+const MAX_RANGE_GAP: u64 = 64 << 10;
+const MAX_RANGE_SIZE: u64 = 4 << 20;
+
+fn fetch_range(
+    client: &Client,
+    url: &Url,
+    range: BlobRange,
+    cancel: Option<CancelToken>,
+    on_done: impl 'static + Send + FnOnce(DataSourceResult<Bytes>),
+) {
+    info!("fetch: {} ({})", url, range.to_header());
+    let request = client
+        .get(url.clone())
+        .header("Accept", "*/*")
+        .header("Range", range.to_header());
+    fetch(request, cancel, move |response| {
+        on_done(response.and_then(|response| range_body(response.body, range)));
+    });
+}
+
+// The requested range of the response
+fn range_body(body: Bytes, range: BlobRange) -> DataSourceResult<Bytes> {
+    let length = body.len() as u64;
+    if length == range.length {
+        Ok(body)
+    } else if length >= range.end() {
+        // The host ignored the Range header and sent the whole file
+        Ok(body.slice(range.offset as usize..range.end() as usize))
+    } else {
+        Err(DataSourceError::Decode(format!(
+            "requested {} bytes but received {}",
+            range.length, length
+        )))
+    }
+}
+
+// Groups blobs that are stored close together, so that each group can be
+// fetched with one request
+fn group_ranges<T>(mut blobs: Vec<(BlobRange, T)>) -> Vec<Vec<(BlobRange, T)>> {
+    blobs.sort_by_key(|(range, _)| range.offset);
+    let mut groups = Vec::new();
+    let mut group: Vec<(BlobRange, T)> = Vec::new();
+    for (range, value) in blobs {
+        if let Some((first, _)) = group.first() {
+            let end = group.iter().map(|(range, _)| range.end()).max().unwrap();
+            if range.offset > end + MAX_RANGE_GAP || range.end() - first.offset > MAX_RANGE_SIZE {
+                groups.push(std::mem::take(&mut group));
+            }
+        }
+        group.push((range, value));
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    groups
+}
+
+// The requests made by fetch_info, one after another: the manifest, the
+// prefix, the table of contents, the dictionary (if there is one) and the
+// info
+struct InfoFetch {
+    client: Client,
+    url: Url,
+    toc: Arc<Mutex<Option<DataSourceResult<Arc<ContainerToc>>>>>,
+    manifest: Arc<Mutex<Arc<Manifest>>>,
+    dictionary: Arc<Mutex<Arc<Vec<u8>>>>,
+    infos: Arc<Mutex<Vec<DataSourceResult<DataSourceInfo>>>>,
+}
+
+impl InfoFetch {
+    fn fail(&self, error: DataSourceError) {
+        // Tiles can't be found without the table of contents either
+        let mut toc = self.toc.lock().unwrap();
+        toc.get_or_insert_with(|| Err(error.clone()));
+        self.infos.lock().unwrap().push(Err(error));
+    }
+
+    fn fetch(self, range: BlobRange, on_done: impl 'static + Send + FnOnce(Self, Bytes)) {
+        let (client, url) = (self.client.clone(), self.url.clone());
+        fetch_range(&client, &url, range, None, move |result| match result {
+            Ok(blob) => on_done(self, blob),
+            Err(error) => self.fail(error),
+        });
+    }
+
+    fn fetch_manifest(self) {
+        let mut url = self.url.clone();
+        url.set_path(&format!("{}.{}", self.url.path(), MANIFEST_KEY));
+        info!("fetch: {}", url);
+        let request = self.client.get(url).header("Accept", "*/*");
+        fetch(request, None, move |response| {
+            let manifest = response.and_then(|response| {
+                let contents = std::str::from_utf8(&response.body)
+                    .map_err(|e| DataSourceError::Decode(e.to_string()))?;
+                Manifest::parse(contents)
+            });
+            match manifest {
+                Ok(manifest) => *self.manifest.lock().unwrap() = Arc::new(manifest),
+                Err(DataSourceError::NotFound(_)) => {}
+                Err(error) => return self.fail(error),
+            }
+            self.fetch_toc();
+        });
+    }
+
+    fn fetch_toc(self) {
+        let prefix = BlobRange {
+            offset: 0,
+            length: PREFIX_SIZE,
+        };
+        self.fetch(prefix, |fetch, prefix| {
+            match container::decode_prefix(&prefix) {
+                Ok(toc_range) => fetch.fetch(toc_range, Self::toc_fetched),
+                Err(error) => fetch.fail(error),
+            }
+        });
+    }
+
+    fn toc_fetched(self, blob: Bytes) {
+        let toc = match format::read::<ContainerToc, _>(&blob[..], "archive") {
+            Ok(toc) => Arc::new(toc),
+            Err(error) => return self.fail(error),
+        };
+        *self.toc.lock().unwrap() = Some(Ok(toc.clone()));
+
+        let info_range = match toc.get(INFO_KEY) {
+            Ok(info_range) => info_range,
+            Err(error) => return self.fail(error),
+        };
+        let Ok(dictionary_range) = toc.get(DICTIONARY_KEY) else {
+            return self.fetch_info(info_range);
+        };
+        self.fetch(dictionary_range, move |fetch, blob| {
+            let manifest = fetch.manifest.lock().unwrap().clone();
+            if let Err(error) = manifest.check(DICTIONARY_KEY, &blob) {
+                return fetch.fail(error);
+            }
+            *fetch.dictionary.lock().unwrap() = Arc::new(blob.to_vec());
+            fetch.fetch_info(info_range);
+        });
+    }
+
+    fn fetch_info(self, info_range: BlobRange) {
+        self.fetch(info_range, |fetch, blob| {
+            let manifest = fetch.manifest.lock().unwrap().clone();
+            let info = manifest
+                .check(INFO_KEY, &blob)
+                .and_then(|()| format::read(&blob[..], "archive"));
+            fetch.infos.lock().unwrap().push(info);
+        });
+    }
+}
+
+impl HTTPRangeDataSource {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            client: ClientBuilder::new().build().unwrap(),
+            toc: Arc::new(Mutex::new(None)),
+            manifest: Arc::new(Mutex::new(Arc::new(Manifest::default()))),
+            dictionary: Arc::new(Mutex::new(Arc::new(Vec::new()))),
+            infos: Arc::new(Mutex::new(Vec::new())),
+            summary_tiles: Arc::new(Mutex::new(Vec::new())),
+            counter_tiles: Arc::new(Mutex::new(Vec::new())),
+            slot_tiles: Arc::new(Mutex::new(Vec::new())),
+            slot_meta_tiles: Arc::new(Mutex::new(Vec::new())),
+            search_results: Vec::new(),
+            pending_tiles: Vec::new(),
+            jobs: Arc::new(Mutex::new(PendingJobs::default())),
+        }
+    }
+
+    fn request_group(&mut self, group: Vec<(BlobRange, TileRequest)>) {
+        let offset = group.first().unwrap().0.offset;
+        let end = group.iter().map(|(range, _)| range.end()).max().unwrap();
+        let span = BlobRange {
+            offset,
+            length: end - offset,
+        };
+
+        let requests: Vec<_> = group.iter().map(|(_, req)| req.clone()).collect();
+        let (id, token) = self.jobs.lock().unwrap().start(&requests);
+        let jobs = self.jobs.clone();
+        let summary_tiles = self.summary_tiles.clone();
+        let counter_tiles = self.counter_tiles.clone();
+        let slot_tiles = self.slot_tiles.clone();
+        let slot_meta_tiles = self.slot_meta_tiles.clone();
+        let manifest = self.manifest.lock().unwrap().clone();
+        let dictionary = self.dictionary.lock().unwrap().clone();
+        fetch_range(&self.client, &self.url, span, Some(token), move |result| {
+            jobs.lock().unwrap().finish(id);
+            let results: Vec<_> = match result {
+                Ok(body) => group
+                    .iter()
+                    .map(|(range, req)| {
+                        let start = (range.offset - span.offset) as usize;
+                        let blob = &body[start..start + range.length as usize];
+                        let key = container::tile_key(req.kind, &req.entry_id, req.tile_id);
+                        manifest.check(&key, blob)?;
+                        container::read_tile(req.kind, blob, &dictionary)
+                    })
+                    .collect(),
+                Err(error) => vec![Err(error); group.len()],
+            };
+            sort_tile_results(
+                &requests,
+                results,
+                &mut summary_tiles.lock().unwrap(),
+                &mut counter_tiles.lock().unwrap(),
+                &mut slot_tiles.lock().unwrap(),
+                &mut slot_meta_tiles.lock().unwrap(),
+            );
+        });
+    }
+
+    // Called before handing out results, which the viewer does once per
+    // frame, so that everything requested in a frame can be coalesced
+    fn flush(&mut self) {
+        if self.pending_tiles.is_empty() {
+            return;
+        }
+        let Some(toc) = self.toc.lock().unwrap().clone() else {
+            return;
+        };
+        let pending_tiles = std::mem::take(&mut self.pending_tiles);
+
+        let mut missing = Vec::new();
+        let mut found = Vec::new();
+        for req in pending_tiles {
+            let key = container::tile_key(req.kind, &req.entry_id, req.tile_id);
+            match toc
+                .as_ref()
+                .map_err(Clone::clone)
+                .and_then(|toc| toc.get(&key))
+            {
+                Ok(range) => found.push((range, req)),
+                Err(error) => missing.push((req, error)),
+            }
+        }
+        let (requests, errors): (Vec<_>, Vec<_>) = missing.into_iter().unzip();
+        sort_tile_results(
+            &requests,
+            errors.into_iter().map(Err),
+            &mut self.summary_tiles.lock().unwrap(),
+            &mut self.counter_tiles.lock().unwrap(),
+            &mut self.slot_tiles.lock().unwrap(),
+            &mut self.slot_meta_tiles.lock().unwrap(),
+        );
+
+        for group in group_ranges(found) {
+            self.request_group(group);
+        }
+    }
+
+    fn fetch_tile(&mut self, kind: TileKind, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.pending_tiles.push(TileRequest {
+            kind,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        });
+    }
+}
+
+impl DeferredDataSource for HTTPRangeDataSource {
+    fn fetch_description(&self) -> DataSourceDescription {
+        DataSourceDescription {
+            source_locator: vec![self.url.to_string()],
+        }
+    }
+
+    fn fetch_info(&mut self) {
+        // Four round trips: the manifest, the prefix, the table of contents,
+        // and the info (plus one for the dictionary, if there is one)
+        InfoFetch {
+            client: self.client.clone(),
+            url: self.url.clone(),
+            toc: self.toc.clone(),
+            manifest: self.manifest.clone(),
+            dictionary: self.dictionary.clone(),
+            infos: self.infos.clone(),
+        }
+        .fetch_manifest();
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+        std::mem::take(&mut self.infos.lock().unwrap())
+    }
+
+    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tile(TileKind::Summary, entry_id, tile_id, full);
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+        self.flush();
+        std::mem::take(&mut self.summary_tiles.lock().unwrap())
+    }
+
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tile(TileKind::Counter, entry_id, tile_id, full);
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        self.flush();
+        std::mem::take(&mut self.counter_tiles.lock().unwrap())
+    }
+
+    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tile(TileKind::Slot, entry_id, tile_id, full);
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+        self.flush();
+        std::mem::take(&mut self.slot_tiles.lock().unwrap())
+    }
+
+    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tile(TileKind::SlotMeta, entry_id, tile_id, full);
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+        self.flush();
+        std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
+    }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        self.pending_tiles.extend_from_slice(requests);
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        cancel_tile_requests(
+            requests,
+            &mut self.pending_tiles,
+            &self.jobs,
+            &mut self.summary_tiles.lock().unwrap(),
+            &mut self.counter_tiles.lock().unwrap(),
+            &mut self.slot_tiles.lock().unwrap(),
+            &mut self.slot_meta_tiles.lock().unwrap(),
+        );
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        // There's no server to search with, and scanning every tile over the
+        // network would be too slow, so let the viewer search its own tiles
+        let error =
+            DataSourceError::NotFound("search is not supported by this data source".to_owned());
+        self.search_results
+            .push(SearchResponse::new(query, Err(error)));
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        std::mem::take(&mut self.search_results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    use crate::container::ContainerWriter;
+    use crate::data::{EntryInfo, FieldSchema, SummaryTileData, TileSet, UtilPoint};
+    use crate::http::test_host::{serve, wait_for};
+    use crate::manifest::ManifestEntry;
+    use crate::timestamp::{Interval, Timestamp};
+
+    fn range(offset: u64, length: u64) -> BlobRange {
+        BlobRange { offset, length }
+    }
+
+    // A single-file archive with an info and one summary tile, its manifest,
+    // and the tile's location in the file
+    fn write_container() -> (Vec<u8>, String, SummaryTile, BlobRange) {
+        let info = DataSourceInfo {
+            entry_info: EntryInfo::Panel {
+                short_name: "root".to_owned(),
+                long_name: "root".to_owned(),
+                summary: None,
+                counters: Vec::new(),
+                slots: Vec::new(),
+            },
+            interval: Interval::new(Timestamp(0), Timestamp(10)),
+            tile_set: TileSet::default(),
+            field_schema: FieldSchema::new(),
+            warning_message: None,
+        };
+        let tile = SummaryTile {
+            entry_id: EntryID::root().summary(),
+            tile_id: TileID(info.interval),
+            data: SummaryTileData {
+                utilization: vec![UtilPoint {
+                    time: Timestamp(5),
+                    util: 0.5,
+                }],
+            },
+        };
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new())).unwrap();
+        let mut manifest = String::new();
+        let key = container::tile_key(TileKind::Summary, &tile.entry_id, tile.tile_id);
+        let mut tile_range = None;
+        for (key, blob) in [
+            (
+                INFO_KEY.to_owned(),
+                format::write(Vec::new(), &info, 1).unwrap(),
+            ),
+            (key, format::write(Vec::new(), &tile, 1).unwrap()),
+        ] {
+            let range = writer.append(key.clone(), &blob).unwrap();
+            manifest += &ManifestEntry::new(&blob, Some(range.offset)).to_line(&key);
+            tile_range = Some(range);
+        }
+        let file = writer.finish(1).unwrap().into_inner();
+        (file, manifest, tile, tile_range.unwrap())
+    }
+
+    fn fetch_summary_tile(
+        data_source: &mut HTTPRangeDataSource,
+        tile: &SummaryTile,
+    ) -> DataSourceResult<SummaryTile> {
+        data_source.fetch_summary_tile(&tile.entry_id, tile.tile_id, false);
+        let mut tiles = wait_for(|| data_source.get_summary_tiles());
+        tiles.pop().unwrap().result
+    }
+
+    #[test]
+    fn test_manifest() {
+        let (mut file, manifest, tile, tile_range) = write_container();
+        let files = BTreeMap::from([
+            ("prof.lpv".to_owned(), file.clone()),
+            (
+                "prof.lpv.manifest".to_owned(),
+                manifest.clone().into_bytes(),
+            ),
+        ]);
+        let (url, _) = serve(files, "no-cache");
+        let mut data_source = HTTPRangeDataSource::new(url.join("prof.lpv").unwrap());
+        data_source.fetch_info();
+        assert!(wait_for(|| data_source.get_infos())[0].is_ok());
+        let result = fetch_summary_tile(&mut data_source, &tile).unwrap();
+        assert_eq!(result.data.utilization, tile.data.utilization);
+
+        // A tile damaged on upload is caught by its checksum
+        file[tile_range.end() as usize - 1] ^= 1;
+        let files = BTreeMap::from([
+            ("prof.lpv".to_owned(), file),
+            ("prof.lpv.manifest".to_owned(), manifest.into_bytes()),
+        ]);
+        let (url, _) = serve(files, "no-cache");
+        let mut data_source = HTTPRangeDataSource::new(url.join("prof.lpv").unwrap());
+        data_source.fetch_info();
+        assert!(wait_for(|| data_source.get_infos())[0].is_ok());
+        let result = fetch_summary_tile(&mut data_source, &tile);
+        let Err(DataSourceError::Decode(message)) = result else {
+            panic!("damaged tile was not detected");
+        };
+        assert!(message.contains("is corrupt"));
+    }
+
+    #[test]
+    fn test_tiles_before_info() {
+        let (file, _, tile, _) = write_container();
+        let (url, _) = serve(BTreeMap::from([("prof.lpv".to_owned(), file)]), "no-cache");
+        let mut data_source = HTTPRangeDataSource::new(url.join("prof.lpv").unwrap());
+
+        // Tiles wait for the table of contents
+        data_source.fetch_summary_tile(&tile.entry_id, tile.tile_id, false);
+        assert!(data_source.get_summary_tiles().is_empty());
+        data_source.fetch_info();
+        let mut tiles = wait_for(|| data_source.get_summary_tiles());
+        let result = tiles.pop().unwrap().result.unwrap();
+        assert_eq!(result.data.utilization, tile.data.utilization);
+
+        // Or fail with it
+        let mut data_source = HTTPRangeDataSource::new(url.join("missing.lpv").unwrap());
+        data_source.fetch_info();
+        let result = fetch_summary_tile(&mut data_source, &tile);
+        assert!(matches!(result, Err(DataSourceError::NotFound(_))));
+        assert!(wait_for(|| data_source.get_infos())[0].is_err());
+    }
+
+    #[test]
+    fn test_group_ranges() {
+        let groups = group_ranges(vec![
+            (range(100, 10), "b"),
+            (range(0, 10), "a"),
+            // Too far from the others
+            (range(100 + MAX_RANGE_GAP + 100, 10), "c"),
+            // Would make the group too large
+            (range(100 + MAX_RANGE_GAP + 200, MAX_RANGE_SIZE), "d"),
+        ]);
+        let names: Vec<Vec<_>> = groups
+            .iter()
+            .map(|group| group.iter().map(|(_, name)| *name).collect())
+            .collect();
+        assert_eq!(names, [vec!["a", "b"], vec!["c"], vec!["d"]]);
+        assert!(group_ranges(Vec::<(BlobRange, ())>::new()).is_empty());
+    }
+
+    #[test]
+    fn test_range_body() {
+        let file = Bytes::from_static(b"0123456789");
+        assert_eq!(
+            range_body(Bytes::from_static(b"234"), range(2, 3)).unwrap(),
+            &b"234"[..]
+        );
+        // The host ignored the Range header
+        assert_eq!(range_body(file.clone(), range(2, 3)).unwrap(), &b"234"[..]);
+        assert!(matches!(
+            range_body(Bytes::from_static(b"23"), range(2, 3)),
+            Err(DataSourceError::Decode(_))
+        ));
+        assert!(range_body(file, range(8, 5)).is_err());
+    }
+}
@@ -1,6 +1,14 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::data::{EntryID, EntryIDSlug, SlugParseError, TileID, TileIDSlug};
+use crate::data::{
+    DataSourceDescription, EntryID, EntryIDSlug, FieldID, SearchQuery, SlugParseError, TileID,
+    TileIDSlug, TileKind, TileRequest,
+};
+use crate::timestamp::{Interval, Timestamp};
+
+// Set on a server's responses, so that clients can tell it apart from a
+// static host (which may have a manifest to check files against)
+pub const SERVER_HEADER: &str = "x-legion-prof-server";
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct TileRequestPath {
@@ -8,12 +16,6 @@ pub struct TileRequestPath {
     pub tile_id: String,
 }
 
-#[derive(Debug, Clone)]
-pub struct TileRequest {
-    pub entry_id: EntryID,
-    pub tile_id: TileID,
-}
-
 #[derive(Debug, Clone)]
 pub struct TileRequestRef<'a> {
     pub entry_id: &'a EntryID,
@@ -25,11 +27,32 @@ pub struct TileQuery {
     pub full: bool,
 }
 
+// SearchQuery, flattened so that it can be sent as a URL query string
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SearchParams {
+    pub query: String,
+    pub field: Option<FieldID>,
+    pub whole_word: bool,
+    pub start: Timestamp,
+    pub stop: Timestamp,
+    pub max_results: u64,
+}
+
+// An entry in a server's list of profiles (at /profiles). The profile is
+// served under /p/{name}/
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProfileListing {
+    pub name: String,
+    pub description: DataSourceDescription,
+}
+
 impl TileRequestPath {
-    pub fn parse(&self) -> Result<TileRequest, SlugParseError> {
+    pub fn parse(&self, kind: TileKind, full: bool) -> Result<TileRequest, SlugParseError> {
         Ok(TileRequest {
+            kind,
             entry_id: EntryID::from_slug(&self.entry_id)?,
             tile_id: TileID::from_slug(&self.tile_id)?,
+            full,
         })
     }
 }
@@ -43,3 +66,28 @@ impl<'a> TileRequestRef<'a> {
         )
     }
 }
+
+impl From<&SearchQuery> for SearchParams {
+    fn from(query: &SearchQuery) -> Self {
+        Self {
+            query: query.query.clone(),
+            field: query.field,
+            whole_word: query.whole_word,
+            start: query.interval.start,
+            stop: query.interval.stop,
+            max_results: query.max_results,
+        }
+    }
+}
+
+impl From<SearchParams> for SearchQuery {
+    fn from(params: SearchParams) -> Self {
+        Self {
+            query: params.query,
+            field: params.field,
+            whole_word: params.whole_word,
+            interval: Interval::new(params.start, params.stop),
+            max_results: params.max_results,
+        }
+    }
+}
@@ -1,104 +1,1023 @@
-use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::{ready, Ready};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use actix_cors::Cors;
 use actix_web::{
-    error, get, http, middleware,
-    web::{self, Data},
-    App, HttpServer, Responder, Result,
+    dev::Payload,
+    error, get,
+    http::{
+        self,
+        header::{
+            Accept, CacheControl, CacheDirective, ContentEncoding, ETag, EntityTag, Header,
+            HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+        },
+        StatusCode,
+    },
+    middleware, post,
+    web::{self, Bytes, Data},
+    App, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer,
+    Responder, Result,
 };
 
-use serde::Serialize;
+use log::{info, warn};
 
-use crate::data::DataSource;
-use crate::http::schema::{TileQuery, TileRequestPath};
+use rayon::prelude::*;
 
+use serde::{Deserialize, Serialize};
+
+use crate::archive_data::{ViewerLocation, DEFAULT_VIEWER_URL};
+use crate::container;
+use crate::data::{
+    DataSource, DataSourceDescription, DataSourceError, DataSourceInfo, DataSourceResult, Tile,
+    TileID, TileKind, TileRequest,
+};
+use crate::deferred_data::{CacheKey, CacheStats};
+use crate::format;
+use crate::http::profiles::{Profile, ProfileDirectory, DEFAULT_SCAN_INTERVAL};
+use crate::http::schema::{
+    ProfileListing, SearchParams, TileQuery, TileRequestPath, SERVER_HEADER,
+};
+
+// A server has either a profile served at its root, or a directory of
+// profiles served under /p/{name}/
 struct AppState {
-    data_source: Box<dyn DataSource + Send + Sync + 'static>,
+    profile: Option<Arc<Profile>>,
+    directory: Option<ProfileDirectory>,
+    max_age: Duration,
+    cache: ResponseCache,
+    // Where the landing page sends the browser to view the profile
+    viewer: ViewerLocation,
+}
+
+// The representations responses can be sent in, chosen by the client's
+// Accept header. The viewer's own format (a CBOR header followed by a
+// zstd-compressed CBOR payload) is the default, so existing clients are
+// unaffected. Plain CBOR and JSON are for scripts, and are compressed (or
+// not) according to their Accept-Encoding header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ContentType {
+    Native,
+    Cbor,
+    Json,
+}
+
+// An encoded response body, along with its ETag
+#[derive(Clone)]
+struct EncodedResponse {
+    body: Bytes,
+    etag: EntityTag,
+    content_type: ContentType,
+}
+
+// Responses that only depend on the profile (by its ID), so that they can be
+// cached
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum ResponseKey {
+    Info(u64, ContentType),
+    Tile(u64, CacheKey, ContentType),
+}
+
+// The profile a request is for: the one named in its path, if it's under
+// /p/{name}/, or otherwise the one served at the root
+struct SelectedProfile(Arc<Profile>);
+
+// Keeps encoded responses, so that a tile requested by many clients (e.g.,
+// everyone in a tutorial opening the same profile) is only fetched and
+// compressed once. Responses are evicted in least recently used order once
+// the total size of their bodies exceeds the capacity.
+struct ResponseCache {
+    state: Mutex<ResponseCacheState>,
+}
+
+struct ResponseCacheState {
+    entries: BTreeMap<ResponseKey, (EncodedResponse, u64)>,
+    lru: BTreeMap<u64, ResponseKey>,
+    next_use: u64,
+    stats: CacheStats,
 }
 
 pub struct DataSourceHTTPServer {
     host: String,
     port: u16,
     state: AppState,
+    prewarm_levels: usize,
+}
+
+pub const DEFAULT_RESPONSE_CACHE_CAPACITY: usize = 256 << 20;
+
+// The viewer sends at most 256 tiles at a time, but scripts may send more.
+// This keeps any one request from tying up the server (or its memory)
+const MAX_BATCH_SIZE: usize = 1024;
+
+impl ContentType {
+    fn negotiate(req: &HttpRequest) -> Self {
+        let Ok(accept) = Accept::parse(req) else {
+            return Self::Native;
+        };
+        for mime in accept.ranked() {
+            match mime.essence_str() {
+                "application/json" => return Self::Json,
+                "application/cbor" => return Self::Cbor,
+                "application/octet-stream" | "application/*" | "*/*" => return Self::Native,
+                _ => {}
+            }
+        }
+        // Rather than 406 Not Acceptable, which would only confuse browsers
+        Self::Native
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Native => "application/octet-stream",
+            Self::Cbor => "application/cbor",
+            Self::Json => "application/json",
+        }
+    }
+
+    // The native format is already compressed, so it's marked as such to
+    // keep the Compress middleware from compressing it again
+    fn insert_headers(self, builder: &mut HttpResponseBuilder) {
+        builder
+            .content_type(self.mime())
+            .insert_header((http::header::VARY, "Accept"));
+        if self == Self::Native {
+            builder.insert_header(ContentEncoding::Identity);
+        }
+    }
 }
 
-fn encode<T>(data: T) -> Result<Vec<u8>>
+fn encode<T>(data: T, content_type: ContentType) -> Result<Vec<u8>>
 where
     T: Serialize,
 {
-    let mut f = zstd::Encoder::new(Vec::new(), 1)?;
-    ciborium::into_writer(&data, &mut f).expect("ciborium encoding failed");
-    let f = f.finish()?;
-    Ok(f)
+    match content_type {
+        ContentType::Native => Ok(format::write(Vec::new(), &data, 1)?),
+        ContentType::Cbor => {
+            let mut body = Vec::new();
+            ciborium::into_writer(&data, &mut body).map_err(error::ErrorInternalServerError)?;
+            Ok(body)
+        }
+        ContentType::Json => serde_json::to_vec(&data).map_err(error::ErrorInternalServerError),
+    }
+}
+
+// Batches of tile requests may be sent in the native format or as JSON,
+// per their Content-Type header
+fn decode<T>(req: &HttpRequest, body: &[u8]) -> Result<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    let result = if req.content_type() == "application/json" {
+        serde_json::from_slice(body).map_err(|e| e.to_string())
+    } else {
+        format::read(body, "request").map_err(|e| e.to_string())
+    };
+    result.map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))
+}
+
+// Content-derived, so that a restarted server (or another replica) serving the
+// same profile gives the same tags
+fn entity_tag(body: &[u8]) -> EntityTag {
+    EntityTag::new_strong(format!("{:08x}-{:x}", crc32fast::hash(body), body.len()))
+}
+
+// Whether the client's copy is still current, per its If-None-Match or (only
+// if that's missing, as in RFC 9110) If-Modified-Since header
+fn not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: Option<HttpDate>) -> bool {
+    if req.headers().contains_key(http::header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+    match (IfModifiedSince::parse(req), last_modified) {
+        (Ok(IfModifiedSince(since)), Some(last_modified)) => {
+            // Header dates only have whole seconds
+            let secs = |date: HttpDate| {
+                SystemTime::from(date)
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs())
+            };
+            secs(since) >= secs(last_modified)
+        }
+        _ => false,
+    }
+}
+
+impl EncodedResponse {
+    fn new(body: Vec<u8>, content_type: ContentType) -> Self {
+        let etag = entity_tag(&body);
+        Self {
+            body: body.into(),
+            etag,
+            content_type,
+        }
+    }
+}
+
+impl ResponseCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(ResponseCacheState {
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_use: 0,
+                stats: CacheStats {
+                    capacity,
+                    ..Default::default()
+                },
+            }),
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.state.lock().unwrap().stats
+    }
+
+    fn get(&self, key: &ResponseKey) -> Option<EncodedResponse> {
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let Some((response, last_use)) = state.entries.get_mut(key) else {
+            state.stats.misses += 1;
+            return None;
+        };
+        state.stats.hits += 1;
+        state.lru.remove(last_use);
+        *last_use = state.next_use;
+        state.lru.insert(state.next_use, key.clone());
+        state.next_use += 1;
+        Some(response.clone())
+    }
+
+    fn insert(&self, key: ResponseKey, response: EncodedResponse) {
+        let mut state = self.state.lock().unwrap();
+        let size = response.body.len();
+        // Another request may have gotten here first
+        if size > state.stats.capacity || state.entries.contains_key(&key) {
+            return;
+        }
+
+        while state.stats.size + size > state.stats.capacity {
+            let (_, old_key) = state.lru.pop_first().unwrap();
+            let (old_response, _) = state.entries.remove(&old_key).unwrap();
+            state.stats.size -= old_response.body.len();
+            state.stats.evictions += 1;
+        }
+
+        let last_use = state.next_use;
+        state.next_use += 1;
+        state.lru.insert(last_use, key.clone());
+        state.entries.insert(key, (response, last_use));
+        state.stats.size += size;
+        state.stats.tiles = state.entries.len();
+    }
+
+    // For profiles that are no longer served. Their IDs aren't given out
+    // again, so their responses would otherwise sit here until evicted
+    fn remove_profiles(&self, ids: &BTreeSet<u64>) {
+        if ids.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.entries.retain(|key, (response, last_use)| {
+            let (ResponseKey::Info(id, _) | ResponseKey::Tile(id, _, _)) = key;
+            if !ids.contains(id) {
+                return true;
+            }
+            state.lru.remove(last_use);
+            state.stats.size -= response.body.len();
+            false
+        });
+        state.stats.tiles = state.entries.len();
+    }
+}
+
+// Tiles are sent as their inner types (e.g., SummaryTile, not Tile)
+fn encode_tile(tile: &Tile, content_type: ContentType) -> Result<Vec<u8>> {
+    match tile {
+        Tile::Summary(tile) => encode(tile, content_type),
+        Tile::Counter(tile) => encode(tile, content_type),
+        Tile::Slot(tile) => encode(tile, content_type),
+        Tile::SlotMeta(tile) => encode(tile, content_type),
+    }
+}
+
+fn cache_key(profile: &Profile, req: &TileRequest, content_type: ContentType) -> ResponseKey {
+    ResponseKey::Tile(
+        profile.id,
+        (req.kind, req.entry_id.clone(), req.tile_id, req.full),
+        content_type,
+    )
+}
+
+impl FromRequest for SelectedProfile {
+    type Error = error::Error;
+    type Future = Ready<Result<Self>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let state = req.app_data::<Data<AppState>>().unwrap();
+        let profile = match req.match_info().get("name") {
+            Some(name) => state.directory.as_ref().and_then(|d| d.get(name)),
+            None => state.profile.clone(),
+        };
+        ready(
+            profile
+                .map(SelectedProfile)
+                .ok_or_else(|| error::ErrorNotFound("no such profile")),
+        )
+    }
+}
+
+impl AppState {
+    fn cached(
+        &self,
+        key: ResponseKey,
+        content_type: ContentType,
+        fetch: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<EncodedResponse> {
+        if let Some(response) = self.cache.get(&key) {
+            return Ok(response);
+        }
+        // Errors aren't cached, since they may be temporary
+        let response = EncodedResponse::new(fetch()?, content_type);
+        self.cache.insert(key, response.clone());
+        Ok(response)
+    }
+
+    fn fetch_info(&self, profile: &Profile, content_type: ContentType) -> Result<EncodedResponse> {
+        self.cached(
+            ResponseKey::Info(profile.id, content_type),
+            content_type,
+            || encode(profile.data_source.fetch_info()?, content_type),
+        )
+    }
+
+    fn fetch_tile(
+        &self,
+        profile: &Profile,
+        req: &TileRequest,
+        content_type: ContentType,
+    ) -> Result<EncodedResponse> {
+        self.cached(cache_key(profile, req, content_type), content_type, || {
+            let tile = profile
+                .data_source
+                .fetch_tiles(std::slice::from_ref(req))
+                .pop()
+                .unwrap()?;
+            encode_tile(&tile, content_type)
+        })
+    }
+
+    // Cached tiles are decoded again, since the batch is sent as a whole.
+    // This is still much cheaper than fetching them. Only the native format
+    // is cached for batches, since that's what the viewer asks for
+    fn fetch_tiles(
+        &self,
+        profile: &Profile,
+        requests: &[TileRequest],
+    ) -> Vec<DataSourceResult<Tile>> {
+        let mut results: Vec<_> = requests
+            .iter()
+            .map(|req| {
+                let response = self
+                    .cache
+                    .get(&cache_key(profile, req, ContentType::Native))?;
+                container::read_tile(req.kind, &response.body, &[])
+                    .ok()
+                    .map(Ok)
+            })
+            .collect();
+
+        let missing: Vec<_> = requests
+            .iter()
+            .zip(&results)
+            .filter(|(_, result)| result.is_none())
+            .map(|(req, _)| req.clone())
+            .collect();
+        let mut fetched = profile.data_source.fetch_tiles(&missing).into_iter();
+        for (req, result) in requests.iter().zip(&mut results) {
+            if result.is_some() {
+                continue;
+            }
+            let tile = fetched.next().unwrap();
+            if let Ok(tile) = &tile {
+                if let Ok(body) = encode_tile(tile, ContentType::Native) {
+                    self.cache.insert(
+                        cache_key(profile, req, ContentType::Native),
+                        EncodedResponse::new(body, ContentType::Native),
+                    );
+                }
+            }
+            *result = Some(tile);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    // Fills the cache with the coarsest levels of tiles (the ones every
+    // client starts with), or for profiles without a tile set, the tiles of
+    // the entire profile. Only the viewer's format is pre-warmed
+    fn prewarm(&self, profile: &Profile, levels: usize) -> DataSourceResult<()> {
+        let info = profile.data_source.fetch_info()?;
+        let tile_ids: Vec<TileID> = if info.tile_set.tiles.is_empty() {
+            vec![TileID(info.interval)]
+        } else {
+            info.tile_set
+                .tiles
+                .iter()
+                .take(levels)
+                .flatten()
+                .copied()
+                .collect()
+        };
+        let mut requests = Vec::new();
+        for entry_id in info.entry_info.entry_ids() {
+            for kind in entry_id.tile_kinds() {
+                requests.extend(tile_ids.iter().map(|tile_id| TileRequest {
+                    kind: *kind,
+                    entry_id: entry_id.clone(),
+                    tile_id: *tile_id,
+                    full: false,
+                }));
+            }
+        }
+
+        info!("Pre-warming response cache with {} tiles", requests.len());
+        // Failures are reported when the response is requested
+        let _ = self.fetch_info(profile, ContentType::Native);
+        requests.par_iter().for_each(|req| {
+            let _ = self.fetch_tile(profile, req, ContentType::Native);
+        });
+        let stats = self.cache.stats();
+        info!(
+            "Response cache holds {} responses ({} bytes)",
+            stats.tiles, stats.size
+        );
+        Ok(())
+    }
+
+    // Picks up archives added to (or removed from) the directory
+    fn rescan(&self) {
+        if let Some(directory) = &self.directory {
+            self.cache.remove_profiles(&directory.rescan());
+        }
+    }
+
+    // Every profile, including the one at the root (which has no name)
+    fn profiles(&self) -> Vec<(String, Arc<Profile>)> {
+        let mut profiles = Vec::new();
+        if let Some(profile) = &self.profile {
+            profiles.push((String::new(), profile.clone()));
+        }
+        if let Some(directory) = &self.directory {
+            profiles.extend(directory.list());
+        }
+        profiles
+    }
+
+    // Only the profiles under /p/{name}/
+    fn profile_listings(&self) -> Vec<ProfileListing> {
+        let Some(directory) = &self.directory else {
+            return Vec::new();
+        };
+        directory
+            .list()
+            .into_iter()
+            .map(|(name, profile)| ProfileListing {
+                name,
+                description: profile.data_source.fetch_description(),
+            })
+            .collect()
+    }
+
+    fn cache_control(&self) -> CacheControl {
+        if self.max_age.is_zero() {
+            // Caches may keep responses, but must check with us before reuse
+            CacheControl(vec![CacheDirective::NoCache])
+        } else {
+            CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(self.max_age.as_secs() as u32),
+                CacheDirective::Extension("immutable".to_owned(), None),
+            ])
+        }
+    }
+
+    // Answers a GET request with the encoded body, or with 304 Not Modified
+    // if the client already has it
+    fn respond(
+        &self,
+        req: &HttpRequest,
+        profile: &Profile,
+        response: &EncodedResponse,
+    ) -> HttpResponse {
+        let last_modified = profile.last_modified.map(HttpDate::from);
+        let current = not_modified(req, &response.etag, last_modified);
+        let mut builder = if current {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        builder
+            .insert_header(ETag(response.etag.clone()))
+            .insert_header(self.cache_control())
+            .insert_header((SERVER_HEADER, "1"));
+        if let Some(last_modified) = last_modified {
+            builder.insert_header(LastModified(last_modified));
+        }
+        response.content_type.insert_headers(&mut builder);
+        if current {
+            builder.finish()
+        } else {
+            builder.body(response.body.clone())
+        }
+    }
+}
+
+impl error::ResponseError for DataSourceError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DataSourceError::Io(..) => StatusCode::INTERNAL_SERVER_ERROR,
+            DataSourceError::Decode(..) => StatusCode::INTERNAL_SERVER_ERROR,
+            DataSourceError::NotFound(..) => StatusCode::NOT_FOUND,
+            DataSourceError::ProtocolVersion(..) => StatusCode::INTERNAL_SERVER_ERROR,
+            // We're proxying for another server, so report it as a bad gateway
+            DataSourceError::Server { .. } => StatusCode::BAD_GATEWAY,
+            DataSourceError::Cancelled => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
 }
 
 #[get("/info")]
-async fn fetch_info(state: web::Data<AppState>) -> Result<impl Responder> {
-    let result = state.data_source.fetch_info();
-    encode(result)
+async fn fetch_info(
+    req: HttpRequest,
+    profile: SelectedProfile,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let response = state.fetch_info(&profile.0, ContentType::negotiate(&req))?;
+    Ok(state.respond(&req, &profile.0, &response))
 }
 
 #[get("/summary_tile/{entry_id}/{tile_id}")]
 async fn fetch_summary_tile(
+    req: HttpRequest,
     path: web::Path<TileRequestPath>,
     query: web::Query<TileQuery>,
+    profile: SelectedProfile,
     state: web::Data<AppState>,
-) -> Result<impl Responder> {
-    let path = path
-        .parse()
+) -> Result<HttpResponse> {
+    let tile = path
+        .parse(TileKind::Summary, query.full)
         .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
-    let result = state
-        .data_source
-        .fetch_summary_tile(&path.entry_id, path.tile_id, query.full);
-    encode(result)
+    let response = state.fetch_tile(&profile.0, &tile, ContentType::negotiate(&req))?;
+    Ok(state.respond(&req, &profile.0, &response))
+}
+
+#[get("/counter_tile/{entry_id}/{tile_id}")]
+async fn fetch_counter_tile(
+    req: HttpRequest,
+    path: web::Path<TileRequestPath>,
+    query: web::Query<TileQuery>,
+    profile: SelectedProfile,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let tile = path
+        .parse(TileKind::Counter, query.full)
+        .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
+    let response = state.fetch_tile(&profile.0, &tile, ContentType::negotiate(&req))?;
+    Ok(state.respond(&req, &profile.0, &response))
 }
 
 #[get("/slot_tile/{entry_id}/{tile_id}")]
 async fn fetch_slot_tile(
+    req: HttpRequest,
     path: web::Path<TileRequestPath>,
     query: web::Query<TileQuery>,
+    profile: SelectedProfile,
     state: web::Data<AppState>,
-) -> Result<impl Responder> {
-    let path = path
-        .parse()
+) -> Result<HttpResponse> {
+    let tile = path
+        .parse(TileKind::Slot, query.full)
         .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
-    let result = state
-        .data_source
-        .fetch_slot_tile(&path.entry_id, path.tile_id, query.full);
-    encode(result)
+    let response = state.fetch_tile(&profile.0, &tile, ContentType::negotiate(&req))?;
+    Ok(state.respond(&req, &profile.0, &response))
 }
 
 #[get("/slot_meta_tile/{entry_id}/{tile_id}")]
 async fn fetch_slot_meta_tile(
+    req: HttpRequest,
     path: web::Path<TileRequestPath>,
     query: web::Query<TileQuery>,
+    profile: SelectedProfile,
     state: web::Data<AppState>,
-) -> Result<impl Responder> {
-    let path = path
-        .parse()
+) -> Result<HttpResponse> {
+    let tile = path
+        .parse(TileKind::SlotMeta, query.full)
         .map_err(|e| error::ErrorBadRequest(format!("bad request: {}", e)))?;
-    let result = state
-        .data_source
-        .fetch_slot_meta_tile(&path.entry_id, path.tile_id, query.full);
-    encode(result)
+    let response = state.fetch_tile(&profile.0, &tile, ContentType::negotiate(&req))?;
+    Ok(state.respond(&req, &profile.0, &response))
+}
+
+#[post("/tiles")]
+async fn fetch_tiles(
+    req: HttpRequest,
+    body: Bytes,
+    profile: SelectedProfile,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let requests: Vec<TileRequest> = decode(&req, &body)?;
+    if requests.len() > MAX_BATCH_SIZE {
+        return Err(error::ErrorPayloadTooLarge(format!(
+            "at most {} tiles can be requested at once",
+            MAX_BATCH_SIZE
+        )));
+    }
+    let result = state.fetch_tiles(&profile.0, &requests);
+    let content_type = ContentType::negotiate(&req);
+    let mut builder = HttpResponse::Ok();
+    // Batches have no ETag (they're POSTs, so they can't be revalidated), but
+    // clients can still keep the tiles for as long as they're allowed to
+    builder.insert_header(state.cache_control());
+    content_type.insert_headers(&mut builder);
+    Ok(builder.body(encode(result, content_type)?))
+}
+
+#[get("/search")]
+async fn search(
+    req: HttpRequest,
+    query: web::Query<SearchParams>,
+    profile: SelectedProfile,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let result = profile.0.data_source.search(&query.into_inner().into())?;
+    let content_type = ContentType::negotiate(&req);
+    let response = EncodedResponse::new(encode(result, content_type)?, content_type);
+    Ok(state.respond(&req, &profile.0, &response))
+}
+
+#[get("/profiles")]
+async fn list_profiles(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse> {
+    let content_type = ContentType::negotiate(&req);
+    let mut builder = HttpResponse::Ok();
+    content_type.insert_headers(&mut builder);
+    Ok(builder.body(encode(state.profile_listings(), content_type)?))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Describes the profile, with a form that opens it in the viewer. The
+// server's URL is filled in from the request, but the page replaces it with
+// its own location, which is more accurate behind a reverse proxy
+fn landing_page(
+    description: &DataSourceDescription,
+    info: &DataSourceResult<DataSourceInfo>,
+    viewer_url: &str,
+    server_url: &str,
+) -> String {
+    let locator = escape_html(&description.source_locator.join(", "));
+    let details = match info {
+        Ok(info) => {
+            let mut details = format!(
+                "<p>Interval: {}</p>\n",
+                escape_html(&info.interval.to_string())
+            );
+            if let Some(warning) = &info.warning_message {
+                details.push_str(&format!(
+                    "<p><strong>Warning:</strong> {}</p>\n",
+                    escape_html(warning)
+                ));
+            }
+            details
+        }
+        Err(e) => format!(
+            "<p><strong>Unable to load profile:</strong> {}</p>\n",
+            escape_html(&e.to_string())
+        ),
+    };
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{locator} - Legion Prof</title>
+</head>
+<body>
+<h1>Legion Prof</h1>
+<p>Profile: {locator}</p>
+{details}<form action=\"{viewer_url}\" method=\"get\">
+<label>Server URL: <input type=\"url\" name=\"url\" id=\"url\" size=\"60\" value=\"{server_url}\"></label>
+<button type=\"submit\">Open in viewer</button>
+</form>
+<script>
+document.getElementById(\"url\").value = location.href.split(/[?#]/)[0];
+</script>
+</body>
+</html>
+",
+        viewer_url = escape_html(viewer_url),
+        server_url = escape_html(server_url),
+    )
+}
+
+// Links to the landing page of each profile in the server's directory
+fn profiles_page(profiles: &[ProfileListing]) -> String {
+    let mut items = String::new();
+    for profile in profiles {
+        items.push_str(&format!(
+            "<li><a href=\"p/{name}/\">{name}</a> ({locator})</li>\n",
+            name = escape_html(&profile.name),
+            locator = escape_html(&profile.description.source_locator.join(", ")),
+        ));
+    }
+    if items.is_empty() {
+        items.push_str("<li>No profiles yet</li>\n");
+    }
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Legion Prof</title>
+</head>
+<body>
+<h1>Legion Prof</h1>
+<ul>
+{items}</ul>
+</body>
+</html>
+"
+    )
+}
+
+// The bundled viewer is at the root, so pages under /p/{name}/ link to it
+// with the given prefix
+fn landing(req: &HttpRequest, state: &AppState, profile: &Profile, root: &str) -> HttpResponse {
+    let viewer_url = match &state.viewer {
+        ViewerLocation::Hosted(url) => url.clone(),
+        ViewerLocation::Bundled(_) => format!("{}viewer/", root),
+    };
+    let conn = req.connection_info();
+    let server_url = format!("{}://{}{}", conn.scheme(), conn.host(), req.path());
+    let page = landing_page(
+        &profile.data_source.fetch_description(),
+        &profile.data_source.fetch_info(),
+        &viewer_url,
+        &server_url,
+    );
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page)
+}
+
+#[get("/")]
+async fn index(req: HttpRequest, state: web::Data<AppState>) -> HttpResponse {
+    if let Some(profile) = &state.profile {
+        return landing(&req, &state, profile, "");
+    }
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(profiles_page(&state.profile_listings()))
+}
+
+#[get("/")]
+async fn profile_index(
+    req: HttpRequest,
+    profile: SelectedProfile,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    landing(&req, &state, &profile.0, "../../")
+}
+
+// Only the kinds of files in a compiled web viewer
+fn viewer_content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+// The file in the bundled viewer at the given URL path, unless the path
+// would leave the viewer's directory
+fn viewer_file(viewer: &Path, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let file = viewer.join(path);
+    if file.is_dir() {
+        Some(file.join("index.html"))
+    } else {
+        Some(file)
+    }
+}
+
+#[get("/viewer/{path:.*}")]
+async fn fetch_viewer(path: web::Path<String>, state: web::Data<AppState>) -> Result<HttpResponse> {
+    let ViewerLocation::Bundled(viewer) = &state.viewer else {
+        return Err(error::ErrorNotFound(
+            "no viewer is bundled with this server",
+        ));
+    };
+    let file = viewer_file(viewer, &path)
+        .ok_or_else(|| error::ErrorBadRequest(format!("bad request: {}", path)))?;
+    let contents =
+        std::fs::read(&file).map_err(|_| error::ErrorNotFound(format!("not found: {}", path)))?;
+    Ok(HttpResponse::Ok()
+        .content_type(viewer_content_type(&file))
+        .body(contents))
+}
+
+// Response cache metrics, in the Prometheus text format
+#[get("/metrics")]
+async fn metrics(state: web::Data<AppState>) -> impl Responder {
+    let stats = state.cache.stats();
+    format!(
+        "response_cache_hits_total {}\n\
+         response_cache_misses_total {}\n\
+         response_cache_evictions_total {}\n\
+         response_cache_entries {}\n\
+         response_cache_size_bytes {}\n\
+         response_cache_capacity_bytes {}\n",
+        stats.hits, stats.misses, stats.evictions, stats.tiles, stats.size, stats.capacity
+    )
+}
+
+// The same for the profile at the root and for those under /p/{name}/
+fn profile_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(fetch_info)
+        .service(fetch_summary_tile)
+        .service(fetch_counter_tile)
+        .service(fetch_slot_tile)
+        .service(fetch_slot_meta_tile)
+        .service(fetch_tiles)
+        .service(search);
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(index)
+        .service(fetch_viewer)
+        .service(list_profiles)
+        .service(metrics)
+        .service(
+            web::scope("/p/{name}")
+                .service(profile_index)
+                .configure(profile_routes),
+        )
+        .configure(profile_routes);
 }
 
 impl DataSourceHTTPServer {
-    pub fn new(
+    fn with_profiles(
         host: String,
         port: u16,
-        data_source: Box<dyn DataSource + Send + Sync + 'static>,
+        profile: Option<Profile>,
+        directory: Option<ProfileDirectory>,
     ) -> Self {
         Self {
             host,
             port,
-            state: AppState { data_source },
+            state: AppState {
+                profile: profile.map(Arc::new),
+                directory,
+                max_age: Duration::ZERO,
+                cache: ResponseCache::new(DEFAULT_RESPONSE_CACHE_CAPACITY),
+                viewer: ViewerLocation::Hosted(DEFAULT_VIEWER_URL.to_owned()),
+            },
+            prewarm_levels: 0,
         }
     }
 
+    // Serves one profile, at the root. Its responses are validated by their
+    // (content-derived) ETags alone, unless with_last_modified is used
+    pub fn new(
+        host: String,
+        port: u16,
+        data_source: Box<dyn DataSource + Send + Sync + 'static>,
+    ) -> Self {
+        let profile = Profile {
+            id: 0,
+            data_source,
+            last_modified: None,
+        };
+        Self::with_profiles(host, port, Some(profile), None)
+    }
+
+    // Serves every archive in the directory under /p/{name}/, and lists them
+    // at /profiles. Archives can be added and removed while the server runs.
+    pub fn from_directory(host: String, port: u16, path: impl AsRef<Path>) -> Self {
+        let directory = ProfileDirectory::new(path, DEFAULT_SCAN_INTERVAL, 1);
+        Self::with_profiles(host, port, None, Some(directory))
+    }
+
+    // How often to look for archives added to (or removed from) the
+    // directory of a server made with from_directory
+    pub fn with_scan_interval(mut self, scan_interval: Duration) -> Self {
+        if let Some(directory) = self.state.directory.take() {
+            self.state.directory = Some(ProfileDirectory::new(directory.path, scan_interval, 1));
+        }
+        self
+    }
+
+    // When the profile served at the root was last modified (e.g., the
+    // modification time of its archive), which is sent with its responses
+    // so that clients can revalidate them with If-Modified-Since
+    pub fn with_last_modified(mut self, last_modified: SystemTime) -> Self {
+        if let Some(profile) = self.state.profile.as_mut().and_then(Arc::get_mut) {
+            profile.last_modified = Some(last_modified);
+        }
+        self
+    }
+
+    // The capacity is the total size of the encoded responses, in bytes
+    pub fn with_response_cache_capacity(mut self, capacity: usize) -> Self {
+        self.state.cache = ResponseCache::new(capacity);
+        self
+    }
+
+    // Fetch the given number of the coarsest tile levels before accepting
+    // requests, so that the first clients don't have to wait for them
+    pub fn with_prewarm(mut self, levels: usize) -> Self {
+        self.prewarm_levels = levels;
+        self
+    }
+
+    // How long clients and proxies may reuse responses without checking back.
+    // Only set this for profiles that won't change (e.g., from a completed
+    // run), since a cache may keep serving the old profile for this long
+    // after the server is restarted with a new one. By default, responses are
+    // revalidated every time, which is still cheap since only their ETags
+    // are sent back when nothing has changed.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.state.max_age = max_age;
+        self
+    }
+
+    // The viewer that landing pages (at / or /p/{name}/) open profiles with. A bundled
+    // viewer must be built with a relative public URL, and is served at
+    // /viewer/.
+    pub fn with_viewer(mut self, viewer: ViewerLocation) -> Self {
+        self.state.viewer = viewer;
+        self
+    }
+
     #[actix_web::main]
     pub async fn run(self) -> std::io::Result<()> {
-        let state = Data::from(Arc::new(self.state));
+        if let ViewerLocation::Bundled(viewer) = &self.state.viewer {
+            if !viewer.join("index.html").is_file() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{:?} is not a compiled web viewer (no index.html)", viewer),
+                ));
+            }
+        }
+
+        if self.prewarm_levels > 0 {
+            for (name, profile) in self.state.profiles() {
+                if let Err(e) = self.state.prewarm(&profile, self.prewarm_levels) {
+                    warn!("Unable to pre-warm response cache for {:?}: {}", name, e);
+                }
+            }
+        }
+
+        let state = Arc::new(self.state);
+        // Scans are slow (every new archive is opened), so they're kept off
+        // the worker threads. The thread stops once the server does
+        if let Some(directory) = &state.directory {
+            let scan_interval = directory.scan_interval;
+            let state = Arc::downgrade(&state);
+            std::thread::spawn(move || loop {
+                std::thread::sleep(scan_interval);
+                let Some(state) = state.upgrade() else {
+                    break;
+                };
+                state.rescan();
+            });
+        }
+        let state = Data::from(state);
         HttpServer::new(move || {
             let cors = Cors::default()
                 .send_wildcard()
@@ -106,18 +1025,580 @@ impl DataSourceHTTPServer {
                 .allowed_methods(vec!["GET", "POST"])
                 .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
                 .allowed_header(http::header::CONTENT_TYPE)
+                .expose_headers(vec![SERVER_HEADER])
                 .max_age(3600);
             App::new()
+                .wrap(middleware::Compress::default())
                 .wrap(middleware::Logger::default())
                 .wrap(cors)
                 .app_data(state.clone())
-                .service(fetch_info)
-                .service(fetch_summary_tile)
-                .service(fetch_slot_tile)
-                .service(fetch_slot_meta_tile)
+                .configure(routes)
         })
         .bind((self.host.as_str(), self.port))?
         .run()
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use actix_web::test::{self, TestRequest};
+
+    use crate::data::{
+        CounterTile, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, FieldSchema,
+        ItemLink, ItemUID, SearchQuery, SearchResults, SlotMetaTile, SlotTile, SummaryTile,
+        SummaryTileData, TileSet,
+    };
+    use crate::timestamp::{Interval, Timestamp};
+
+    // Counts the summary tiles fetched from it (and has nothing else)
+    struct CountingDataSource {
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl DataSource for CountingDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+            Ok(DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: "root".to_owned(),
+                    long_name: "root".to_owned(),
+                    summary: Some(Box::new(EntryInfo::Summary {
+                        color: crate::data::Color32::BLUE,
+                    })),
+                    counters: Vec::new(),
+                    slots: Vec::new(),
+                },
+                interval: Interval::new(Timestamp(0), Timestamp(100)),
+                tile_set: TileSet::default(),
+                field_schema: FieldSchema::new(),
+                warning_message: None,
+            })
+        }
+
+        fn fetch_summary_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SummaryTile> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            Ok(SummaryTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SummaryTileData {
+                    utilization: Vec::new(),
+                },
+            })
+        }
+
+        fn fetch_counter_tile(
+            &self,
+            _entry_id: &EntryID,
+            _tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<CounterTile> {
+            Err(DataSourceError::NotFound("counter".to_owned()))
+        }
+
+        fn fetch_slot_tile(
+            &self,
+            _entry_id: &EntryID,
+            _tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SlotTile> {
+            unreachable!()
+        }
+
+        fn fetch_slot_meta_tile(
+            &self,
+            _entry_id: &EntryID,
+            _tile_id: TileID,
+            _full: bool,
+        ) -> DataSourceResult<SlotMetaTile> {
+            unreachable!()
+        }
+
+        // Finds a single item, titled after the query
+        fn search(&self, query: &SearchQuery) -> DataSourceResult<SearchResults> {
+            Ok(SearchResults {
+                items: vec![ItemLink {
+                    item_uid: ItemUID(1),
+                    title: query.query.clone(),
+                    interval: query.interval,
+                    entry_id: EntryID::root().child(0).child(0),
+                }],
+                truncated: false,
+            })
+        }
+    }
+
+    fn state(capacity: usize) -> (AppState, Arc<AtomicUsize>) {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let data_source = CountingDataSource {
+            fetches: fetches.clone(),
+        };
+        let server = DataSourceHTTPServer::new("127.0.0.1".to_owned(), 8080, Box::new(data_source))
+            .with_response_cache_capacity(capacity);
+        (server.state, fetches)
+    }
+
+    fn request(kind: TileKind, start: i64) -> TileRequest {
+        TileRequest {
+            kind,
+            entry_id: EntryID::root().summary(),
+            tile_id: TileID(Interval::new(Timestamp(start), Timestamp(start + 10))),
+            full: false,
+        }
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let etag = entity_tag(b"tile");
+        assert_eq!(etag, entity_tag(b"tile"));
+        assert_ne!(etag, entity_tag(b"other tile"));
+
+        let last_modified: HttpDate = SystemTime::now().into();
+        let earlier: HttpDate = (SystemTime::now() - Duration::from_secs(60)).into();
+        let check =
+            |req: TestRequest| not_modified(&req.to_http_request(), &etag, Some(last_modified));
+
+        assert!(!check(TestRequest::default()));
+        assert!(check(
+            TestRequest::default().insert_header(IfNoneMatch::Items(vec![etag.clone()]))
+        ));
+        assert!(check(
+            TestRequest::default().insert_header(IfNoneMatch::Any)
+        ));
+        assert!(!check(TestRequest::default().insert_header(
+            IfNoneMatch::Items(vec![entity_tag(b"other tile")])
+        )));
+        assert!(check(
+            TestRequest::default().insert_header(IfModifiedSince(last_modified))
+        ));
+        assert!(!check(
+            TestRequest::default().insert_header(IfModifiedSince(earlier))
+        ));
+        // If-None-Match takes precedence
+        assert!(!check(
+            TestRequest::default()
+                .insert_header(IfNoneMatch::Items(vec![entity_tag(b"other tile")]))
+                .insert_header(IfModifiedSince(last_modified))
+        ));
+        // Without a modification time, only ETags are checked
+        assert!(!not_modified(
+            &TestRequest::default()
+                .insert_header(IfModifiedSince(last_modified))
+                .to_http_request(),
+            &etag,
+            None
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_last_modified() {
+        let (state, _) = state(1 << 20);
+        let app = test::init_service(App::new().app_data(Data::new(state)).configure(routes)).await;
+        let resp = test::call_service(&app, TestRequest::get().uri("/info").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // The server has no way to know when the profile was made
+        assert!(!resp.headers().contains_key(http::header::LAST_MODIFIED));
+        assert!(resp.headers().contains_key(http::header::ETAG));
+
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let data_source = CountingDataSource {
+            fetches: Arc::new(AtomicUsize::new(0)),
+        };
+        let server = DataSourceHTTPServer::new("127.0.0.1".to_owned(), 8080, Box::new(data_source))
+            .with_last_modified(modified);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(server.state))
+                .configure(routes),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/info")
+            .insert_header(IfModifiedSince(modified.into()))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(
+            resp.headers().get(http::header::LAST_MODIFIED).unwrap(),
+            &HttpDate::from(modified).to_string()
+        );
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let (state, fetches) = state(1 << 20);
+        let profile = state.profile.clone().unwrap();
+        let first = state
+            .fetch_tile(
+                &profile,
+                &request(TileKind::Summary, 0),
+                ContentType::Native,
+            )
+            .unwrap();
+        let second = state
+            .fetch_tile(
+                &profile,
+                &request(TileKind::Summary, 0),
+                ContentType::Native,
+            )
+            .unwrap();
+        assert_eq!(first.etag, second.etag);
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // Batches share the cache, but errors aren't cached
+        let batch = [
+            request(TileKind::Summary, 0),
+            request(TileKind::Summary, 10),
+            request(TileKind::Counter, 0),
+        ];
+        for _ in 0..2 {
+            let results = state.fetch_tiles(&profile, &batch);
+            assert!(matches!(results[0], Ok(Tile::Summary(_))));
+            assert!(matches!(results[1], Ok(Tile::Summary(_))));
+            assert!(matches!(results[2], Err(DataSourceError::NotFound(_))));
+        }
+        assert_eq!(fetches.load(Ordering::Relaxed), 2);
+        let stats = state.cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.tiles), (4, 4, 2));
+        assert!(state
+            .fetch_tile(
+                &profile,
+                &request(TileKind::Counter, 0),
+                ContentType::Native
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_response_cache_eviction() {
+        // These tiles all encode to the same size
+        let (sizing, _) = state(1 << 20);
+        let size = sizing
+            .fetch_tile(
+                sizing.profile.as_ref().unwrap(),
+                &request(TileKind::Summary, 30),
+                ContentType::Native,
+            )
+            .unwrap()
+            .body
+            .len();
+        let (state, fetches) = state(2 * size);
+        let profile = state.profile.clone().unwrap();
+        for start in [30, 40, 30, 50] {
+            state
+                .fetch_tile(
+                    &profile,
+                    &request(TileKind::Summary, start),
+                    ContentType::Native,
+                )
+                .unwrap();
+        }
+        let stats = state.cache.stats();
+        assert_eq!((stats.tiles, stats.evictions), (2, 1));
+        assert_eq!(stats.size, 2 * size);
+
+        // The least recently used tile was evicted
+        state
+            .fetch_tile(
+                &profile,
+                &request(TileKind::Summary, 30),
+                ContentType::Native,
+            )
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::Relaxed), 3);
+        state
+            .fetch_tile(
+                &profile,
+                &request(TileKind::Summary, 40),
+                ContentType::Native,
+            )
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_prewarm() {
+        let (state, fetches) = state(1 << 20);
+        let profile = state.profile.clone().unwrap();
+        state.prewarm(&profile, 1).unwrap();
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+        assert_eq!(state.cache.stats().tiles, 2);
+
+        let whole = TileRequest {
+            tile_id: TileID(Interval::new(Timestamp(0), Timestamp(100))),
+            ..request(TileKind::Summary, 0)
+        };
+        state
+            .fetch_tile(&profile, &whole, ContentType::Native)
+            .unwrap();
+        state.fetch_info(&profile, ContentType::Native).unwrap();
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let negotiate = |accept: &str| {
+            ContentType::negotiate(
+                &TestRequest::default()
+                    .insert_header(("Accept", accept))
+                    .to_http_request(),
+            )
+        };
+        assert_eq!(
+            ContentType::negotiate(&TestRequest::default().to_http_request()),
+            ContentType::Native
+        );
+        assert_eq!(negotiate("*/*"), ContentType::Native);
+        assert_eq!(negotiate("application/json"), ContentType::Json);
+        assert_eq!(negotiate("application/cbor"), ContentType::Cbor);
+        assert_eq!(
+            negotiate("application/cbor;q=0.5, application/json"),
+            ContentType::Json
+        );
+        assert_eq!(negotiate("text/html, */*;q=0.8"), ContentType::Native);
+        assert_eq!(negotiate("text/html"), ContentType::Native);
+    }
+
+    #[test]
+    fn test_content_types() {
+        let (state, fetches) = state(1 << 20);
+        let profile = state.profile.clone().unwrap();
+        let req = request(TileKind::Summary, 0);
+        let native = state
+            .fetch_tile(&profile, &req, ContentType::Native)
+            .unwrap();
+        let json = state.fetch_tile(&profile, &req, ContentType::Json).unwrap();
+        let cbor = state.fetch_tile(&profile, &req, ContentType::Cbor).unwrap();
+        assert_ne!(native.etag, json.etag);
+        assert_eq!(state.cache.stats().tiles, 3);
+        assert_eq!(fetches.load(Ordering::Relaxed), 3);
+
+        let tile: SummaryTile = serde_json::from_slice(&json.body).unwrap();
+        assert_eq!(tile.tile_id, req.tile_id);
+        let tile: SummaryTile = ciborium::from_reader(&cbor.body[..]).unwrap();
+        assert_eq!(tile.tile_id, req.tile_id);
+
+        let info = state.fetch_info(&profile, ContentType::Json).unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&info.body).unwrap();
+        assert_eq!(info["entry_info"]["Panel"]["short_name"], "root");
+    }
+
+    #[actix_web::test]
+    async fn test_compression() {
+        let (state, _) = state(1 << 20);
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::Compress::default())
+                .app_data(Data::new(state))
+                .configure(routes),
+        )
+        .await;
+
+        // JSON is compressed on request
+        let req = TestRequest::get()
+            .uri("/info")
+            .insert_header(("Accept", "application/json"))
+            .insert_header(("Accept-Encoding", "zstd"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "zstd");
+        let body = zstd::decode_all(&test::read_body(resp).await[..]).unwrap();
+        let info: DataSourceInfo = serde_json::from_slice(&body).unwrap();
+        assert_eq!(info.interval, Interval::new(Timestamp(0), Timestamp(100)));
+
+        // The native format isn't compressed twice
+        let req = TestRequest::get()
+            .uri("/info")
+            .insert_header(("Accept-Encoding", "zstd"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Content-Encoding").unwrap(), "identity");
+        let info: DataSourceInfo = format::read(&test::read_body(resp).await[..], "info").unwrap();
+        assert_eq!(info.interval, Interval::new(Timestamp(0), Timestamp(100)));
+
+        // Batches can be requested as JSON too
+        let batch = serde_json::to_vec(&[request(TileKind::Summary, 0)]).unwrap();
+        let req = TestRequest::post()
+            .uri("/tiles")
+            .insert_header(("Accept", "application/json"))
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(batch)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-cache");
+        let body = test::read_body(resp).await;
+        let tiles: Vec<DataSourceResult<Tile>> = serde_json::from_slice(&body).unwrap();
+        assert!(matches!(tiles[..], [Ok(Tile::Summary(_))]));
+
+        // But not too many at once
+        let batch = vec![request(TileKind::Summary, 0); MAX_BATCH_SIZE + 1];
+        let req = TestRequest::post()
+            .uri("/tiles")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(serde_json::to_vec(&batch).unwrap())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn test_search() {
+        let (state, _) = state(1 << 20);
+        let app = test::init_service(App::new().app_data(Data::new(state)).configure(routes)).await;
+
+        let req = TestRequest::get()
+            .uri("/search?query=copy&whole_word=false&start=10&stop=20&max_results=5")
+            .insert_header(("Accept", "application/json"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().contains_key(SERVER_HEADER));
+        let results: SearchResults = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(results.items[0].title, "copy");
+        assert_eq!(
+            results.items[0].interval,
+            Interval::new(Timestamp(10), Timestamp(20))
+        );
+
+        // Queries must be complete
+        let req = TestRequest::get().uri("/search?query=copy").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_landing_page() {
+        let description = DataSourceDescription {
+            source_locator: vec!["prof_<0>.gz".to_owned()],
+        };
+        let (state, _) = state(1 << 20);
+        let profile = state.profile.clone().unwrap();
+        let mut info = profile.data_source.fetch_info();
+        info.as_mut().unwrap().warning_message = Some("dropped \"events\"".to_owned());
+        let page = landing_page(&description, &info, DEFAULT_VIEWER_URL, "http://a/");
+        assert!(page.contains("Profile: prof_&lt;0&gt;.gz"));
+        assert!(page.contains(&escape_html(
+            &Interval::new(Timestamp(0), Timestamp(100)).to_string()
+        )));
+        assert!(page.contains("dropped &quot;events&quot;"));
+        assert!(page.contains(&format!("action=\"{}\"", DEFAULT_VIEWER_URL)));
+        assert!(page.contains("value=\"http://a/\""));
+
+        let info = Err(DataSourceError::NotFound("prof_0.gz".to_owned()));
+        let page = landing_page(&description, &info, "viewer/", "http://a/");
+        assert!(page.contains("Unable to load profile"));
+    }
+
+    #[actix_web::test]
+    async fn test_bundled_viewer() {
+        let viewer = std::env::temp_dir().join(format!("server_viewer_{}", std::process::id()));
+        std::fs::create_dir_all(viewer.join("assets")).unwrap();
+        std::fs::write(viewer.join("index.html"), "viewer").unwrap();
+        std::fs::write(viewer.join("assets").join("viewer.wasm"), "wasm").unwrap();
+
+        let (mut state, _) = state(1 << 20);
+        state.viewer = ViewerLocation::Bundled(viewer.clone());
+        let app = test::init_service(App::new().app_data(Data::new(state)).configure(routes)).await;
+
+        let page = test::call_and_read_body(&app, TestRequest::get().uri("/").to_request()).await;
+        assert!(String::from_utf8_lossy(&page).contains("action=\"viewer/\""));
+        let viewer_index =
+            test::call_and_read_body(&app, TestRequest::get().uri("/viewer/").to_request()).await;
+        assert_eq!(&viewer_index[..], b"viewer");
+        let resp = test::call_service(
+            &app,
+            TestRequest::get()
+                .uri("/viewer/assets/viewer.wasm")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(
+            resp.headers().get("Content-Type").unwrap(),
+            "application/wasm"
+        );
+
+        assert!(viewer_file(&viewer, "../secret").is_none());
+        assert!(viewer_file(&viewer, "/etc/passwd").is_none());
+        let resp = test::call_service(
+            &app,
+            TestRequest::get().uri("/viewer/missing.js").to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(&viewer).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_profile_directory() {
+        let dir = std::env::temp_dir().join(format!("server_profiles_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (state, _) = state(1 << 20);
+        let info = state.profile.unwrap().data_source.fetch_info().unwrap();
+        let file = std::fs::File::create(dir.join("a.lpv")).unwrap();
+        let mut writer = container::ContainerWriter::new(file).unwrap();
+        writer
+            .append(
+                container::INFO_KEY.to_owned(),
+                &format::write(Vec::new(), &info, 1).unwrap(),
+            )
+            .unwrap();
+        writer.finish(1).unwrap();
+
+        let server = DataSourceHTTPServer::from_directory("127.0.0.1".to_owned(), 8080, &dir);
+        let state = Data::new(server.state);
+        let app = test::init_service(App::new().app_data(state.clone()).configure(routes)).await;
+        let get = |uri: &str| {
+            TestRequest::get()
+                .uri(uri)
+                .insert_header(("Accept", "application/json"))
+                .to_request()
+        };
+
+        let profiles: Vec<ProfileListing> =
+            serde_json::from_slice(&test::call_and_read_body(&app, get("/profiles")).await)
+                .unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].name, "a");
+        let body = test::call_and_read_body(&app, get("/p/a/info")).await;
+        let served: DataSourceInfo = serde_json::from_slice(&body).unwrap();
+        assert_eq!(served.interval, info.interval);
+        let page = test::call_and_read_body(&app, get("/")).await;
+        assert!(String::from_utf8_lossy(&page).contains("href=\"p/a/\""));
+        let page = test::call_and_read_body(&app, get("/p/a/")).await;
+        assert!(String::from_utf8_lossy(&page).contains("Interval"));
+
+        // There's no profile at the root, and removed profiles are gone
+        let resp = test::call_service(&app, get("/info")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        std::fs::remove_file(dir.join("a.lpv")).unwrap();
+        let resp = test::call_service(&app, get("/p/a/info")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_ne!(state.cache.stats().tiles, 0);
+        state.rescan();
+        let resp = test::call_service(&app, get("/p/a/info")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        // Along with their cached responses
+        assert_eq!(state.cache.stats().tiles, 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+// The method, path and status of every request
+pub type Requests = Arc<Mutex<Vec<(String, u16)>>>;
+
+// A static host serving the given files (with ETags and the given
+// Cache-Control), one connection at a time. POSTs get the file too
+pub fn serve(files: BTreeMap<String, Vec<u8>>, cache_control: &str) -> (Url, Requests) {
+    serve_with_headers(files, format!("Cache-Control: {}\r\n", cache_control))
+}
+
+// Like serve, but with the given header lines on every file. Range requests
+// are answered with just the range
+pub fn serve_with_headers(files: BTreeMap<String, Vec<u8>>, extra: String) -> (Url, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let log = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut fields = line.split(' ');
+            let (method, path) = (fields.next().unwrap(), fields.next().unwrap());
+            let (mut if_none_match, mut range, mut length) = (None, None, 0);
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let Some((name, value)) = header.trim_end().split_once(": ") else {
+                    break;
+                };
+                if name.eq_ignore_ascii_case("if-none-match") {
+                    if_none_match = Some(value.to_owned());
+                } else if name.eq_ignore_ascii_case("range") {
+                    let (start, stop) = value.trim_start_matches("bytes=").split_once('-').unwrap();
+                    range = Some((
+                        start.parse::<usize>().unwrap(),
+                        stop.parse::<usize>().unwrap(),
+                    ));
+                } else if name.eq_ignore_ascii_case("content-length") {
+                    length = value.parse().unwrap();
+                }
+            }
+            reader.read_exact(&mut vec![0; length]).unwrap();
+
+            let key = path.split('?').next().unwrap().trim_start_matches('/');
+            let (status, headers, body) = match files.get(key) {
+                Some(body) => {
+                    let etag = format!("\"{:08x}\"", crc32fast::hash(body));
+                    let headers = format!("ETag: {}\r\n{}", etag, extra);
+                    match range {
+                        _ if if_none_match == Some(etag) => (304, headers, &[][..]),
+                        Some((start, stop)) => {
+                            (206, headers, &body[start..(stop + 1).min(body.len())])
+                        }
+                        None => (200, headers, &body[..]),
+                    }
+                }
+                // As python -m http.server does
+                None if method == "POST" => (501, String::new(), &[][..]),
+                None => (404, String::new(), &[][..]),
+            };
+            log.lock()
+                .unwrap()
+                .push((format!("{} {}", method, path), status));
+            write!(
+                stream,
+                "HTTP/1.1 {} \r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                headers,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+        }
+    });
+    (url, requests)
+}
+
+pub fn wait_for<T>(mut poll: impl FnMut() -> Vec<T>) -> Vec<T> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let results = poll();
+        if !results.is_empty() {
+            return results;
+        }
+        assert!(Instant::now() < deadline, "timed out");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
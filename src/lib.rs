@@ -3,12 +3,25 @@
 pub mod app;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod archive_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cached_file_data;
+pub mod container;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod container_data;
+pub mod critical_path;
 pub mod data;
 pub mod deferred_data;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod file_data;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod file_io;
+pub mod format;
 pub mod http;
+pub mod manifest;
 pub mod merge_data;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod parallel_data;
+pub mod retile_data;
+pub mod search;
+pub mod size;
 pub mod timestamp;
@@ -7,18 +7,37 @@ use std::collections::BTreeMap;
 use std::sync::Mutex;
 
 use legion_prof_viewer::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, EntryInfo, Field, FieldID,
-    FieldSchema, Item, ItemMeta, ItemUID, SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData,
-    SummaryTile, SummaryTileData, TileID, TileSet, UtilPoint,
+    CounterPoint, CounterTile, CounterTileData, DataSource, DataSourceDescription, DataSourceInfo,
+    DataSourceResult, EdgeKind, EntryID, EntryInfo, Field, FieldID, FieldSchema, Item, ItemEdge,
+    ItemMeta, ItemUID, SlotMetaTile, SlotMetaTileData, SlotTile, SlotTileData, SummaryTile,
+    SummaryTileData, TileID, TileSet, UtilPoint,
 };
 
 #[cfg(not(target_arch = "wasm32"))]
-use legion_prof_viewer::deferred_data::DeferredDataSourceWrapper;
+use legion_prof_viewer::archive_data::{
+    repair_archive, verify_archive, ArchiveFormat, ArchiveReport, DataSourceArchiveWriter,
+    TilingMode,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::container::tile_key;
+use legion_prof_viewer::container::CONTAINER_EXTENSION;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::container_data::ContainerDataSource;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::deferred_data::{DeferredDataSource, DeferredDataSourceWrapper};
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::file_data::FileDataSource;
+#[cfg(all(not(target_arch = "wasm32"), feature = "client"))]
+use legion_prof_viewer::http::client::HTTPClientDataSource;
+#[cfg(not(target_arch = "wasm32"))]
+use legion_prof_viewer::parallel_data::ParallelDeferredDataSource;
 use legion_prof_viewer::timestamp::{Interval, Timestamp};
 
 #[cfg(target_arch = "wasm32")]
 use legion_prof_viewer::http::client::HTTPClientDataSource;
 #[cfg(target_arch = "wasm32")]
+use legion_prof_viewer::http::range_client::HTTPRangeDataSource;
+#[cfg(target_arch = "wasm32")]
 use url::Url;
 
 #[cfg(target_arch = "wasm32")]
@@ -26,11 +45,166 @@ const DEFAULT_URL: &str = "http://127.0.0.1:8080";
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    match &args[..] {
+        [_, command, path] if command == "verify" => std::process::exit(verify(path)),
+        [_, command, path, source] if command == "repair" => {
+            std::process::exit(repair(path, source))
+        }
+        [_, command, source, path, options @ ..] if command == "archive" => {
+            std::process::exit(archive(source, path, options))
+        }
+        _ => {}
+    }
+
     legion_prof_viewer::app::start(vec![Box::new(DeferredDataSourceWrapper::new(
         RandomDataSource::new(),
     ))]);
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn check_archive(path: &str) -> Option<ArchiveReport> {
+    let result = if std::path::Path::new(path).is_dir() {
+        verify_archive(&FileDataSource::new(path))
+    } else {
+        verify_archive(&ContainerDataSource::new(path))
+    };
+    result
+        .map_err(|e| eprintln!("Unable to read archive {}: {}", path, e))
+        .ok()
+}
+
+// Checks an archive (directory or single file) and lists any missing or
+// corrupt tiles. These can be regenerated with the repair command.
+#[cfg(not(target_arch = "wasm32"))]
+fn verify(path: &str) -> i32 {
+    let Some(report) = check_archive(path) else {
+        return 2;
+    };
+    print_report(&report);
+    if report.is_ok() {
+        0
+    } else {
+        1
+    }
+}
+
+// Checks an archive, and regenerates any missing or corrupt tiles from
+// source: a copy of the archive or (with the client feature) the URL of a
+// server for the original profile.
+#[cfg(not(target_arch = "wasm32"))]
+fn repair(path: &str, source: &str) -> i32 {
+    let Some(report) = check_archive(path) else {
+        return 2;
+    };
+    print_report(&report);
+    if report.is_ok() {
+        return 0;
+    }
+
+    let Some(data_source) = open_source(source) else {
+        return 2;
+    };
+    let zstd_compression = zstd::DEFAULT_COMPRESSION_LEVEL;
+    if let Err(e) = repair_archive(path, &report, data_source, zstd_compression) {
+        eprintln!("Unable to repair archive {}: {}", path, e);
+        return 2;
+    }
+    println!("Repaired {} tiles", report.problems.len());
+    0
+}
+
+// Writes a new archive (a single file if the path ends in .lpv) from a copy
+// of the profile, as the source is given to repair. With --items-per-tile N,
+// tiles are sized to hold about N items each rather than evenly spaced.
+#[cfg(not(target_arch = "wasm32"))]
+fn archive(source: &str, path: &str, options: &[String]) -> i32 {
+    const LEVELS: u32 = 4;
+    const BRANCH_FACTOR: u64 = 4;
+
+    let tiling = match options {
+        [] => TilingMode::Uniform,
+        [option, n] if option == "--items-per-tile" => match n.parse() {
+            Ok(items_per_tile) if items_per_tile >= 1 => TilingMode::Adaptive { items_per_tile },
+            _ => {
+                eprintln!("Invalid number of items per tile {}", n);
+                return 2;
+            }
+        },
+        _ => {
+            eprintln!("Usage: archive <source> <path> [--items-per-tile N]");
+            return 2;
+        }
+    };
+    let format = if path.ends_with(&format!(".{CONTAINER_EXTENSION}")) {
+        ArchiveFormat::Container
+    } else {
+        ArchiveFormat::Directory
+    };
+
+    let Some(data_source) = open_source(source) else {
+        return 2;
+    };
+    let zstd_compression = zstd::DEFAULT_COMPRESSION_LEVEL;
+    let writer = DataSourceArchiveWriter::new(
+        data_source,
+        LEVELS,
+        BRANCH_FACTOR,
+        path,
+        false,
+        zstd_compression,
+    )
+    .with_format(format)
+    .with_tiling(tiling);
+    if let Err(e) = writer.write() {
+        eprintln!("Unable to write archive {}: {}", path, e);
+        return 2;
+    }
+    0
+}
+
+// An archive (directory or single file) or, with the client feature, the URL
+// of a server
+#[cfg(not(target_arch = "wasm32"))]
+fn open_source(source: &str) -> Option<Box<dyn DeferredDataSource>> {
+    if std::path::Path::new(source).is_dir() {
+        Some(Box::new(ParallelDeferredDataSource::new(
+            FileDataSource::new(source),
+        )))
+    } else if std::path::Path::new(source).is_file() {
+        Some(Box::new(ParallelDeferredDataSource::new(
+            ContainerDataSource::new(source),
+        )))
+    } else {
+        #[cfg(feature = "client")]
+        match url::Url::parse(source) {
+            Ok(url) => Some(Box::new(HTTPClientDataSource::new(url))),
+            Err(e) => {
+                eprintln!("Unable to parse source {}: {}", source, e);
+                None
+            }
+        }
+        #[cfg(not(feature = "client"))]
+        {
+            eprintln!("Source {} is not an archive", source);
+            None
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn print_report(report: &ArchiveReport) {
+    for (req, problem) in &report.problems {
+        let key = tile_key(req.kind, &req.entry_id, req.tile_id);
+        println!("{}: {}", key, problem);
+    }
+    println!(
+        "Checked {} tiles, found {} problems",
+        report.tiles_checked,
+        report.problems.len()
+    );
+}
+
 #[cfg(target_arch = "wasm32")]
 fn main() {
     let loc: web_sys::Location = web_sys::window().unwrap().location();
@@ -47,10 +221,23 @@ fn main() {
     )
     .expect("Unable to parse query URL");
 
-    legion_prof_viewer::app::start(vec![Box::new(HTTPClientDataSource::new(url))]);
+    let is_container = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .is_some_and(|name| name.ends_with(&format!(".{CONTAINER_EXTENSION}")));
+    if is_container {
+        legion_prof_viewer::app::start(vec![Box::new(HTTPRangeDataSource::new(url))]);
+    } else {
+        legion_prof_viewer::app::start(vec![Box::new(HTTPClientDataSource::new(url))]);
+    }
 }
 
-type SlotCacheTile = (Vec<Vec<Item>>, Vec<Vec<ItemMeta>>);
+// Edges are stored along with the interval of their destination item
+type SlotCacheTile = (
+    Vec<Vec<Item>>,
+    Vec<Vec<ItemMeta>>,
+    Vec<(Interval, ItemEdge)>,
+);
 
 struct ItemUIDGenerator {
     next: ItemUID,
@@ -72,6 +259,7 @@ impl ItemUIDGenerator {
 
 struct RandomState {
     summary_cache: BTreeMap<EntryID, Vec<UtilPoint>>,
+    counter_cache: BTreeMap<EntryID, Vec<CounterPoint>>,
     slot_cache: BTreeMap<EntryID, SlotCacheTile>,
     rng: rand::rngs::ThreadRng,
     item_uid_generator: ItemUIDGenerator,
@@ -81,6 +269,8 @@ struct RandomDataSource {
     info: DataSourceInfo,
     item_uid_field: FieldID,
     interval_field: FieldID,
+    duration_field: FieldID,
+    size_field: FieldID,
     state: Mutex<RandomState>,
 }
 
@@ -91,6 +281,8 @@ impl RandomDataSource {
         let mut field_schema = FieldSchema::new();
         let item_uid_field = field_schema.insert("Item UID".to_owned(), false);
         let interval_field = field_schema.insert("Interval".to_owned(), false);
+        let duration_field = field_schema.insert("Duration".to_owned(), false);
+        let size_field = field_schema.insert("Size".to_owned(), false);
 
         let info = DataSourceInfo {
             entry_info,
@@ -102,6 +294,7 @@ impl RandomDataSource {
 
         let state = RandomState {
             summary_cache: BTreeMap::new(),
+            counter_cache: BTreeMap::new(),
             slot_cache: BTreeMap::new(),
             rng,
             item_uid_generator: ItemUIDGenerator::default(),
@@ -111,6 +304,8 @@ impl RandomDataSource {
             info,
             item_uid_field,
             interval_field,
+            duration_field,
+            size_field,
             state: Mutex::new(state),
         }
     }
@@ -170,6 +365,34 @@ impl RandomDataSource {
         state.summary_cache.get(entry_id).unwrap().clone()
     }
 
+    fn generate_counter(&self, entry_id: &EntryID) -> Vec<CounterPoint> {
+        let mut state = self.state.lock().unwrap();
+        if !state.counter_cache.contains_key(entry_id) {
+            let entry = self.info.entry_info.get(entry_id);
+
+            let (min, max) = if let EntryInfo::Counter { min, max, .. } = entry.unwrap() {
+                (*min, *max)
+            } else {
+                panic!("trying to fetch tile on something that is not a counter")
+            };
+
+            // Random walk, sampled at regular intervals
+            const POINTS: i64 = 1000;
+            let interval = self.info.interval;
+            let mut value = (min + max) * 0.5;
+            let mut points = Vec::new();
+            for i in 0..POINTS {
+                let time = Timestamp(interval.start.0 + interval.duration_ns() * i / POINTS);
+                points.push(CounterPoint { time, value });
+                let diff = (state.rng.gen::<f64>() - 0.5) * (max - min) * 0.05;
+                value = (value + diff).clamp(min, max);
+            }
+
+            state.counter_cache.insert(entry_id.clone(), points);
+        }
+        state.counter_cache.get(entry_id).unwrap().clone()
+    }
+
     fn generate_slot(&self, entry_id: &EntryID) -> SlotCacheTile {
         let mut state = self.state.lock().unwrap();
         if !state.slot_cache.contains_key(entry_id) {
@@ -181,8 +404,9 @@ impl RandomDataSource {
                 panic!("trying to fetch tile on something that is not a slot")
             };
 
-            let mut items = Vec::new();
+            let mut items: Vec<Vec<Item>> = Vec::new();
             let mut item_metas = Vec::new();
+            let mut edges = Vec::new();
             for row in 0..*max_rows {
                 let mut row_items = Vec::new();
                 let mut row_item_metas = Vec::new();
@@ -203,6 +427,25 @@ impl RandomDataSource {
                     };
 
                     let item_uid = state.item_uid_generator.next();
+
+                    // Connect some items to the row above them, so that
+                    // there are edges to look at
+                    if let Some(prev_row) = items.last() {
+                        let kind = match i % 10 {
+                            0 if i > 0 => Some((EdgeKind::Dependence, &prev_row[i as usize - 1])),
+                            5 => Some((EdgeKind::Creation, &prev_row[i as usize])),
+                            _ => None,
+                        };
+                        if let Some((kind, src)) = kind {
+                            let edge = ItemEdge {
+                                src: src.item_uid,
+                                dst: item_uid,
+                                kind,
+                            };
+                            edges.push((Interval::new(start, stop), edge));
+                        }
+                    }
+
                     row_items.push(Item {
                         item_uid,
                         interval: Interval::new(start, stop),
@@ -223,6 +466,12 @@ impl RandomDataSource {
                                 Field::U64(item_uid.0),
                                 Some(Color32::RED),
                             ),
+                            (self.duration_field, Field::Duration(stop.0 - start.0), None),
+                            (
+                                self.size_field,
+                                Field::Bytes(state.rng.gen_range(0..1 << 24)),
+                                None,
+                            ),
                         ],
                     });
                 }
@@ -232,7 +481,7 @@ impl RandomDataSource {
 
             state
                 .slot_cache
-                .insert(entry_id.clone(), (items, item_metas));
+                .insert(entry_id.clone(), (items, item_metas, edges));
         }
         state.slot_cache.get(entry_id).unwrap().clone()
     }
@@ -273,13 +522,33 @@ impl RandomDataSource {
                     short_name: kind.to_lowercase(),
                     long_name: format!("Node {node} {kind}"),
                     summary: Some(Box::new(EntryInfo::Summary { color })),
+                    counters: Vec::new(),
                     slots: proc_slots,
                 });
             }
+            let counters = vec![
+                EntryInfo::Counter {
+                    short_name: "mem".to_owned(),
+                    long_name: format!("Node {node} Memory Usage"),
+                    color: Color32::DARK_GREEN,
+                    unit: "B".to_owned(),
+                    min: 0.0,
+                    max: (16u64 << 30) as f64,
+                },
+                EntryInfo::Counter {
+                    short_name: "reqs".to_owned(),
+                    long_name: format!("Node {node} Outstanding Requests"),
+                    color: Color32::BROWN,
+                    unit: "".to_owned(),
+                    min: 0.0,
+                    max: 1000.0,
+                },
+            ];
             node_slots.push(EntryInfo::Panel {
                 short_name: format!("n{node}"),
                 long_name: format!("Node {node}"),
                 summary: None,
+                counters,
                 slots: kind_slots,
             });
         }
@@ -287,6 +556,7 @@ impl RandomDataSource {
             short_name: "root".to_owned(),
             long_name: "root".to_owned(),
             summary: None,
+            counters: Vec::new(),
             slots: node_slots,
         }
     }
@@ -298,11 +568,16 @@ impl DataSource for RandomDataSource {
             source_locator: vec!["Random Data Source".to_string()],
         }
     }
-    fn fetch_info(&self) -> DataSourceInfo {
-        self.info.clone()
+    fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+        Ok(self.info.clone())
     }
 
-    fn fetch_summary_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SummaryTile {
+    fn fetch_summary_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SummaryTile> {
         let utilization = self.generate_summary(entry_id);
 
         let mut tile_utilization = Vec::new();
@@ -339,17 +614,36 @@ impl DataSource for RandomDataSource {
 
             last_point = Some(point);
         }
-        SummaryTile {
+        Ok(SummaryTile {
             entry_id: entry_id.clone(),
             tile_id,
             data: SummaryTileData {
                 utilization: tile_utilization,
             },
-        }
+        })
+    }
+
+    fn fetch_counter_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<CounterTile> {
+        let points = self.generate_counter(entry_id);
+        Ok(CounterTile {
+            entry_id: entry_id.clone(),
+            tile_id,
+            data: CounterTileData::slice(&points, tile_id.0),
+        })
     }
 
-    fn fetch_slot_tile(&self, entry_id: &EntryID, tile_id: TileID, _full: bool) -> SlotTile {
-        let items = &self.generate_slot(entry_id).0;
+    fn fetch_slot_tile(
+        &self,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        _full: bool,
+    ) -> DataSourceResult<SlotTile> {
+        let (items, _, edges) = &self.generate_slot(entry_id);
 
         let mut slot_items = Vec::new();
         for row in items {
@@ -366,11 +660,20 @@ impl DataSource for RandomDataSource {
             slot_items.push(slot_row);
         }
 
-        SlotTile {
+        let slot_edges = edges
+            .iter()
+            .filter(|(interval, _)| tile_id.0.overlaps(*interval))
+            .map(|(_, edge)| *edge)
+            .collect();
+
+        Ok(SlotTile {
             entry_id: entry_id.clone(),
             tile_id,
-            data: SlotTileData { items: slot_items },
-        }
+            data: SlotTileData {
+                items: slot_items,
+                edges: slot_edges,
+            },
+        })
     }
 
     fn fetch_slot_meta_tile(
@@ -378,13 +681,13 @@ impl DataSource for RandomDataSource {
         entry_id: &EntryID,
         tile_id: TileID,
         _full: bool,
-    ) -> SlotMetaTile {
-        let (items, item_metas) = self.generate_slot(entry_id);
+    ) -> DataSourceResult<SlotMetaTile> {
+        let (items, item_metas, _) = self.generate_slot(entry_id);
 
         let mut slot_items = Vec::new();
-        for (row, row_meta) in items.iter().zip(item_metas.into_iter()) {
+        for (row, row_meta) in items.iter().zip(item_metas) {
             let mut slot_row = Vec::new();
-            for (item, item_meta) in row.iter().zip(row_meta.into_iter()) {
+            for (item, item_meta) in row.iter().zip(row_meta) {
                 // When the item straddles a tile boundary, it has to be
                 // sliced to fit
                 if tile_id.0.overlaps(item.interval) {
@@ -394,10 +697,10 @@ impl DataSource for RandomDataSource {
             slot_items.push(slot_row);
         }
 
-        SlotMetaTile {
+        Ok(SlotMetaTile {
             entry_id: entry_id.clone(),
             tile_id,
             data: SlotMetaTileData { items: slot_items },
-        }
+        })
     }
 }
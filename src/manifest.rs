@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::data::{DataSourceError, DataSourceResult};
+
+// The manifest lists every blob of an archive that has been completely
+// written, one per line:
+//
+//     <key> <length> <crc32> [<offset>]
+//
+// where the key is the blob's path in a directory archive (e.g., "info" or
+// "slot_tile/<entry>/<tile>"), the CRC-32 is in hex, and the offset is only
+// present for single-file archives. Lines are appended as blobs are written,
+// so only lines ending in a newline are complete. Readers use the manifest to
+// catch blobs that were truncated or damaged (e.g., on upload), and the
+// writer uses it to resume an interrupted write.
+
+// Directory archives store the manifest alongside the tiles, under this key
+pub const MANIFEST_KEY: &str = "manifest";
+
+// Single-file archives have theirs next to them (e.g., "prof.lpv.manifest")
+pub fn container_manifest_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(MANIFEST_KEY);
+    path.with_file_name(name)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub length: u64,
+    pub checksum: u32,
+    pub offset: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub blobs: BTreeMap<String, ManifestEntry>,
+}
+
+fn parse_error(line: &str) -> DataSourceError {
+    DataSourceError::Decode(format!("malformed manifest line {:?}", line))
+}
+
+impl ManifestEntry {
+    pub fn new(blob: &[u8], offset: Option<u64>) -> Self {
+        Self {
+            length: blob.len() as u64,
+            checksum: crc32fast::hash(blob),
+            offset,
+        }
+    }
+
+    pub fn to_line(&self, key: &str) -> String {
+        match self.offset {
+            Some(offset) => format!("{} {} {:08x} {}\n", key, self.length, self.checksum, offset),
+            None => format!("{} {} {:08x}\n", key, self.length, self.checksum),
+        }
+    }
+
+    pub fn check(&self, key: &str, blob: &[u8]) -> DataSourceResult<()> {
+        if blob.len() as u64 != self.length {
+            return Err(DataSourceError::Decode(format!(
+                "{} is corrupt: expected {} bytes but found {}",
+                key,
+                self.length,
+                blob.len()
+            )));
+        }
+        let checksum = crc32fast::hash(blob);
+        if checksum != self.checksum {
+            return Err(DataSourceError::Decode(format!(
+                "{} is corrupt: expected checksum {:08x} but found {:08x}",
+                key, self.checksum, checksum
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Manifest {
+    // Ignores a partial line at the end
+    pub fn parse(contents: &str) -> DataSourceResult<Self> {
+        let mut blobs = BTreeMap::new();
+        for line in contents.split_inclusive('\n') {
+            let Some(line) = line.strip_suffix('\n') else {
+                break;
+            };
+            let fields: Vec<_> = line.split(' ').collect();
+            let (key, length, checksum, offset) = match fields[..] {
+                [key, length, checksum] => (key, length, checksum, None),
+                [key, length, checksum, offset] => (key, length, checksum, Some(offset)),
+                _ => return Err(parse_error(line)),
+            };
+            let entry = ManifestEntry {
+                length: length.parse().map_err(|_| parse_error(line))?,
+                checksum: u32::from_str_radix(checksum, 16).map_err(|_| parse_error(line))?,
+                offset: offset
+                    .map(|offset| offset.parse().map_err(|_| parse_error(line)))
+                    .transpose()?,
+            };
+            blobs.insert(key.to_owned(), entry);
+        }
+        Ok(Self { blobs })
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.blobs.contains_key(key)
+    }
+
+    // Blobs that aren't listed pass, since archives written before the
+    // manifest was introduced don't have one
+    pub fn check(&self, key: &str, blob: &[u8]) -> DataSourceResult<()> {
+        match self.blobs.get(key) {
+            Some(entry) => entry.check(key, blob),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let info = ManifestEntry::new(b"info", None);
+        let tile = ManifestEntry::new(b"tile", Some(24));
+        let contents = format!(
+            "{}{}slot_tile/0 12",
+            info.to_line("info"),
+            tile.to_line("summary_tile/0/0_10")
+        );
+
+        let manifest = Manifest::parse(&contents).unwrap();
+        assert_eq!(manifest.blobs.len(), 2);
+        assert_eq!(manifest.blobs["info"], info);
+        assert_eq!(manifest.blobs["summary_tile/0/0_10"], tile);
+        assert!(!manifest.contains("slot_tile/0"));
+
+        assert!(matches!(
+            Manifest::parse("info 12\n"),
+            Err(DataSourceError::Decode(_))
+        ));
+        assert!(matches!(
+            Manifest::parse("info 12 xyz\n"),
+            Err(DataSourceError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_check() {
+        let mut manifest = Manifest::default();
+        manifest
+            .blobs
+            .insert("info".to_owned(), ManifestEntry::new(b"info", None));
+
+        assert!(manifest.check("info", b"info").is_ok());
+        assert!(manifest.check("other", b"anything").is_ok());
+        assert_eq!(
+            manifest.check("info", b"inf"),
+            Err(DataSourceError::Decode(
+                "info is corrupt: expected 4 bytes but found 3".to_owned()
+            ))
+        );
+        assert!(matches!(
+            manifest.check("info", b"infx"),
+            Err(DataSourceError::Decode(_))
+        ));
+    }
+}
@@ -1,15 +1,17 @@
 use std::collections::VecDeque;
 
 use crate::data::{
-    DataSourceDescription, DataSourceInfo, EntryID, EntryIndex, EntryInfo, Field, ItemLink,
-    ItemUID, SlotMetaTile, SlotTile, SummaryTile, TileID,
+    CounterTile, DataSourceDescription, DataSourceInfo, DataSourceResult, EntryID, EntryIndex,
+    EntryInfo, Field, ItemLink, ItemUID, SearchQuery, SearchResults, SlotMetaTile, SlotTile,
+    SummaryTile, TileID, TileRequest,
 };
-use crate::deferred_data::DeferredDataSource;
+use crate::deferred_data::{DeferredDataSource, SearchResponse, TileResult};
 use crate::timestamp::Interval;
 
 pub struct MergeDeferredDataSource {
     data_sources: Vec<Box<dyn DeferredDataSource>>,
-    infos: Vec<VecDeque<DataSourceInfo>>,
+    infos: Vec<VecDeque<DataSourceResult<DataSourceInfo>>>,
+    search_results: Vec<Vec<SearchResponse>>,
     mapping: Vec<u64>,
 }
 
@@ -17,9 +19,11 @@ impl MergeDeferredDataSource {
     pub fn new(data_sources: Vec<Box<dyn DeferredDataSource>>) -> Self {
         assert!(!data_sources.is_empty());
         let infos = vec![VecDeque::new(); data_sources.len()];
+        let search_results = vec![Vec::new(); data_sources.len()];
         Self {
             data_sources,
             infos,
+            search_results,
             mapping: Vec::new(),
         }
     }
@@ -29,6 +33,7 @@ impl MergeDeferredDataSource {
             short_name,
             long_name,
             summary: first_summary,
+            counters: first_counters,
             mut slots,
         } = first
         else {
@@ -37,6 +42,7 @@ impl MergeDeferredDataSource {
 
         let EntryInfo::Panel {
             summary: second_summary,
+            counters: second_counters,
             slots: second_slots,
             ..
         } = second
@@ -46,6 +52,8 @@ impl MergeDeferredDataSource {
 
         assert!(first_summary.is_none());
         assert!(second_summary.is_none());
+        assert!(first_counters.is_empty());
+        assert!(second_counters.is_empty());
 
         slots.extend(second_slots);
 
@@ -53,6 +61,7 @@ impl MergeDeferredDataSource {
             short_name,
             long_name,
             summary: None,
+            counters: Vec::new(),
             slots,
         }
     }
@@ -91,7 +100,10 @@ impl MergeDeferredDataSource {
         let warning_message = first_info.warning_message.clone();
 
         for info in &source_infos {
-            assert_eq!(tile_set, info.tile_set);
+            assert_eq!(
+                tile_set, info.tile_set,
+                "sources must have the same tiles (wrap static sources in RetilingDeferredDataSource)"
+            );
             assert_eq!(field_schema, info.field_schema);
             assert_eq!(warning_message, info.warning_message);
         }
@@ -131,6 +143,19 @@ impl MergeDeferredDataSource {
         (idx, dst_entry.shift_level0(-(self.mapping[idx] as i64)))
     }
 
+    // Keeps requests batched by giving each source its share
+    fn split_requests(&self, requests: &[TileRequest]) -> Vec<Vec<TileRequest>> {
+        let mut source_requests = vec![Vec::new(); self.data_sources.len()];
+        for req in requests {
+            let (idx, entry_id) = self.map_dst_to_src_entry(&req.entry_id);
+            source_requests[idx].push(TileRequest {
+                entry_id,
+                ..req.clone()
+            });
+        }
+        source_requests
+    }
+
     fn map_src_to_dst_item_uid(&self, idx: usize, item_uid: ItemUID) -> ItemUID {
         ItemUID(item_uid.0 * (self.mapping.len() as u64) + (idx as u64))
     }
@@ -143,12 +168,24 @@ impl MergeDeferredDataSource {
         }
     }
 
+    fn map_src_to_dst_counter(&self, idx: usize, tile: CounterTile) -> CounterTile {
+        CounterTile {
+            entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
+            tile_id: tile.tile_id,
+            data: tile.data,
+        }
+    }
+
     fn map_src_to_dst_slot(&self, idx: usize, mut tile: SlotTile) -> SlotTile {
         for items in &mut tile.data.items {
             for item in items {
                 item.item_uid = self.map_src_to_dst_item_uid(idx, item.item_uid);
             }
         }
+        for edge in &mut tile.data.edges {
+            edge.src = self.map_src_to_dst_item_uid(idx, edge.src);
+            edge.dst = self.map_src_to_dst_item_uid(idx, edge.dst);
+        }
 
         SlotTile {
             entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
@@ -192,6 +229,30 @@ impl MergeDeferredDataSource {
             data: tile.data,
         }
     }
+
+    fn merge_search_results(
+        &self,
+        query: &SearchQuery,
+        source_results: Vec<SearchResults>,
+    ) -> SearchResults {
+        let mut items = Vec::new();
+        let mut truncated = false;
+        for (idx, source_result) in source_results.into_iter().enumerate() {
+            truncated |= source_result.truncated;
+            items.extend(source_result.items.into_iter().map(|item| ItemLink {
+                item_uid: self.map_src_to_dst_item_uid(idx, item.item_uid),
+                entry_id: self.map_src_to_dst_entry(idx, &item.entry_id),
+                ..item
+            }));
+        }
+
+        // Each source respects the limit, but together they may not
+        if items.len() as u64 > query.max_results {
+            items.truncate(query.max_results as usize);
+            truncated = true;
+        }
+        SearchResults { items, truncated }
+    }
 }
 
 impl DeferredDataSource for MergeDeferredDataSource {
@@ -211,22 +272,26 @@ impl DeferredDataSource for MergeDeferredDataSource {
         }
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
         for (data_source, infos) in self.data_sources.iter_mut().zip(self.infos.iter_mut()) {
             infos.extend(data_source.get_infos());
         }
 
-        let max_available = self.infos.iter().map(|infos| infos.len()).max().unwrap();
+        // We can only merge once every source has responded
+        let min_available = self.infos.iter().map(|infos| infos.len()).min().unwrap();
 
         let mut result = Vec::new();
-        for _ in 0..max_available {
-            let source_infos: Vec<_> = self
+        for _ in 0..min_available {
+            let source_infos: DataSourceResult<Vec<_>> = self
                 .infos
                 .iter_mut()
                 .map(|infos| infos.pop_front().unwrap())
                 .collect();
-            self.mapping = Self::compute_mapping(&source_infos);
-            result.push(Self::merge_infos(source_infos));
+            // If any source failed, the merged info fails with it
+            result.push(source_infos.map(|source_infos| {
+                self.mapping = Self::compute_mapping(&source_infos);
+                Self::merge_infos(source_infos)
+            }));
         }
         result
     }
@@ -237,7 +302,7 @@ impl DeferredDataSource for MergeDeferredDataSource {
         self.data_sources[idx].fetch_summary_tile(&src_entry, tile_id, full);
     }
 
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile> {
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
         let mut tiles = Vec::new();
         for (idx, data_source) in self.data_sources.iter_mut().enumerate() {
             tiles.extend(
@@ -251,7 +316,41 @@ impl DeferredDataSource for MergeDeferredDataSource {
         // Hack: doing this in two stages to avoid mutability conflict
         tiles
             .into_iter()
-            .map(|(idx, tile)| self.map_src_to_dst_summary(idx, tile))
+            .map(|(idx, tile)| TileResult {
+                entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
+                tile_id: tile.tile_id,
+                full: tile.full,
+                result: tile.result.map(|t| self.map_src_to_dst_summary(idx, t)),
+            })
+            .collect()
+    }
+
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let (idx, src_entry) = self.map_dst_to_src_entry(entry_id);
+
+        self.data_sources[idx].fetch_counter_tile(&src_entry, tile_id, full);
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        let mut tiles = Vec::new();
+        for (idx, data_source) in self.data_sources.iter_mut().enumerate() {
+            tiles.extend(
+                data_source
+                    .get_counter_tiles()
+                    .into_iter()
+                    .map(|tile| (idx, tile)),
+            );
+        }
+
+        // Hack: doing this in two stages to avoid mutability conflict
+        tiles
+            .into_iter()
+            .map(|(idx, tile)| TileResult {
+                entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
+                tile_id: tile.tile_id,
+                full: tile.full,
+                result: tile.result.map(|t| self.map_src_to_dst_counter(idx, t)),
+            })
             .collect()
     }
 
@@ -261,7 +360,7 @@ impl DeferredDataSource for MergeDeferredDataSource {
         self.data_sources[idx].fetch_slot_tile(&src_entry, tile_id, full);
     }
 
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile> {
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
         let mut tiles = Vec::new();
         for (idx, data_source) in self.data_sources.iter_mut().enumerate() {
             tiles.extend(
@@ -275,7 +374,12 @@ impl DeferredDataSource for MergeDeferredDataSource {
         // Hack: doing this in two stages to avoid mutability conflict
         tiles
             .into_iter()
-            .map(|(idx, tile)| self.map_src_to_dst_slot(idx, tile))
+            .map(|(idx, tile)| TileResult {
+                entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
+                tile_id: tile.tile_id,
+                full: tile.full,
+                result: tile.result.map(|t| self.map_src_to_dst_slot(idx, t)),
+            })
             .collect()
     }
 
@@ -285,7 +389,7 @@ impl DeferredDataSource for MergeDeferredDataSource {
         self.data_sources[idx].fetch_slot_meta_tile(&src_entry, tile_id, full);
     }
 
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile> {
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
         let mut tiles = Vec::new();
         for (idx, data_source) in self.data_sources.iter_mut().enumerate() {
             tiles.extend(
@@ -299,9 +403,76 @@ impl DeferredDataSource for MergeDeferredDataSource {
         // Hack: doing this in two stages to avoid mutability conflict
         tiles
             .into_iter()
-            .map(|(idx, tile)| self.map_src_to_dst_slot_meta(idx, tile))
+            .map(|(idx, tile)| TileResult {
+                entry_id: self.map_src_to_dst_entry(idx, &tile.entry_id),
+                tile_id: tile.tile_id,
+                full: tile.full,
+                result: tile.result.map(|t| self.map_src_to_dst_slot_meta(idx, t)),
+            })
             .collect()
     }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        let source_requests = self.split_requests(requests);
+        for (data_source, requests) in self.data_sources.iter_mut().zip(source_requests) {
+            if !requests.is_empty() {
+                data_source.fetch_tiles(&requests);
+            }
+        }
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        let source_requests = self.split_requests(requests);
+        for (data_source, requests) in self.data_sources.iter_mut().zip(source_requests) {
+            if !requests.is_empty() {
+                data_source.cancel_tiles(&requests);
+            }
+        }
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        for data_source in &mut self.data_sources {
+            data_source.fetch_search(query);
+        }
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        for (data_source, search_results) in self
+            .data_sources
+            .iter_mut()
+            .zip(self.search_results.iter_mut())
+        {
+            search_results.extend(data_source.get_search_results());
+        }
+
+        // Sources may respond in any order, so match up responses by query
+        // and merge once every source has responded
+        let mut result = Vec::new();
+        while let Some(query) = self.search_results[0]
+            .iter()
+            .map(|response| &response.query)
+            .find(|query| {
+                self.search_results
+                    .iter()
+                    .all(|responses| responses.iter().any(|r| r.query == **query))
+            })
+            .cloned()
+        {
+            let source_results: DataSourceResult<Vec<_>> = self
+                .search_results
+                .iter_mut()
+                .map(|responses| {
+                    let idx = responses.iter().position(|r| r.query == query).unwrap();
+                    responses.remove(idx).result
+                })
+                .collect();
+            // If any source failed, the merged search fails with it
+            let merged = source_results
+                .map(|source_results| self.merge_search_results(&query, source_results));
+            result.push(SearchResponse::new(&query, merged));
+        }
+        result
+    }
 }
 
 #[cfg(test)]
@@ -317,6 +488,7 @@ mod tests {
             short_name: "F".to_string(),
             long_name: "First".to_string(),
             summary: None,
+            counters: Vec::new(),
             slots: vec![EntryInfo::Slot {
                 short_name: "S1".to_string(),
                 long_name: "Slot 1".to_string(),
@@ -327,6 +499,7 @@ mod tests {
             short_name: "S".to_string(),
             long_name: "Second".to_string(),
             summary: None,
+            counters: Vec::new(),
             slots: vec![EntryInfo::Slot {
                 short_name: "S2".to_string(),
                 long_name: "Slot 2".to_string(),
@@ -340,6 +513,7 @@ mod tests {
             short_name,
             long_name,
             summary,
+            counters,
             slots,
         } = merge
         else {
@@ -349,6 +523,7 @@ mod tests {
         assert_eq!(short_name, "F");
         assert_eq!(long_name, "First");
         assert!(summary.is_none());
+        assert!(counters.is_empty());
         assert_eq!(slots.len(), 2);
 
         let EntryInfo::Slot {
@@ -377,6 +552,7 @@ mod tests {
                 short_name: "F".to_string(),
                 long_name: "First".to_string(),
                 summary: None,
+                counters: Vec::new(),
                 slots: vec![
                     EntryInfo::Slot {
                         short_name: "S1".to_string(),
@@ -400,6 +576,7 @@ mod tests {
                 short_name: "S".to_string(),
                 long_name: "Second".to_string(),
                 summary: None,
+                counters: Vec::new(),
                 slots: vec![EntryInfo::Slot {
                     short_name: "S3".to_string(),
                     long_name: "Slot 3".to_string(),
@@ -426,6 +603,7 @@ mod tests {
             short_name,
             long_name,
             summary,
+            counters,
             slots,
         } = merge.entry_info
         else {
@@ -435,6 +613,7 @@ mod tests {
         assert_eq!(short_name, "F");
         assert_eq!(long_name, "First");
         assert!(summary.is_none());
+        assert!(counters.is_empty());
         assert_eq!(slots.len(), 3);
 
         let EntryInfo::Slot {
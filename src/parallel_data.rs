@@ -1,17 +1,22 @@
 use std::sync::{Arc, Mutex};
 
 use crate::data::{
-    DataSource, DataSourceDescription, DataSourceInfo, EntryID, SlotMetaTile, SlotTile,
-    SummaryTile, TileID,
+    CounterTile, DataSource, DataSourceDescription, DataSourceError, DataSourceInfo,
+    DataSourceResult, EntryID, SearchQuery, SlotMetaTile, SlotTile, SummaryTile, TileID, TileKind,
+    TileRequest,
 };
-use crate::deferred_data::DeferredDataSource;
+use crate::deferred_data::{DeferredDataSource, PendingJobs, SearchResponse, TileResult};
 
 pub struct ParallelDeferredDataSource<T: DataSource + Send + Sync + 'static> {
     data_source: Arc<T>,
-    infos: Arc<Mutex<Vec<DataSourceInfo>>>,
-    summary_tiles: Arc<Mutex<Vec<SummaryTile>>>,
-    slot_tiles: Arc<Mutex<Vec<SlotTile>>>,
-    slot_meta_tiles: Arc<Mutex<Vec<SlotMetaTile>>>,
+    infos: Arc<Mutex<Vec<DataSourceResult<DataSourceInfo>>>>,
+    summary_tiles: Arc<Mutex<Vec<TileResult<SummaryTile>>>>,
+    counter_tiles: Arc<Mutex<Vec<TileResult<CounterTile>>>>,
+    slot_tiles: Arc<Mutex<Vec<TileResult<SlotTile>>>>,
+    slot_meta_tiles: Arc<Mutex<Vec<TileResult<SlotMetaTile>>>>,
+    search_results: Arc<Mutex<Vec<SearchResponse>>>,
+    // Tile jobs that are still waiting for a thread
+    jobs: Arc<Mutex<PendingJobs>>,
 }
 
 impl<T: DataSource + Send + Sync + 'static> ParallelDeferredDataSource<T> {
@@ -20,10 +25,47 @@ impl<T: DataSource + Send + Sync + 'static> ParallelDeferredDataSource<T> {
             data_source: Arc::new(data_source),
             infos: Arc::new(Mutex::new(Vec::new())),
             summary_tiles: Arc::new(Mutex::new(Vec::new())),
+            counter_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_tiles: Arc::new(Mutex::new(Vec::new())),
             slot_meta_tiles: Arc::new(Mutex::new(Vec::new())),
+            search_results: Arc::new(Mutex::new(Vec::new())),
+            jobs: Arc::new(Mutex::new(PendingJobs::default())),
         }
     }
+
+    fn spawn_tile<R: Send + 'static>(
+        &mut self,
+        kind: TileKind,
+        entry_id: &EntryID,
+        tile_id: TileID,
+        full: bool,
+        tiles: Arc<Mutex<Vec<TileResult<R>>>>,
+        fetch: fn(&T, &EntryID, TileID, bool) -> DataSourceResult<R>,
+    ) {
+        let req = TileRequest {
+            kind,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        };
+        let (id, token) = self.jobs.lock().unwrap().start(&[req]);
+        let entry_id = entry_id.clone();
+        let data_source = self.data_source.clone();
+        let jobs = self.jobs.clone();
+        rayon::spawn(move || {
+            // Once the job is running, it's too late to cancel it
+            jobs.lock().unwrap().finish(id);
+            let result = if token.is_cancelled() {
+                Err(DataSourceError::Cancelled)
+            } else {
+                fetch(&data_source, &entry_id, tile_id, full)
+            };
+            tiles
+                .lock()
+                .unwrap()
+                .push(TileResult::new(&entry_id, tile_id, full, result));
+        });
+    }
 }
 
 impl<T: DataSource + Send + Sync + 'static> DeferredDataSource for ParallelDeferredDataSource<T> {
@@ -40,49 +82,92 @@ impl<T: DataSource + Send + Sync + 'static> DeferredDataSource for ParallelDefer
         });
     }
 
-    fn get_infos(&mut self) -> Vec<DataSourceInfo> {
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
         std::mem::take(&mut self.infos.lock().unwrap())
     }
 
     fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let entry_id = entry_id.clone();
-        let data_source = self.data_source.clone();
-        let summary_tiles = self.summary_tiles.clone();
-        rayon::spawn(move || {
-            let result = data_source.fetch_summary_tile(&entry_id, tile_id, full);
-            summary_tiles.lock().unwrap().push(result);
-        });
+        let tiles = self.summary_tiles.clone();
+        self.spawn_tile(
+            TileKind::Summary,
+            entry_id,
+            tile_id,
+            full,
+            tiles,
+            T::fetch_summary_tile,
+        );
     }
 
-    fn get_summary_tiles(&mut self) -> Vec<SummaryTile> {
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
         std::mem::take(&mut self.summary_tiles.lock().unwrap())
     }
 
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        let tiles = self.counter_tiles.clone();
+        self.spawn_tile(
+            TileKind::Counter,
+            entry_id,
+            tile_id,
+            full,
+            tiles,
+            T::fetch_counter_tile,
+        );
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        std::mem::take(&mut self.counter_tiles.lock().unwrap())
+    }
+
     fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let entry_id = entry_id.clone();
-        let data_source = self.data_source.clone();
-        let slot_tiles = self.slot_tiles.clone();
-        rayon::spawn(move || {
-            let result = data_source.fetch_slot_tile(&entry_id, tile_id, full);
-            slot_tiles.lock().unwrap().push(result);
-        });
+        let tiles = self.slot_tiles.clone();
+        self.spawn_tile(
+            TileKind::Slot,
+            entry_id,
+            tile_id,
+            full,
+            tiles,
+            T::fetch_slot_tile,
+        );
     }
 
-    fn get_slot_tiles(&mut self) -> Vec<SlotTile> {
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
         std::mem::take(&mut self.slot_tiles.lock().unwrap())
     }
 
     fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
-        let entry_id = entry_id.clone();
+        let tiles = self.slot_meta_tiles.clone();
+        self.spawn_tile(
+            TileKind::SlotMeta,
+            entry_id,
+            tile_id,
+            full,
+            tiles,
+            T::fetch_slot_meta_tile,
+        );
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+        std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        self.jobs.lock().unwrap().cancel(requests);
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        let query = query.clone();
         let data_source = self.data_source.clone();
-        let slot_meta_tiles = self.slot_meta_tiles.clone();
+        let search_results = self.search_results.clone();
         rayon::spawn(move || {
-            let result = data_source.fetch_slot_meta_tile(&entry_id, tile_id, full);
-            slot_meta_tiles.lock().unwrap().push(result);
+            let result = data_source.search(&query);
+            search_results
+                .lock()
+                .unwrap()
+                .push(SearchResponse::new(&query, result));
         });
     }
 
-    fn get_slot_meta_tiles(&mut self) -> Vec<SlotMetaTile> {
-        std::mem::take(&mut self.slot_meta_tiles.lock().unwrap())
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        std::mem::take(&mut self.search_results.lock().unwrap())
     }
 }
@@ -0,0 +1,930 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::data::{
+    CounterPoint, CounterTile, CounterTileData, DataSourceDescription, DataSourceError,
+    DataSourceInfo, DataSourceResult, EntryID, Item, ItemUID, SearchQuery, SlotMetaTile,
+    SlotMetaTileData, SlotTile, SlotTileData, SummaryTile, SummaryTileData, Tile, TileID, TileKind,
+    TileRequest, TileSet, UtilPoint,
+};
+use crate::deferred_data::{sort_tile_results, DeferredDataSource, SearchResponse, TileResult};
+use crate::timestamp::Interval;
+
+// Presents a statically tiled data source (e.g., an archive) as a dynamic
+// one, which accepts requests for any tile. Each requested tile is built by
+// fetching the source tiles that overlap it, then cutting them down to size
+// and stitching them together. Dynamic sources are passed through as is.
+//
+// This is what allows an archive to be re-archived with a different tile
+// hierarchy, and archives whose tile sets don't line up to be merged.
+pub struct RetilingDeferredDataSource<T: DeferredDataSource> {
+    data_source: T,
+    // Tiles of the underlying source, once its info has arrived (or the
+    // reason it didn't)
+    tile_set: Option<DataSourceResult<TileSet>>,
+    // Source tiles that have been requested, along with the number of
+    // requested tiles still waiting on each
+    sources: BTreeMap<TileRequest, SourceTile>,
+    // Requested tiles, in the order they were requested
+    targets: Vec<Target>,
+    summary_tiles: Vec<TileResult<SummaryTile>>,
+    counter_tiles: Vec<TileResult<CounterTile>>,
+    slot_tiles: Vec<TileResult<SlotTile>>,
+    slot_meta_tiles: Vec<TileResult<SlotMetaTile>>,
+}
+
+struct SourceTile {
+    tile: Option<DataSourceResult<Tile>>,
+    waiters: u64,
+}
+
+struct Target {
+    request: TileRequest,
+    sources: Vec<TileRequest>,
+}
+
+// An item (or a slice of one) in a retiled slot tile: where it came from in
+// the source tiles, and its interval cut down to the new tile
+#[derive(Debug, Clone)]
+struct Pick {
+    item_uid: ItemUID,
+    source: usize,
+    row: usize,
+    index: usize,
+    interval: Interval,
+}
+
+// Full tiles must come from the finest level, since that's the only one
+// guaranteed to hold every item. Otherwise, use the coarsest level whose tiles
+// are no larger than the requested one: coarser tiles have been simplified
+// more than the requester expects. (Tiles in a level may vary in size, so
+// only the ones overlapping the requested tile count.)
+fn source_tile_ids(tile_set: &TileSet, tile_id: TileID, full: bool) -> Vec<TileID> {
+    let overlapping = |level: &Vec<TileID>| -> Vec<TileID> {
+        level
+            .iter()
+            .filter(|tile| tile.0.overlaps(tile_id.0))
+            .copied()
+            .collect()
+    };
+    let Some(finest) = tile_set.tiles.last() else {
+        return Vec::new();
+    };
+    if full {
+        return overlapping(finest);
+    }
+    tile_set
+        .tiles
+        .iter()
+        .map(overlapping)
+        .find(|tiles| {
+            tiles
+                .iter()
+                .all(|tile| tile.0.duration_ns() <= tile_id.0.duration_ns())
+        })
+        .unwrap_or_else(|| overlapping(finest))
+}
+
+fn retile_summary<'a>(
+    interval: Interval,
+    sources: impl Iterator<Item = &'a SummaryTileData>,
+) -> SummaryTileData {
+    let mut utilization: Vec<UtilPoint> = Vec::new();
+    for point in sources.flat_map(|data| data.utilization.iter()) {
+        if point.time < interval.start {
+            // Carry the utilization before the tile in at its start
+            utilization.clear();
+            utilization.push(UtilPoint {
+                time: interval.start,
+                ..*point
+            });
+        } else if point.time <= interval.stop {
+            // Neighboring source tiles may both have a point on their shared
+            // edge
+            if utilization
+                .last()
+                .is_some_and(|last| last.time == point.time)
+            {
+                utilization.pop();
+            }
+            utilization.push(*point);
+        }
+    }
+    SummaryTileData { utilization }
+}
+
+fn retile_counter<'a>(
+    interval: Interval,
+    sources: impl Iterator<Item = &'a CounterTileData>,
+) -> CounterTileData {
+    let mut points: Vec<CounterPoint> = Vec::new();
+    for data in sources {
+        for (i, point) in data.points.iter().enumerate() {
+            if point.time <= interval.start {
+                // Only the value in effect at the start of the tile matters
+                points.clear();
+                points.push(CounterPoint {
+                    time: interval.start,
+                    ..*point
+                });
+            } else if point.time < interval.stop {
+                let last = points.last();
+                if last.is_some_and(|last| last.time == point.time) {
+                    points.pop();
+                } else if i == 0 && last.is_some_and(|last| last.value == point.value) {
+                    // Source tiles restate the value at their start, which
+                    // isn't needed if it hasn't changed
+                    continue;
+                }
+                points.push(*point);
+            }
+        }
+    }
+    CounterTileData { points }
+}
+
+fn pick_items(interval: Interval, sources: &[&SlotTileData]) -> Vec<Vec<Pick>> {
+    let rows = sources.iter().map(|data| data.items.len()).max();
+    let mut picks = vec![Vec::<Pick>::new(); rows.unwrap_or(0)];
+    for (source, data) in sources.iter().enumerate() {
+        for (row, items) in data.items.iter().enumerate() {
+            for (index, item) in items.iter().enumerate() {
+                if !item.interval.overlaps(interval) {
+                    continue;
+                }
+                let clipped = item.interval.intersection(interval);
+
+                // Items that cross a tile boundary are sliced, so join the
+                // slices back up
+                if let Some(last) = picks[row].last_mut() {
+                    if last.item_uid == item.item_uid && last.interval.stop >= clipped.start {
+                        last.interval.stop = last.interval.stop.max(clipped.stop);
+                        continue;
+                    }
+                }
+                picks[row].push(Pick {
+                    item_uid: item.item_uid,
+                    source,
+                    row,
+                    index,
+                    interval: clipped,
+                });
+            }
+        }
+    }
+    picks
+}
+
+fn retile_slot(interval: Interval, sources: &[&SlotTileData]) -> SlotTileData {
+    let picks = pick_items(interval, sources);
+    let items: Vec<Vec<_>> = picks
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|pick| Item {
+                    item_uid: pick.item_uid,
+                    interval: pick.interval,
+                    color: sources[pick.source].items[pick.row][pick.index].color,
+                })
+                .collect()
+        })
+        .collect();
+
+    // Edges go with the tiles holding their destination
+    let item_uids: BTreeSet<_> = picks.iter().flatten().map(|pick| pick.item_uid).collect();
+    let edges: BTreeSet<_> = sources
+        .iter()
+        .flat_map(|data| data.edges.iter())
+        .filter(|edge| item_uids.contains(&edge.dst))
+        .copied()
+        .collect();
+
+    SlotTileData {
+        items,
+        edges: edges.into_iter().collect(),
+    }
+}
+
+// Meta tiles mirror the layout of slot tiles, so pick items the same way
+fn retile_slot_meta(
+    interval: Interval,
+    sources: &[&SlotTileData],
+    meta_sources: &[&SlotMetaTileData],
+) -> DataSourceResult<SlotMetaTileData> {
+    let mut items = Vec::new();
+    for row in pick_items(interval, sources) {
+        let metas: Option<Vec<_>> = row
+            .iter()
+            .map(|pick| {
+                meta_sources
+                    .get(pick.source)?
+                    .items
+                    .get(pick.row)?
+                    .get(pick.index)
+                    .cloned()
+            })
+            .collect();
+        let metas = metas.ok_or_else(|| {
+            DataSourceError::Decode("slot meta tile does not match slot tile".to_owned())
+        })?;
+        items.push(metas);
+    }
+    Ok(SlotMetaTileData { items })
+}
+
+impl<T: DeferredDataSource> RetilingDeferredDataSource<T> {
+    pub fn new(data_source: T) -> Self {
+        Self {
+            data_source,
+            tile_set: None,
+            sources: BTreeMap::new(),
+            targets: Vec::new(),
+            summary_tiles: Vec::new(),
+            counter_tiles: Vec::new(),
+            slot_tiles: Vec::new(),
+            slot_meta_tiles: Vec::new(),
+        }
+    }
+
+    fn is_dynamic(&self) -> bool {
+        matches!(&self.tile_set, Some(Ok(tile_set)) if tile_set.tiles.is_empty())
+    }
+
+    fn source_requests(tile_set: &TileSet, req: &TileRequest) -> Vec<TileRequest> {
+        let kinds = match req.kind {
+            TileKind::SlotMeta => vec![TileKind::Slot, TileKind::SlotMeta],
+            kind => vec![kind],
+        };
+        let tile_ids = source_tile_ids(tile_set, req.tile_id, req.full);
+        kinds
+            .into_iter()
+            .flat_map(|kind| {
+                tile_ids.iter().map(move |tile_id| TileRequest {
+                    kind,
+                    entry_id: req.entry_id.clone(),
+                    tile_id: *tile_id,
+                    full: req.full,
+                })
+            })
+            .collect()
+    }
+
+    fn receive<R>(&mut self, kind: TileKind, tiles: Vec<TileResult<R>>, wrap: fn(R) -> Tile) {
+        for tile in tiles {
+            let req = TileRequest {
+                kind,
+                entry_id: tile.entry_id,
+                tile_id: tile.tile_id,
+                full: tile.full,
+            };
+            if let Some(source) = self.sources.get_mut(&req) {
+                source.tile = Some(tile.result.map(wrap));
+            }
+        }
+    }
+
+    fn build(&self, target: &Target) -> DataSourceResult<Tile> {
+        let mut tiles = Vec::new();
+        for source in &target.sources {
+            match self.sources[source].tile.as_ref().unwrap() {
+                Ok(tile) => tiles.push(tile),
+                Err(e) => return Err(e.clone()),
+            }
+        }
+
+        let TileRequest {
+            kind,
+            entry_id,
+            tile_id,
+            ..
+        } = &target.request;
+        let (entry_id, tile_id, interval) = (entry_id.clone(), *tile_id, tile_id.0);
+        let slots: Vec<_> = tiles
+            .iter()
+            .filter_map(|tile| match tile {
+                Tile::Slot(tile) => Some(&tile.data),
+                _ => None,
+            })
+            .collect();
+        Ok(match kind {
+            TileKind::Summary => Tile::Summary(SummaryTile {
+                entry_id,
+                tile_id,
+                data: retile_summary(
+                    interval,
+                    tiles.iter().filter_map(|tile| match tile {
+                        Tile::Summary(tile) => Some(&tile.data),
+                        _ => None,
+                    }),
+                ),
+            }),
+            TileKind::Counter => Tile::Counter(CounterTile {
+                entry_id,
+                tile_id,
+                data: retile_counter(
+                    interval,
+                    tiles.iter().filter_map(|tile| match tile {
+                        Tile::Counter(tile) => Some(&tile.data),
+                        _ => None,
+                    }),
+                ),
+            }),
+            TileKind::Slot => Tile::Slot(SlotTile {
+                entry_id,
+                tile_id,
+                data: retile_slot(interval, &slots),
+            }),
+            TileKind::SlotMeta => {
+                let metas: Vec<_> = tiles
+                    .iter()
+                    .filter_map(|tile| match tile {
+                        Tile::SlotMeta(tile) => Some(&tile.data),
+                        _ => None,
+                    })
+                    .collect();
+                Tile::SlotMeta(SlotMetaTile {
+                    entry_id,
+                    tile_id,
+                    data: retile_slot_meta(interval, &slots, &metas)?,
+                })
+            }
+        })
+    }
+
+    // Builds every requested tile whose sources have all arrived
+    fn assemble(&mut self) {
+        let (ready, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.targets)
+            .into_iter()
+            .partition(|target| {
+                target
+                    .sources
+                    .iter()
+                    .all(|source| self.sources[source].tile.is_some())
+            });
+        self.targets = pending;
+
+        for target in ready {
+            let result = self.build(&target);
+            for source in &target.sources {
+                let entry = self.sources.get_mut(source).unwrap();
+                entry.waiters -= 1;
+                if entry.waiters == 0 {
+                    self.sources.remove(source);
+                }
+            }
+            sort_tile_results(
+                &[target.request],
+                [result],
+                &mut self.summary_tiles,
+                &mut self.counter_tiles,
+                &mut self.slot_tiles,
+                &mut self.slot_meta_tiles,
+            );
+        }
+    }
+
+    fn poll(&mut self) {
+        let tiles = self.data_source.get_summary_tiles();
+        self.receive(TileKind::Summary, tiles, Tile::Summary);
+        let tiles = self.data_source.get_counter_tiles();
+        self.receive(TileKind::Counter, tiles, Tile::Counter);
+        let tiles = self.data_source.get_slot_tiles();
+        self.receive(TileKind::Slot, tiles, Tile::Slot);
+        let tiles = self.data_source.get_slot_meta_tiles();
+        self.receive(TileKind::SlotMeta, tiles, Tile::SlotMeta);
+        self.assemble();
+    }
+
+    fn fail(&mut self, requests: &[TileRequest], error: DataSourceError) {
+        sort_tile_results(
+            requests,
+            vec![Err(error); requests.len()],
+            &mut self.summary_tiles,
+            &mut self.counter_tiles,
+            &mut self.slot_tiles,
+            &mut self.slot_meta_tiles,
+        );
+    }
+
+    fn fetch(&mut self, kind: TileKind, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch_tiles(&[TileRequest {
+            kind,
+            entry_id: entry_id.clone(),
+            tile_id,
+            full,
+        }]);
+    }
+}
+
+impl<T: DeferredDataSource> DeferredDataSource for RetilingDeferredDataSource<T> {
+    fn fetch_description(&self) -> DataSourceDescription {
+        self.data_source.fetch_description()
+    }
+
+    fn fetch_info(&mut self) {
+        self.data_source.fetch_info()
+    }
+
+    fn get_infos(&mut self) -> Vec<DataSourceResult<DataSourceInfo>> {
+        let mut infos = self.data_source.get_infos();
+        for info in &mut infos {
+            self.tile_set = Some(match info {
+                // We accept requests for any tile, so look like a dynamic source
+                Ok(info) => Ok(std::mem::take(&mut info.tile_set)),
+                Err(e) => Err(e.clone()),
+            });
+        }
+        infos
+    }
+
+    fn fetch_summary_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Summary, entry_id, tile_id, full)
+    }
+
+    fn get_summary_tiles(&mut self) -> Vec<TileResult<SummaryTile>> {
+        if self.is_dynamic() {
+            return self.data_source.get_summary_tiles();
+        }
+        self.poll();
+        std::mem::take(&mut self.summary_tiles)
+    }
+
+    fn fetch_counter_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Counter, entry_id, tile_id, full)
+    }
+
+    fn get_counter_tiles(&mut self) -> Vec<TileResult<CounterTile>> {
+        if self.is_dynamic() {
+            return self.data_source.get_counter_tiles();
+        }
+        self.poll();
+        std::mem::take(&mut self.counter_tiles)
+    }
+
+    fn fetch_slot_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::Slot, entry_id, tile_id, full)
+    }
+
+    fn get_slot_tiles(&mut self) -> Vec<TileResult<SlotTile>> {
+        if self.is_dynamic() {
+            return self.data_source.get_slot_tiles();
+        }
+        self.poll();
+        std::mem::take(&mut self.slot_tiles)
+    }
+
+    fn fetch_slot_meta_tile(&mut self, entry_id: &EntryID, tile_id: TileID, full: bool) {
+        self.fetch(TileKind::SlotMeta, entry_id, tile_id, full)
+    }
+
+    fn get_slot_meta_tiles(&mut self) -> Vec<TileResult<SlotMetaTile>> {
+        if self.is_dynamic() {
+            return self.data_source.get_slot_meta_tiles();
+        }
+        self.poll();
+        std::mem::take(&mut self.slot_meta_tiles)
+    }
+
+    fn fetch_tiles(&mut self, requests: &[TileRequest]) {
+        if self.is_dynamic() {
+            return self.data_source.fetch_tiles(requests);
+        }
+        // Without the source's tiles, there's no telling which to fetch
+        let tile_set = match &self.tile_set {
+            Some(Ok(tile_set)) => tile_set,
+            Some(Err(e)) => {
+                let error = e.clone();
+                return self.fail(requests, error);
+            }
+            None => {
+                let error = DataSourceError::NotFound("info has not arrived yet".to_owned());
+                return self.fail(requests, error);
+            }
+        };
+
+        let mut fetch = Vec::new();
+        for req in requests {
+            let sources = Self::source_requests(tile_set, req);
+            for source in &sources {
+                let entry = self.sources.entry(source.clone()).or_insert_with(|| {
+                    fetch.push(source.clone());
+                    SourceTile {
+                        tile: None,
+                        waiters: 0,
+                    }
+                });
+                entry.waiters += 1;
+            }
+            self.targets.push(Target {
+                request: req.clone(),
+                sources,
+            });
+        }
+
+        if !fetch.is_empty() {
+            self.data_source.fetch_tiles(&fetch);
+        }
+        // Tiles outside of the source's tiles are ready immediately (empty)
+        self.assemble();
+    }
+
+    fn cancel_tiles(&mut self, requests: &[TileRequest]) {
+        if self.is_dynamic() {
+            self.data_source.cancel_tiles(requests);
+        }
+    }
+
+    fn fetch_search(&mut self, query: &SearchQuery) {
+        self.data_source.fetch_search(query)
+    }
+
+    fn get_search_results(&mut self) -> Vec<SearchResponse> {
+        self.data_source.get_search_results()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::data::{Color32, DataSource, EntryInfo, FieldSchema, ItemEdge, ItemMeta, UtilPoint};
+    use crate::deferred_data::DeferredDataSourceWrapper;
+    use crate::timestamp::Timestamp;
+
+    fn interval(start: i64, stop: i64) -> Interval {
+        Interval::new(Timestamp(start), Timestamp(stop))
+    }
+
+    fn tile(start: i64, stop: i64) -> TileID {
+        TileID(interval(start, stop))
+    }
+
+    // A profile over [0, 100) with one coarse tile and two fine ones. Row 0
+    // has item 1 over [20, 80) (sliced in the fine tiles); row 1 has item 2
+    // over [60, 70), which depends on item 1. Utilization rises steadily, and
+    // the counter steps up at 0, 30 and 60. Every tile request is recorded.
+    struct StaticDataSource {
+        tile_set: TileSet,
+        requests: Requests,
+        info_error: bool,
+    }
+
+    impl StaticDataSource {
+        fn new(tiles: Vec<Vec<TileID>>, requests: Requests) -> Self {
+            Self {
+                tile_set: TileSet { tiles },
+                requests,
+                info_error: false,
+            }
+        }
+
+        fn record(&self, kind: TileKind, tile_id: TileID, full: bool) {
+            self.requests.lock().unwrap().push((kind, tile_id, full));
+        }
+
+        fn items(tile_id: TileID) -> Vec<Vec<(ItemUID, Interval)>> {
+            [(1, interval(20, 80)), (2, interval(60, 70))]
+                .into_iter()
+                .map(|(uid, i)| {
+                    if i.overlaps(tile_id.0) {
+                        vec![(ItemUID(uid), i.intersection(tile_id.0))]
+                    } else {
+                        Vec::new()
+                    }
+                })
+                .collect()
+        }
+    }
+
+    impl DataSource for StaticDataSource {
+        fn fetch_description(&self) -> DataSourceDescription {
+            DataSourceDescription {
+                source_locator: Vec::new(),
+            }
+        }
+
+        fn fetch_info(&self) -> DataSourceResult<DataSourceInfo> {
+            if self.info_error {
+                return Err(DataSourceError::Io("unreadable".to_owned()));
+            }
+            Ok(DataSourceInfo {
+                entry_info: EntryInfo::Panel {
+                    short_name: String::new(),
+                    long_name: String::new(),
+                    summary: None,
+                    counters: Vec::new(),
+                    slots: Vec::new(),
+                },
+                interval: interval(0, 100),
+                tile_set: self.tile_set.clone(),
+                field_schema: FieldSchema::new(),
+                warning_message: None,
+            })
+        }
+
+        fn fetch_summary_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            full: bool,
+        ) -> DataSourceResult<SummaryTile> {
+            self.record(TileKind::Summary, tile_id, full);
+            let utilization = (tile_id.0.start.0..=tile_id.0.stop.0)
+                .step_by(10)
+                .map(|t| UtilPoint {
+                    time: Timestamp(t),
+                    util: t as f32 / 100.0,
+                })
+                .collect();
+            Ok(SummaryTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SummaryTileData { utilization },
+            })
+        }
+
+        fn fetch_counter_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            full: bool,
+        ) -> DataSourceResult<CounterTile> {
+            self.record(TileKind::Counter, tile_id, full);
+            let steps = [(0, 1.0), (30, 2.0), (60, 3.0)];
+            let mut points = Vec::new();
+            for (t, value) in steps {
+                let time = Timestamp(t.max(tile_id.0.start.0));
+                if time >= tile_id.0.stop {
+                    break;
+                }
+                if points.last().is_some_and(|p: &CounterPoint| p.time == time) {
+                    points.pop();
+                }
+                points.push(CounterPoint { time, value });
+            }
+            Ok(CounterTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: CounterTileData { points },
+            })
+        }
+
+        fn fetch_slot_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            full: bool,
+        ) -> DataSourceResult<SlotTile> {
+            self.record(TileKind::Slot, tile_id, full);
+            let items = Self::items(tile_id)
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|(item_uid, interval)| Item {
+                            item_uid,
+                            interval,
+                            color: Color32::BLUE,
+                        })
+                        .collect()
+                })
+                .collect();
+            let edge = ItemEdge {
+                src: ItemUID(1),
+                dst: ItemUID(2),
+                kind: crate::data::EdgeKind::Dependence,
+            };
+            let edges = if interval(60, 70).overlaps(tile_id.0) {
+                vec![edge]
+            } else {
+                Vec::new()
+            };
+            Ok(SlotTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SlotTileData { items, edges },
+            })
+        }
+
+        fn fetch_slot_meta_tile(
+            &self,
+            entry_id: &EntryID,
+            tile_id: TileID,
+            full: bool,
+        ) -> DataSourceResult<SlotMetaTile> {
+            self.record(TileKind::SlotMeta, tile_id, full);
+            let items = Self::items(tile_id)
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|(item_uid, _)| ItemMeta {
+                            item_uid,
+                            original_interval: interval(0, 0),
+                            title: format!("item {}", item_uid.0),
+                            fields: Vec::new(),
+                        })
+                        .collect()
+                })
+                .collect();
+            Ok(SlotMetaTile {
+                entry_id: entry_id.clone(),
+                tile_id,
+                data: SlotMetaTileData { items },
+            })
+        }
+    }
+
+    type Requests = Arc<Mutex<Vec<(TileKind, TileID, bool)>>>;
+    type Retiler = RetilingDeferredDataSource<DeferredDataSourceWrapper<StaticDataSource>>;
+
+    fn retiler(tiles: Vec<Vec<TileID>>) -> (Retiler, Requests) {
+        let requests = Requests::default();
+        let mut retiler = RetilingDeferredDataSource::new(DeferredDataSourceWrapper::new(
+            StaticDataSource::new(tiles, requests.clone()),
+        ));
+        retiler.fetch_info();
+        let info = retiler.get_infos().pop().unwrap().unwrap();
+        assert!(info.tile_set.tiles.is_empty());
+        (retiler, requests)
+    }
+
+    fn static_retiler() -> (Retiler, Requests) {
+        retiler(vec![vec![tile(0, 100)], vec![tile(0, 50), tile(50, 100)]])
+    }
+
+    fn entry() -> EntryID {
+        EntryID::root().child(0).child(0).child(0)
+    }
+
+    #[test]
+    fn test_source_level() {
+        let (mut retiler, requests) = static_retiler();
+        retiler.fetch_summary_tile(&entry(), tile(0, 100), false);
+        retiler.fetch_summary_tile(&entry(), tile(0, 60), false);
+        retiler.fetch_summary_tile(&entry(), tile(0, 100), true);
+        assert_eq!(
+            requests.lock().unwrap().clone(),
+            vec![
+                (TileKind::Summary, tile(0, 100), false),
+                (TileKind::Summary, tile(0, 50), false),
+                (TileKind::Summary, tile(50, 100), false),
+                (TileKind::Summary, tile(0, 50), true),
+                (TileKind::Summary, tile(50, 100), true),
+            ]
+        );
+        assert_eq!(retiler.get_summary_tiles().len(), 3);
+        assert!(retiler.sources.is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let (mut retiler, _) = static_retiler();
+        let tile_id = tile(25, 75);
+        retiler.fetch_summary_tile(&entry(), tile_id, false);
+        let tile = retiler.get_summary_tiles().pop().unwrap();
+        assert_eq!(tile.tile_id, tile_id);
+        let points: Vec<_> = tile
+            .result
+            .unwrap()
+            .data
+            .utilization
+            .iter()
+            .map(|p| (p.time.0, p.util))
+            .collect();
+        // The point before the tile is carried in at its start
+        assert_eq!(
+            points,
+            vec![
+                (25, 0.2),
+                (30, 0.3),
+                (40, 0.4),
+                (50, 0.5),
+                (60, 0.6),
+                (70, 0.7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_counter() {
+        let (mut retiler, _) = static_retiler();
+        retiler.fetch_counter_tile(&entry(), tile(40, 90), false);
+        let tile = retiler.get_counter_tiles().pop().unwrap();
+        let points: Vec<_> = tile
+            .result
+            .unwrap()
+            .data
+            .points
+            .iter()
+            .map(|p| (p.time.0, p.value))
+            .collect();
+        // The second source tile's restatement of the value at its start is
+        // dropped
+        assert_eq!(points, vec![(40, 2.0), (60, 3.0)]);
+    }
+
+    #[test]
+    fn test_counter_round_trip() {
+        // Slicing a counter into tiles and stitching them back together gives
+        // the same points as slicing it to the stitched tile
+        let points: Vec<_> = [(0, 1.0), (25, 2.0), (50, 3.0), (55, 4.0), (90, 5.0)]
+            .into_iter()
+            .map(|(t, value)| CounterPoint {
+                time: Timestamp(t),
+                value,
+            })
+            .collect();
+        let sources: Vec<_> = [tile(0, 25), tile(25, 50), tile(50, 75), tile(75, 100)]
+            .into_iter()
+            .map(|source| (source, CounterTileData::slice(&points, source.0)))
+            .collect();
+        for (start, stop) in [(0, 100), (10, 60), (25, 50), (50, 51), (80, 100)] {
+            let tile_id = tile(start, stop);
+            let retiled = retile_counter(
+                tile_id.0,
+                sources
+                    .iter()
+                    .filter(|(source, _)| source.0.overlaps(tile_id.0))
+                    .map(|(_, data)| data),
+            );
+            assert_eq!(
+                retiled.points,
+                CounterTileData::slice(&points, tile_id.0).points,
+                "{:?}",
+                tile_id
+            );
+        }
+    }
+
+    #[test]
+    fn test_slot() {
+        let (mut retiler, requests) = static_retiler();
+        retiler.fetch_slot_tile(&entry(), tile(25, 75), false);
+        retiler.fetch_slot_meta_tile(&entry(), tile(25, 75), false);
+
+        let slot = retiler.get_slot_tiles().pop().unwrap().result.unwrap();
+        let items: Vec<Vec<_>> = slot
+            .data
+            .items
+            .iter()
+            .map(|row| row.iter().map(|i| (i.item_uid.0, i.interval)).collect())
+            .collect();
+        // The slices of item 1 are joined back up
+        assert_eq!(
+            items,
+            vec![vec![(1, interval(25, 75))], vec![(2, interval(60, 70))]]
+        );
+        assert_eq!(slot.data.edges.len(), 1);
+
+        let meta = retiler.get_slot_meta_tiles().pop().unwrap().result.unwrap();
+        let titles: Vec<Vec<_>> = meta
+            .data
+            .items
+            .iter()
+            .map(|row| row.iter().map(|i| i.title.as_str()).collect())
+            .collect();
+        assert_eq!(titles, vec![vec!["item 1"], vec!["item 2"]]);
+
+        // Slot tiles are only fetched once, even though both need them
+        let slot_requests = requests
+            .lock()
+            .unwrap()
+            .clone()
+            .iter()
+            .filter(|r| r.0 == TileKind::Slot)
+            .count();
+        assert_eq!(slot_requests, 2);
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let (mut retiler, requests) = retiler(Vec::new());
+        retiler.fetch_slot_tile(&entry(), tile(25, 75), false);
+        assert_eq!(
+            requests.lock().unwrap().clone(),
+            vec![(TileKind::Slot, tile(25, 75), false)]
+        );
+        assert_eq!(retiler.get_slot_tiles().len(), 1);
+    }
+
+    #[test]
+    fn test_info_missing() {
+        let requests = Requests::default();
+        let source = StaticDataSource {
+            info_error: true,
+            ..StaticDataSource::new(Vec::new(), requests.clone())
+        };
+        let mut retiler = RetilingDeferredDataSource::new(DeferredDataSourceWrapper::new(source));
+
+        // Before the info arrives, and after it fails
+        retiler.fetch_summary_tile(&entry(), tile(0, 100), false);
+        let summary = retiler.get_summary_tiles().pop().unwrap();
+        assert!(matches!(summary.result, Err(DataSourceError::NotFound(_))));
+        retiler.fetch_info();
+        assert!(retiler.get_infos().pop().unwrap().is_err());
+        retiler.fetch_slot_tile(&entry(), tile(0, 100), false);
+        let slot = retiler.get_slot_tiles().pop().unwrap();
+        assert!(matches!(slot.result, Err(DataSourceError::Io(_))));
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::BTreeSet;
+
+use regex::{escape, Regex};
+
+use crate::data::{
+    DataSource, DataSourceResult, EntryIndex, Field, FieldID, FieldSchema, ItemLink, ItemMeta,
+    SearchQuery, SearchResults, TileID,
+};
+use crate::size::Bytes;
+use crate::timestamp::Timestamp;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CompareOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Quantity {
+    Number(f64),
+    Bool(bool),
+    Duration(i64 /* ns */),
+    Bytes(u64),
+}
+
+// A numeric search query, e.g., "size > 1MiB" or ">= 10us". When the field
+// name is omitted, the comparison applies to the selected search field, or
+// when searching titles, durations are compared against each item's own
+// duration (other field-less comparisons are searched for as text).
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct FieldComparison {
+    field: Option<FieldID>,
+    op: CompareOp,
+    value: Quantity,
+}
+
+// Decides whether an item matches a search query. This is shared between
+// the viewer (which searches the tiles it has loaded) and data sources that
+// implement DataSource::search, so that both agree on what matches.
+#[derive(Debug, Clone)]
+pub struct SearchMatcher {
+    query: String,
+    field: Option<FieldID>,
+    word_regex: Option<Regex>,
+    comparison: Option<FieldComparison>,
+}
+
+impl CompareOp {
+    // Longer operators first, so that "<=" isn't parsed as "<"
+    const ALL: [(&'static str, CompareOp); 7] = [
+        ("<=", CompareOp::Le),
+        (">=", CompareOp::Ge),
+        ("==", CompareOp::Eq),
+        ("!=", CompareOp::Ne),
+        ("<", CompareOp::Lt),
+        (">", CompareOp::Gt),
+        ("=", CompareOp::Eq),
+    ];
+
+    fn accepts(self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering;
+        match self {
+            CompareOp::Lt => ordering == Ordering::Less,
+            CompareOp::Le => ordering != Ordering::Greater,
+            CompareOp::Gt => ordering == Ordering::Greater,
+            CompareOp::Ge => ordering != Ordering::Less,
+            CompareOp::Eq => ordering == Ordering::Equal,
+            CompareOp::Ne => ordering != Ordering::Equal,
+        }
+    }
+}
+
+impl Quantity {
+    fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        match s.to_lowercase().as_str() {
+            "true" => return Some(Quantity::Bool(true)),
+            "false" => return Some(Quantity::Bool(false)),
+            _ => {}
+        }
+        if let Ok(value) = s.parse::<f64>() {
+            return Some(Quantity::Number(value));
+        }
+        if let Ok(value) = Timestamp::parse(s) {
+            return Some(Quantity::Duration(value.0));
+        }
+        if let Ok(value) = Bytes::parse(s) {
+            return Some(Quantity::Bytes(value.0));
+        }
+        None
+    }
+}
+
+impl FieldComparison {
+    fn parse(query: &str, field_schema: &FieldSchema) -> Option<Self> {
+        let op_idx = query.find(['<', '>', '=', '!'])?;
+        let (name, rest) = query.split_at(op_idx);
+        let (op_s, op) = CompareOp::ALL
+            .into_iter()
+            .find(|(op_s, _)| rest.starts_with(op_s))?;
+
+        let name = name.trim();
+        let field = if name.is_empty() {
+            None
+        } else {
+            // Field names are usually capitalized, but queries often aren't
+            let field = field_schema
+                .get_id(name)
+                .or_else(|| field_schema.get_id_ignore_case(name))?;
+            Some(field)
+        };
+
+        let value = Quantity::parse(&rest[op_s.len()..])?;
+        Some(Self { field, op, value })
+    }
+
+    fn is_match(&self, field: &Field) -> bool {
+        let ordering = match (field, self.value) {
+            (Field::I64(x), Quantity::Number(y)) => (*x as f64).partial_cmp(&y),
+            (Field::U64(x), Quantity::Number(y)) => (*x as f64).partial_cmp(&y),
+            (Field::F64(x), Quantity::Number(y)) => x.partial_cmp(&y),
+            (Field::Bool(x), Quantity::Bool(y)) => Some(x.cmp(&y)),
+            (Field::Duration(x), Quantity::Duration(y)) => Some(x.cmp(&y)),
+            // Bare numbers are interpreted in the base unit
+            (Field::Duration(x), Quantity::Number(y)) => (*x as f64).partial_cmp(&y),
+            (Field::Bytes(x), Quantity::Bytes(y)) => Some(x.cmp(&y)),
+            (Field::Bytes(x), Quantity::Number(y)) => (*x as f64).partial_cmp(&y),
+            (Field::Interval(x), Quantity::Duration(y)) => Some(x.duration_ns().cmp(&y)),
+            (Field::Vec(fields), _) => return fields.iter().any(|f| self.is_match(f)),
+            _ => None,
+        };
+        ordering.is_some_and(|x| self.op.accepts(x))
+    }
+}
+
+impl SearchMatcher {
+    pub fn new(query: &SearchQuery, field_schema: &FieldSchema) -> Self {
+        let word_regex = query.whole_word.then(|| {
+            let regex_string = format!("\\b{}\\b", escape(&query.query));
+            Regex::new(&regex_string).unwrap()
+        });
+        Self {
+            query: query.query.clone(),
+            field: query.field,
+            word_regex,
+            comparison: FieldComparison::parse(&query.query, field_schema),
+        }
+    }
+
+    fn is_string_match(&self, s: &str) -> bool {
+        if let Some(regex) = &self.word_regex {
+            regex.is_match(s)
+        } else {
+            s.contains(&self.query)
+        }
+    }
+
+    fn is_field_match(&self, field: &Field) -> bool {
+        match field {
+            Field::String(s) => self.is_string_match(s),
+            Field::ItemLink(ItemLink { title, .. }) => self.is_string_match(title),
+            Field::Vec(fields) => fields.iter().any(|f| self.is_field_match(f)),
+            _ => false,
+        }
+    }
+
+    fn find_field(item: &ItemMeta, field: FieldID) -> Option<&Field> {
+        item.fields
+            .iter()
+            .find(|(x, _, _)| *x == field)
+            .map(|(_, value, _)| value)
+    }
+
+    pub fn is_match(&self, item: &ItemMeta) -> bool {
+        if let Some(comparison) = &self.comparison {
+            match comparison.field.or(self.field) {
+                Some(field) => {
+                    return Self::find_field(item, field)
+                        .is_some_and(|value| comparison.is_match(value));
+                }
+                None if matches!(comparison.value, Quantity::Duration(_)) => {
+                    return comparison.is_match(&Field::Interval(item.original_interval));
+                }
+                None => {}
+            }
+        }
+
+        match self.field {
+            None => self.is_string_match(&item.title),
+            Some(field) => Self::find_field(item, field).is_some_and(|x| self.is_field_match(x)),
+        }
+    }
+}
+
+// A straightforward implementation of DataSource::search for data sources
+// that don't have an index: scan the finest level of meta tiles (which are
+// the only ones guaranteed to contain every item) in the query interval.
+pub fn scan_slot_meta_tiles<D>(
+    data_source: &D,
+    query: &SearchQuery,
+) -> DataSourceResult<SearchResults>
+where
+    D: DataSource + ?Sized,
+{
+    let info = data_source.fetch_info()?;
+    let matcher = SearchMatcher::new(query, &info.field_schema);
+
+    let interval = query.interval.intersection(info.interval);
+    let tile_ids: Vec<_> = match info.tile_set.tiles.last() {
+        Some(level) => level
+            .iter()
+            .filter(|tile| interval.overlaps(tile.0))
+            .copied()
+            .collect(),
+        None => vec![TileID(interval)],
+    };
+
+    let mut result = SearchResults {
+        items: Vec::new(),
+        truncated: false,
+    };
+    // Items may be split across tiles, so make sure each is reported once
+    let mut seen = BTreeSet::new();
+    for entry_id in info.entry_info.entry_ids() {
+        let Some(EntryIndex::Slot(..)) = entry_id.last_index() else {
+            continue;
+        };
+        for tile_id in &tile_ids {
+            let tile = data_source.fetch_slot_meta_tile(&entry_id, *tile_id, true)?;
+            for item in tile.data.items.iter().flatten() {
+                if !interval.overlaps(item.original_interval)
+                    || !matcher.is_match(item)
+                    || !seen.insert(item.item_uid)
+                {
+                    continue;
+                }
+                if result.items.len() as u64 >= query.max_results {
+                    result.truncated = true;
+                    return Ok(result);
+                }
+                result.items.push(ItemLink {
+                    item_uid: item.item_uid,
+                    title: item.title.clone(),
+                    interval: item.original_interval,
+                    entry_id: entry_id.clone(),
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data::ItemUID;
+    use crate::timestamp::Interval;
+
+    fn schema() -> (FieldSchema, FieldID, FieldID) {
+        let mut schema = FieldSchema::new();
+        let provenance = schema.insert("Provenance".to_owned(), true);
+        let size = schema.insert("Size".to_owned(), true);
+        (schema, provenance, size)
+    }
+
+    fn item(title: &str, fields: Vec<(FieldID, Field)>) -> ItemMeta {
+        ItemMeta {
+            item_uid: ItemUID(0),
+            original_interval: Interval::new(Timestamp(0), Timestamp(10)),
+            title: title.to_owned(),
+            fields: fields.into_iter().map(|(id, f)| (id, f, None)).collect(),
+        }
+    }
+
+    fn matcher(query: &str, field: Option<FieldID>, whole_word: bool) -> SearchMatcher {
+        let query = SearchQuery {
+            query: query.to_owned(),
+            field,
+            whole_word,
+            interval: Interval::new(Timestamp(0), Timestamp(10)),
+            max_results: 10,
+        };
+        SearchMatcher::new(&query, &schema().0)
+    }
+
+    #[test]
+    fn test_title() {
+        let m = matcher("copy", None, false);
+        assert!(m.is_match(&item("task copy_in", Vec::new())));
+        assert!(!m.is_match(&item("task fill", Vec::new())));
+    }
+
+    #[test]
+    fn test_whole_word() {
+        let m = matcher("copy", None, true);
+        assert!(m.is_match(&item("copy in", Vec::new())));
+        assert!(!m.is_match(&item("copy_in", Vec::new())));
+    }
+
+    #[test]
+    fn test_field() {
+        let (_, provenance, _) = schema();
+        let m = matcher("main.py", Some(provenance), false);
+        let hit = item(
+            "t",
+            vec![(provenance, Field::String("main.py:12".to_owned()))],
+        );
+        assert!(m.is_match(&hit));
+        assert!(!m.is_match(&item("main.py", Vec::new())));
+    }
+
+    #[test]
+    fn test_comparison() {
+        let (_, _, size) = schema();
+        let m = matcher("size >= 1 KiB", None, false);
+        assert!(m.is_match(&item("t", vec![(size, Field::Bytes(1024))])));
+        assert!(!m.is_match(&item("t", vec![(size, Field::Bytes(1023))])));
+        assert!(!m.is_match(&item("size >= 1 KiB", Vec::new())));
+    }
+
+    fn item_with_duration(duration_ns: i64) -> ItemMeta {
+        ItemMeta {
+            original_interval: Interval::new(Timestamp(100), Timestamp(100 + duration_ns)),
+            ..item("t", Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_duration_units() {
+        for (query, ns) in [
+            (">= 10ns", 10),
+            (">= 10us", 10_000),
+            (">= 10ms", 10_000_000),
+            (">= 10s", 10_000_000_000),
+            (">= 1.5 us", 1_500),
+        ] {
+            let m = matcher(query, None, false);
+            assert!(m.is_match(&item_with_duration(ns)), "{}", query);
+            assert!(!m.is_match(&item_with_duration(ns - 1)), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_size_units() {
+        let (_, _, size) = schema();
+        for (query, bytes) in [
+            ("size >= 10B", 10),
+            ("size >= 10KB", 10_000),
+            ("size >= 10MB", 10_000_000),
+            ("size >= 10GB", 10_000_000_000),
+            ("size >= 10TB", 10_000_000_000_000),
+            ("size >= 10KiB", 10 << 10),
+            ("size >= 10MiB", 10 << 20),
+            ("size >= 10GiB", 10 << 30),
+            ("size >= 10TiB", 10 << 40),
+            // Bare numbers are in bytes
+            ("size >= 10", 10),
+        ] {
+            let m = matcher(query, None, false);
+            let hit = item("t", vec![(size, Field::Bytes(bytes))]);
+            let miss = item("t", vec![(size, Field::Bytes(bytes - 1))]);
+            assert!(m.is_match(&hit), "{}", query);
+            assert!(!m.is_match(&miss), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_operators() {
+        // Durations 9us, 10us and 11us
+        for (query, expected) in [
+            ("< 10us", [true, false, false]),
+            ("<= 10us", [true, true, false]),
+            ("> 10us", [false, false, true]),
+            (">= 10us", [false, true, true]),
+            ("= 10us", [false, true, false]),
+            ("== 10us", [false, true, false]),
+            ("!= 10us", [true, false, true]),
+        ] {
+            let m = matcher(query, None, false);
+            let actual = [9_000, 10_000, 11_000].map(|ns| m.is_match(&item_with_duration(ns)));
+            assert_eq!(actual, expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_comparison_selected_field() {
+        let (_, _, size) = schema();
+        let m = matcher("> 1KiB", Some(size), false);
+        assert!(m.is_match(&item("t", vec![(size, Field::Bytes(2048))])));
+        assert!(!m.is_match(&item("t", vec![(size, Field::Bytes(1024))])));
+        // The item's duration is only used when searching titles
+        let m = matcher("> 1us", Some(size), false);
+        assert!(!m.is_match(&item_with_duration(2_000)));
+    }
+
+    #[test]
+    fn test_comparison_title_fallback() {
+        // Only durations can be compared without a field, so anything else
+        // is searched for in the title
+        let m = matcher("=1", None, false);
+        assert!(m.is_match(&item("x=1", Vec::new())));
+        assert!(!m.is_match(&item("x=2", Vec::new())));
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize)]
+pub struct Bytes(pub u64);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BytesParseError {
+    InvalidValue,
+    NoUnit,
+    InvalidUnit,
+}
+
+const UNITS: [(&str, u64); 9] = [
+    ("b", 1),
+    ("kb", 1_000),
+    ("mb", 1_000_000),
+    ("gb", 1_000_000_000),
+    ("tb", 1_000_000_000_000),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+    ("tib", 1 << 40),
+];
+
+impl Bytes {
+    pub fn parse(s: &str) -> Result<Bytes, BytesParseError> {
+        let s = s.trim();
+        let split_idx = s
+            .find(|c| !(char::is_ascii_digit(&c) || c == '.'))
+            .ok_or(BytesParseError::NoUnit)?;
+
+        let (value_s, unit_s) = s.split_at(split_idx);
+        let value = value_s
+            .parse::<f64>()
+            .map_err(|_| BytesParseError::InvalidValue)?;
+        let unit = unit_s.trim().to_lowercase();
+
+        let (_, factor) = UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .ok_or(BytesParseError::InvalidUnit)?;
+
+        Ok(Bytes((value * *factor as f64) as u64))
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Always display in binary units, since that's what allocators and
+        // memory sizes are usually measured in
+        const NAMES: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+        let mut index = 0;
+        while index + 1 < NAMES.len() && self.0 >= 1 << (10 * (index + 1)) {
+            index += 1;
+        }
+
+        if index == 0 {
+            write!(f, "{} {}", self.0, NAMES[0])
+        } else {
+            let value = self.0 as f64 / (1u64 << (10 * index)) as f64;
+            write!(f, "{value:.2} {}", NAMES[index])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod bytes_parse {
+        use super::*;
+
+        #[test]
+        fn test_b() {
+            assert_eq!(Bytes::parse("123 B"), Ok(Bytes(123)));
+        }
+
+        #[test]
+        fn test_kb() {
+            assert_eq!(Bytes::parse("1.5 KB"), Ok(Bytes(1_500)));
+        }
+
+        #[test]
+        fn test_mib() {
+            assert_eq!(Bytes::parse("1MiB"), Ok(Bytes(1 << 20)));
+        }
+
+        #[test]
+        fn test_gib_lower() {
+            assert_eq!(Bytes::parse("  2 gib  "), Ok(Bytes(2 << 30)));
+        }
+
+        #[test]
+        fn test_no_unit() {
+            assert_eq!(Bytes::parse("500"), Err(BytesParseError::NoUnit));
+        }
+
+        #[test]
+        fn test_invalid_unit() {
+            assert_eq!(Bytes::parse("500 ms"), Err(BytesParseError::InvalidUnit));
+        }
+
+        #[test]
+        fn test_invalid_value() {
+            assert_eq!(
+                Bytes::parse("500.0.0 MiB"),
+                Err(BytesParseError::InvalidValue)
+            );
+        }
+    }
+
+    mod bytes_display {
+        use super::*;
+
+        #[test]
+        fn test_b() {
+            assert_eq!(Bytes(1023).to_string(), "1023 B");
+        }
+
+        #[test]
+        fn test_kib() {
+            assert_eq!(Bytes(1536).to_string(), "1.50 KiB");
+        }
+
+        #[test]
+        fn test_mib() {
+            assert_eq!(Bytes(1 << 20).to_string(), "1.00 MiB");
+        }
+
+        #[test]
+        fn test_tib() {
+            assert_eq!(Bytes(3 << 40).to_string(), "3.00 TiB");
+        }
+
+        #[test]
+        fn test_large() {
+            assert_eq!(Bytes(2048 << 40).to_string(), "2048.00 TiB");
+        }
+    }
+}